@@ -1,6 +1,11 @@
 extern crate winres;
 
 fn main() {
+    // Resources only mean something in the Windows DLL
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        return;
+    }
+
     let mut res = winres::WindowsResource::new();
     res.set_resource_file("resources.rc");
     res.compile().unwrap();
@@ -0,0 +1,503 @@
+// What the user can do to a node. Each node kind declares its actions here
+// and the context menu, and anything else that offers commands, is built
+// from them.
+
+use crate::registry::NodeKind;
+use crate::smbus::drivers::DeviceKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionId {
+    RescanBus,
+    AllowWrites,
+    ReadNow,
+    WordMode,
+    PausePolling,
+    DryRun,
+    ApplyProfile,
+    ConnectTo,
+    ExportHistory,
+    EditRegister,
+    BackupEeprom,
+    RestoreEeprom,
+    SetFanMode,
+    SaveSpdImage,
+    AddDevice,
+}
+
+impl ActionId {
+    pub const ALL: [ActionId; 15] = [
+        ActionId::RescanBus,
+        ActionId::AllowWrites,
+        ActionId::ReadNow,
+        ActionId::WordMode,
+        ActionId::PausePolling,
+        ActionId::DryRun,
+        ActionId::ApplyProfile,
+        ActionId::ConnectTo,
+        ActionId::ExportHistory,
+        ActionId::EditRegister,
+        ActionId::BackupEeprom,
+        ActionId::RestoreEeprom,
+        ActionId::SetFanMode,
+        ActionId::SaveSpdImage,
+        ActionId::AddDevice,
+    ];
+
+    /// The id MMC hands back when the action is picked. Zero isn't allowed.
+    pub fn command_id(self) -> i32 {
+        match self {
+            ActionId::RescanBus => 1,
+            ActionId::AllowWrites => 2,
+            ActionId::ReadNow => 3,
+            ActionId::WordMode => 4,
+            ActionId::PausePolling => 5,
+            ActionId::DryRun => 6,
+            ActionId::ApplyProfile => 7,
+            ActionId::ConnectTo => 8,
+            ActionId::ExportHistory => 9,
+            ActionId::EditRegister => 10,
+            ActionId::BackupEeprom => 11,
+            ActionId::RestoreEeprom => 12,
+            ActionId::SetFanMode => 13,
+            ActionId::SaveSpdImage => 14,
+            ActionId::AddDevice => 15,
+        }
+    }
+
+    pub fn from_command_id(id: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.command_id() == id)
+    }
+}
+
+/// Where an action goes in MMC's context menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuGroup {
+    Top,
+    New,
+    Task,
+    View,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub id: ActionId,
+    pub label: &'static str,
+    /// Shown in the status bar while the item is highlighted.
+    pub description: &'static str,
+    pub group: MenuGroup,
+    pub enabled: bool,
+    /// `None` for actions that aren't toggles.
+    pub checked: Option<bool>,
+}
+
+/// The state of the snap-in that decides which actions are enabled or
+/// checked for a node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActionState {
+    pub has_bus: bool,
+    pub writes_enabled: bool,
+    /// Whether the node's register browser reads words.
+    pub word_mode: bool,
+    /// Whether the device didn't answer the last time it was read.
+    pub stale: bool,
+    pub polling_paused: bool,
+    /// Whether writes are only logged instead of sent to the bus.
+    pub dry_run: bool,
+    pub has_profiles: bool,
+    /// Whether anything the node covers has been recorded in the history.
+    pub has_history: bool,
+    /// Whether the node is an EEPROM at one of the SPD addresses, i.e. a
+    /// memory module's.
+    pub spd: bool,
+    /// Whether the device was added by hand rather than found by a scan.
+    pub manual: bool,
+}
+
+pub fn actions(kind: NodeKind, state: &ActionState) -> Vec<Action> {
+    match kind {
+        NodeKind::Root => vec![
+            Action {
+                id: ActionId::RescanBus,
+                label: "&Rescan bus",
+                description: "Scan the bus again for devices",
+                group: MenuGroup::Top,
+                enabled: state.has_bus,
+                checked: None,
+            },
+            Action {
+                id: ActionId::ConnectTo,
+                label: "&Connect to another computer...",
+                description: "Manage the SMBus devices of another computer",
+                group: MenuGroup::Top,
+                enabled: true,
+                checked: None,
+            },
+            Action {
+                id: ActionId::AddDevice,
+                label: "&Add device...",
+                description: "Show a device at an address the scan doesn't find",
+                group: MenuGroup::New,
+                enabled: true,
+                checked: None,
+            },
+            Action {
+                id: ActionId::AllowWrites,
+                label: "Allow &writes",
+                description: "Allow writes to devices outside the protected ranges",
+                group: MenuGroup::Task,
+                enabled: true,
+                checked: Some(state.writes_enabled),
+            },
+            Action {
+                id: ActionId::DryRun,
+                label: "&Dry run",
+                description: "Log writes instead of sending them to the bus",
+                group: MenuGroup::Task,
+                enabled: true,
+                checked: Some(state.dry_run),
+            },
+            pause_polling(state),
+            apply_profile(state),
+            export_history(state, "Save every device's readings over time as CSV"),
+        ],
+        NodeKind::Device(_, DeviceKind::Eeprom) => vec![
+            read_now(state),
+            pause_polling(state),
+            apply_profile(state),
+            match state.spd {
+                true => Action {
+                    id: ActionId::SaveSpdImage,
+                    label: "&Save SPD image...",
+                    description: "Save the memory module's SPD to an image file",
+                    group: MenuGroup::Task,
+                    enabled: state.has_bus,
+                    checked: None,
+                },
+                false => Action {
+                    id: ActionId::BackupEeprom,
+                    label: "&Back up EEPROM...",
+                    description: "Save the EEPROM's contents to an image file",
+                    group: MenuGroup::Task,
+                    enabled: state.has_bus,
+                    checked: None,
+                },
+            },
+            Action {
+                id: ActionId::RestoreEeprom,
+                label: "Re&store EEPROM...",
+                description: "Write an image file back to the EEPROM and check it",
+                group: MenuGroup::Task,
+                enabled: state.has_bus && state.writes_enabled,
+                checked: None,
+            },
+        ],
+        NodeKind::Device(_, DeviceKind::FanController) => vec![
+            read_now(state),
+            Action {
+                id: ActionId::SetFanMode,
+                label: "Set &fan mode...",
+                description: "Switch the fan between automatic and manual control",
+                group: MenuGroup::Top,
+                enabled: state.has_bus && state.writes_enabled,
+                checked: None,
+            },
+            pause_polling(state),
+            apply_profile(state),
+        ],
+        NodeKind::Device(..) => vec![read_now(state), pause_polling(state), apply_profile(state)],
+        NodeKind::Registers(_) => vec![
+            read_now(state),
+            Action {
+                id: ActionId::EditRegister,
+                label: "&Edit register...",
+                description: "Write a value to one of the device's registers",
+                group: MenuGroup::Top,
+                enabled: state.has_bus && state.writes_enabled,
+                checked: None,
+            },
+            pause_polling(state),
+            Action {
+                id: ActionId::WordMode,
+                label: "&Word registers",
+                description: "Read registers as 16-bit words instead of bytes",
+                group: MenuGroup::View,
+                enabled: true,
+                checked: Some(state.word_mode),
+            },
+        ],
+        NodeKind::History(_) => vec![
+            pause_polling(state),
+            export_history(state, "Save the device's readings over time as CSV"),
+        ],
+        NodeKind::Folder => Vec::new(),
+    }
+}
+
+fn read_now(state: &ActionState) -> Action {
+    Action {
+        id: ActionId::ReadNow,
+        label: "Read &now",
+        description: "Read the device again",
+        group: MenuGroup::Top,
+        enabled: state.has_bus,
+        checked: None,
+    }
+}
+
+fn pause_polling(state: &ActionState) -> Action {
+    Action {
+        id: ActionId::PausePolling,
+        label: "&Pause polling",
+        description: "Stop reading devices in the background",
+        group: MenuGroup::View,
+        enabled: true,
+        checked: Some(state.polling_paused),
+    }
+}
+
+fn apply_profile(state: &ActionState) -> Action {
+    Action {
+        id: ActionId::ApplyProfile,
+        label: "Apply p&rofile...",
+        description: "Write one of the saved profiles' settings to the devices",
+        group: MenuGroup::Task,
+        enabled: state.has_bus && state.has_profiles,
+        checked: None,
+    }
+}
+
+fn export_history(state: &ActionState, description: &'static str) -> Action {
+    Action {
+        id: ActionId::ExportHistory,
+        label: "E&xport history...",
+        description,
+        group: MenuGroup::Task,
+        enabled: state.has_history,
+        checked: None,
+    }
+}
+
+/// A button on the snap-in's toolbar, or a menu button next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolbarButton {
+    pub action: ActionId,
+    /// Only menu buttons show text; toolbar buttons just have an image.
+    pub text: &'static str,
+    pub tooltip: &'static str,
+    /// Whether the button stays pressed while the action is checked.
+    pub toggle: bool,
+}
+
+/// The toolbar's buttons, in the order of their images in the toolbar
+/// bitmap.
+pub const TOOLBAR: [ToolbarButton; 3] = [
+    ToolbarButton { action: ActionId::RescanBus, text: "", tooltip: "Rescan bus", toggle: false },
+    ToolbarButton { action: ActionId::PausePolling, text: "", tooltip: "Pause polling", toggle: true },
+    ToolbarButton { action: ActionId::DryRun, text: "", tooltip: "Dry run", toggle: true },
+];
+
+/// Actions that get a menu button instead, since they need a choice made.
+pub const MENU_BUTTONS: [ToolbarButton; 1] = [
+    ToolbarButton { action: ActionId::ApplyProfile, text: "Profile", tooltip: "Apply a profile", toggle: false },
+];
+
+/// Looks up one of a node's actions, e.g. to check it's still enabled
+/// before running a command.
+pub fn find(kind: NodeKind, state: &ActionState, id: ActionId) -> Option<Action> {
+    actions(kind, state).into_iter().find(|action| action.id == id)
+}
+
+/// MMC's own commands, which it shows in its toolbar and at the bottom of
+/// the context menu once a node enables them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verb {
+    Refresh,
+    Rename,
+    Delete,
+    Properties,
+    Copy,
+}
+
+impl Verb {
+    pub const ALL: [Verb; 5] = [Verb::Refresh, Verb::Rename, Verb::Delete, Verb::Properties, Verb::Copy];
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verbs {
+    pub enabled: Vec<Verb>,
+    /// What double-clicking the node does.
+    pub default: Option<Verb>,
+}
+
+impl Verbs {
+    pub fn allows(&self, verb: Verb) -> bool {
+        self.enabled.contains(&verb)
+    }
+}
+
+/// The verbs a node enables. Refresh rescans the bus on the root and reads
+/// devices again; on a History node it shows what's been recorded since.
+/// Renaming a device gives it an alias, and copying one puts its properties
+/// on the clipboard. Devices added by hand can be deleted, and so can ones
+/// that stopped answering; a scan would only find the others again.
+pub fn verbs(kind: NodeKind, state: &ActionState) -> Verbs {
+    let refresh = state.has_bus;
+    match kind {
+        NodeKind::Root => Verbs {
+            enabled: match refresh {
+                true => vec![Verb::Properties, Verb::Refresh],
+                false => vec![Verb::Properties],
+            },
+            default: None,
+        },
+        NodeKind::Device(..) => {
+            let mut enabled = vec![Verb::Rename, Verb::Properties, Verb::Copy];
+            if refresh {
+                enabled.push(Verb::Refresh);
+            }
+            if state.stale || state.manual {
+                enabled.push(Verb::Delete);
+            }
+            Verbs { enabled, default: Some(Verb::Properties) }
+        }
+        NodeKind::Registers(_) => Verbs {
+            enabled: match refresh {
+                true => vec![Verb::Refresh],
+                false => Vec::new(),
+            },
+            default: None,
+        },
+        NodeKind::History(_) => Verbs { enabled: vec![Verb::Refresh], default: None },
+        NodeKind::Folder => Verbs::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(actions: &[Action]) -> Vec<ActionId> {
+        actions.iter().map(|action| action.id).collect()
+    }
+
+    fn online() -> ActionState {
+        ActionState { has_bus: true, ..Default::default() }
+    }
+
+    #[test]
+    fn command_ids_round_trip() {
+        for action in ActionId::ALL {
+            assert_ne!(action.command_id(), 0);
+            assert_eq!(ActionId::from_command_id(action.command_id()), Some(action));
+        }
+        assert_eq!(ActionId::from_command_id(0), None);
+    }
+
+    #[test]
+    fn each_kind_declares_its_actions() {
+        let state = online();
+        assert_eq!(ids(&actions(NodeKind::Root, &state)), vec![
+            ActionId::RescanBus,
+            ActionId::ConnectTo,
+            ActionId::AddDevice,
+            ActionId::AllowWrites,
+            ActionId::DryRun,
+            ActionId::PausePolling,
+            ActionId::ApplyProfile,
+            ActionId::ExportHistory,
+        ]);
+        assert_eq!(ids(&actions(NodeKind::Device(0x4c, DeviceKind::FanController), &state)), vec![
+            ActionId::ReadNow,
+            ActionId::SetFanMode,
+            ActionId::PausePolling,
+            ActionId::ApplyProfile,
+        ]);
+        assert_eq!(ids(&actions(NodeKind::Device(0x48, DeviceKind::TemperatureSensor), &state)), vec![
+            ActionId::ReadNow,
+            ActionId::PausePolling,
+            ActionId::ApplyProfile,
+        ]);
+        assert_eq!(ids(&actions(NodeKind::Registers(0x4c), &state)), vec![
+            ActionId::ReadNow,
+            ActionId::EditRegister,
+            ActionId::PausePolling,
+            ActionId::WordMode,
+        ]);
+        assert_eq!(ids(&actions(NodeKind::History(0x4c), &state)), vec![ActionId::PausePolling, ActionId::ExportHistory]);
+        assert!(actions(NodeKind::Folder, &state).is_empty());
+    }
+
+    #[test]
+    fn spd_eeproms_save_an_spd_image() {
+        let kind = NodeKind::Device(0x50, DeviceKind::Eeprom);
+        let plain = ids(&actions(kind, &online()));
+        assert!(plain.contains(&ActionId::BackupEeprom));
+        assert!(!plain.contains(&ActionId::SaveSpdImage));
+
+        let spd = ids(&actions(kind, &ActionState { spd: true, ..online() }));
+        assert!(spd.contains(&ActionId::SaveSpdImage));
+        assert!(!spd.contains(&ActionId::BackupEeprom));
+        assert!(spd.contains(&ActionId::RestoreEeprom));
+    }
+
+    #[test]
+    fn writes_need_the_bus_and_permission() {
+        let fan = NodeKind::Device(0x4c, DeviceKind::FanController);
+        let eeprom = NodeKind::Device(0x50, DeviceKind::Eeprom);
+        let writable = ActionState { writes_enabled: true, ..online() };
+        for (kind, id) in [(fan, ActionId::SetFanMode), (eeprom, ActionId::RestoreEeprom), (NodeKind::Registers(0x4c), ActionId::EditRegister)] {
+            assert!(!find(kind, &ActionState::default(), id).unwrap().enabled);
+            assert!(!find(kind, &online(), id).unwrap().enabled);
+            assert!(find(kind, &writable, id).unwrap().enabled);
+        }
+        assert!(!find(NodeKind::Root, &ActionState::default(), ActionId::RescanBus).unwrap().enabled);
+        assert!(find(NodeKind::Root, &ActionState::default(), ActionId::ConnectTo).unwrap().enabled);
+        assert!(!find(NodeKind::Root, &online(), ActionId::ApplyProfile).unwrap().enabled);
+        assert!(find(NodeKind::Root, &ActionState { has_profiles: true, ..online() }, ActionId::ApplyProfile).unwrap().enabled);
+        assert!(!find(NodeKind::History(0x4c), &online(), ActionId::ExportHistory).unwrap().enabled);
+    }
+
+    #[test]
+    fn toggles_are_checked_from_the_state() {
+        let state = ActionState { writes_enabled: true, dry_run: true, ..online() };
+        assert_eq!(find(NodeKind::Root, &state, ActionId::AllowWrites).unwrap().checked, Some(true));
+        assert_eq!(find(NodeKind::Root, &state, ActionId::DryRun).unwrap().checked, Some(true));
+        assert_eq!(find(NodeKind::Root, &state, ActionId::PausePolling).unwrap().checked, Some(false));
+        assert_eq!(find(NodeKind::Registers(0x4c), &state, ActionId::WordMode).unwrap().checked, Some(false));
+        let words = ActionState { word_mode: true, ..state };
+        assert_eq!(find(NodeKind::Registers(0x4c), &words, ActionId::WordMode).unwrap().checked, Some(true));
+        assert_eq!(find(NodeKind::Root, &state, ActionId::RescanBus).unwrap().checked, None);
+    }
+
+    #[test]
+    fn find_only_sees_the_nodes_own_actions() {
+        assert!(find(NodeKind::Root, &online(), ActionId::EditRegister).is_none());
+        assert!(find(NodeKind::Device(0x48, DeviceKind::TemperatureSensor), &online(), ActionId::SetFanMode).is_none());
+        assert!(find(NodeKind::Folder, &online(), ActionId::ReadNow).is_none());
+        let found = find(NodeKind::Device(0x4c, DeviceKind::FanController), &online(), ActionId::SetFanMode).unwrap();
+        assert_eq!(found.label, "Set &fan mode...");
+        assert_eq!(found.group, MenuGroup::Top);
+    }
+
+    #[test]
+    fn devices_can_be_copied_and_stale_or_manual_ones_deleted() {
+        let sensor = NodeKind::Device(0x48, DeviceKind::TemperatureSensor);
+        let verbs = verbs(sensor, &online());
+        assert!(verbs.allows(Verb::Copy));
+        assert!(verbs.allows(Verb::Refresh));
+        assert!(!verbs.allows(Verb::Delete));
+        assert_eq!(verbs.default, Some(Verb::Properties));
+        assert!(super::verbs(sensor, &ActionState { stale: true, ..online() }).allows(Verb::Delete));
+        assert!(super::verbs(sensor, &ActionState { manual: true, ..online() }).allows(Verb::Delete));
+        assert!(!super::verbs(sensor, &ActionState::default()).allows(Verb::Refresh));
+    }
+
+    #[test]
+    fn other_nodes_enable_only_refresh() {
+        assert_eq!(verbs(NodeKind::Root, &online()).enabled, vec![Verb::Properties, Verb::Refresh]);
+        assert_eq!(verbs(NodeKind::Root, &ActionState::default()).enabled, vec![Verb::Properties]);
+        assert_eq!(verbs(NodeKind::Registers(0x4c), &online()).enabled, vec![Verb::Refresh]);
+        assert_eq!(verbs(NodeKind::History(0x4c), &ActionState::default()).enabled, vec![Verb::Refresh]);
+        assert_eq!(verbs(NodeKind::Folder, &online()), Verbs::default());
+    }
+}
@@ -0,0 +1,239 @@
+// The bus service. Run it with the privileges raw bus access needs; the
+// snap-in and other clients then reach the bus through it.
+//
+//     smbus-service [--endpoint NAME] [--simulate | --snapshot FILE]
+//                   [--read-only] [--profile FILE]... [--metrics ADDR]
+//                   [--openrgb ADDR [--openrgb-profiles FILE]]
+//                   [--listen ADDR --cert FILE --key FILE --access FILE
+//                    [--client-ca FILE]]
+//
+// Without --simulate or --snapshot it drives the computer's SMBus host:
+// the i2c-dev adapter named "SMBus ..." on Linux, or an Intel host through
+// inpoutx64.dll on Windows.
+//
+// With --listen it also takes clients from other computers over TLS; see
+// `smbus_snapin::service::remote` for the access file. With --metrics it
+// serves readings to Prometheus at http://ADDR/metrics, and with --openrgb
+// it lets OpenRGB's clients set the RGB lighting; ADDR without a port
+// gets OpenRGB's own, 6742.
+
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+
+use log::LevelFilter;
+
+use smbus_snapin::connect::DataSource;
+use smbus_snapin::profile::Profile;
+use smbus_snapin::service::ipc::{Listener, DEFAULT_ENDPOINT};
+use smbus_snapin::service::metrics;
+use smbus_snapin::service::openrgb::{self, OpenRgbServer};
+use smbus_snapin::service::remote::{AccessList, RemoteListener};
+use smbus_snapin::service::server::{self, BusService};
+use smbus_snapin::smbus::safety::WritePolicy;
+use smbus_snapin::smbus::scan::SCAN_RANGE;
+
+const USAGE: &str = "usage: smbus-service [--endpoint NAME] [--simulate | --snapshot FILE] [--read-only] [--profile FILE]...
+                     [--metrics ADDR] [--openrgb ADDR [--openrgb-profiles FILE]]
+                     [--listen ADDR --cert FILE --key FILE --access FILE [--client-ca FILE]]";
+
+struct Options {
+    endpoint: String,
+    source: DataSource,
+    read_only: bool,
+    profiles: Vec<PathBuf>,
+    metrics: Option<String>,
+    openrgb: Option<String>,
+    openrgb_profiles: Option<PathBuf>,
+    remote: Option<RemoteOptions>,
+}
+
+struct RemoteOptions {
+    listen: String,
+    certificate: PathBuf,
+    key: PathBuf,
+    access: PathBuf,
+    client_ca: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        endpoint: DEFAULT_ENDPOINT.to_owned(),
+        source: DataSource::Hardware,
+        read_only: false,
+        profiles: Vec::new(),
+        metrics: None,
+        openrgb: None,
+        openrgb_profiles: None,
+        remote: None,
+    };
+    let (mut listen, mut certificate, mut key, mut access, mut client_ca) = (None, None, None, None, None);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--endpoint" => options.endpoint = value("--endpoint")?,
+            "--simulate" => options.source = DataSource::Simulation,
+            "--snapshot" => options.source = DataSource::Snapshot(value("--snapshot")?.into()),
+            "--read-only" => options.read_only = true,
+            "--profile" => options.profiles.push(value("--profile")?.into()),
+            "--metrics" => options.metrics = Some(value("--metrics")?),
+            "--openrgb" => options.openrgb = Some(with_port(&value("--openrgb")?, openrgb::DEFAULT_PORT)),
+            "--openrgb-profiles" => options.openrgb_profiles = Some(value("--openrgb-profiles")?.into()),
+            "--listen" => listen = Some(value("--listen")?),
+            "--cert" => certificate = Some(value("--cert")?.into()),
+            "--key" => key = Some(value("--key")?.into()),
+            "--access" => access = Some(value("--access")?.into()),
+            "--client-ca" => client_ca = Some(value("--client-ca")?.into()),
+            "-h" | "--help" => return Err(USAGE.to_owned()),
+            arg => return Err(format!("unknown argument \"{}\"\n{}", arg, USAGE)),
+        }
+    }
+    options.remote = match (listen, certificate, key, access) {
+        (Some(listen), Some(certificate), Some(key), Some(access)) => {
+            Some(RemoteOptions { listen, certificate, key, access, client_ca })
+        }
+        (None, None, None, None) if client_ca.is_none() => None,
+        _ => return Err(format!("--listen needs --cert, --key and --access\n{}", USAGE)),
+    };
+    if options.openrgb_profiles.is_some() && options.openrgb.is_none() {
+        return Err(format!("--openrgb-profiles needs --openrgb\n{}", USAGE));
+    }
+    Ok(options)
+}
+
+// `address`, or `address:port` if it doesn't have a port of its own
+fn with_port(address: &str, port: u16) -> String {
+    match (address.parse::<SocketAddr>(), address.trim_matches(['[', ']']).parse::<IpAddr>()) {
+        (Ok(_), _) => address.to_owned(),
+        (_, Ok(ip)) => SocketAddr::new(ip, port).to_string(),
+        _ => match address.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+            true => address.to_owned(),
+            false => format!("{}:{}", address, port),
+        },
+    }
+}
+
+// The controller label on every metric
+fn controller_name(source: &DataSource) -> String {
+    match source {
+        DataSource::Hardware => "smbus".to_owned(),
+        DataSource::Simulation => "simulation".to_owned(),
+        DataSource::Snapshot(path) => path.file_stem().map_or("snapshot".into(), |stem| stem.to_string_lossy().into_owned()),
+    }
+}
+
+fn main() -> ExitCode {
+    simple_logging::log_to_stderr(LevelFilter::Info);
+
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let bus = match options.source.open() {
+        Ok(bus) => bus,
+        Err(e) => {
+            log::error!("Couldn't open the bus: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut profiles = Profile::builtin();
+    for path in &options.profiles {
+        match Profile::load(path) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => log::error!("{}: {}", path.display(), e),
+        }
+    }
+
+    let policy = WritePolicy { writes_enabled: !options.read_only, ..WritePolicy::default() };
+    let mut service = BusService::new(bus, policy, profiles);
+    service.scan(SCAN_RANGE);
+
+    let listener = match Listener::bind(&options.endpoint) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Couldn't listen on {}: {}", options.endpoint, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    log::info!("Listening on {}", options.endpoint);
+
+    let service = Arc::new(Mutex::new(service));
+    if let Some(remote) = &options.remote {
+        let access = match AccessList::load(&remote.access) {
+            Ok(access) => Arc::new(access),
+            Err(e) => {
+                log::error!("Couldn't load the access list: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        let listener = match RemoteListener::bind(&remote.listen, &remote.certificate, &remote.key, remote.client_ca.as_deref()) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Couldn't listen on {}: {}", remote.listen, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        log::info!("Listening for other computers on {}", remote.listen);
+        let service = service.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = server::serve_remote(listener, service, access) {
+                log::error!("Stopped accepting remote connections: {}", e);
+            }
+        });
+    }
+
+    if let Some(address) = &options.metrics {
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Couldn't serve metrics on {}: {}", address, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        log::info!("Serving metrics on http://{}/metrics", address);
+        let service = service.clone();
+        let controller = controller_name(&options.source);
+        std::thread::spawn(move || {
+            if let Err(e) = metrics::serve_metrics(listener, service, controller) {
+                log::error!("Stopped serving metrics: {}", e);
+            }
+        });
+    }
+
+    if let Some(address) = &options.openrgb {
+        let server = match OpenRgbServer::new(service.clone(), options.openrgb_profiles.clone()) {
+            Ok(server) => Arc::new(server),
+            Err(e) => {
+                log::error!("Couldn't load the OpenRGB profiles: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Couldn't serve OpenRGB clients on {}: {}", address, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        log::info!("Serving OpenRGB clients on {}", address);
+        std::thread::spawn(move || {
+            if let Err(e) = openrgb::serve_openrgb(listener, server) {
+                log::error!("Stopped serving OpenRGB clients: {}", e);
+            }
+        });
+    }
+
+    match server::serve(listener, service) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("Stopped accepting connections: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
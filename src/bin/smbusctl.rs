@@ -0,0 +1,684 @@
+// Command-line access to the bus, using the same drivers as the snap-in:
+// for scripts, and for trying drivers out without MMC.
+//
+//     smbusctl [BUS] [--json] [-v] [--dry-run] [--unlock ADDR]... COMMAND
+//
+// BUS is the local bus service unless one of --service ENDPOINT,
+// --computer NAME, --simulate, --snapshot FILE or (on Linux) --device
+// /dev/i2c-N picks another. Addresses and registers are decimal or 0x hex.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use log::LevelFilter;
+use serde::Serialize;
+use serde_json::json;
+
+use smbus_snapin::connect::{Computer, Connection, DataSource};
+use smbus_snapin::profile::Profile;
+use smbus_snapin::render;
+use smbus_snapin::service::client::ServiceBus;
+use smbus_snapin::service::ipc::DEFAULT_ENDPOINT;
+use smbus_snapin::service::protocol::DeviceInfo;
+use smbus_snapin::smbus::drivers::rgb::{self, Color, RgbDevice};
+use smbus_snapin::smbus::drivers::{self, eeprom, DeviceKind, Driver, SensorReading};
+use smbus_snapin::smbus::dump::{DumpMode, RegisterDump};
+use smbus_snapin::smbus::regmap::RegisterMap;
+use smbus_snapin::smbus::safety::{GuardedBus, WritePolicy};
+use smbus_snapin::smbus::scan::{scan, SCAN_RANGE};
+use smbus_snapin::smbus::{Property, SmbusError, SmbusTransport, Value};
+
+const USAGE: &str = "usage: smbusctl [BUS] [--json] [-v] [--dry-run] [--unlock ADDR]... COMMAND
+
+BUS: --service ENDPOINT | --computer NAME | --simulate | --snapshot FILE | --device /dev/i2c-N
+
+commands:
+  detect [FIRST LAST]                  find and identify devices
+  dump ADDR [--word]                   read registers 0x00-0xff
+  get ADDR REGISTER [--word]           read one register
+  set ADDR REGISTER VALUE [--word]     write one register
+  identify ADDR                        name a device and what identifies it
+  spd ADDR                             decode a memory module's SPD header
+  eeprom backup ADDR FILE              save an EEPROM's contents to an image file
+  eeprom restore ADDR FILE             write an image file back to an EEPROM; SPD
+                                       needs --unlock ADDR, which also unlocks
+                                       an EE1004's page selects
+  sensors [ADDR]                       read sensors with their limits
+  fan ADDR [SETTING VALUE]             read a fan controller, or change a fan setting
+  rgb ADDR                             show an RGB controller's mode and colours
+  rgb ADDR mode NAME [--speed N] [--reverse] [COLOUR]...
+  rgb ADDR leds COLOUR...              show fixed colours, the last repeated
+  profile apply NAME|FILE              apply a built-in or saved profile";
+
+enum BusChoice {
+    Service(String),
+    Connection(Connection),
+    #[cfg(target_os = "linux")]
+    Device(PathBuf),
+}
+
+struct Options {
+    bus: BusChoice,
+    json: bool,
+    verbose: bool,
+    policy: WritePolicy,
+    word: bool,
+    speed: Option<u8>,
+    reverse: bool,
+    command: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        bus: BusChoice::Service(DEFAULT_ENDPOINT.to_owned()),
+        json: false,
+        verbose: false,
+        policy: WritePolicy::default(),
+        word: false,
+        speed: None,
+        reverse: false,
+        command: Vec::new(),
+    };
+    let source = |source| BusChoice::Connection(Connection { computer: Computer::Local, source });
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--service" => options.bus = BusChoice::Service(value("--service")?),
+            "--computer" => options.bus = BusChoice::Connection(Connection {
+                computer: Computer::Remote(value("--computer")?),
+                source: DataSource::Hardware,
+            }),
+            "--simulate" => options.bus = source(DataSource::Simulation),
+            "--snapshot" => options.bus = source(DataSource::Snapshot(value("--snapshot")?.into())),
+            #[cfg(target_os = "linux")]
+            "--device" => options.bus = BusChoice::Device(value("--device")?.into()),
+            "--json" => options.json = true,
+            "-v" | "--verbose" => options.verbose = true,
+            "--dry-run" => options.policy.dry_run = true,
+            "--unlock" => options.policy.unlocked.push(parse_addr(&value("--unlock")?)?),
+            "--word" => options.word = true,
+            "--speed" => options.speed = Some(parse_number(&value("--speed")?, u8::MAX.into())? as u8),
+            "--reverse" => options.reverse = true,
+            "-h" | "--help" => return Err(USAGE.to_owned()),
+            arg if arg.starts_with("--") => return Err(format!("unknown option \"{}\"\n{}", arg, USAGE)),
+            _ => options.command.push(arg),
+        }
+    }
+    if options.command.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+    Ok(options)
+}
+
+// Decimal, or hex with 0x
+fn parse_number(text: &str, max: u32) -> Result<u32, String> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    value.ok()
+        .filter(|value| *value <= max)
+        .ok_or_else(|| format!("\"{}\" isn't a number from 0 to {:#x}", text, max))
+}
+
+fn parse_addr(text: &str) -> Result<u8, String> {
+    parse_number(text, 0x7f).map(|addr| addr as u8)
+}
+
+fn parse_register(text: &str) -> Result<u8, String> {
+    parse_number(text, 0xff).map(|register| register as u8)
+}
+
+/// What went wrong, and whether it was the user's fault.
+enum Failure {
+    Usage(String),
+    Bus(String),
+}
+
+impl From<SmbusError> for Failure {
+    fn from(e: SmbusError) -> Self {
+        Failure::Bus(e.to_string())
+    }
+}
+
+impl From<eeprom::EepromError> for Failure {
+    fn from(e: eeprom::EepromError) -> Self {
+        Failure::Bus(e.to_string())
+    }
+}
+
+type CommandResult = Result<(), Failure>;
+
+struct Ctl {
+    bus: Box<dyn SmbusTransport>,
+    options: Options,
+    maps: Vec<RegisterMap>,
+}
+
+impl Ctl {
+    fn run(&mut self) -> CommandResult {
+        let command = self.options.command.clone();
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        // The patterns below make sure the arguments are there
+        let addr = |i: usize| parse_addr(args[i]).map_err(Failure::Usage);
+        let register = |i: usize| parse_register(args[i]).map_err(Failure::Usage);
+
+        match args.as_slice() {
+            ["detect"] => self.detect(*SCAN_RANGE.start(), *SCAN_RANGE.end()),
+            ["detect", _, _] => self.detect(addr(1)?, addr(2)?),
+            ["dump", _] => self.dump(addr(1)?),
+            ["get", _, _] => self.get(addr(1)?, register(2)?),
+            ["set", _, _, value] => {
+                let max = match self.options.word {
+                    true => u16::MAX,
+                    false => u8::MAX.into(),
+                };
+                let value = parse_number(value, max.into()).map_err(Failure::Usage)? as u16;
+                self.set(addr(1)?, register(2)?, value)
+            }
+            ["identify", _] => self.identify(addr(1)?),
+            ["spd", _] => self.spd(addr(1)?),
+            ["eeprom", "backup", _, path] => self.eeprom_backup(addr(2)?, Path::new(path)),
+            ["eeprom", "restore", _, path] => self.eeprom_restore(addr(2)?, Path::new(path)),
+            ["sensors"] => self.sensors(None),
+            ["sensors", _] => self.sensors(Some(addr(1)?)),
+            ["fan", _] => self.fan(addr(1)?, None),
+            ["fan", _, key, value] => self.fan(addr(1)?, Some((key, value))),
+            ["rgb", _] => self.rgb_show(addr(1)?),
+            ["rgb", _, "mode", name, colors @ ..] => self.rgb_mode(addr(1)?, name, colors),
+            ["rgb", _, "leds", colors @ ..] if !colors.is_empty() => self.rgb_leds(addr(1)?, colors),
+            ["profile", "apply", name] => self.apply_profile(name),
+            _ => Err(Failure::Usage(format!("can't make sense of \"{}\"\n{}", args.join(" "), USAGE))),
+        }
+    }
+
+    fn print_json(&self, value: &impl Serialize) {
+        match serde_json::to_string_pretty(value) {
+            Ok(text) => println!("{}", text),
+            Err(e) => log::error!("Couldn't write JSON: {}", e),
+        }
+    }
+
+    fn identify_driver(&mut self, addr: u8) -> Result<Box<dyn Driver>, Failure> {
+        drivers::identify(self.bus.as_mut(), addr, &self.maps)
+            .ok_or_else(|| Failure::Bus(format!("no driver recognises the device at {:#04x}", addr)))
+    }
+
+    // Every device that answers in the scan range, with the drivers of the
+    // ones that were recognised
+    fn discover(&mut self) -> (Vec<u8>, HashMap<u8, Box<dyn Driver>>) {
+        let found = scan(self.bus.as_mut(), SCAN_RANGE);
+        let drivers = found.iter()
+            .filter_map(|addr| drivers::identify(self.bus.as_mut(), *addr, &self.maps).map(|driver| (*addr, driver)))
+            .collect();
+        (found, drivers)
+    }
+
+    fn detect(&mut self, first: u8, last: u8) -> CommandResult {
+        if first > last {
+            return Err(Failure::Usage(format!("{:#04x} comes after {:#04x}", first, last)));
+        }
+        let devices: Vec<DeviceInfo> = scan(self.bus.as_mut(), first..=last).into_iter()
+            .map(|addr| match drivers::identify(self.bus.as_mut(), addr, &self.maps) {
+                Some(driver) => DeviceInfo { addr, name: driver.name(), kind: driver.kind(), identified: true },
+                None => DeviceInfo { addr, name: "Unknown device".to_owned(), kind: DeviceKind::Unknown, identified: false },
+            })
+            .collect();
+
+        match self.options.json {
+            true => self.print_json(&devices),
+            false if devices.is_empty() => println!("No devices found between {:#04x} and {:#04x}", first, last),
+            false => {
+                for device in &devices {
+                    println!("{:#04x}  {:<20}  {}", device.addr, device.kind.to_string(), device.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> DumpMode {
+        match self.options.word {
+            true => DumpMode::Word,
+            false => DumpMode::Byte,
+        }
+    }
+
+    fn dump(&mut self, addr: u8) -> CommandResult {
+        let mode = self.mode();
+        let dump = RegisterDump::read(self.bus.as_mut(), addr, mode);
+        if dump.values.iter().all(Option::is_none) {
+            return Err(SmbusError::Nack { addr }.into());
+        }
+
+        match self.options.json {
+            true => self.print_json(&json!({
+                "addr": addr,
+                "mode": match dump.mode { DumpMode::Byte => "byte", DumpMode::Word => "word" },
+                "values": dump.values,
+            })),
+            false => {
+                let per_row = dump.mode.per_row();
+                let width = match dump.mode {
+                    DumpMode::Byte => 2,
+                    DumpMode::Word => 4,
+                };
+                let step = 16 / per_row;
+                let header: Vec<String> = (0..per_row).map(|i| format!("{:>width$x}", i * step, width = width)).collect();
+                println!("    {}", header.join(" "));
+                for row in dump.rows(&[]) {
+                    println!("{}", row);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, addr: u8, register: u8) -> CommandResult {
+        let value = match self.mode() {
+            DumpMode::Byte => self.bus.read_byte_data(addr, register)? as u16,
+            DumpMode::Word => self.bus.read_word_data(addr, register)?,
+        };
+        match (self.options.json, self.mode()) {
+            (true, _) => self.print_json(&json!({ "addr": addr, "register": register, "value": value })),
+            (false, DumpMode::Byte) => println!("{:#04x}", value),
+            (false, DumpMode::Word) => println!("{:#06x}", value),
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, addr: u8, register: u8, value: u16) -> CommandResult {
+        let mode = self.mode();
+        let policy = self.options.policy.clone();
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        match mode {
+            DumpMode::Byte => guarded.write_byte_data(addr, register, value as u8)?,
+            DumpMode::Word => guarded.write_word_data(addr, register, value)?,
+        }
+        self.report_write(!policy.dry_run);
+        Ok(())
+    }
+
+    fn report_write(&self, performed: bool) {
+        match (self.options.json, performed) {
+            (true, _) => self.print_json(&json!({ "performed": performed })),
+            (false, true) => println!("Written"),
+            (false, false) => println!("Dry run, nothing written"),
+        }
+    }
+
+    fn print_properties(&self, title: &str, properties: &[Property]) {
+        match self.options.json {
+            true => self.print_json(&properties),
+            false => println!("{}", render::text(title, properties)),
+        }
+    }
+
+    fn identify(&mut self, addr: u8) -> CommandResult {
+        let driver = self.identify_driver(addr)?;
+        let identification = driver.identification(self.bus.as_mut());
+        match self.options.json {
+            true => self.print_json(&json!({
+                "addr": addr,
+                "name": driver.name(),
+                "kind": driver.kind(),
+                "identification": identification,
+            })),
+            false => {
+                let mut properties = vec![Property::text("Kind", driver.kind().to_string())];
+                properties.extend(identification);
+                println!("{}", render::text(&format!("{:#04x}  {}", addr, driver.name()), &properties));
+            }
+        }
+        Ok(())
+    }
+
+    fn spd(&mut self, addr: u8) -> CommandResult {
+        let properties = eeprom::spd_summary(self.bus.as_mut(), addr)?;
+        self.print_properties(&format!("{:#04x}  SPD", addr), &properties);
+        Ok(())
+    }
+
+    fn eeprom(&mut self, addr: u8) -> Result<eeprom::Eeprom, Failure> {
+        eeprom::Eeprom::detect(self.bus.as_mut(), addr)
+            .ok_or_else(|| Failure::Bus(format!("there's no EEPROM at {:#04x}", addr)))
+    }
+
+    fn eeprom_backup(&mut self, addr: u8, path: &Path) -> CommandResult {
+        let part = self.eeprom(addr)?;
+        let image = part.backup(self.bus.as_mut(), path)?;
+        match self.options.json {
+            true => self.print_json(&json!({
+                "addr": addr,
+                "part": part.model.name(),
+                "bytes": image.data.len(),
+                "checksum": format!("{:08x}", image.checksum()),
+            })),
+            false => println!("Saved the {} bytes of the {} at {:#04x} to {} (CRC-32 {:08x})",
+                image.data.len(), part.model, addr, path.display(), image.checksum()),
+        }
+        Ok(())
+    }
+
+    fn eeprom_restore(&mut self, addr: u8, path: &Path) -> CommandResult {
+        let part = self.eeprom(addr)?;
+        // Unlocking the part unlocks the addresses it's written through
+        let mut policy = self.options.policy.clone();
+        if policy.unlocked.contains(&addr) {
+            policy.unlocked.extend(part.write_addresses());
+        }
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        part.restore(&mut guarded, path, !policy.dry_run)?;
+        self.report_write(!policy.dry_run);
+        Ok(())
+    }
+
+    // Drivers without sensors of their own still have readings with units,
+    // which are shown without limits
+    fn read_sensors(&mut self, driver: &dyn Driver) -> Result<Vec<SensorReading>, SmbusError> {
+        let sensors = driver.sensors(self.bus.as_mut())?;
+        if !sensors.is_empty() {
+            return Ok(sensors);
+        }
+        Ok(driver.read(self.bus.as_mut())?.into_iter()
+            .filter(|property| property.unit.is_some() && property.value.number().is_some())
+            .map(|property| SensorReading {
+                name: property.name,
+                value: property.value,
+                unit: property.unit,
+                min: None,
+                max: None,
+                critical: None,
+            })
+            .collect())
+    }
+
+    fn sensors(&mut self, addr: Option<u8>) -> CommandResult {
+        let drivers: Vec<Box<dyn Driver>> = match addr {
+            Some(addr) => vec![self.identify_driver(addr)?],
+            None => {
+                let mut drivers: Vec<Box<dyn Driver>> = self.discover().1.into_values().collect();
+                drivers.sort_by_key(|driver| driver.addr());
+                drivers
+            }
+        };
+
+        let mut devices = Vec::new();
+        for driver in &drivers {
+            match self.read_sensors(driver.as_ref()) {
+                Ok(sensors) if sensors.is_empty() => {}
+                Ok(sensors) => devices.push((driver, sensors)),
+                // One device failing doesn't stop the others being shown
+                Err(e) if addr.is_none() => log::error!("Reading {} at {:#04x} failed: {}", driver.name(), driver.addr(), e),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match self.options.json {
+            true => self.print_json(&devices.iter()
+                .map(|(driver, sensors)| json!({
+                    "addr": driver.addr(),
+                    "name": driver.name(),
+                    "sensors": sensors.iter()
+                        .map(|sensor| json!({
+                            "name": sensor.name,
+                            "value": sensor.value.number(),
+                            "unit": sensor.unit,
+                            "min": sensor.min,
+                            "max": sensor.max,
+                            "critical": sensor.critical,
+                            "status": sensor.status().to_string(),
+                        }))
+                        .collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>()),
+            false => {
+                for (driver, sensors) in &devices {
+                    println!("{:#04x}  {}", driver.addr(), driver.name());
+                    let width = sensors.iter().map(|sensor| sensor.name.chars().count()).max().unwrap_or(0);
+                    for sensor in sensors {
+                        let limits = [("min", sensor.min), ("max", sensor.max), ("crit", sensor.critical)]
+                            .iter()
+                            .filter_map(|(name, limit)| limit.map(|limit| format!("{} {}", name, Value::Scaled(limit))))
+                            .collect::<Vec<_>>();
+                        let limits = match limits.is_empty() {
+                            true => String::new(),
+                            false => format!("  ({})", limits.join(", ")),
+                        };
+                        println!("  {:<width$}  {:<12}  {}{}", sensor.name, sensor.value.with_unit(sensor.unit.as_deref()),
+                            sensor.status(), limits, width = width);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Fan readings are the ones in RPM or percent of full drive, and fan
+    // settings the ones named after fans or PWM
+    fn fan(&mut self, addr: u8, change: Option<(&str, &str)>) -> CommandResult {
+        let driver = self.identify_driver(addr)?;
+        if driver.kind() != DeviceKind::FanController {
+            return Err(Failure::Bus(format!("{} at {:#04x} isn't a fan controller", driver.name(), addr)));
+        }
+        let is_fan = |key: &str| {
+            let key = key.to_ascii_uppercase();
+            key.contains("FAN") || key.contains("PWM")
+        };
+        let settings: Vec<_> = driver.settings(self.bus.as_mut())?.into_iter()
+            .filter(|setting| is_fan(&setting.key))
+            .collect();
+
+        if let Some((key, text)) = change {
+            let setting = settings.iter()
+                .find(|setting| setting.key.eq_ignore_ascii_case(key))
+                .ok_or_else(|| {
+                    let keys: Vec<&str> = settings.iter().map(|setting| setting.key.as_str()).collect();
+                    Failure::Usage(format!("{} has no fan setting \"{}\", try one of {}", driver.name(), key, keys.join(", ")))
+                })?;
+            let value = setting.parse(text).map_err(|e| Failure::Usage(format!("{}: {}", setting.key, e)))?;
+            let policy = self.options.policy.clone();
+            let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+            driver.write_setting(&mut guarded, &setting.key, value)?;
+            self.report_write(!policy.dry_run);
+            return Ok(());
+        }
+
+        let readings: Vec<Property> = driver.read(self.bus.as_mut())?.into_iter()
+            .filter(|property| matches!(property.unit.as_deref(), Some("RPM" | "%")))
+            .collect();
+        match self.options.json {
+            true => self.print_json(&json!({ "addr": addr, "name": driver.name(), "readings": readings, "settings": settings })),
+            false => {
+                let mut properties = readings;
+                properties.extend(settings.iter().map(|setting| Property::new(
+                    &format!("{} (setting)", setting.key),
+                    Value::Text(setting.text()),
+                    setting.unit.as_deref(),
+                )));
+                println!("{}", render::text(&format!("{:#04x}  {}", addr, driver.name()), &properties));
+            }
+        }
+        Ok(())
+    }
+
+    fn rgb_driver(&mut self, addr: u8) -> Result<Box<dyn Driver>, Failure> {
+        let driver = self.identify_driver(addr)?;
+        match driver.rgb() {
+            Some(_) => Ok(driver),
+            None => Err(Failure::Bus(format!("{} at {:#04x} isn't an RGB controller", driver.name(), addr))),
+        }
+    }
+
+    fn rgb_show(&mut self, addr: u8) -> CommandResult {
+        let driver = self.rgb_driver(addr)?;
+        let rgb = driver.rgb().unwrap();
+        let state = rgb.state(self.bus.as_mut())?;
+        let mode = match state.direct {
+            true => "Direct",
+            false => rgb.modes().iter().find(|mode| mode.value == state.mode).map_or("Unknown", |mode| mode.name),
+        };
+
+        match self.options.json {
+            true => self.print_json(&json!({
+                "addr": addr,
+                "name": driver.name(),
+                "zones": rgb.zones(),
+                "modes": rgb.modes().iter().map(|mode| mode.name).collect::<Vec<_>>(),
+                "mode": mode,
+                "speed": state.speed,
+                "reverse": state.reverse,
+                "colors": state.colors.iter().map(Color::to_string).collect::<Vec<_>>(),
+            })),
+            false => {
+                let zones: Vec<String> = rgb.zones().iter().map(|zone| format!("{} ({})", zone.name, zone.leds)).collect();
+                let colors: Vec<String> = state.colors.iter().map(Color::to_string).collect();
+                let modes: Vec<&str> = rgb.modes().iter().map(|mode| mode.name).collect();
+                let properties = vec![
+                    Property::text("Mode", mode),
+                    Property::new("Speed", Value::Integer(state.speed.into()), None),
+                    Property::text("Direction", if state.reverse { "Reverse" } else { "Forward" }),
+                    Property::text("Zones", zones.join(", ")),
+                    Property::text("Colours", colors.join(" ")),
+                    Property::text("Modes", modes.join(", ")),
+                ];
+                println!("{}", render::text(&format!("{:#04x}  {}", addr, driver.name()), &properties));
+            }
+        }
+        Ok(())
+    }
+
+    // One colour per LED, the last one repeated for the rest
+    fn colors(rgb: &dyn RgbDevice, texts: &[&str]) -> Result<Vec<Color>, Failure> {
+        let colors = texts.iter().map(|text| text.parse::<Color>()).collect::<Result<Vec<_>, _>>().map_err(Failure::Usage)?;
+        let last = colors.last().copied().unwrap_or(Color::BLACK);
+        Ok((0..rgb.leds()).map(|led| colors.get(led).copied().unwrap_or(last)).collect())
+    }
+
+    fn rgb_mode(&mut self, addr: u8, name: &str, colors: &[&str]) -> CommandResult {
+        let driver = self.rgb_driver(addr)?;
+        let rgb = driver.rgb().unwrap();
+        let mode = rgb::find_mode(rgb.modes(), name).ok_or_else(|| {
+            let names: Vec<&str> = rgb.modes().iter().map(|mode| mode.name).collect();
+            Failure::Usage(format!("{} has no mode \"{}\", try one of {}", driver.name(), name, names.join(", ")))
+        })?;
+        let state = rgb.state(self.bus.as_mut())?;
+        let colors = match colors.is_empty() {
+            true => state.colors.clone(),
+            false => Self::colors(rgb, colors)?,
+        };
+
+        let policy = self.options.policy.clone();
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        let speed = self.options.speed.unwrap_or(state.speed);
+        rgb.set_mode(&mut guarded, mode.value, speed, self.options.reverse, &colors)?;
+        self.report_write(!policy.dry_run);
+        Ok(())
+    }
+
+    fn rgb_leds(&mut self, addr: u8, colors: &[&str]) -> CommandResult {
+        let driver = self.rgb_driver(addr)?;
+        let rgb = driver.rgb().unwrap();
+        let colors = Self::colors(rgb, colors)?;
+
+        let policy = self.options.policy.clone();
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        rgb.set_leds(&mut guarded, &colors)?;
+        self.report_write(!policy.dry_run);
+        Ok(())
+    }
+
+    // A file if there's one by that name, otherwise a built-in profile
+    fn apply_profile(&mut self, name: &str) -> CommandResult {
+        let path = PathBuf::from(name);
+        let profile = match path.is_file() {
+            true => Profile::load(&path).map_err(|e| Failure::Usage(format!("{}: {}", path.display(), e)))?,
+            false => {
+                let builtin = Profile::builtin();
+                let names: Vec<String> = builtin.iter().map(|profile| profile.name.clone()).collect();
+                builtin.into_iter()
+                    .find(|profile| profile.name.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| Failure::Usage(format!("no profile file or built-in profile \"{}\", try one of {}", name, names.join(", "))))?
+            }
+        };
+
+        let (_, drivers) = self.discover();
+        let policy = self.options.policy.clone();
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        let failures = match profile.apply(&drivers, &mut guarded) {
+            Ok(()) => Vec::new(),
+            Err(failures) => failures,
+        };
+
+        match self.options.json {
+            true => self.print_json(&json!({
+                "profile": profile.name,
+                "performed": !policy.dry_run,
+                "failures": failures.iter()
+                    .map(|(setting, e)| json!({ "setting": setting, "error": e.to_string() }))
+                    .collect::<Vec<_>>(),
+            })),
+            false => {
+                for (setting, e) in &failures {
+                    println!("{} at {:#04x}: {}", setting.key, setting.addr, e);
+                }
+                match (failures.len(), policy.dry_run) {
+                    (0, false) => println!("Applied \"{}\"", profile.name),
+                    (0, true) => println!("Dry run of \"{}\", nothing written", profile.name),
+                    (count, _) => println!("{} of {} settings failed", count, profile.settings.len()),
+                }
+            }
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(Failure::Bus(format!("\"{}\" wasn't applied completely", profile.name))),
+        }
+    }
+}
+
+fn open_bus(choice: &BusChoice) -> Result<Box<dyn SmbusTransport>, SmbusError> {
+    match choice {
+        BusChoice::Service(endpoint) => Ok(Box::new(ServiceBus::connect(endpoint, "smbusctl")?)),
+        BusChoice::Connection(connection) => connection.open(),
+        #[cfg(target_os = "linux")]
+        BusChoice::Device(path) => Ok(Box::new(smbus_snapin::smbus::i2cdev::I2cDev::open(path)?)),
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    // Failures are reported once on their own; the log is for -v
+    simple_logging::log_to_stderr(match options.verbose {
+        true => LevelFilter::Info,
+        false => LevelFilter::Error,
+    });
+
+    let bus = match open_bus(&options.bus) {
+        Ok(bus) => bus,
+        Err(e) => {
+            eprintln!("Couldn't open the bus: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut ctl = Ctl { bus, options, maps: RegisterMap::builtin() };
+    match ctl.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(e)) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+        Err(Failure::Bus(e)) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
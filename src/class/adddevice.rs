@@ -0,0 +1,112 @@
+// The Add Device dialog: the address of a device the scan doesn't find,
+// such as one that ignores the scan's probe. The snap-in adds it and
+// remembers it in the console file.
+
+use windows::Win32::Foundation::{HMODULE, HWND, LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DialogBoxIndirectParamW, EndDialog, GetDlgItem, GetDlgItemTextW, GetWindowLongPtrW, GetWindowTextLengthW,
+    SetWindowLongPtrW, BS_DEFPUSHBUTTON, BS_PUSHBUTTON, DLGTEMPLATE, ES_AUTOHSCROLL, WM_COMMAND, WM_INITDIALOG,
+    WS_BORDER, WS_CHILD, WS_TABSTOP, WS_VISIBLE,
+};
+
+use crate::__INTERCOM_DLL_INSTANCE;
+use crate::smbus::scan;
+
+use super::poll;
+use super::propertysheet::{
+    message_box, push_dialog_header, push_item, to_template, CLASS_BUTTON, CLASS_EDIT, CLASS_STATIC, DWLP_USER, MARGIN,
+};
+
+// In dialog units
+const WIDTH: i16 = 180;
+const HEIGHT: i16 = 54;
+const LABEL_WIDTH: i16 = 60;
+const BUTTON_WIDTH: i16 = 50;
+
+// IDOK and IDCANCEL, which Enter and Esc send
+const ID_OK: i32 = 1;
+const ID_CANCEL: i32 = 2;
+const ID_ADDRESS: i32 = 100;
+
+/// Asks for the address of a device to add. Returns `None` if the user
+/// cancels.
+pub fn run(owner: HWND) -> Option<u8> {
+    let _suspend = poll::suspend();
+
+    // What the dialog proc finds at DWLP_USER: the address, once the user
+    // clicks OK
+    let mut addr: Option<u8> = None;
+    let template = dialog_template();
+    let dll = unsafe { HMODULE(__INTERCOM_DLL_INSTANCE as isize) };
+    let param = LPARAM(&mut addr as *mut Option<u8> as isize);
+    let result = unsafe {
+        DialogBoxIndirectParamW(dll, template.as_ptr() as *const DLGTEMPLATE, owner, Some(dialog_proc), param)
+    };
+    if result == -1 {
+        log::error!("DialogBoxIndirectParamW() error: {}", windows::core::Error::from_win32());
+    }
+    addr
+}
+
+unsafe extern "system" fn dialog_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, DWLP_USER, lparam.0);
+            1
+        }
+        WM_COMMAND => {
+            let addr = match (GetWindowLongPtrW(hwnd, DWLP_USER) as *mut Option<u8>).as_mut() {
+                Some(addr) => addr,
+                None => return 0,
+            };
+            match (wparam.0 & 0xffff) as i32 {
+                ID_OK => {
+                    match scan::parse_address(&text(hwnd, ID_ADDRESS)) {
+                        Ok(parsed) => {
+                            *addr = Some(parsed);
+                            EndDialog(hwnd, ID_OK as isize);
+                        }
+                        Err(e) => message_box(hwnd, &e),
+                    }
+                    1
+                }
+                ID_CANCEL => {
+                    EndDialog(hwnd, ID_CANCEL as isize);
+                    1
+                }
+                _ => 0,
+            }
+        }
+        _ => 0,
+    }
+}
+
+unsafe fn text(hwnd: HWND, id: i32) -> String {
+    let len = GetWindowTextLengthW(GetDlgItem(hwnd, id)).max(0) as usize;
+    let mut buffer = vec![0u16; len + 1];
+    let copied = GetDlgItemTextW(hwnd, id, &mut buffer) as usize;
+    String::from_utf16_lossy(&buffer[..copied])
+}
+
+// The address box, then OK and Cancel at the bottom right
+fn dialog_template() -> Vec<u32> {
+    let mut words: Vec<u16> = Vec::new();
+    push_dialog_header(&mut words, 4, WIDTH, HEIGHT, "Add Device");
+
+    let visible = WS_CHILD.0 | WS_VISIBLE.0;
+    let edit = visible | WS_TABSTOP.0 | WS_BORDER.0 | ES_AUTOHSCROLL as u32;
+    let control_x = MARGIN + LABEL_WIDTH;
+    let control_width = WIDTH - control_x - MARGIN;
+
+    push_item(&mut words, visible, (MARGIN, MARGIN + 2, LABEL_WIDTH, 8), u16::MAX, CLASS_STATIC, "&Address (hex):");
+    push_item(&mut words, edit, (control_x, MARGIN, control_width, 12), ID_ADDRESS as u16, CLASS_EDIT, "");
+
+    let y = HEIGHT - MARGIN - 14;
+    let button = visible | WS_TABSTOP.0;
+    let cancel_x = WIDTH - MARGIN - BUTTON_WIDTH;
+    let ok_x = cancel_x - 4 - BUTTON_WIDTH;
+    push_item(&mut words, button | BS_DEFPUSHBUTTON as u32, (ok_x, y, BUTTON_WIDTH, 14), ID_OK as u16, CLASS_BUTTON, "OK");
+    push_item(&mut words, button | BS_PUSHBUTTON as u32, (cancel_x, y, BUTTON_WIDTH, 14), ID_CANCEL as u16, CLASS_BUTTON, "Cancel");
+
+    to_template(words)
+}
@@ -0,0 +1,52 @@
+use intercom::prelude::*;
+use windows::core::PCWSTR;
+
+use crate::actions::{Action, MenuGroup};
+use crate::interfaces::*;
+
+// MF_* flags MMC understands in CONTEXTMENUITEM::fFlags
+const MF_GRAYED: i32 = 0x0001;
+const MF_CHECKED: i32 = 0x0008;
+
+// Adds every action whose group MMC allows right now. MMC copies the
+// strings, so they only need to live for the add_item call.
+pub fn add_actions(actions: &[Action], callback: &ComItf<dyn IContextMenuCallback>, insertion_allowed: i32) -> ComResult<()> {
+    for action in actions {
+        let (allowed, insertion_point) = match action.group {
+            MenuGroup::Top => (CCM_INSERTIONALLOWED_TOP, CCM_INSERTIONPOINTID_PRIMARY_TOP),
+            MenuGroup::New => (CCM_INSERTIONALLOWED_NEW, CCM_INSERTIONPOINTID_PRIMARY_NEW),
+            MenuGroup::Task => (CCM_INSERTIONALLOWED_TASK, CCM_INSERTIONPOINTID_PRIMARY_TASK),
+            MenuGroup::View => (CCM_INSERTIONALLOWED_VIEW, CCM_INSERTIONPOINTID_PRIMARY_VIEW),
+        };
+        if insertion_allowed & allowed == 0 {
+            continue;
+        }
+
+        let name: Vec<u16> = action.label.encode_utf16().chain(std::iter::once(0)).collect();
+        let status: Vec<u16> = action.description.encode_utf16().chain(std::iter::once(0)).collect();
+
+        let mut flags = 0;
+        if !action.enabled {
+            flags |= MF_GRAYED;
+        }
+        if action.checked == Some(true) {
+            flags |= MF_CHECKED;
+        }
+
+        let mut item = CONTEXTMENUITEM {
+            name: PCWSTR::from_raw(name.as_ptr()),
+            status_bar_text: PCWSTR::from_raw(status.as_ptr()),
+            command_id: action.id.command_id(),
+            insertion_point_id: insertion_point,
+            flags,
+            special_flags: 0,
+        };
+
+        if let Err(e) = callback.add_item(&mut item as *mut _) {
+            log::error!("IContextMenuCallback::AddItem() error: {}", e);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
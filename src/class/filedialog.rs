@@ -0,0 +1,82 @@
+// The common Save As and Open dialogs, for writing something out to a file
+// the user picks and reading it back in.
+
+use std::path::PathBuf;
+
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Controls::Dialogs::{
+    GetOpenFileNameW, GetSaveFileNameW, OFN_FILEMUSTEXIST, OFN_NOCHANGEDIR, OFN_OVERWRITEPROMPT, OFN_PATHMUSTEXIST,
+    OPEN_FILENAME_FLAGS, OPENFILENAMEW,
+};
+use windows::core::{PCWSTR, PWSTR};
+
+use super::poll;
+
+// Longest path the dialog can hand back, in UTF-16 units
+const MAX_PATH_LEN: usize = 1024;
+
+/// The files a dialog lists, and the extension it adds to names typed
+/// without one.
+pub struct FileType {
+    // Pairs of description and pattern, ending with an empty one
+    filter: &'static str,
+    extension: &'static str,
+}
+
+pub const CSV: FileType = FileType {
+    filter: "CSV files (*.csv)\0*.csv\0All files (*.*)\0*.*\0\0",
+    extension: "csv\0",
+};
+
+/// What `Eeprom::backup` writes.
+pub const EEPROM_IMAGE: FileType = FileType {
+    filter: "EEPROM images (*.eep)\0*.eep\0All files (*.*)\0*.*\0\0",
+    extension: "eep\0",
+};
+
+/// Asks where to save a file, suggesting `name`. Returns `None` if the user
+/// cancels.
+pub fn choose_save(owner: HWND, title: &str, name: &str, file_type: &FileType) -> Option<PathBuf> {
+    choose(owner, title, name, file_type, OFN_OVERWRITEPROMPT | OFN_PATHMUSTEXIST | OFN_NOCHANGEDIR, false)
+}
+
+/// Asks which existing file to open. Returns `None` if the user cancels.
+pub fn choose_open(owner: HWND, title: &str, file_type: &FileType) -> Option<PathBuf> {
+    choose(owner, title, "", file_type, OFN_FILEMUSTEXIST | OFN_PATHMUSTEXIST | OFN_NOCHANGEDIR, true)
+}
+
+fn choose(owner: HWND, title: &str, name: &str, file_type: &FileType, flags: OPEN_FILENAME_FLAGS, open: bool) -> Option<PathBuf> {
+    let _suspend = poll::suspend();
+
+    let mut file = vec![0u16; MAX_PATH_LEN];
+    for (slot, unit) in file.iter_mut().zip(name.encode_utf16().take(MAX_PATH_LEN - 1)) {
+        *slot = unit;
+    }
+    let filter: Vec<u16> = file_type.filter.encode_utf16().collect();
+    let title: Vec<u16> = title.encode_utf16().chain(std::iter::once(0)).collect();
+    let extension: Vec<u16> = file_type.extension.encode_utf16().collect();
+
+    let mut dialog = OPENFILENAMEW {
+        lStructSize: std::mem::size_of::<OPENFILENAMEW>() as u32,
+        hwndOwner: owner,
+        lpstrFilter: PCWSTR(filter.as_ptr()),
+        nFilterIndex: 1,
+        lpstrFile: PWSTR(file.as_mut_ptr()),
+        nMaxFile: file.len() as u32,
+        lpstrTitle: PCWSTR(title.as_ptr()),
+        lpstrDefExt: PCWSTR(extension.as_ptr()),
+        Flags: flags,
+        ..Default::default()
+    };
+    let chosen = match open {
+        true => unsafe { GetOpenFileNameW(&mut dialog) },
+        false => unsafe { GetSaveFileNameW(&mut dialog) },
+    };
+    match chosen.as_bool() {
+        true => {
+            let len = file.iter().position(|unit| *unit == 0).unwrap_or(file.len());
+            Some(PathBuf::from(String::from_utf16_lossy(&file[..len])))
+        }
+        false => None,
+    }
+}
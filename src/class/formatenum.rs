@@ -0,0 +1,72 @@
+use intercom::prelude::*;
+use windows::Win32::System::Com::FORMATETC;
+
+use crate::interfaces::{ComFORMATETC, IEnumFORMATETC};
+
+// Hands out a fixed list of HGLOBAL formats, for IDataObject::EnumFormatEtc
+#[com_class(IEnumFORMATETC)]
+#[derive(Debug, Default, Clone)]
+pub struct FormatEnumerator {
+    formats: Vec<u16>,
+    position: usize,
+}
+
+impl FormatEnumerator {
+    pub fn new(formats: Vec<u16>) -> Self {
+        Self {
+            formats,
+            position: 0,
+        }
+    }
+}
+
+impl IEnumFORMATETC for FormatEnumerator {
+    fn next(&mut self, celt: u32, rgelt: *mut ComFORMATETC, pcelt_fetched: *mut u32) -> ComResult<()> {
+        if rgelt.is_null() {
+            return Err(ComError::E_POINTER);
+        }
+
+        let remaining = &self.formats[self.position.min(self.formats.len())..];
+        let count = remaining.len().min(celt as usize);
+
+        for (index, format) in remaining[..count].iter().enumerate() {
+            let formatetc = FORMATETC {
+                cfFormat: *format,
+                ptd: std::ptr::null_mut(),
+                dwAspect: 1, // DVASPECT_CONTENT
+                lindex: -1,
+                tymed: 1, // TYMED_HGLOBAL
+            };
+            unsafe { *rgelt.add(index) = ComFORMATETC(formatetc); }
+        }
+        self.position += count;
+
+        if !pcelt_fetched.is_null() {
+            unsafe { *pcelt_fetched = count as u32; }
+        }
+
+        if count < celt as usize {
+            // S_FALSE
+            return Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }));
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, celt: u32) -> ComResult<()> {
+        self.position += celt as usize;
+        if self.position > self.formats.len() {
+            self.position = self.formats.len();
+            return Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> ComResult<()> {
+        self.position = 0;
+        Ok(())
+    }
+
+    fn clone_enum(&self) -> ComResult<ComRc<dyn IEnumFORMATETC>> {
+        Ok(ComRc::from(ComBox::new(self.clone())))
+    }
+}
@@ -0,0 +1,51 @@
+use intercom::prelude::*;
+use windows::Win32::Foundation::{COLORREF, HMODULE};
+use windows::Win32::Graphics::Gdi::{DeleteObject, HBITMAP};
+use windows::Win32::UI::WindowsAndMessaging::{LoadImageW, IMAGE_BITMAP, IMAGE_FLAGS};
+use windows::core::PCWSTR;
+
+use crate::__INTERCOM_DLL_INSTANCE;
+use crate::images::{self, IDB_STRIP_16, IDB_STRIP_32};
+use crate::interfaces::{ComCOLORREF, ComHBITMAP, IImageList};
+
+// Loads one of our bitmap resources. The caller owns it and has to
+// DeleteObject it.
+pub fn load_bitmap(id: u16) -> ComResult<HBITMAP> {
+    let dll = unsafe { HMODULE(__INTERCOM_DLL_INSTANCE as isize) };
+
+    // MAKEINTRESOURCE
+    let bitmap = unsafe { LoadImageW(dll, PCWSTR(id as usize as *const u16), IMAGE_BITMAP, 0, 0, IMAGE_FLAGS(0)) };
+    match bitmap {
+        Ok(bitmap) => Ok(HBITMAP(bitmap.0)),
+        Err(e) => {
+            log::error!("Couldn't load bitmap {}: {}", id, e);
+            Err(ComError::new_hr(intercom::raw::HRESULT { hr: e.code().0 }))
+        }
+    }
+}
+
+pub fn delete_bitmap(bitmap: HBITMAP) {
+    unsafe { DeleteObject(bitmap) };
+}
+
+// Hands MMC the node icon strips. MMC keeps copies, so ours are deleted
+// again straight away.
+pub fn set_strip(list: &ComRc<dyn IImageList>) -> ComResult<()> {
+    let small = load_bitmap(IDB_STRIP_16)?;
+    let large = match load_bitmap(IDB_STRIP_32) {
+        Ok(large) => large,
+        Err(e) => {
+            delete_bitmap(small);
+            return Err(e);
+        }
+    };
+
+    let result = list.image_list_set_strip(ComHBITMAP(small), ComHBITMAP(large), 0, ComCOLORREF(COLORREF(images::MASK_COLOR)));
+    if let Err(e) = &result {
+        log::error!("IImageList::ImageListSetStrip() error: {}", e);
+    }
+
+    delete_bitmap(small);
+    delete_bitmap(large);
+    result
+}
@@ -8,4 +8,24 @@ mod snapinabout;
 pub use snapinabout::*;
 
 mod node;
-pub use node::*;
\ No newline at end of file
+pub use node::*;
+
+mod formatenum;
+pub use formatenum::*;
+
+mod contextmenu;
+
+mod propertysheet;
+pub use propertysheet::PropertyChange;
+
+mod imagelist;
+
+mod filedialog;
+
+mod poll;
+
+mod registeredit;
+
+mod wizard;
+
+mod adddevice;
@@ -1,10 +1,14 @@
-use guid::GUID;
+use std::sync::OnceLock;
+
 use intercom::{prelude::*, raw::HRESULT};
-use windows::{Win32::{System::{Memory::{ GlobalUnlock, GlobalLock, GlobalSize }, DataExchange::GetClipboardFormatNameW, Com::{ CoTaskMemFree, CoTaskMemAlloc }}, Foundation::{MAX_PATH, GetLastError, NO_ERROR}}, core::PCWSTR};
+use windows::{Win32::{System::{Memory::{ GlobalAlloc, GlobalFree, GlobalUnlock, GlobalLock, GlobalSize, GMEM_MOVEABLE }, DataExchange::RegisterClipboardFormatW, Com::{ CoTaskMemFree, CoTaskMemAlloc, FORMATETC, STGMEDIUM, STGMEDIUM_0, TYMED_HGLOBAL }}, Foundation::{GetLastError, HGLOBAL, NO_ERROR}}, core::PCWSTR};
 
-use crate::{interfaces::{IDataObject, ComFORMATETC, ComSTGMEDIUM, HSCOPEITEM, ComPCWSTR}, class::snapin::CLSID_MMCSnapIn};
+use crate::interfaces::{IDataObject, IEnumFORMATETC, ComFORMATETC, ComSTGMEDIUM, HSCOPEITEM, ComPCWSTR};
+use crate::clipformat::{self, NodeData};
+use crate::id;
+use crate::registry::{NodeId, NodeModel};
 
-use super::MMCSnapIn;
+use super::{FormatEnumerator, MMCSnapIn};
 
 pub const DV_E_FORMATETC: ComError = ComError{
     hresult: HRESULT { hr: windows::Win32::Foundation::DV_E_FORMATETC.0 },
@@ -16,29 +20,27 @@ pub const DV_E_TYMED: ComError = ComError{
     error_info: None,
 };
 
-#[derive(Debug, Default, PartialEq)]
-pub enum NodeType {
-    #[default]
-    Folder,
-    Root,
-}
+pub const STG_E_MEDIUMFULL: ComError = ComError{
+    hresult: HRESULT { hr: windows::Win32::Foundation::STG_E_MEDIUMFULL.0 },
+    error_info: None,
+};
 
+// The IDataObject MMC holds for a node. What the node is lives in the
+// snap-in's NodeRegistry; this only keeps the id and the MMC-side state.
 #[com_class(IDataObject)]
 #[derive(Debug)]
 pub struct Node {
-    _owner: *const MMCSnapIn,
-    pub node_type: NodeType,
+    owner: *const MMCSnapIn,
+    pub id: NodeId,
     pcwstr_name: Option<PCWSTR>,
-    pub display_name: String,
     pub hscopeitem: HSCOPEITEM,
 }
 
 impl Default for Node {
     fn default() -> Self {
         Self {
-            _owner: std::ptr::null(),
-            node_type: NodeType::Folder,
-            display_name: String::new(),
+            owner: std::ptr::null(),
+            id: NodeId::ROOT,
             pcwstr_name: None,
             hscopeitem: HSCOPEITEM(0),
         }
@@ -46,16 +48,63 @@ impl Default for Node {
 }
 
 impl Node {
-    pub fn new(owner: *const MMCSnapIn, name: String, ntype: NodeType) -> Self {
+    pub fn new(owner: *const MMCSnapIn, id: NodeId) -> Self {
         Node {
-            _owner: owner,
-            display_name: name,
-            node_type: ntype,
+            owner,
+            id,
             pcwstr_name: None,
             hscopeitem: HSCOPEITEM(0),
         }
     }
     
+    pub fn model(&self) -> Option<&NodeModel> {
+        let owner = unsafe { self.owner.as_ref() }?;
+        owner.registry.get(self.id)
+    }
+    
+    // Folder if the node has gone from the registry, which shouldn't happen
+    pub fn node_type(&self) -> windows::core::GUID {
+        self.model()
+            .map(|model| id::node_type(model.kind))
+            .unwrap_or(id::NODETYPE_FOLDER)
+    }
+    
+    // Everything the clipboard formats can ask about this node
+    pub fn clip_data(&self) -> NodeData {
+        let owner = unsafe { self.owner.as_ref() };
+        let model = self.model();
+        let node_type = self.node_type();
+        let label = self.label();
+        
+        let path = owner.map(|owner| owner.registry.path(self.id)).unwrap_or_default();
+        let description = model.and_then(|model| model.description());
+        
+        NodeData {
+            cookie: self.id.cookie(),
+            node_type: id::guid_bytes(&node_type),
+            node_type_string: id::guid_string(&node_type),
+            snapin_clsid: id::guid_bytes(&id::SNAPIN_CLSID),
+            window_title: path.join(" - "),
+            persistent_id: owner.map(|owner| owner.registry.persistent_id(self.id)).unwrap_or_default(),
+            column_set_id: model.map(|model| model.kind.column_set()).unwrap_or_default().to_owned(),
+            html_details: description.as_deref().map(|description| clipformat::html_details(&label, description)),
+            description,
+            preload: if self.id == NodeId::ROOT { Some(false) } else { None },
+            properties: owner.map(|owner| owner.node_properties(self.id)).unwrap_or_default(),
+            display_name: label,
+        }
+    }
+    
+    pub fn label(&self) -> String {
+        match self.model() {
+            Some(model) => model.label.clone(),
+            None => {
+                log::error!("Node {:?} isn't in the registry", self.id);
+                String::new()
+            }
+        }
+    }
+    
     // Calls to this function release the pointer to the PCWSTR and allocate a new one.
     pub fn pcwstr(&mut self) -> ComResult<ComPCWSTR> {
         if let Some(old) = self.pcwstr_name {
@@ -63,10 +112,10 @@ impl Node {
             self.pcwstr_name = None;
         }
         
-        log::debug!("Converting string \"{}\" to PCWSTR", self.display_name);
+        let label = self.label();
+        log::debug!("Converting string \"{}\" to PCWSTR", label);
         
-        let wide: Vec<u16> = self.display_name
-            .to_owned()
+        let wide: Vec<u16> = label
             .encode_utf16()
             .chain(std::iter::once(0))
             .collect();
@@ -104,145 +153,103 @@ impl Drop for Node {
 }
 
 impl IDataObject for Node {
-    fn get_data(&self, _pformatetc: *const ComFORMATETC) -> ComResult<*mut ComSTGMEDIUM> {
-        Err(ComError::E_NOTIMPL)
+    // Like get_data_here, but we allocate the HGLOBAL and the caller frees it
+    fn get_data(&self, pformatetc: *const ComFORMATETC, pmedium: *mut ComSTGMEDIUM) -> ComResult<()> {
+        if pformatetc.is_null() || pmedium.is_null() {
+            return Err(ComError::E_POINTER);
+        }
+        
+        let clipformat: u32 = unsafe { (*pformatetc).0.cfFormat.into() };
+        if unsafe { (*pformatetc).0.tymed } & TagTYMED::HGlobal as u32 == 0 {
+            log::debug!("Caller doesn't accept HGLOBAL for {}", clipformat);
+            return Err(DV_E_TYMED);
+        }
+        
+        let name = match clipformat_name(clipformat) {
+            Some(name) => name,
+            None => {
+                log::debug!("Not one of our clipboard formats: {}", clipformat);
+                return Err(DV_E_FORMATETC);
+            }
+        };
+        log::debug!("GetData for clipformat: {} ({})", name, clipformat);
+        
+        let payload = match clipformat::serialize(name, &self.clip_data()) {
+            Some(payload) => payload,
+            None => {
+                log::debug!("{} has nothing for {}", self.label(), name);
+                return Err(DV_E_FORMATETC);
+            }
+        };
+        
+        let hglobal = match unsafe { GlobalAlloc(GMEM_MOVEABLE, payload.len()) } {
+            Ok(hglobal) => hglobal,
+            Err(e) => {
+                log::error!("GlobalAlloc failed: {}", e);
+                return Err(ComError::E_FAIL);
+            }
+        };
+        
+        if let Err(e) = write_hglobal(hglobal, &payload) {
+            unsafe { let _ = GlobalFree(hglobal); }
+            return Err(e);
+        }
+        
+        unsafe {
+            (*pmedium).0.tymed = TYMED_HGLOBAL;
+            (*pmedium).0.Anonymous.hGlobal = hglobal;
+            // Null pUnkForRelease: the caller releases the HGLOBAL itself
+            std::ptr::write(&mut (*pmedium).0.pUnkForRelease, std::mem::ManuallyDrop::new(None));
+        }
+        Ok(())
     }
     
     fn get_data_here(&self, pformatetc: *const ComFORMATETC, pmedium: *mut ComSTGMEDIUM) -> ComResult<()> {
         let clipformat: u32 = unsafe { (*pformatetc).0.cfFormat.into() };
-        log::debug!("Got Clipformat: {}", clipformat);
-
         let tymed: TagTYMED = unsafe { std::mem::transmute((*pmedium).0.tymed.0) };
         
-        let mut clipformat_name: [u16; MAX_PATH as usize] = [0; MAX_PATH as usize];
+        let name = match clipformat_name(clipformat) {
+            Some(name) => name,
+            None => {
+                log::debug!("Not one of our clipboard formats: {}", clipformat);
+                return Err(DV_E_FORMATETC);
+            }
+        };
+        log::debug!("Got clipformat: {} ({})", name, clipformat);
         
-        let clipformat_name_len: i32 = unsafe { GetClipboardFormatNameW(clipformat, &mut clipformat_name) };
+        let payload = match clipformat::serialize(name, &self.clip_data()) {
+            Some(payload) => payload,
+            None => {
+                log::debug!("{} has nothing for {}", self.label(), name);
+                return Err(DV_E_FORMATETC);
+            }
+        };
         
-        if clipformat_name_len > 0 {
-            let name = String::from_utf16_lossy(&clipformat_name[0..clipformat_name_len as usize]);
-            log::debug!("Got clipformat name: {}", name);
-            
-            match name.as_str() {
-                "CCF_DISPLAY_NAME" => {
-                    // Return node display name as WSTR
-                    unsafe {
-                        match tymed {
-                            TagTYMED::HGlobal => {
-                                let ptr = GlobalLock((*pmedium).0.Anonymous.hGlobal);
-                                
-                                if ptr.is_null() {
-                                    log::error!("HGLOBAL returns null pointer");
-                                    return Err(ComError::E_FAIL);
-                                }
-                                
-                                let node_name_utf16: Vec<u16> = self.display_name.encode_utf16().chain(std::iter::once::<u16>(0)).collect();
-                                
-                                std::ptr::copy_nonoverlapping(
-                                    node_name_utf16.as_ptr(),
-                                    ptr as *mut u16,
-                                    node_name_utf16.len(),
-                                );
-                                
-                                global_unlock_checked((*pmedium).0.Anonymous.hGlobal)?;
-                                
-                            }
-                            _ => {
-                                log::error!("Unsupported TYMED: {:?}", tymed);
-                                return Err(DV_E_TYMED);
-                            }
-                        }
-                    }
-                }
-                "CCF_NODETYPE" => {
-                    // Return the GUID of the node type
-                    unsafe {
-                        match tymed {
-                            TagTYMED::HGlobal => {
-                                let ptr = GlobalLock((*pmedium).0.Anonymous.hGlobal);
-                                
-                                if ptr.is_null() {
-                                    log::error!("HGLOBAL returns null pointer");
-                                    return Err(ComError::E_FAIL);
-                                }
-                                
-                                let hglobal_size = GlobalSize((*pmedium).0.Anonymous.hGlobal);
-                                log::debug!("HGLOBAL is {} bytes", hglobal_size);
-                                log::debug!("Writing {:?} to HGLOBAL", &CLSID_MMCSnapIn);
-                                
-                                let guid_size_in_bytes = std::mem::size_of::<GUID>();
-                                
-                                std::ptr::copy_nonoverlapping(&CLSID_MMCSnapIn as *const _ as *const u8, ptr as *mut u8, guid_size_in_bytes);
-
-                                global_unlock_checked((*pmedium).0.Anonymous.hGlobal)?;
-                            }
-                            _ => {
-                                log::error!("Unsupported TYMED: {:?}", tymed);
-                                return Err(DV_E_TYMED);
-                                
-                            }
-                        }
-                    }
-                }
-                "CCF_SNAPIN_CLSID" => {
-                    // Return the CLSID of the snap in
-                    unsafe {
-                        match tymed {
-                            TagTYMED::HGlobal => {
-                                let ptr = GlobalLock((*pmedium).0.Anonymous.hGlobal);
-                                
-                                if ptr.is_null() {
-                                    log::error!("HGLOBAL returns null pointer");
-                                    return Err(ComError::E_FAIL);
-                                }
-                                
-                                let guid_size_in_bytes = std::mem::size_of::<GUID>();
-                                std::ptr::copy_nonoverlapping(&CLSID_MMCSnapIn as *const _ as *const u8, ptr as *mut u8, guid_size_in_bytes);
-
-                                global_unlock_checked((*pmedium).0.Anonymous.hGlobal)?;
-                            }
-                            _ => {
-                                log::error!("Unsupported TYMED: {:?}", tymed);
-                                return Err(DV_E_TYMED);
-                                
-                            }
-                        }
-                    }
-                    
-                }
-                /*
-                 * Clipboard formats:
-                 *   - CCF_NODETYPE
-                 *   - CCF_SZNODETYPE
-                 *   - CCF_DISPLAY_NAME
-                 *   - CCF_SNAPIN_CLASSID
-                 *   - CCF_SNAPIN_CLASS
-                 *   - CCF_WINDOW_TITLE
-                 *   - CCF_MMC_MULTISELECT_DATAOBJECT
-                 *   - CCF_MULTI_SELECT_SNAPINS
-                 *   - CCF_OBJECT_TYPES_IN_MULTI_SELECT
-                 *   - CCF_MMC_DYNAMIC_EXTENSIONS
-                 *   - CCF_SNAPIN_PRELOADS
-                 *   - CCF_NODEID2
-                 *   - CCF_NODEID
-                 *   - CCF_COLUMN_SET_ID
-                 *   - CCF_DESCRIPTION
-                 *   - CCF_HTML_DETAILS
-                */
-                _ => {
-                    log::error!("Can't handle this type of clipboard format. tymed: {:?} btw", tymed);
-                    return Err(DV_E_FORMATETC);
-                }
+        match tymed {
+            TagTYMED::HGlobal => unsafe { write_hglobal((*pmedium).0.Anonymous.hGlobal, &payload) },
+            _ => {
+                log::error!("Unsupported TYMED: {:?}", tymed);
+                Err(DV_E_TYMED)
             }
-        } else {
-            log::error!("Couldn't get clipboard format name. Dieing I guess");
-            return Err(ComError::E_FAIL);
         }
-
-        Ok(())
     }
     
-    fn query_get_data(&self,) -> ComResult<()> {
-        Ok(())
+    // Answers for exactly the formats get_data would render, so MMC's Copy
+    // only offers the clipboard what we can actually paste
+    fn query_get_data(&self, pformatetc: *const ComFORMATETC) -> ComResult<()> {
+        if pformatetc.is_null() {
+            return Err(ComError::E_POINTER);
+        }
+        
+        let clipformat: u32 = unsafe { (*pformatetc).0.cfFormat.into() };
+        if unsafe { (*pformatetc).0.tymed } & TagTYMED::HGlobal as u32 == 0 {
+            return Err(DV_E_TYMED);
+        }
+        
+        match clipformat_name(clipformat) {
+            Some(name) if clipformat::serialize(name, &self.clip_data()).is_some() => Ok(()),
+            _ => Err(DV_E_FORMATETC),
+        }
     }
     
     fn get_canonical_format(&self,) -> ComResult<()> {
@@ -253,8 +260,19 @@ impl IDataObject for Node {
         Ok(())
     }
     
-    fn enum_format_etc(&self,) -> ComResult<()> {
-        Ok(())
+    fn enum_format_etc(&self, direction: u32) -> ComResult<ComRc<dyn IEnumFORMATETC>> {
+        // DATADIR_GET; we don't accept SetData
+        if direction != 1 {
+            return Err(ComError::E_NOTIMPL);
+        }
+        
+        let data = self.clip_data();
+        let formats = clipformat_ids().iter()
+            .filter(|(_, name)| clipformat::serialize(name, &data).is_some())
+            .map(|(id, _)| *id as u16)
+            .collect();
+        
+        Ok(ComRc::from(ComBox::new(FormatEnumerator::new(formats))))
     }
     
     fn d_advise(&self,) -> ComResult<()> {
@@ -270,6 +288,101 @@ impl IDataObject for Node {
     }
 }
 
+// Wraps a data object MMC passed as an LPARAM, as the control bar's
+// notifications do. Doesn't add a reference, so the ComItf mustn't outlive
+// the notification.
+pub unsafe fn dataobject_from_param(param: isize) -> Option<ComItf<dyn IDataObject>> {
+    // Special data objects, like DOBJ_CUSTOMOCX, are small negative numbers
+    if param <= 0 {
+        return None;
+    }
+    let ptr = intercom::raw::InterfacePtr::<intercom::type_system::RawTypeSystem, dyn IDataObject>::new(param as intercom::raw::RawComPtr)?;
+    Some(ComItf::wrap(ptr))
+}
+
+// Finds which of our nodes a data object MMC passed in belongs to. Data
+// objects from anyone else don't serve our private format.
+pub fn node_id_of(dataobject: &ComItf<dyn IDataObject>) -> Option<NodeId> {
+    let (clipformat, _) = clipformat_ids().iter().find(|(_, name)| *name == clipformat::NODE_COOKIE)?;
+    let size = std::mem::size_of::<isize>();
+    
+    let hglobal = unsafe { GlobalAlloc(GMEM_MOVEABLE, size) }.ok()?;
+    let formatetc = FORMATETC {
+        cfFormat: *clipformat as u16,
+        ptd: std::ptr::null_mut(),
+        dwAspect: 1, // DVASPECT_CONTENT
+        lindex: -1,
+        tymed: TYMED_HGLOBAL.0 as u32,
+    };
+    let mut medium = STGMEDIUM {
+        tymed: TYMED_HGLOBAL,
+        Anonymous: STGMEDIUM_0 { hGlobal: hglobal },
+        pUnkForRelease: std::mem::ManuallyDrop::new(None),
+    };
+    
+    let mut cookie = None;
+    let formatetc = ComFORMATETC(formatetc);
+    let medium_ptr = &mut medium as *mut STGMEDIUM as *mut ComSTGMEDIUM;
+    if dataobject.get_data_here(&formatetc as *const _, medium_ptr).is_ok() {
+        let ptr = unsafe { GlobalLock(hglobal) };
+        if !ptr.is_null() {
+            let mut bytes = [0u8; std::mem::size_of::<isize>()];
+            unsafe { std::ptr::copy_nonoverlapping(ptr as *const u8, bytes.as_mut_ptr(), size); }
+            let _ = global_unlock_checked(hglobal);
+            cookie = Some(isize::from_le_bytes(bytes));
+        }
+    }
+    
+    unsafe { let _ = GlobalFree(hglobal); }
+    cookie.map(NodeId)
+}
+
+// Clipboard format ids are registered once per session, so the ids are
+// looked up once and kept, in the serializer table's order.
+fn clipformat_ids() -> &'static [(u32, &'static str)] {
+    static FORMATS: OnceLock<Vec<(u32, &'static str)>> = OnceLock::new();
+    
+    FORMATS.get_or_init(|| {
+        clipformat::SERIALIZERS.iter()
+            .map(|(name, _)| {
+                let id = clipformat::predefined(name).unwrap_or_else(|| {
+                    let wide: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+                    unsafe { RegisterClipboardFormatW(PCWSTR::from_raw(wide.as_ptr())) }
+                });
+                (id, *name)
+            })
+            .filter(|(id, _)| *id != 0)
+            .collect()
+    })
+}
+
+fn clipformat_name(clipformat: u32) -> Option<&'static str> {
+    clipformat_ids().iter()
+        .find(|(id, _)| *id == clipformat)
+        .map(|(_, name)| *name)
+}
+
+// Copies a payload into a caller-allocated HGLOBAL
+fn write_hglobal(hglobal: HGLOBAL, payload: &[u8]) -> ComResult<()> {
+    let size = unsafe { GlobalSize(hglobal) };
+    if size < payload.len() {
+        log::error!("HGLOBAL is {} bytes, need {}", size, payload.len());
+        return Err(STG_E_MEDIUMFULL);
+    }
+    
+    let ptr = unsafe { GlobalLock(hglobal) };
+    if ptr.is_null() {
+        log::error!("HGLOBAL returns null pointer");
+        return Err(ComError::E_FAIL);
+    }
+    
+    unsafe {
+        std::ptr::copy_nonoverlapping(payload.as_ptr(), ptr as *mut u8, payload.len());
+    }
+    
+    global_unlock_checked(hglobal)
+}
+
 fn global_unlock_checked(hglobal: HGLOBAL) -> ComResult<()> {
     let res = unsafe { GlobalUnlock(hglobal) };
     if res.0 == 0 {
@@ -0,0 +1,76 @@
+// Background polling. MMC calls the snap-in on its UI thread only, so
+// rather than reading devices on a thread of our own, a thread timer on the
+// UI thread tells the snap-in to read them between messages.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::WindowsAndMessaging::{KillTimer, SetTimer};
+
+use crate::MMCSnapIn;
+
+thread_local! {
+    // The snap-in each running timer polls
+    static TIMERS: RefCell<HashMap<usize, *mut MMCSnapIn>> = RefCell::new(HashMap::new());
+
+    // Nonzero while the snap-in runs a modal loop, a message box or a popup
+    // menu, during which ticks are skipped: the snap-in is still borrowed
+    static SUSPENDED: Cell<u32> = const { Cell::new(0) };
+}
+
+#[derive(Debug)]
+pub struct Poller {
+    timer: usize,
+}
+
+impl Poller {
+    // Starts polling `snapin` every `interval_ms` until the Poller is
+    // dropped. The snap-in must not move in the meantime, which it doesn't
+    // once it's in its ComBox.
+    pub fn start(snapin: *mut MMCSnapIn, interval_ms: u32) -> Option<Self> {
+        let timer = unsafe { SetTimer(HWND(0), 0, interval_ms, Some(tick)) };
+        match timer {
+            0 => {
+                log::error!("SetTimer() failed: {}", windows::core::Error::from_win32());
+                None
+            }
+            timer => {
+                TIMERS.with(|timers| timers.borrow_mut().insert(timer, snapin));
+                Some(Poller { timer })
+            }
+        }
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        TIMERS.with(|timers| timers.borrow_mut().remove(&self.timer));
+        unsafe { KillTimer(HWND(0), self.timer) };
+    }
+}
+
+// Skips ticks until the returned guard is dropped
+pub fn suspend() -> Suspend {
+    SUSPENDED.with(|suspended| suspended.set(suspended.get() + 1));
+    Suspend
+}
+
+pub struct Suspend;
+
+impl Drop for Suspend {
+    fn drop(&mut self) {
+        SUSPENDED.with(|suspended| suspended.set(suspended.get() - 1));
+    }
+}
+
+unsafe extern "system" fn tick(_hwnd: HWND, _msg: u32, timer: usize, _time: u32) {
+    if SUSPENDED.with(|suspended| suspended.get()) > 0 {
+        return;
+    }
+
+    let snapin = TIMERS.with(|timers| timers.borrow().get(&timer).copied());
+    if let Some(snapin) = snapin {
+        (*snapin).poll();
+    }
+}
@@ -0,0 +1,445 @@
+use std::sync::{Arc, Mutex};
+
+use intercom::prelude::*;
+use windows::core::{HRESULT, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::UI::Controls::{
+    CreatePropertySheetPageW, DestroyPropertySheetPage, NMHDR, PROPSHEETPAGEW, PROPSHEETPAGEW_0,
+    PSPCB_MESSAGE, PSPCB_RELEASE, PSM_CHANGED, PSNRET_INVALID_NOCHANGEPAGE, PSNRET_NOERROR, PSN_APPLY,
+    PSN_KILLACTIVE, PSP_DLGINDIRECT, PSP_USECALLBACK, PSP_USETITLE,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetDlgItem, GetDlgItemTextW, GetParent, GetWindowLongPtrW, GetWindowTextLengthW, MessageBoxW,
+    SendMessageW, SetDlgItemTextW, SetWindowLongPtrW, CBN_SELCHANGE, CBS_DROPDOWNLIST, CB_ADDSTRING,
+    CB_GETCURSEL, CB_RESETCONTENT, CB_SETCURSEL, DLGTEMPLATE, DS_3DLOOK, DS_CENTER, DS_CONTROL, DS_MODALFRAME,
+    DS_SETFONT, EN_CHANGE, ES_AUTOHSCROLL, ES_READONLY, MB_ICONWARNING, MB_OK, WINDOW_LONG_PTR_INDEX,
+    WM_COMMAND, WM_INITDIALOG, WM_NOTIFY, WS_BORDER, WS_CAPTION, WS_CHILD, WS_POPUP, WS_SYSMENU, WS_TABSTOP,
+    WS_VISIBLE, WS_VSCROLL,
+};
+
+use crate::interfaces::{ComHPROPSHEETPAGE, IPropertySheetCallback};
+use crate::propsheet::{Change, DeviceSheet, Page};
+use crate::registry::NodeId;
+use crate::smbus::drivers::SettingKind;
+
+#[link(name = "mmc")]
+extern "system" {
+    fn MMCPropertyChangeNotify(notify_handle: isize, param: isize) -> HRESULT;
+    fn MMCFreeNotifyHandle(notify_handle: isize) -> HRESULT;
+}
+
+// Dialog window longs. DWLP_USER isn't in the windows crate.
+pub(super) const DWLP_MSGRESULT: WINDOW_LONG_PTR_INDEX = WINDOW_LONG_PTR_INDEX(0);
+pub(super) const DWLP_USER: WINDOW_LONG_PTR_INDEX = WINDOW_LONG_PTR_INDEX(2 * std::mem::size_of::<isize>() as i32);
+
+// Predefined window class atoms for dialog templates
+pub(super) const CLASS_BUTTON: u16 = 0x0080;
+pub(super) const CLASS_EDIT: u16 = 0x0081;
+pub(super) const CLASS_STATIC: u16 = 0x0082;
+const CLASS_COMBOBOX: u16 = 0x0085;
+
+// Layout, in dialog units. 252x218 is the usual property page size.
+const PAGE_WIDTH: i16 = 252;
+const PAGE_HEIGHT: i16 = 218;
+pub(super) const MARGIN: i16 = 7;
+const ROW_HEIGHT: i16 = 14;
+const LABEL_WIDTH: i16 = 96;
+const CONTROL_WIDTH: i16 = 100;
+
+// The control for field n has id FIELD_ID + n
+const FIELD_ID: i32 = 100;
+
+const CAPTION: &str = "SMBus Snap-in";
+
+/// Values a sheet wants written. Property sheets run on their own thread,
+/// so the sheet doesn't touch the bus; it hands this to the snap-in's
+/// thread as the param of MMCN_PROPERTY_CHANGE.
+#[derive(Debug)]
+pub struct PropertyChange {
+    pub id: NodeId,
+    pub changes: Vec<Change>,
+}
+
+impl PropertyChange {
+    /// Takes back the change a sheet sent with MMCPropertyChangeNotify.
+    ///
+    /// # Safety
+    ///
+    /// `param` has to be the param of an MMCN_PROPERTY_CHANGE we caused,
+    /// and can only be taken once.
+    pub unsafe fn from_param(param: isize) -> Option<Box<Self>> {
+        match param {
+            0 => None,
+            _ => Some(Box::from_raw(param as *mut Self)),
+        }
+    }
+}
+
+// Shared by every page of one sheet
+#[derive(Debug)]
+struct SheetState {
+    id: NodeId,
+    sheet: DeviceSheet,
+    notify_handle: isize,
+}
+
+impl Drop for SheetState {
+    fn drop(&mut self) {
+        if self.notify_handle != 0 {
+            let hr = unsafe { MMCFreeNotifyHandle(self.notify_handle) };
+            if hr.is_err() {
+                log::error!("MMCFreeNotifyHandle() error: {:?}", hr);
+            }
+        }
+    }
+}
+
+// What a page's dialog proc finds at DWLP_USER. Freed when the page is
+// released; the template and title have to live that long.
+struct PageState {
+    sheet: Arc<Mutex<SheetState>>,
+    index: usize,
+    template: Vec<u32>,
+    title: Vec<u16>,
+}
+
+/// Adds a page to the sheet for each page of `sheet`. `notify_handle` is
+/// the handle from CreatePropertyPages, freed once the last page goes.
+pub fn add_pages(callback: &ComItf<dyn IPropertySheetCallback>, notify_handle: isize, id: NodeId, sheet: DeviceSheet) -> ComResult<()> {
+    let pages: Vec<(Vec<u32>, Vec<u16>)> = sheet.pages.iter()
+        .map(|page| (dialog_template(page), wide(&page.title)))
+        .collect();
+    let shared = Arc::new(Mutex::new(SheetState { id, sheet, notify_handle }));
+
+    for (index, (template, title)) in pages.into_iter().enumerate() {
+        let page = Box::into_raw(Box::new(PageState {
+            sheet: shared.clone(),
+            index,
+            template,
+            title,
+        }));
+
+        let mut psp = PROPSHEETPAGEW {
+            dwSize: std::mem::size_of::<PROPSHEETPAGEW>() as u32,
+            dwFlags: PSP_DLGINDIRECT | PSP_USETITLE | PSP_USECALLBACK,
+            Anonymous1: PROPSHEETPAGEW_0 {
+                pResource: unsafe { (*page).template.as_ptr() } as *mut DLGTEMPLATE,
+            },
+            pszTitle: PCWSTR::from_raw(unsafe { (*page).title.as_ptr() }),
+            pfnDlgProc: Some(page_proc),
+            lParam: LPARAM(page as isize),
+            pfnCallback: Some(page_callback),
+            ..Default::default()
+        };
+
+        let hpage = unsafe { CreatePropertySheetPageW(&mut psp) };
+        if hpage.is_invalid() {
+            log::error!("CreatePropertySheetPageW() failed");
+            drop(unsafe { Box::from_raw(page) });
+            return Err(ComError::E_FAIL);
+        }
+
+        if let Err(e) = callback.add_page(ComHPROPSHEETPAGE(hpage)) {
+            log::error!("IPropertySheetCallback::AddPage() error: {}", e);
+            // Releases the page, which frees its state
+            unsafe { DestroyPropertySheetPage(hpage); }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+unsafe extern "system" fn page_callback(_hwnd: HWND, msg: PSPCB_MESSAGE, psp: *mut PROPSHEETPAGEW) -> u32 {
+    if msg == PSPCB_RELEASE && !psp.is_null() {
+        let page = (*psp).lParam.0 as *mut PageState;
+        if !page.is_null() {
+            drop(Box::from_raw(page));
+        }
+    }
+    1
+}
+
+unsafe extern "system" fn page_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            // lparam is the page's PROPSHEETPAGEW
+            let psp = lparam.0 as *const PROPSHEETPAGEW;
+            SetWindowLongPtrW(hwnd, DWLP_USER, (*psp).lParam.0);
+            if let Some(page) = page_state(hwnd) {
+                fill(hwnd, page);
+            }
+            1
+        }
+        WM_COMMAND => {
+            let id = (wparam.0 & 0xffff) as i32;
+            let code = ((wparam.0 >> 16) & 0xffff) as u32;
+            match page_state(hwnd) {
+                Some(page) if id >= FIELD_ID => {
+                    if field_changed(hwnd, page, (id - FIELD_ID) as usize, code) {
+                        SendMessageW(GetParent(hwnd), PSM_CHANGED, WPARAM(hwnd.0 as usize), LPARAM(0));
+                    }
+                    1
+                }
+                _ => 0,
+            }
+        }
+        WM_NOTIFY => {
+            let page = match page_state(hwnd) {
+                Some(page) => page,
+                None => return 0,
+            };
+            let result = match (*(lparam.0 as *const NMHDR)).code {
+                PSN_KILLACTIVE => match validate(hwnd, page) {
+                    true => 0,
+                    false => 1,
+                },
+                PSN_APPLY => apply(hwnd, page),
+                _ => return 0,
+            };
+            SetWindowLongPtrW(hwnd, DWLP_MSGRESULT, result as isize);
+            1
+        }
+        _ => 0,
+    }
+}
+
+unsafe fn page_state<'a>(hwnd: HWND) -> Option<&'a PageState> {
+    (GetWindowLongPtrW(hwnd, DWLP_USER) as *const PageState).as_ref()
+}
+
+// Puts the page's text into its controls
+unsafe fn fill(hwnd: HWND, page: &PageState) {
+    let state = match page.sheet.lock() {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+    let fields = match state.sheet.pages.get(page.index) {
+        Some(sheet_page) => sheet_page.fields.clone(),
+        None => return,
+    };
+    // Setting the text sends EN_CHANGE, which locks the sheet again
+    drop(state);
+
+    for (index, field) in fields.iter().enumerate() {
+        let id = FIELD_ID + index as i32;
+        match field.setting.as_ref().map(|setting| &setting.kind) {
+            Some(SettingKind::Choice(choices)) => {
+                let combo = GetDlgItem(hwnd, id);
+                SendMessageW(combo, CB_RESETCONTENT, WPARAM(0), LPARAM(0));
+                for (name, _) in choices {
+                    let name = wide(name);
+                    SendMessageW(combo, CB_ADDSTRING, WPARAM(0), LPARAM(name.as_ptr() as isize));
+                }
+                let selected = choices.iter().position(|(name, _)| *name == field.text);
+                SendMessageW(combo, CB_SETCURSEL, WPARAM(selected.map(|i| i as usize).unwrap_or(usize::MAX)), LPARAM(0));
+            }
+            _ => {
+                let text = wide(&field.text);
+                SetDlgItemTextW(hwnd, id, PCWSTR::from_raw(text.as_ptr()));
+            }
+        }
+    }
+}
+
+// Copies a control's new text into the sheet. Returns true if the sheet
+// now has something to apply.
+unsafe fn field_changed(hwnd: HWND, page: &PageState, index: usize, code: u32) -> bool {
+    let id = FIELD_ID + index as i32;
+    let mut state = match page.sheet.lock() {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+    let sheet_page = match state.sheet.pages.get_mut(page.index) {
+        Some(sheet_page) => sheet_page,
+        None => return false,
+    };
+
+    let text = match (sheet_page.fields.get(index).and_then(|field| field.setting.as_ref()).map(|setting| &setting.kind), code) {
+        (Some(SettingKind::Choice(choices)), CBN_SELCHANGE) => {
+            let selected = SendMessageW(GetDlgItem(hwnd, id), CB_GETCURSEL, WPARAM(0), LPARAM(0)).0;
+            match choices.get(selected as usize) {
+                Some((name, _)) => name.clone(),
+                None => return false,
+            }
+        }
+        (Some(SettingKind::Number { .. }), EN_CHANGE) => {
+            let len = GetWindowTextLengthW(GetDlgItem(hwnd, id)).max(0) as usize;
+            let mut buffer = vec![0u16; len + 1];
+            let copied = GetDlgItemTextW(hwnd, id, &mut buffer) as usize;
+            String::from_utf16_lossy(&buffer[..copied])
+        }
+        _ => return false,
+    };
+
+    sheet_page.set_text(index, &text);
+    state.sheet.is_modified()
+}
+
+// Checks the page before the user leaves it. Returns false, after saying
+// why, if something on it can't be written.
+unsafe fn validate(hwnd: HWND, page: &PageState) -> bool {
+    let error = match page.sheet.lock() {
+        Ok(state) => match state.sheet.pages.get(page.index) {
+            Some(sheet_page) => sheet_page.changes(page.index).err(),
+            None => None,
+        },
+        Err(_) => None,
+    };
+
+    match error {
+        Some(error) => {
+            message_box(hwnd, &error.to_string());
+            false
+        }
+        None => true,
+    }
+}
+
+// Sends the sheet's changes to the snap-in to be written. Every page gets
+// PSN_APPLY; whichever gets it first sends the changes for all of them.
+unsafe fn apply(hwnd: HWND, page: &PageState) -> u32 {
+    let mut state = match page.sheet.lock() {
+        Ok(state) => state,
+        Err(_) => return PSNRET_INVALID_NOCHANGEPAGE,
+    };
+
+    let changes = match state.sheet.changes() {
+        Ok(changes) => changes,
+        Err(error) => {
+            drop(state);
+            message_box(hwnd, &error.to_string());
+            return PSNRET_INVALID_NOCHANGEPAGE;
+        }
+    };
+
+    if !changes.is_empty() {
+        let change = Box::new(PropertyChange { id: state.id, changes: changes.clone() });
+        let param = Box::into_raw(change);
+        let hr = MMCPropertyChangeNotify(state.notify_handle, param as isize);
+        if hr.is_err() {
+            log::error!("MMCPropertyChangeNotify() error: {:?}", hr);
+            drop(Box::from_raw(param));
+            drop(state);
+            message_box(hwnd, "The changes couldn't be sent to the snap-in.");
+            return PSNRET_INVALID_NOCHANGEPAGE;
+        }
+        state.sheet.commit(&changes);
+    }
+    drop(state);
+
+    fill(hwnd, page);
+    PSNRET_NOERROR
+}
+
+pub(super) unsafe fn message_box(hwnd: HWND, text: &str) {
+    let text = wide(text);
+    let caption = wide(CAPTION);
+    MessageBoxW(hwnd, PCWSTR::from_raw(text.as_ptr()), PCWSTR::from_raw(caption.as_ptr()), MB_OK | MB_ICONWARNING);
+}
+
+pub(super) fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+// Builds an in-memory dialog template for a page: a label, a control and
+// the unit on each row. The edit controls start empty and are filled in at
+// WM_INITDIALOG.
+fn dialog_template(page: &Page) -> Vec<u32> {
+    let mut words: Vec<u16> = Vec::new();
+
+    let units = page.fields.iter().filter(|field| field.unit.is_some()).count();
+    push_header(&mut words, page.fields.len() * 2 + units, PAGE_WIDTH, PAGE_HEIGHT);
+
+    let control_x = MARGIN + LABEL_WIDTH + 3;
+    for (index, field) in page.fields.iter().enumerate() {
+        let y = MARGIN + index as i16 * ROW_HEIGHT;
+        let id = (FIELD_ID + index as i32) as u16;
+        let visible = WS_CHILD.0 | WS_VISIBLE.0;
+
+        push_item(&mut words, visible, (MARGIN, y + 2, LABEL_WIDTH, 8), u16::MAX, CLASS_STATIC, &field.label);
+        match field.setting.as_ref().map(|setting| &setting.kind) {
+            Some(SettingKind::Choice(_)) => {
+                // A combo box's height includes its drop-down list
+                let style = visible | WS_TABSTOP.0 | WS_VSCROLL.0 | CBS_DROPDOWNLIST as u32;
+                push_item(&mut words, style, (control_x, y, CONTROL_WIDTH, 80), id, CLASS_COMBOBOX, "");
+            }
+            Some(SettingKind::Number { .. }) => {
+                let style = visible | WS_TABSTOP.0 | WS_BORDER.0 | ES_AUTOHSCROLL as u32;
+                push_item(&mut words, style, (control_x, y, CONTROL_WIDTH, 12), id, CLASS_EDIT, "");
+            }
+            None => {
+                // Read-only text gets the rest of the row, since it has no unit
+                let style = visible | WS_BORDER.0 | ES_AUTOHSCROLL as u32 | ES_READONLY as u32;
+                let width = PAGE_WIDTH - control_x - MARGIN;
+                push_item(&mut words, style, (control_x, y, width, 12), id, CLASS_EDIT, "");
+            }
+        }
+        if let Some(unit) = &field.unit {
+            let x = control_x + CONTROL_WIDTH + 3;
+            push_item(&mut words, visible, (x, y + 2, PAGE_WIDTH - x - MARGIN, 8), u16::MAX, CLASS_STATIC, unit);
+        }
+    }
+
+    to_template(words)
+}
+
+// Starts a template for a page with `items` controls
+pub(super) fn push_header(words: &mut Vec<u16>, items: usize, width: i16, height: i16) {
+    let style = (DS_SETFONT | DS_3DLOOK | DS_CONTROL) as u32 | WS_CHILD.0;
+    push_u32(words, style);
+    push_u32(words, 0);
+    words.push(items as u16);
+    words.extend([0, 0, width as u16, height as u16]);
+    // No menu, the default class and no caption
+    words.extend([0, 0, 0]);
+    words.push(8);
+    push_str(words, "MS Shell Dlg");
+}
+
+// Starts a template for a modal dialog of its own, with a caption and a
+// close box
+pub(super) fn push_dialog_header(words: &mut Vec<u16>, items: usize, width: i16, height: i16, caption: &str) {
+    let style = (DS_SETFONT | DS_3DLOOK | DS_MODALFRAME | DS_CENTER) as u32 | WS_POPUP.0 | WS_CAPTION.0 | WS_SYSMENU.0;
+    push_u32(words, style);
+    push_u32(words, 0);
+    words.push(items as u16);
+    words.extend([0, 0, width as u16, height as u16]);
+    // No menu and the default class
+    words.extend([0, 0]);
+    push_str(words, caption);
+    words.push(8);
+    push_str(words, "MS Shell Dlg");
+}
+
+// Templates have to be DWORD aligned, so hand them out as u32s
+pub(super) fn to_template(mut words: Vec<u16>) -> Vec<u32> {
+    if words.len() % 2 != 0 {
+        words.push(0);
+    }
+    words.chunks(2)
+        .map(|pair| u32::from(pair[0]) | (u32::from(pair[1]) << 16))
+        .collect()
+}
+
+// Appends a DLGITEMTEMPLATE and its class, text and (empty) creation data
+pub(super) fn push_item(words: &mut Vec<u16>, style: u32, (x, y, cx, cy): (i16, i16, i16, i16), id: u16, class: u16, text: &str) {
+    if words.len() % 2 != 0 {
+        words.push(0);
+    }
+    push_u32(words, style);
+    push_u32(words, 0);
+    words.extend([x as u16, y as u16, cx as u16, cy as u16, id]);
+    words.extend([0xffff, class]);
+    push_str(words, text);
+    words.push(0);
+}
+
+fn push_u32(words: &mut Vec<u16>, value: u32) {
+    words.extend([value as u16, (value >> 16) as u16]);
+}
+
+fn push_str(words: &mut Vec<u16>, text: &str) {
+    words.extend(text.encode_utf16());
+    words.push(0);
+}
@@ -0,0 +1,173 @@
+// The Edit Register dialog: one register of a device and the value to write
+// to it, with what the register held at the last read beside it. The
+// snap-in does the writing, through the write-safety layer.
+
+use windows::Win32::Foundation::{HMODULE, HWND, LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DialogBoxIndirectParamW, EndDialog, GetDlgItem, GetDlgItemTextW, GetWindowLongPtrW, GetWindowTextLengthW,
+    SetDlgItemTextW, SetWindowLongPtrW, BS_DEFPUSHBUTTON, BS_PUSHBUTTON, DLGTEMPLATE, EN_CHANGE, ES_AUTOHSCROLL,
+    WM_COMMAND, WM_INITDIALOG, WS_BORDER, WS_CHILD, WS_TABSTOP, WS_VISIBLE,
+};
+use windows::core::PCWSTR;
+
+use crate::__INTERCOM_DLL_INSTANCE;
+use crate::smbus::dump::{self, DumpMode};
+
+use super::poll;
+use super::propertysheet::{
+    message_box, push_dialog_header, push_item, to_template, wide, CLASS_BUTTON, CLASS_EDIT, CLASS_STATIC, DWLP_USER,
+    MARGIN,
+};
+
+// In dialog units
+const WIDTH: i16 = 180;
+const HEIGHT: i16 = 86;
+const LABEL_WIDTH: i16 = 60;
+const ROW_HEIGHT: i16 = 16;
+const BUTTON_WIDTH: i16 = 50;
+
+// IDOK and IDCANCEL, which Enter and Esc send
+const ID_OK: i32 = 1;
+const ID_CANCEL: i32 = 2;
+const ID_REGISTER: i32 = 100;
+const ID_CURRENT: i32 = 101;
+const ID_VALUE: i32 = 102;
+
+// What the dialog proc finds at DWLP_USER
+struct DialogState {
+    mode: DumpMode,
+    // The last read's values, by register
+    values: Vec<Option<u16>>,
+    register: u8,
+    // What the user chose to write, once they click OK
+    write: Option<(u8, u16)>,
+}
+
+/// Asks for a register of the device at `addr` and a value to write to it,
+/// starting at `register`. `values` are the registers as last read in
+/// `mode`. Returns `None` if the user cancels.
+pub fn run(owner: HWND, addr: u8, mode: DumpMode, values: Vec<Option<u16>>, register: u8) -> Option<(u8, u16)> {
+    let _suspend = poll::suspend();
+
+    let mut state = DialogState { mode, values, register, write: None };
+    let template = dialog_template(&format!("Edit Register - {:#04x}", addr));
+    let dll = unsafe { HMODULE(__INTERCOM_DLL_INSTANCE as isize) };
+    let param = LPARAM(&mut state as *mut DialogState as isize);
+    let result = unsafe {
+        DialogBoxIndirectParamW(dll, template.as_ptr() as *const DLGTEMPLATE, owner, Some(dialog_proc), param)
+    };
+    if result == -1 {
+        log::error!("DialogBoxIndirectParamW() error: {}", windows::core::Error::from_win32());
+    }
+    state.write
+}
+
+unsafe extern "system" fn dialog_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, DWLP_USER, lparam.0);
+            if let Some(state) = dialog_state(hwnd) {
+                // Setting the register sends EN_CHANGE, which shows its value
+                set_text(hwnd, ID_REGISTER, &format!("{:02x}", state.register));
+                let current = state.values.get(state.register as usize).copied().flatten();
+                set_text(hwnd, ID_VALUE, &current.map(|value| format_value(state.mode, value)).unwrap_or_default());
+            }
+            1
+        }
+        WM_COMMAND => {
+            let id = (wparam.0 & 0xffff) as i32;
+            let code = ((wparam.0 >> 16) & 0xffff) as u32;
+            let state = match dialog_state(hwnd) {
+                Some(state) => state,
+                None => return 0,
+            };
+            match (id, code) {
+                (ID_REGISTER, EN_CHANGE) => {
+                    show_current(hwnd, state);
+                    1
+                }
+                (ID_OK, _) => {
+                    match dump::parse_write(&text(hwnd, ID_REGISTER), &text(hwnd, ID_VALUE), state.mode) {
+                        Ok(write) => {
+                            state.write = Some(write);
+                            EndDialog(hwnd, ID_OK as isize);
+                        }
+                        Err(e) => message_box(hwnd, &e),
+                    }
+                    1
+                }
+                (ID_CANCEL, _) => {
+                    EndDialog(hwnd, ID_CANCEL as isize);
+                    1
+                }
+                _ => 0,
+            }
+        }
+        _ => 0,
+    }
+}
+
+unsafe fn dialog_state<'a>(hwnd: HWND) -> Option<&'a mut DialogState> {
+    (GetWindowLongPtrW(hwnd, DWLP_USER) as *mut DialogState).as_mut()
+}
+
+// Shows what the register being typed held at the last read
+unsafe fn show_current(hwnd: HWND, state: &DialogState) {
+    let register = dump::parse_hex(&text(hwnd, ID_REGISTER)).and_then(|register| u8::try_from(register).ok());
+    let current = match register {
+        Some(register) => match state.values.get(register as usize).copied().flatten() {
+            Some(value) => format_value(state.mode, value),
+            None => "Couldn't be read".to_owned(),
+        },
+        None => String::new(),
+    };
+    set_text(hwnd, ID_CURRENT, &current);
+}
+
+fn format_value(mode: DumpMode, value: u16) -> String {
+    match mode {
+        DumpMode::Byte => format!("{:02x}", value),
+        DumpMode::Word => format!("{:04x}", value),
+    }
+}
+
+unsafe fn set_text(hwnd: HWND, id: i32, text: &str) {
+    let text = wide(text);
+    SetDlgItemTextW(hwnd, id, PCWSTR::from_raw(text.as_ptr()));
+}
+
+unsafe fn text(hwnd: HWND, id: i32) -> String {
+    let len = GetWindowTextLengthW(GetDlgItem(hwnd, id)).max(0) as usize;
+    let mut buffer = vec![0u16; len + 1];
+    let copied = GetDlgItemTextW(hwnd, id, &mut buffer) as usize;
+    String::from_utf16_lossy(&buffer[..copied])
+}
+
+// A label and a box on each of three rows, then OK and Cancel at the
+// bottom right
+fn dialog_template(caption: &str) -> Vec<u32> {
+    let mut words: Vec<u16> = Vec::new();
+    push_dialog_header(&mut words, 8, WIDTH, HEIGHT, caption);
+
+    let visible = WS_CHILD.0 | WS_VISIBLE.0;
+    let edit = visible | WS_TABSTOP.0 | WS_BORDER.0 | ES_AUTOHSCROLL as u32;
+    let control_x = MARGIN + LABEL_WIDTH;
+    let control_width = WIDTH - control_x - MARGIN;
+    let row = |index: i16| MARGIN + index * ROW_HEIGHT;
+
+    push_item(&mut words, visible, (MARGIN, row(0) + 2, LABEL_WIDTH, 8), u16::MAX, CLASS_STATIC, "&Register (hex):");
+    push_item(&mut words, edit, (control_x, row(0), control_width, 12), ID_REGISTER as u16, CLASS_EDIT, "");
+    push_item(&mut words, visible, (MARGIN, row(1) + 2, LABEL_WIDTH, 8), u16::MAX, CLASS_STATIC, "Last read:");
+    push_item(&mut words, visible, (control_x, row(1) + 2, control_width, 8), ID_CURRENT as u16, CLASS_STATIC, "");
+    push_item(&mut words, visible, (MARGIN, row(2) + 2, LABEL_WIDTH, 8), u16::MAX, CLASS_STATIC, "&Value (hex):");
+    push_item(&mut words, edit, (control_x, row(2), control_width, 12), ID_VALUE as u16, CLASS_EDIT, "");
+
+    let y = HEIGHT - MARGIN - 14;
+    let button = visible | WS_TABSTOP.0;
+    let cancel_x = WIDTH - MARGIN - BUTTON_WIDTH;
+    let ok_x = cancel_x - 4 - BUTTON_WIDTH;
+    push_item(&mut words, button | BS_DEFPUSHBUTTON as u32, (ok_x, y, BUTTON_WIDTH, 14), ID_OK as u16, CLASS_BUTTON, "OK");
+    push_item(&mut words, button | BS_PUSHBUTTON as u32, (cancel_x, y, BUTTON_WIDTH, 14), ID_CANCEL as u16, CLASS_BUTTON, "Cancel");
+
+    to_template(words)
+}
@@ -1,65 +1,1322 @@
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 
 use intercom::{ IUnknown, prelude::* };
-use windows::Win32::Foundation::LPARAM;
+use windows::Win32::Foundation::{HWND, LPARAM, POINT};
+use windows::Win32::UI::WindowsAndMessaging::{AppendMenuW, CreatePopupMenu, DestroyMenu, GetCursorPos, TrackPopupMenu, MF_CHECKED, MF_STRING, TPM_NONOTIFY, TPM_RETURNCMD};
+use windows::core::PCWSTR;
 
-use crate::class::node::NodeType;
 use crate::MMCSnapInComponent;
+use crate::actions::{self, Action, ActionId, ActionState, Verb, Verbs};
 use crate::interfaces::*;
 use crate::Node;
+use crate::class::{contextmenu, node_id_of, propertysheet, PropertyChange};
+use crate::class::imagelist;
+use crate::class::poll::{self, Poller};
+use crate::class::registeredit;
+use crate::class::adddevice;
+use crate::class::filedialog;
+use crate::class::wizard::{self, WizardChoice};
+use crate::columns;
+use crate::connect::Connection;
+use crate::history::{self, History};
+use crate::images::{Icon, IconState, Image};
+use crate::propsheet::{self, DeviceSheet};
+use crate::registry::{NodeId, NodeKind, NodeRegistry};
+use crate::service::client::{ServiceBus, ServiceClient};
+use crate::service::protocol::{DeviceInfo, DeviceReading, ProfileInfo};
+use crate::settings::ConsoleSettings;
+use crate::smbus::{Property, SmbusError};
+use crate::smbus::drivers::{self, DeviceKind, SensorReading, SettingKind};
+use crate::smbus::drivers::eeprom::Eeprom;
+use crate::smbus::dump::{DumpMode, RegisterBrowser};
+use crate::smbus::safety::{GuardedBus, WritePolicy, SPD_EEPROM_RANGE};
 
-#[com_class(clsid = "d39d9c35-6106-4735-b944-7e929d607000", IComponentData)]
+#[com_class(clsid = "d39d9c35-6106-4735-b944-7e929d607000", IComponentData, IExtendContextMenu, IExtendPropertySheet, IExtendPropertySheet2, IPersistStream)]
 #[derive(Debug)]
 pub struct MMCSnapIn {
     console: Option<ComRc<dyn IConsole2>>,
     console_namespace: Option<ComRc<dyn IConsoleNamespace>>,
-    pub nodes: HashMap<isize, ComBox<Node>>,
+    pub registry: NodeRegistry,
+    // Data objects handed to MMC, one per registered node
+    pub nodes: HashMap<NodeId, ComBox<Node>>,
     //_components: Vec<ComBox<MMCSnapInComponent>>,
-    //nodes: HashMap<isize, ComRc<dyn IDataObject>>,
-    next_cookie: isize,
+    // The bus service's connection. Its raw transactions are only for the
+    // register browser and EEPROM images, which work on a device's bytes;
+    // everything else is asked of the service.
+    service: Option<ServiceBus>,
+    pub write_policy: WritePolicy,
+    registers: HashMap<u8, RegisterBrowser>,
+    // The devices the service identified
+    devices: HashMap<u8, DeviceInfo>,
+    // Each device's properties as of the last time the result pane read them
+    readings: HashMap<u8, Vec<Property>>,
+    // Readings the subscription sent that no view has shown yet
+    fresh: HashMap<u8, DeviceReading>,
+    // How each device fared the last time it was read, for its icon
+    states: HashMap<u8, IconState>,
+    // What devices read over time. Kept across rescans, since the same
+    // address is the same device, but not across connections.
+    history: History,
+    // What the console file remembers, including the names the user gave
+    // devices, which outlive rescans
+    settings: ConsoleSettings,
+    // Whether settings changed since the console file was last saved
+    dirty: bool,
+    // The service's profiles
+    profiles: Vec<ProfileInfo>,
+    poller: Option<Poller>,
+    // Set while the Add Snap-in wizard has our root's data object, so its
+    // property pages are the connect wizard
+    in_snapin_manager: bool,
+    wizard_choice: WizardChoice,
 }
 
-// Impl'd because default Default implementation makes next_cookie = 0.
+// Impl'd because the registry needs the root node's label.
 impl Default for MMCSnapIn {
     fn default() -> Self {
         MMCSnapIn {
             console: None,
             console_namespace: None,
-            nodes: HashMap::<isize, ComBox<Node>>::default(),
+            registry: NodeRegistry::new("SMBus Snap-in"),
+            nodes: HashMap::new(),
             //_components: Vec::new(),
-            next_cookie: 1,
+            service: None,
+            write_policy: WritePolicy::default(),
+            registers: HashMap::new(),
+            devices: HashMap::new(),
+            readings: HashMap::new(),
+            fresh: HashMap::new(),
+            states: HashMap::new(),
+            history: History::default(),
+            settings: ConsoleSettings::default(),
+            dirty: false,
+            profiles: Vec::new(),
+            poller: None,
+            in_snapin_manager: false,
+            wizard_choice: WizardChoice::default(),
         }
     }
 }
 
 impl MMCSnapIn {
-    fn add_node(&mut self, name: &str) {
-        let new_node = Node::new(
-            self as *const _,
-            name.to_owned(),
-            NodeType::Folder,
-        );
+    // Registers a node under `parent` and creates its data object
+    fn add_node(&mut self, parent: NodeId, label: &str, kind: NodeKind) -> Option<NodeId> {
+        let id = match self.registry.insert(parent, label, kind) {
+            Some(id) => id,
+            None => {
+                log::error!("Can't add \"{}\": parent {:?} doesn't exist", label, parent);
+                return None;
+            }
+        };
+        
+        self.nodes.insert(id, ComBox::new(Node::new(self as *const _, id)));
+        Some(id)
+    }
+    
+    // Removes a node and everything below it, along with their data objects
+    // and scope items
+    pub fn remove_node(&mut self, id: NodeId) {
+        if let (Some(node), Some(consolens)) = (self.nodes.get(&id), &self.console_namespace) {
+            if node.hscopeitem.0 != 0 {
+                if let Err(e) = consolens.delete_item(node.hscopeitem, 1) {
+                    log::error!("IConsoleNamespace::DeleteItem() error: {}", e);
+                }
+            }
+        }
+        
+        for removed in self.registry.remove(id) {
+            self.nodes.remove(&removed);
+        }
+    }
+    
+    // Fills in the children of a node that finds them lazily. Only does any
+    // work on the node's first expansion.
+    fn discover_children(&mut self, id: NodeId) {
+        if !self.registry.begin_discovery(id) {
+            return;
+        }
+        
+        // The root's children are the devices a scan finds, so this is
+        // when the bus is first opened
+        if let Some(NodeKind::Root) = self.registry.get(id).map(|node| node.kind) {
+            self.connect();
+        }
+    }
+    
+    // Inserts the children of `id` that aren't in the scope pane yet under
+    // `item`, the node's own scope item
+    fn insert_scope_items(&mut self, id: NodeId, item: HSCOPEITEM) {
+        let consolens = match &self.console_namespace {
+            Some(consolens) => consolens,
+            None => {
+                log::error!("No IConsoleNamespace to insert items into");
+                return;
+            }
+        };
+        
+        for child in self.registry.children(id) {
+            let model = match self.registry.get(*child) {
+                Some(model) => model,
+                None => continue,
+            };
+            let (image, open_image) = self.node_images(*child);
+            let node = match self.nodes.get_mut(child) {
+                Some(node) => node,
+                None => continue,
+            };
+            
+            if node.hscopeitem.0 != 0 {
+                continue;
+            }
+            
+            let mut scopedataitem = SCOPEDATAITEM {
+                mask: 0x00002 | 0x00004 | 0x00008 | 0x00020 | 0x00040, // SDI_STR | SDI_IMAGE | SDI_OPENIMAGE | SDI_PARAM | SDI_CHILDREN
+                display_name: crate::interfaces::MMC_CALLBACK,
+                image,
+                open_image,
+                state: 0,
+                // Only used as a flag for whether to draw the expand button
+                children: if model.may_have_children() { 1 } else { 0 },
+                lparam: LPARAM(child.cookie()),
+                relative_id: item,
+                id: HSCOPEITEM(0),
+            };
+            
+            match consolens.insert_item((&mut scopedataitem) as *mut _) {
+                Ok(_) => {
+                    // Store the id back in the Node struct
+                    node.hscopeitem = scopedataitem.id;
+                }
+                Err(e) => {
+                    log::error!("IConsoleNamespace::InsertItem() error: {}", e)
+                }
+            }
+        }
+    }
+    
+    // Every device gets a Registers node, whether or not we know what it
+    // is, and the ones that read anything that changes a History node
+    fn add_device_node(&mut self, addr: u8, name: &str, kind: DeviceKind) {
+        let label = match self.settings.aliases.get(&addr) {
+            Some(alias) => alias.clone(),
+            None => format!("{} ({:#04x})", name, addr),
+        };
+        let device = self.add_node(NodeId::ROOT, &label, NodeKind::Device(addr, kind));
+        if let Some(device) = device {
+            self.add_node(device, "Registers", NodeKind::Registers(addr));
+            self.registers.insert(addr, RegisterBrowser::new(addr));
+            if history::keeps_history(kind) {
+                self.add_node(device, "History", NodeKind::History(addr));
+            }
+        }
+    }
+    
+    fn scan_bus(&mut self) {
+        let client = match self.service.as_mut() {
+            Some(service) => service.client(),
+            None => {
+                log::info!("No SMBus service available, skipping scan");
+                return;
+            }
+        };
+        
+        let mut found = match client.scan(self.settings.scan_range.clone()) {
+            Ok(found) => found,
+            Err(e) => {
+                log::error!("Scanning the bus failed: {}", e);
+                Vec::new()
+            }
+        };
+        // Devices added by hand are shown even if they ignore the scan's
+        // probes
+        for addr in &self.settings.manual_devices {
+            if !found.iter().any(|device| device.addr == *addr) {
+                found.push(identify(client, *addr));
+            }
+        }
+        
+        log::info!("Scan found {} device(s)", found.len());
+        for device in found {
+            self.add_found_device(device);
+        }
+        self.subscribe();
+    }
+    
+    fn add_found_device(&mut self, device: DeviceInfo) {
+        self.add_device_node(device.addr, &device.name, device.kind);
+        if device.identified {
+            self.devices.insert(device.addr, device);
+        }
+    }
+    
+    // Shows a device the scan doesn't find and remembers it, so later scans
+    // show it too
+    fn add_manual_device(&mut self, addr: u8) {
+        let shown = self.registry.children(NodeId::ROOT).iter()
+            .any(|child| matches!(self.registry.get(*child).map(|node| node.kind), Some(NodeKind::Device(device, _)) if device == addr));
+        if shown || self.settings.manual_devices.contains(&addr) {
+            self.message_box(&format!("The device at {:#04x} is already shown.", addr));
+            return;
+        }
+        
+        log::info!("Adding the device at {:#04x} by hand", addr);
+        self.settings.manual_devices.push(addr);
+        self.dirty = true;
+        
+        // Before the root is first expanded, the scan picks it up
+        if !self.registry.root().is_discovered() {
+            return;
+        }
+        let device = match self.service.as_mut() {
+            Some(service) => identify(service.client(), addr),
+            None => unknown_device(addr),
+        };
+        self.add_found_device(device);
+        self.subscribe();
+        if let Some(root) = self.nodes.get(&NodeId::ROOT) {
+            let item = root.hscopeitem;
+            if item.0 != 0 {
+                self.insert_scope_items(NodeId::ROOT, item);
+            }
+        }
+    }
+    
+    pub fn node_for_scope_item(&self, item: HSCOPEITEM) -> Option<NodeId> {
+        self.nodes.iter()
+            .find(|(_, node)| node.hscopeitem.0 == item.0)
+            .map(|(id, _)| *id)
+    }
+    
+    pub fn data_object(&self, id: NodeId) -> Option<ComRc<dyn IDataObject>> {
+        self.nodes.get(&id).map(ComRc::from)
+    }
+    
+    // Indexes of a node's icon and its expanded icon in the image strips,
+    // badged with how the device fared when it was last read
+    pub fn node_images(&self, id: NodeId) -> (i32, i32) {
+        let kind = match self.registry.get(id) {
+            Some(node) => node.kind,
+            None => return (0, 0),
+        };
+        let state = match kind {
+            NodeKind::Device(addr, _) => self.states.get(&addr).copied().unwrap_or_default(),
+            _ => IconState::Normal,
+        };
+        let (icon, open_icon) = Icon::for_kind(kind);
+        (Image::new(icon, state).index(), Image::new(open_icon, state).index())
+    }
+    
+    // Remembers a device's state and redraws its scope item if that
+    // changes its icon
+    fn set_state(&mut self, id: NodeId, addr: u8, state: IconState) {
+        if self.states.insert(addr, state).unwrap_or_default() == state {
+            return;
+        }
+        
+        let (image, open_image) = self.node_images(id);
+        let (consolens, node) = match (&self.console_namespace, self.nodes.get(&id)) {
+            (Some(consolens), Some(node)) if node.hscopeitem.0 != 0 => (consolens, node),
+            _ => return,
+        };
+        let mut scopedataitem = SCOPEDATAITEM {
+            mask: 0x00004 | 0x00008, // SDI_IMAGE | SDI_OPENIMAGE
+            display_name: PCWSTR::null(),
+            image,
+            open_image,
+            state: 0,
+            children: 0,
+            lparam: LPARAM(0),
+            relative_id: HSCOPEITEM(0),
+            id: node.hscopeitem,
+        };
+        if let Err(e) = consolens.set_item((&mut scopedataitem) as *mut _) {
+            log::error!("IConsoleNamespace::SetItem() error: {}", e);
+        }
+    }
+    
+    // Reads what the result pane should show for a node, one cell per
+    // column of its column set along with the row's image: a fresh hex
+    // dump for Registers nodes (with changes since the previous read
+    // marked), the decoded SPD for memory modules and what the service
+    // reads for other device nodes.
+    pub fn read_result_rows(&mut self, id: NodeId) -> Option<Vec<(Vec<String>, Image)>> {
+        let kind = self.registry.get(id)?.kind;
+        // History is shown whether or not there's a bus to add to it
+        if let NodeKind::History(addr) = kind {
+            let icon = |unit: Option<&str>| Icon::for_unit(unit).unwrap_or(Icon::Registers);
+            return Some(self.history.rows(addr, columns::HISTORY_ROWS).iter()
+                .map(|row| (columns::history_row(row), Image::new(icon(row.unit.as_deref()), IconState::Normal)))
+                .collect());
+        }
+        let service = self.service.as_mut()?;
+        
+        match kind {
+            NodeKind::Registers(addr) => {
+                let browser = self.registers.entry(addr).or_insert_with(|| RegisterBrowser::new(addr));
+                browser.refresh(service);
+                let image = Image::new(Icon::Registers, IconState::Normal);
+                Some(browser.rows().iter().map(|row| (columns::register_row(row), image)).collect())
+            }
+            // Memory modules are one row, decoded from their SPD
+            NodeKind::Device(addr, _) if columns::for_kind(kind) == &columns::MEMORY => {
+                let (icon, _) = Icon::for_kind(kind);
+                match service.client().read(addr) {
+                    Ok(reading) => {
+                        let row = columns::memory_row(addr, &reading.properties);
+                        self.readings.insert(addr, reading.properties);
+                        self.set_state(id, addr, IconState::Normal);
+                        Some(vec![(row, Image::new(icon, IconState::Normal))])
+                    }
+                    Err(e) => {
+                        log::error!("Reading the SPD at {:#04x} failed: {}", addr, e);
+                        self.set_state(id, addr, IconState::Offline);
+                        Some(vec![(vec![format!("Read failed: {}", e)], Image::new(icon, IconState::Offline))])
+                    }
+                }
+            }
+            NodeKind::Device(addr, _) => {
+                let name = self.devices.get(&addr)?.name.clone();
+                let (device_icon, _) = Icon::for_kind(kind);
+                let icon = |unit: Option<&str>| Icon::for_unit(unit).unwrap_or(device_icon);
+                
+                // What the subscription sent is already in the history
+                let (reading, recorded) = match self.fresh.remove(&addr) {
+                    Some(reading) => (Ok(reading), true),
+                    None => (service.client().read(addr), false),
+                };
+                match reading.map(|reading| shown(reading, kind)) {
+                    Ok((properties, sensors)) => {
+                        if !recorded {
+                            self.history.record_properties(addr, &properties, SystemTime::now());
+                        }
+                        let summary = |name: &str| self.history.summary(addr, name);
+                        let rows: Vec<_> = match columns::for_kind(kind) == &columns::SENSORS {
+                            true => sensors.iter()
+                                .map(|sensor| {
+                                    let state = IconState::of_sensor(sensor.status());
+                                    let row = columns::sensor_row(sensor, summary(&sensor.name).as_ref());
+                                    (row, Image::new(icon(sensor.unit.as_deref()), state))
+                                })
+                                .collect(),
+                            false => properties.iter()
+                                .map(|property| {
+                                    let row = columns::property_row(property, summary(&property.name).as_ref());
+                                    (row, Image::new(icon(property.unit.as_deref()), IconState::Normal))
+                                })
+                                .collect(),
+                        };
+                        let state = IconState::worst(rows.iter().map(|(_, image)| image.state));
+                        self.readings.insert(addr, properties);
+                        self.set_state(id, addr, state);
+                        Some(rows)
+                    }
+                    Err(e) => {
+                        log::error!("Reading {} at {:#04x} failed: {}", name, addr, e);
+                        let row = (vec![format!("Read failed: {}", e)], Image::new(device_icon, IconState::Offline));
+                        self.set_state(id, addr, IconState::Offline);
+                        Some(vec![row])
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+    
+    // Takes the readings the subscription sent since the last poll into
+    // the history, keeping each device's latest for its views
+    fn take_events(&mut self) {
+        let client = match self.service.as_mut() {
+            Some(service) => service.client(),
+            None => return,
+        };
+        let now = SystemTime::now();
+        
+        while let Some(event) = client.next_event(Duration::ZERO) {
+            let kind = match self.devices.get(&event.addr) {
+                Some(device) => NodeKind::Device(event.addr, device.kind),
+                None => continue,
+            };
+            match event.reading {
+                Ok(reading) => {
+                    let (properties, _) = shown(reading.clone(), kind);
+                    self.history.record_properties(event.addr, &properties, now);
+                    self.fresh.insert(event.addr, reading);
+                }
+                Err(e) => {
+                    log::debug!("Reading the device at {:#04x} for its history failed: {}", event.addr, e);
+                    self.fresh.remove(&event.addr);
+                }
+            }
+        }
+    }
+    
+    // Has the service read the devices that keep a history every poll
+    // interval and send what they read, or stop while polling is paused
+    fn subscribe(&mut self) {
+        let client = match self.service.as_mut() {
+            Some(service) => service.client(),
+            None => return,
+        };
+        let mut addrs: Vec<u8> = self.devices.values()
+            .filter(|device| history::keeps_history(device.kind))
+            .map(|device| device.addr)
+            .collect();
+        addrs.sort_unstable();
+        
+        self.fresh.clear();
+        // No addresses would mean every device
+        let result = match self.settings.polling_paused || addrs.is_empty() {
+            true => client.unsubscribe(),
+            false => client.subscribe(&addrs, self.settings.poll_interval_ms),
+        };
+        if let Err(e) = result {
+            log::error!("Subscribing to {:02x?} failed: {}", addrs, e);
+        }
+    }
+    
+    // Saves the history of the device at `addr`, or of every device, to a
+    // CSV file the user picks
+    fn export_history(&self, addr: Option<u8>) {
+        let name = match addr {
+            Some(addr) => format!("smbus-history-{:02x}.csv", addr),
+            None => "smbus-history.csv".to_owned(),
+        };
+        let path = match filedialog::choose_save(self.main_window(), "Export History", &name, &filedialog::CSV) {
+            Some(path) => path,
+            None => return,
+        };
+        log::info!("Exporting the history to {}", path.display());
+        // With a byte order mark, or Excel takes the units for ANSI
+        let csv = format!("\u{feff}{}", self.history.to_csv(addr));
+        if let Err(e) = std::fs::write(&path, csv) {
+            log::error!("Couldn't write {}: {}", path.display(), e);
+            self.message_box(&format!("The history wasn't saved to {}:\n\n{}", path.display(), e));
+        }
+    }
+    
+    // Saves the EEPROM at `addr` to an image file the user picks. A memory
+    // module's is offered as its SPD image.
+    fn backup_eeprom(&mut self, addr: u8, spd: bool) {
+        let (title, name) = match spd {
+            true => ("Save SPD Image", format!("spd-{:02x}.eep", addr)),
+            false => ("Back Up EEPROM", format!("eeprom-{:02x}.eep", addr)),
+        };
+        let path = match filedialog::choose_save(self.main_window(), title, &name, &filedialog::EEPROM_IMAGE) {
+            Some(path) => path,
+            None => return,
+        };
+        let result = match self.service.as_mut() {
+            Some(service) => match Eeprom::detect(service, addr) {
+                Some(part) => part.backup(service, &path).map(|_| ()).map_err(|e| e.to_string()),
+                None => Err(format!("There's no EEPROM at {:#04x} any more", addr)),
+            },
+            None => return,
+        };
+        if let Err(e) = result {
+            log::error!("Backing up the EEPROM at {:#04x} failed: {}", addr, e);
+            self.message_box(&format!("The EEPROM wasn't saved to {}:\n\n{}", path.display(), e));
+        }
+    }
+    
+    // Writes an image file the user picks back to the EEPROM at `addr`. The
+    // writes go through the write-safety layer; a protected part, such as
+    // an SPD, is unlocked for this restore only once the user agrees.
+    fn restore_eeprom(&mut self, addr: u8) {
+        let path = match filedialog::choose_open(self.main_window(), "Restore EEPROM", &filedialog::EEPROM_IMAGE) {
+            Some(path) => path,
+            None => return,
+        };
+        let part = match self.service.as_mut() {
+            Some(service) => Eeprom::detect(service, addr),
+            None => return,
+        };
+        let mut policy = self.write_policy.clone();
+        if let (Some(part), true) = (&part, policy.writes_enabled) {
+            let locked: Vec<u8> = part.write_addresses().into_iter()
+                .filter(|addr| policy.check(*addr).is_some())
+                .collect();
+            if !locked.is_empty() {
+                let addrs: Vec<String> = locked.iter().map(|addr| format!("{:#04x}", addr)).collect();
+                let question = format!(
+                    "The {} at {:#04x} is write protected. If this is a memory module's SPD, a wrong image \
+                     can stop the computer from starting.\n\nUnlock {} for this restore and write {}?",
+                    part.model, addr, addrs.join(", "), path.display(),
+                );
+                if !self.confirm(&question) {
+                    return;
+                }
+                policy.unlocked.extend(locked);
+            }
+        }
+        let result = match (self.service.as_mut(), part) {
+            (Some(service), Some(part)) => {
+                let mut guarded = GuardedBus::new(service, &policy);
+                part.restore(&mut guarded, &path, !policy.dry_run).map_err(|e| e.to_string())
+            }
+            (Some(_), None) => Err(format!("There's no EEPROM at {:#04x} any more", addr)),
+            (None, _) => return,
+        };
+        if let Err(e) = result {
+            log::error!("Restoring the EEPROM at {:#04x} from {} failed: {}", addr, path.display(), e);
+            self.message_box(&format!("The EEPROM wasn't restored from {}:\n\n{}", path.display(), e));
+        }
+    }
+    
+    pub fn action_state(&self, id: NodeId) -> ActionState {
+        let kind = self.registry.get(id).map(|node| node.kind);
+        let word_mode = match kind {
+            Some(NodeKind::Registers(addr)) => self.registers.get(&addr)
+                .map(|browser| browser.mode() == DumpMode::Word)
+                .unwrap_or(false),
+            _ => false,
+        };
+        let stale = match kind {
+            Some(NodeKind::Device(addr, _)) => self.states.get(&addr) == Some(&IconState::Offline),
+            _ => false,
+        };
+        let has_history = match kind {
+            Some(NodeKind::Root) => !self.history.is_empty(),
+            Some(NodeKind::Device(addr, _) | NodeKind::History(addr)) => self.history.last_recorded(addr).is_some(),
+            _ => false,
+        };
+        
+        let spd = matches!(kind, Some(NodeKind::Device(addr, DeviceKind::Eeprom)) if SPD_EEPROM_RANGE.contains(&addr));
+        let manual = matches!(kind, Some(NodeKind::Device(addr, _)) if self.settings.manual_devices.contains(&addr));
+        
+        ActionState {
+            has_bus: self.service.is_some(),
+            writes_enabled: self.write_policy.writes_enabled,
+            word_mode,
+            stale,
+            polling_paused: self.settings.polling_paused,
+            dry_run: self.write_policy.dry_run,
+            has_profiles: !self.profiles.is_empty(),
+            has_history,
+            spd,
+            manual,
+        }
+    }
+    
+    pub fn node_verbs(&self, id: NodeId) -> Verbs {
+        match self.registry.get(id) {
+            Some(node) => actions::verbs(node.kind, &self.action_state(id)),
+            None => Verbs::default(),
+        }
+    }
+    
+    pub fn node_actions(&self, id: NodeId) -> Vec<Action> {
+        match self.registry.get(id) {
+            Some(node) => actions::actions(node.kind, &self.action_state(id)),
+            None => Vec::new(),
+        }
+    }
+    
+    // Runs a context menu command for a node. `dataobject` is the node's, and
+    // is used to tell every view showing the node to refresh.
+    pub fn run_action(&mut self, id: NodeId, action: ActionId, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let kind = match self.registry.get(id) {
+            Some(node) => node.kind,
+            None => return Err(ComError::E_INVALIDARG),
+        };
+        match actions::find(kind, &self.action_state(id), action) {
+            Some(action) if action.enabled => {}
+            _ => {
+                log::error!("{:?} isn't available for {:?}", action, id);
+                return Err(ComError::E_INVALIDARG);
+            }
+        }
+        
+        log::info!("Running {:?} on {:?}", action, id);
+        match action {
+            ActionId::RescanBus => self.rescan(),
+            ActionId::AllowWrites => {
+                self.write_policy.writes_enabled = !self.write_policy.writes_enabled;
+                return Ok(());
+            }
+            ActionId::DryRun => {
+                self.write_policy.dry_run = !self.write_policy.dry_run;
+                return Ok(());
+            }
+            ActionId::PausePolling => {
+                self.settings.polling_paused = !self.settings.polling_paused;
+                self.dirty = true;
+                self.subscribe();
+                return Ok(());
+            }
+            ActionId::ApplyProfile => {
+                match self.choose_profile() {
+                    Some(index) => self.apply_profile(index),
+                    None => return Ok(()),
+                }
+            }
+            ActionId::ConnectTo => {
+                if let Some(connection) = self.choose_connection() {
+                    self.set_connection(connection);
+                }
+                return Ok(());
+            }
+            ActionId::AddDevice => {
+                if let Some(addr) = adddevice::run(self.main_window()) {
+                    self.add_manual_device(addr);
+                }
+                return Ok(());
+            }
+            ActionId::ExportHistory => {
+                let addr = match kind {
+                    NodeKind::History(addr) => Some(addr),
+                    _ => None,
+                };
+                self.export_history(addr);
+                return Ok(());
+            }
+            ActionId::EditRegister => return self.edit_register(id, None),
+            ActionId::BackupEeprom | ActionId::SaveSpdImage => {
+                if let NodeKind::Device(addr, _) = kind {
+                    self.backup_eeprom(addr, action == ActionId::SaveSpdImage);
+                }
+                return Ok(());
+            }
+            ActionId::SetFanMode => {
+                if let NodeKind::Device(addr, _) = kind {
+                    self.set_fan_mode(addr);
+                }
+            }
+            ActionId::RestoreEeprom => {
+                if let NodeKind::Device(addr, _) = kind {
+                    self.restore_eeprom(addr);
+                }
+            }
+            ActionId::ReadNow => {}
+            ActionId::WordMode => {
+                if let NodeKind::Registers(addr) = kind {
+                    let browser = self.registers.entry(addr).or_insert_with(|| RegisterBrowser::new(addr));
+                    let mode = match browser.mode() {
+                        DumpMode::Byte => DumpMode::Word,
+                        DumpMode::Word => DumpMode::Byte,
+                    };
+                    browser.set_mode(mode);
+                }
+            }
+        }
+        
+        self.refresh_views(id, dataobject)
+    }
+    
+    // Runs the connect wizard on its own, starting from the current connection
+    fn choose_connection(&self) -> Option<Connection> {
+        let _suspend = poll::suspend();
+        wizard::run(self.main_window(), self.settings.connection.clone())
+    }
+    
+    // MMC's main window, to own our dialogs and menus
+    fn main_window(&self) -> HWND {
+        match self.console.as_ref().map(|console| console.get_main_window()) {
+            Some(Ok(hwnd)) => hwnd.0,
+            _ => HWND(0),
+        }
+    }
+    
+    // Lets the user pick a profile from a menu at the cursor, which is over
+    // the context menu item or the toolbar's menu button that asked
+    fn choose_profile(&self) -> Option<usize> {
+        let names: Vec<&str> = self.profiles.iter().map(|profile| profile.name.as_str()).collect();
+        self.choose_from_menu(&names, None)
+    }
+    
+    // Pops up a menu of `items` at the cursor, with `checked` ticked, and
+    // returns the index of the one picked
+    fn choose_from_menu(&self, items: &[&str], checked: Option<usize>) -> Option<usize> {
+        let _suspend = poll::suspend();
+        let owner = self.main_window();
+        
+        unsafe {
+            let menu = match CreatePopupMenu() {
+                Ok(menu) => menu,
+                Err(e) => {
+                    log::error!("CreatePopupMenu() error: {}", e);
+                    return None;
+                }
+            };
+            // Item ids are the item's index plus one, as zero means nothing
+            // was picked
+            for (index, item) in items.iter().enumerate() {
+                let name: Vec<u16> = item.encode_utf16().chain(std::iter::once(0)).collect();
+                let flags = match checked == Some(index) {
+                    true => MF_STRING | MF_CHECKED,
+                    false => MF_STRING,
+                };
+                AppendMenuW(menu, flags, index + 1, PCWSTR::from_raw(name.as_ptr()));
+            }
+            
+            let mut cursor = POINT::default();
+            GetCursorPos(&mut cursor);
+            let picked = TrackPopupMenu(menu, TPM_RETURNCMD | TPM_NONOTIFY, cursor.x, cursor.y, 0, owner, None);
+            DestroyMenu(menu);
+            
+            match picked.0 {
+                0 => None,
+                picked => Some(picked as usize - 1),
+            }
+        }
+    }
+    
+    // Offers the fan controller's modes in a menu, the current one ticked,
+    // and has the service write the one picked
+    fn set_fan_mode(&mut self, addr: u8) {
+        let (name, client) = match (self.devices.get(&addr), self.service.as_mut()) {
+            (Some(device), Some(service)) => (device.name.clone(), service.client()),
+            _ => return,
+        };
+        let setting = match client.settings(addr) {
+            Ok(settings) => settings.into_iter().find(|setting| setting.key == drivers::FAN_MODE_SETTING),
+            Err(e) => {
+                let text = format!("The fan mode of the {} couldn't be read:\n\n{}", name, e);
+                self.message_box(&text);
+                return;
+            }
+        };
+        let choices = match setting.as_ref().map(|setting| &setting.kind) {
+            Some(SettingKind::Choice(choices)) => choices.clone(),
+            _ => {
+                let text = format!("The {} can't switch fan modes.", name);
+                self.message_box(&text);
+                return;
+            }
+        };
+        
+        let current = setting.map(|setting| setting.value);
+        let names: Vec<&str> = choices.iter().map(|(name, _)| name.as_str()).collect();
+        let checked = choices.iter().position(|(_, value)| Some(*value) == current);
+        let (name, value) = match self.choose_from_menu(&names, checked) {
+            Some(index) => choices[index].clone(),
+            None => return,
+        };
+        
+        if let Err(e) = self.write_setting(addr, drivers::FAN_MODE_SETTING, value) {
+            log::error!("Setting the fan at {:#04x} to {} failed: {}", addr, name, e);
+            self.message_box(&format!("The fan wasn't set to {}:\n\n{}", name, e));
+        }
+    }
+    
+    // Has the service write one of a device's settings, as a dry run while
+    // that's on. The snap-in's own policy is checked first, like a
+    // GuardedBus would.
+    fn write_setting(&mut self, addr: u8, key: &str, value: f64) -> Result<(), SmbusError> {
+        if let Some(reason) = self.write_policy.check(addr) {
+            return Err(SmbusError::Blocked { addr, reason });
+        }
+        let service = self.service.as_mut()
+            .ok_or(SmbusError::Unsupported("no bus transport"))?;
+        service.client().write(addr, key, value, self.write_policy.dry_run).map(|_| ())
+    }
+    
+    // Has the service write a profile's settings to the devices it names.
+    // Everything they showed is read again afterwards.
+    fn apply_profile(&mut self, index: usize) {
+        let name = match self.profiles.get(index) {
+            Some(profile) => profile.name.clone(),
+            None => return,
+        };
+        if !self.write_policy.writes_enabled {
+            self.message_box(&format!("\"{}\" wasn't applied: writes are disabled.", name));
+            return;
+        }
+        let client = match self.service.as_mut() {
+            Some(service) => service.client(),
+            None => return,
+        };
+        
+        match client.apply_profile(&name, self.write_policy.dry_run) {
+            Ok(failures) if failures.is_empty() => {}
+            Ok(failures) => {
+                let failures: Vec<String> = failures.iter()
+                    .map(|failure| format!("{} at {:#04x}: {}", failure.setting.key, failure.setting.addr, failure.error))
+                    .collect();
+                self.message_box(&format!("Some of \"{}\"'s settings weren't applied:\n\n{}", name, failures.join("\n")));
+            }
+            Err(e) => {
+                log::error!("Applying \"{}\" failed: {}", name, e);
+                self.message_box(&format!("\"{}\" wasn't applied:\n\n{}", name, e));
+            }
+        }
+        self.readings.clear();
+        self.fresh.clear();
+    }
+    
+    // Called by the poller every poll interval: takes what the
+    // subscription sent into the history, has every view showing a device
+    // show it or read the device again, then has history views show what
+    // was added
+    pub fn poll(&mut self) {
+        if self.settings.polling_paused || self.service.is_none() {
+            return;
+        }
+        self.take_events();
+        
+        let devices: Vec<NodeId> = self.registry.children(NodeId::ROOT).iter()
+            .copied()
+            .filter(|child| matches!(self.registry.get(*child).map(|node| node.kind), Some(NodeKind::Device(..))))
+            .collect();
+        let histories: Vec<NodeId> = devices.iter()
+            .flat_map(|device| self.registry.children(*device).iter().copied())
+            .filter(|child| matches!(self.registry.get(*child).map(|node| node.kind), Some(NodeKind::History(_))))
+            .collect();
+        
+        self.refresh_all(&devices);
+        self.refresh_all(&histories);
+    }
+    
+    fn refresh_all(&self, ids: &[NodeId]) {
+        for id in ids {
+            if let Some(dataobject) = self.data_object(*id) {
+                if let Err(e) = self.refresh_views(*id, &dataobject) {
+                    log::error!("Polling {:?} failed: {}", id, e);
+                }
+            }
+        }
+    }
+    
+    // Asks every component showing `id` to read it again
+    fn refresh_views(&self, id: NodeId, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        match &self.console {
+            Some(console) => console.update_all_views(dataobject, id.cookie(), 0),
+            None => Ok(()),
+        }
+    }
+    
+    // Answers MMCN_REFRESH: the root scans the bus again, everything else
+    // is read again
+    pub fn refresh(&mut self, id: NodeId) -> ComResult<()> {
+        if !self.node_verbs(id).allows(Verb::Refresh) {
+            return Err(ComError::E_INVALIDARG);
+        }
+        
+        log::info!("Refreshing {:?}", id);
+        if id == NodeId::ROOT {
+            self.rescan();
+        }
+        match self.data_object(id) {
+            Some(dataobject) => self.refresh_views(id, &dataobject),
+            None => Ok(()),
+        }
+    }
+    
+    // Answers MMCN_RENAME. Devices take the new name as an alias, or go
+    // back to their own name if it's empty.
+    pub fn rename(&mut self, id: NodeId, name: &str) -> ComResult<()> {
+        if !self.node_verbs(id).allows(Verb::Rename) {
+            return Err(ComError::E_INVALIDARG);
+        }
+        
+        match self.registry.get(id).map(|node| node.kind) {
+            Some(NodeKind::Device(addr, _)) => {
+                let name = name.trim();
+                let label = match name.is_empty() {
+                    true => {
+                        self.settings.aliases.remove(&addr);
+                        let device = self.devices.get(&addr).map(|device| device.name.as_str());
+                        format!("{} ({:#04x})", device.unwrap_or("Unknown device"), addr)
+                    }
+                    false => {
+                        self.settings.aliases.insert(addr, name.to_owned());
+                        name.to_owned()
+                    }
+                };
+                log::info!("Renaming the device at {:#04x} to \"{}\"", addr, label);
+                self.dirty = true;
+                if let Some(node) = self.registry.get_mut(id) {
+                    node.label = label;
+                }
+                // MMC only takes the new name as typed, so have it ask again
+                // when the alias was cleared
+                match name.is_empty() {
+                    true => {
+                        self.update_scope_item(id);
+                        Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }))
+                    }
+                    false => Ok(()),
+                }
+            }
+            _ => Err(ComError::E_INVALIDARG),
+        }
+    }
+    
+    // Answers MMCN_DELETE by forgetting a device that stopped answering or
+    // was added by hand
+    pub fn delete(&mut self, id: NodeId) -> ComResult<()> {
+        if !self.node_verbs(id).allows(Verb::Delete) {
+            return Err(ComError::E_INVALIDARG);
+        }
+        
+        if let Some(NodeKind::Device(addr, _)) = self.registry.get(id).map(|node| node.kind) {
+            log::info!("Removing the device at {:#04x}", addr);
+            self.devices.remove(&addr);
+            self.registers.remove(&addr);
+            self.readings.remove(&addr);
+            self.states.remove(&addr);
+            self.history.forget(addr);
+            // Or the next scan would add it back
+            if self.settings.manual_devices.contains(&addr) {
+                self.settings.manual_devices.retain(|manual| *manual != addr);
+                self.dirty = true;
+            }
+            self.subscribe();
+        }
+        self.remove_node(id);
+        Ok(())
+    }
+    
+    // MMCN_REFRESH, MMCN_RENAME and MMCN_DELETE, which come to
+    // IComponentData or IComponent depending on where the item was
+    // selected. Returns None for any other event.
+    pub fn notify_verb(&mut self, event: &MmcNotifyType, dataobject: Option<&ComItf<dyn IDataObject>>, arg: i64, param: i64) -> Option<ComResult<()>> {
+        if !matches!(event, MmcNotifyType::Refresh | MmcNotifyType::Rename | MmcNotifyType::Delete) {
+            return None;
+        }
+        let id = match dataobject.and_then(node_id_of) {
+            Some(id) => id,
+            None => return Some(Err(ComError::E_INVALIDARG)),
+        };
+        
+        Some(match event {
+            MmcNotifyType::Refresh => self.refresh(id),
+            // arg is 0 when MMC asks whether the item can be renamed, and
+            // 1 with the new name in param once the user has typed it
+            MmcNotifyType::Rename if arg == 0 => match self.node_verbs(id).allows(Verb::Rename) {
+                true => Ok(()),
+                false => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+            },
+            MmcNotifyType::Rename => match unsafe { PCWSTR(param as *const u16).to_string() } {
+                Ok(name) => self.rename(id, &name),
+                Err(_) => Err(ComError::E_INVALIDARG),
+            },
+            _ => self.delete(id),
+        })
+    }
+    
+    // Has MMC ask for a scope item's text again
+    fn update_scope_item(&self, id: NodeId) {
+        let (consolens, node) = match (&self.console_namespace, self.nodes.get(&id)) {
+            (Some(consolens), Some(node)) if node.hscopeitem.0 != 0 => (consolens, node),
+            _ => return,
+        };
+        let mut scopedataitem = SCOPEDATAITEM {
+            mask: 0x00002, // SDI_STR
+            display_name: crate::interfaces::MMC_CALLBACK,
+            image: 0,
+            open_image: 0,
+            state: 0,
+            children: 0,
+            lparam: LPARAM(0),
+            relative_id: HSCOPEITEM(0),
+            id: node.hscopeitem,
+        };
+        if let Err(e) = consolens.set_item((&mut scopedataitem) as *mut _) {
+            log::error!("IConsoleNamespace::SetItem() error: {}", e);
+        }
+    }
+    
+    fn name_root(&mut self) {
+        let label = self.settings.connection.root_label();
+        if let Some(root) = self.registry.get_mut(NodeId::ROOT) {
+            root.label = label;
+        }
+        self.update_scope_item(NodeId::ROOT);
+    }
+    
+    // Names the root after the connection and connects to its service.
+    // Devices found on the old bus are replaced by the new bus's. Runs when
+    // the root is first expanded, by which time any console file has been
+    // loaded, and again whenever the connection changes.
+    fn connect(&mut self) {
+        self.name_root();
+        
+        self.history.clear();
+        self.profiles.clear();
+        self.service = match self.settings.connection.open_service("smbus-snapin") {
+            Ok(service) => Some(service),
+            Err(e) => {
+                log::error!("Couldn't open the bus for {:?}: {}", self.settings.connection, e);
+                None
+            }
+        };
+        if let Some(service) = self.service.as_mut() {
+            match service.client().profiles() {
+                Ok(profiles) => self.profiles = profiles,
+                Err(e) => log::error!("Couldn't list the service's profiles: {}", e),
+            }
+        }
+        if self.registry.root().is_discovered() {
+            self.rescan();
+        }
+    }
+    
+    // Picks up what the user chose in the connect wizard, once it's finished
+    fn take_wizard_choice(&mut self) {
+        let choice = match self.wizard_choice.lock() {
+            Ok(mut choice) => choice.take(),
+            Err(_) => None,
+        };
+        if let Some(connection) = choice {
+            self.set_connection(connection);
+        }
+    }
+    
+    pub fn set_connection(&mut self, connection: Connection) {
+        log::info!("Connecting to {:?}", connection);
+        self.settings.connection = connection;
+        self.dirty = true;
+        self.connect();
+    }
+    
+    // Forgets every device and scans the bus again
+    fn rescan(&mut self) {
+        let devices: Vec<NodeId> = self.registry.children(NodeId::ROOT).iter()
+            .copied()
+            .filter(|child| matches!(self.registry.get(*child).map(|node| node.kind), Some(NodeKind::Device(..))))
+            .collect();
+        for device in devices {
+            self.remove_node(device);
+        }
         
-        self.nodes.insert(self.next_cookie, ComBox::new(new_node));
+        self.devices.clear();
+        self.registers.clear();
+        self.readings.clear();
+        self.fresh.clear();
+        self.states.clear();
+        self.scan_bus();
         
-        self.next_cookie += 1;
+        if let Some(root) = self.nodes.get(&NodeId::ROOT) {
+            let item = root.hscopeitem;
+            if item.0 != 0 {
+                self.insert_scope_items(NodeId::ROOT, item);
+            }
+        }
+    }
+    
+    // What copying a node puts on the clipboard. Doesn't touch the bus, so
+    // devices show what was last read for the result pane.
+    pub fn node_properties(&self, id: NodeId) -> Vec<Property> {
+        let kind = match self.registry.get(id) {
+            Some(node) => node.kind,
+            None => return Vec::new(),
+        };
+        
+        match kind {
+            NodeKind::Device(addr, device) => {
+                let mut properties = vec![
+                    Property::text("Address", format!("{:#04x}", addr)),
+                    Property::text("Type", device.to_string()),
+                ];
+                if let Some(readings) = self.readings.get(&addr) {
+                    properties.extend(readings.iter().cloned());
+                }
+                properties
+            }
+            NodeKind::Registers(addr) => {
+                self.registers.get(&addr)
+                    .and_then(|browser| browser.current())
+                    .map(|dump| dump.properties())
+                    .unwrap_or_default()
+            }
+            NodeKind::History(addr) => self.history.summaries(addr),
+            NodeKind::Root | NodeKind::Folder => {
+                self.registry.children(id).iter()
+                    .filter_map(|child| self.registry.get(*child))
+                    .map(|child| Property::text(&child.label, child.description().unwrap_or_default()))
+                    .collect()
+            }
+        }
     }
     
+    // Lets the user write one of a Registers node's registers in a dialog,
+    // starting at the first register on result row `row` when one is
+    // picked. The write goes through the write-safety layer.
+    pub fn edit_register(&mut self, id: NodeId, row: Option<usize>) -> ComResult<()> {
+        let addr = match self.registry.get(id).map(|node| node.kind) {
+            Some(NodeKind::Registers(addr)) => addr,
+            _ => return Err(ComError::E_INVALIDARG),
+        };
+        match actions::find(NodeKind::Registers(addr), &self.action_state(id), ActionId::EditRegister) {
+            Some(action) if action.enabled => {}
+            _ => {
+                log::error!("Registers at {:#04x} can't be edited now", addr);
+                return Err(ComError::E_INVALIDARG);
+            }
+        }
+        
+        let browser = self.registers.entry(addr).or_insert_with(|| RegisterBrowser::new(addr));
+        let mode = browser.mode();
+        let values = browser.current().map(|dump| dump.values.clone()).unwrap_or_default();
+        let register = row.map(|row| (row * mode.per_row()).min(0xff) as u8).unwrap_or(0);
+        let (register, value) = match registeredit::run(self.main_window(), addr, mode, values, register) {
+            Some(write) => write,
+            None => return Ok(()),
+        };
+        
+        if let Err(e) = self.write_register(addr, register, value) {
+            log::error!("Writing {:#06x} to register {:#04x} at {:#04x} failed: {}", value, register, addr, e);
+            self.message_box(&format!("The register wasn't written:\n\n{}", e));
+        }
+        match self.data_object(id) {
+            Some(dataobject) => self.refresh_views(id, &dataobject),
+            None => Ok(()),
+        }
+    }
+    
+    fn write_register(&mut self, addr: u8, register: u8, value: u16) -> Result<(), SmbusError> {
+        let service = self.service.as_mut()
+            .ok_or(SmbusError::Unsupported("no bus transport"))?;
+        let browser = self.registers.entry(addr).or_insert_with(|| RegisterBrowser::new(addr));
+        
+        let mut guarded = GuardedBus::new(service, &self.write_policy);
+        browser.write(&mut guarded, register, value)
+    }
+    
+    // Asks the service what a device's Properties dialog shows. Without
+    // it, only what the snap-in already knows is shown.
+    pub fn device_sheet(&mut self, id: NodeId) -> Option<DeviceSheet> {
+        let (addr, kind) = match self.registry.get(id)?.kind {
+            NodeKind::Device(addr, kind) => (addr, kind),
+            _ => return None,
+        };
+        let identified = self.devices.contains_key(&addr);
+        let client = match self.service.as_mut() {
+            Some(service) => service.client(),
+            None => return Some(DeviceSheet::build(addr, kind, None, None)),
+        };
+        let identification = match client.identify(addr) {
+            Ok((_, properties)) => properties,
+            Err(e) => {
+                log::error!("Identifying the device at {:#04x} failed: {}", addr, e);
+                propsheet::identification(addr, kind, None, None)
+            }
+        };
+        let settings = identified.then(|| client.settings(addr));
+        Some(DeviceSheet::from_service(addr, &identification, settings))
+    }
+    
+    // Takes what the user applied on the root's property sheet as the
+    // console's settings
+    fn apply_console_change(&mut self, change: &PropertyChange) -> ComResult<()> {
+        match propsheet::history_config(self.settings.history, &change.changes) {
+            Ok(history) => {
+                log::info!("Keeping readings for {:?} in {:?} buckets", history.retention, history.resolution);
+                self.settings.history = history;
+                self.history.set_config(history);
+                self.dirty = true;
+            }
+            Err(e) => self.message_box(&format!("The history settings weren't changed:\n\n{}", e)),
+        }
+        Ok(())
+    }
+    
+    // Writes what the user applied on a device's property sheet, then has
+    // every view showing the device read it again
+    pub fn apply_property_change(&mut self, change: &PropertyChange) -> ComResult<()> {
+        if change.id == NodeId::ROOT {
+            return self.apply_console_change(change);
+        }
+        let addr = match self.registry.get(change.id).map(|node| node.kind) {
+            Some(NodeKind::Device(addr, _)) => addr,
+            _ => {
+                log::error!("Property change for {:?}, which isn't a device", change.id);
+                return Err(ComError::E_INVALIDARG);
+            }
+        };
+        
+        let result = match self.devices.contains_key(&addr) && self.service.is_some() {
+            true => {
+                let failures: Vec<String> = change.changes.iter()
+                    .filter_map(|change| {
+                        log::info!("Setting {} at {:#04x} to {}", change.label, addr, change.value);
+                        let e = self.write_setting(addr, &change.key, change.value).err()?;
+                        log::error!("Couldn't set {}: {}", change.label, e);
+                        Some(format!("{}: {}", change.label, e))
+                    })
+                    .collect();
+                match failures.is_empty() {
+                    true => Ok(()),
+                    false => Err(failures),
+                }
+            }
+            false => Err(vec![format!("The device at {:#04x} can't be written to", addr)]),
+        };
+        
+        if let Err(failures) = result {
+            self.message_box(&format!("Some settings weren't changed:\n\n{}", failures.join("\n")));
+        }
+        
+        self.readings.remove(&addr);
+        match self.data_object(change.id) {
+            Some(dataobject) => self.refresh_views(change.id, &dataobject),
+            None => Ok(()),
+        }
+    }
+    
+    fn message_box(&self, text: &str) {
+        let style = 0x30; // MB_OK | MB_ICONWARNING
+        self.ask(text, style);
+    }
+    
+    // Asks a yes or no question, with No the default
+    fn confirm(&self, text: &str) -> bool {
+        let style = 0x134; // MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2
+        self.ask(text, style) == Some(6) // IDYES
+    }
+    
+    fn ask(&self, text: &str, style: u32) -> Option<i32> {
+        let _suspend = poll::suspend();
+        let console = self.console.as_ref()?;
+        let text: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+        let title: Vec<u16> = "SMBus Snap-in".encode_utf16().chain(std::iter::once(0)).collect();
+        match console.message_box(ComPCWSTR(PCWSTR::from_raw(text.as_ptr())), ComPCWSTR(PCWSTR::from_raw(title.as_ptr())), style) {
+            Ok(button) => Some(button),
+            Err(e) => {
+                log::error!("IConsole::MessageBox() error: {}", e);
+                None
+            }
+        }
+    }
+    
+    // The root is always in the registry; only its data object is made lazily
     fn add_root_node(&mut self) {
-        match self.nodes.get(&0) {
+        match self.nodes.get(&NodeId::ROOT) {
             Some(_) => return,
             None => {
-                let root_node = Node::new(
-                    self as *const _,
-                    "SMBus Snap-in".to_owned(),
-                    NodeType::Root,
-                );
-                self.nodes.insert(0, ComBox::new(root_node));
+                let root_node = Node::new(self as *const _, NodeId::ROOT);
+                self.nodes.insert(NodeId::ROOT, ComBox::new(root_node));
             }
         }
     }
 }
 
+// A device's reading as its result pane shows it: for sensor devices the
+// sensors with their limits, and for the rest every property
+fn shown(reading: DeviceReading, kind: NodeKind) -> (Vec<Property>, Vec<SensorReading>) {
+    match columns::for_kind(kind) == &columns::SENSORS {
+        true => (reading.sensors.iter().map(|sensor| sensor.to_property()).collect(), reading.sensors),
+        false => (reading.properties, Vec::new()),
+    }
+}
+
+// The device at `addr` as the service identifies it. Services older than
+// 1.2 can't, so it's shown as unknown.
+fn identify(client: &mut ServiceClient, addr: u8) -> DeviceInfo {
+    match client.identify(addr) {
+        Ok((device, _)) => device,
+        Err(e) => {
+            log::error!("Identifying the device at {:#04x} failed: {}", addr, e);
+            unknown_device(addr)
+        }
+    }
+}
+
+fn unknown_device(addr: u8) -> DeviceInfo {
+    DeviceInfo { addr, name: "Unknown device".to_owned(), kind: DeviceKind::Unknown, identified: false }
+}
+
 impl IComponentData for MMCSnapIn {
     fn initialize(&mut self, lp_unknown: &ComItf<dyn IUnknown>) -> ComResult<()> {
         log::debug!("IComponentData for MMCSnapIn called");
@@ -78,6 +1335,18 @@ impl IComponentData for MMCSnapIn {
             Err(e) => { log::error!("Error {:?}: QI for IConsole", e); }
         }
         
+        // The scope pane's icons; the result pane's come with MMCN_ADD_IMAGES
+        if let Some(console) = &self.console {
+            match console.query_scope_image_list() {
+                Ok(list) => {
+                    if let Err(e) = imagelist::set_strip(&list) {
+                        log::error!("Couldn't set the scope pane's images: {}", e);
+                    }
+                }
+                Err(e) => { log::error!("IConsole::QueryScopeImageList() error: {}", e); }
+            }
+        }
+        
         match console_namespace {
             Ok(console_namespace) => {
                 log::debug!("Got {:p} for IConsoleNamespace", console_namespace.as_raw_iunknown());
@@ -86,12 +1355,12 @@ impl IComponentData for MMCSnapIn {
             Err(e) => { log::error!("Error {:?}: QI for IConsoleNamespace", e); }
         }
         
+        self.poller = Poller::start(self as *mut _, self.settings.poll_interval_ms);
+        self.name_root();
+        
         log::debug!("IComponentData::Initialize done");
         
-        // Make a few nodes
-        self.add_node("Node 1");
-        self.add_node("Node 2");
-        self.add_node("Node 3");
+        // The root's children are discovered when it is first expanded
         
         Ok(())
     }
@@ -111,49 +1380,43 @@ impl IComponentData for MMCSnapIn {
 
     }
     
-    fn notify(&mut self, _lp_dataobject: &ComItf<dyn IDataObject>, event:u32, arg:i64, param:i64) -> ComResult<()> {
+    fn notify(&mut self, lp_dataobject: Option<&ComItf<dyn IDataObject>>, event:u32, arg:i64, param:i64) -> ComResult<()> {
         let mmc_event: MmcNotifyType = unsafe { std::mem::transmute(event) };
         log::info!("Received event: {:#06X} ({:?})", event, mmc_event);
+        self.take_wizard_choice();
         
-        // Try adding the node to the scope pane
-        if mmc_event == MmcNotifyType::Expand {
-            log::info!("{} {}", param, if arg == 0 { "Collapsed" } else { "Expanded" });
-            match &self.console_namespace {
-                Some(consolens) => {
-                    for (cookie, node) in self.nodes.iter_mut() {
-                        
-                        if node.node_type == NodeType::Root {
-                            continue;
-                        }
-
-                        if node.hscopeitem.0 == 0 {
-
-                            let mut scopedataitem = SCOPEDATAITEM {
-                                mask: 0x00002 | 0x00020 | 0x00040, // SDI_STR | SDI_PARAM | SDI_CHILDREN
-                                display_name: crate::interfaces::MMC_CALLBACK,
-                                image: 0,
-                                open_image: 0,
-                                state: 0,
-                                children: 0,
-                                lparam: LPARAM(cookie.clone()),
-                                relative_id: HSCOPEITEM(param as isize),
-                                id: HSCOPEITEM(0),
-                            };
-            
-                            match consolens.insert_item((&mut scopedataitem) as *mut _) {
-                                Ok(_) => {
-                                    // Store the id back in the Node struct
-                                    node.hscopeitem = scopedataitem.id;
-                                }
-                                Err(e) => {
-                                    log::error!("IConsoleNamespace::InsertItem() error: {}", e)
-                                }
-                            }
-                        }
+        if let Some(result) = self.notify_verb(&mmc_event, lp_dataobject, arg, param) {
+            return result;
+        }
+        
+        // param is what one of our property sheets passed to
+        // MMCPropertyChangeNotify
+        if mmc_event == MmcNotifyType::PropertyChange {
+            return match unsafe { PropertyChange::from_param(param as isize) } {
+                Some(change) => self.apply_property_change(&change),
+                None => Ok(()),
+            };
+        }
+        
+        // arg is TRUE when expanding; param is the expanded item's HSCOPEITEM
+        if mmc_event == MmcNotifyType::Expand && arg != 0 {
+            let item = HSCOPEITEM(param as isize);
+            let id = match self.node_for_scope_item(item) {
+                Some(id) => id,
+                None => {
+                    // MMC inserts the static node itself, so the first time
+                    // we learn its HSCOPEITEM is when it's expanded
+                    self.add_root_node();
+                    if let Some(root) = self.nodes.get_mut(&NodeId::ROOT) {
+                        root.hscopeitem = item;
                     }
+                    NodeId::ROOT
                 }
-                None => {}
-            }
+            };
+            
+            log::info!("Expanding {:?}", id);
+            self.discover_children(id);
+            self.insert_scope_items(id, item);
         }
         Ok(())
     }
@@ -167,47 +1430,37 @@ impl IComponentData for MMCSnapIn {
     
         log::debug!("QueryDataObject: cookie: {}, ppviewtype: {:?}", cookie, view_type);
         
-        match &cookie {
-            0 => {
-                // View type doesn't matter for root node
-                match self.nodes.get(&cookie) {
-                    None => {
-                        log::debug!("No root node has been made yet.");
-                        
-                        // Create the root node
-                        self.add_root_node();
-                        return Ok(ComRc::from(self.nodes.get(&cookie).unwrap()));
-                    }
-                    Some(root_node) => {
-                        log::debug!("Root node IDataObject returned to caller");
-                        return Ok(ComRc::from(root_node));
-                    }
-                }
+        let id = NodeId(cookie);
+        
+        // View type doesn't matter for root node, except that the snap-in
+        // manager asks for it to show the connect wizard
+        if id == NodeId::ROOT {
+            self.add_root_node();
+            self.in_snapin_manager = matches!(view_type, MmcDataObjectType::SnapinManager);
+        }
+        
+        match self.data_object(id) {
+            Some(node) => {
+                log::debug!("Node {:?} IDataObject returned to caller", id);
+                Ok(node)
             }
-            // Anything but the root node
-            _ => {
-                match self.nodes.get(&cookie) {
-                    Some(node) => {
-                        log::debug!("Node #{} IDataObject returned to caller", &cookie);
-                        return Ok(ComRc::from(node));
-                    }
-                    None => {
-                        log::error!("MMC tried to get node with cookie: {}", &cookie);
-                        return Err(ComError::E_POINTER);
-                    }
-                }
+            None => {
+                log::error!("MMC tried to get node with cookie: {}", cookie);
+                Err(ComError::E_POINTER)
             }
         }
     }
     
     fn get_display_info(&mut self,lpscopedataitem: *mut SCOPEDATAITEM) -> ComResult<()> {
         log::debug!("Got {:?}", unsafe { *lpscopedataitem });
-        let cookie = &(unsafe {*lpscopedataitem}.lparam.0);
-        let nameptr = self.nodes.get_mut(cookie);
+        // The static node is first shown right after the wizard finishes
+        self.take_wizard_choice();
+        let cookie = unsafe { (*lpscopedataitem).lparam.0 };
+        let nameptr = self.nodes.get_mut(&NodeId(cookie));
         
         match nameptr {
             None => {
-                log::error!("Couldn't match cookie: {}", &cookie);
+                log::error!("Couldn't match cookie: {}", cookie);
                 return Err(ComError::E_POINTER);
             }
             Some(obj) => {
@@ -236,6 +1489,133 @@ impl IComponentData for MMCSnapIn {
     }
 }
 
+impl IExtendContextMenu for MMCSnapIn {
+    fn add_menu_items(&mut self, dataobject: &ComItf<dyn IDataObject>, callback: &ComItf<dyn IContextMenuCallback>, insertion_allowed: *mut i32) -> ComResult<()> {
+        let id = match node_id_of(dataobject) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let allowed = unsafe { *insertion_allowed };
+        contextmenu::add_actions(&self.node_actions(id), callback, allowed)
+    }
+    
+    fn command(&mut self, command_id: i32, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let id = node_id_of(dataobject);
+        match (id, ActionId::from_command_id(command_id)) {
+            (Some(id), Some(action)) => self.run_action(id, action, dataobject),
+            _ => {
+                log::error!("Unknown command {} for {:?}", command_id, id);
+                Err(ComError::E_INVALIDARG)
+            }
+        }
+    }
+}
+
+impl IExtendPropertySheet for MMCSnapIn {
+    fn create_property_pages(&mut self, callback: &ComItf<dyn IPropertySheetCallback>, handle: isize, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let id = node_id_of(dataobject).ok_or(ComError::E_INVALIDARG)?;
+        if id == NodeId::ROOT && self.in_snapin_manager {
+            self.in_snapin_manager = false;
+            return wizard::add_page(callback, self.settings.connection.clone(), self.wizard_choice.clone());
+        }
+        
+        let sheet = match id {
+            NodeId::ROOT => Some(DeviceSheet::console(self.settings.history)),
+            _ => self.device_sheet(id),
+        };
+        match sheet {
+            Some(sheet) => propertysheet::add_pages(callback, handle, id, sheet),
+            None => {
+                log::error!("{:?} has no property pages", id);
+                Err(ComError::E_INVALIDARG)
+            }
+        }
+    }
+    
+    fn query_pages_for(&self, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let kind = node_id_of(dataobject)
+            .and_then(|id| self.registry.get(id))
+            .map(|node| node.kind);
+        match kind {
+            Some(NodeKind::Root | NodeKind::Device(..)) => Ok(()),
+            _ => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+        }
+    }
+}
+
+impl IExtendPropertySheet2 for MMCSnapIn {
+    // Only wizards have watermarks
+    fn get_watermarks(&self, _dataobject: &ComItf<dyn IDataObject>) -> ComResult<(ComHBITMAP, ComHBITMAP, isize, i32)> {
+        Err(ComError::E_NOTIMPL)
+    }
+}
+
+// The console file's copy of our settings, see src/settings.rs for the layout
+impl IPersistStream for MMCSnapIn {
+    fn get_class_id(&self) -> ComResult<intercom::GUID> {
+        Ok(crate::CLSID_MMCSnapIn)
+    }
+    
+    fn is_dirty(&self) -> ComResult<()> {
+        match self.dirty {
+            true => Ok(()),
+            false => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+        }
+    }
+    
+    fn load(&mut self, stream: &ComItf<dyn IStream>) -> ComResult<()> {
+        let mut bytes = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let read = stream.read(chunk.as_mut_ptr(), chunk.len() as u32)? as usize;
+            bytes.extend_from_slice(&chunk[..read]);
+            if read < chunk.len() {
+                break;
+            }
+        }
+        
+        // A console we can't read still opens, just with the defaults
+        let settings = match ConsoleSettings::from_bytes(&bytes) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Couldn't load the console file's settings: {}", e);
+                ConsoleSettings::default()
+            }
+        };
+        log::debug!("Loaded settings: {:?}", settings);
+        
+        let restart = self.poller.is_some() && settings.poll_interval_ms != self.settings.poll_interval_ms;
+        self.history.set_config(settings.history);
+        self.settings = settings;
+        self.dirty = false;
+        if restart {
+            self.poller = None;
+            self.poller = Poller::start(self as *mut _, self.settings.poll_interval_ms);
+        }
+        self.subscribe();
+        self.name_root();
+        Ok(())
+    }
+    
+    fn save(&mut self, stream: &ComItf<dyn IStream>, clear_dirty: i32) -> ComResult<()> {
+        let bytes = self.settings.to_bytes();
+        let written = stream.write(bytes.as_ptr(), bytes.len() as u32)?;
+        if written as usize != bytes.len() {
+            log::error!("Only {} of {} bytes of settings were saved", written, bytes.len());
+            return Err(ComError::E_FAIL);
+        }
+        
+        if clear_dirty != 0 {
+            self.dirty = false;
+        }
+        Ok(())
+    }
+    
+    fn get_size_max(&self) -> ComResult<u64> {
+        Ok(self.settings.to_bytes().len() as u64)
+    }
+}
+
 /*
 impl IRequiredExtensions for MMCSnapIn {
     fn enable_all_extensions(&self) -> ComResult<()> {
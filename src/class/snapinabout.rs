@@ -1,14 +1,24 @@
+use std::cell::RefCell;
+
 use intercom::prelude::*;
 use windows::Win32::UI::WindowsAndMessaging::{HICON, LoadImageW, IMAGE_ICON, LR_DEFAULTSIZE};
-use windows::Win32::Foundation::HMODULE;
+use windows::Win32::Foundation::{COLORREF, HMODULE};
+use windows::Win32::Graphics::Gdi::HBITMAP;
 use windows::{Win32::System::Com::CoTaskMemAlloc, core::PCWSTR};
 
 use crate::__INTERCOM_DLL_INSTANCE;
+use crate::images::{self, IDB_STATIC_16, IDB_STATIC_OPEN_16, IDB_STATIC_32};
 use crate::interfaces::{ISnapinAbout, ComHICON, ComHBITMAP, ComCOLORREF, ComPCWSTR};
 
+use super::imagelist;
+
 #[com_class(clsid = "d39d9c35-6106-4735-b944-7e929d607001", ISnapinAbout)]
 #[derive(Default)]
-pub struct MMCSnapInAbout {}
+pub struct MMCSnapInAbout {
+    // Bitmaps handed out by get_static_folder_image. MMC doesn't copy them,
+    // so they have to live as long as we do.
+    bitmaps: RefCell<Vec<HBITMAP>>,
+}
 
 impl ISnapinAbout for MMCSnapInAbout {
     fn get_snapin_description(&self) -> ComResult<ComPCWSTR> {
@@ -61,7 +71,26 @@ impl ISnapinAbout for MMCSnapInAbout {
     }
     
     fn get_static_folder_image(&self) -> ComResult<(ComHBITMAP,ComHBITMAP,ComHBITMAP,ComCOLORREF)> {
-        Err(ComError{ hresult: intercom::raw::HRESULT{ hr: 1 } , error_info: None })
+        let mut bitmaps = self.bitmaps.borrow_mut();
+        
+        if bitmaps.is_empty() {
+            for id in [IDB_STATIC_16, IDB_STATIC_OPEN_16, IDB_STATIC_32] {
+                match imagelist::load_bitmap(id) {
+                    Ok(bitmap) => bitmaps.push(bitmap),
+                    Err(e) => {
+                        bitmaps.drain(..).for_each(imagelist::delete_bitmap);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        
+        Ok((
+            ComHBITMAP(bitmaps[0]),
+            ComHBITMAP(bitmaps[1]),
+            ComHBITMAP(bitmaps[2]),
+            ComCOLORREF(COLORREF(images::MASK_COLOR)),
+        ))
     }
 }
 
@@ -87,10 +116,6 @@ fn str_to_cotaskmem_compcwstr(s: &str) -> ComResult<ComPCWSTR> {
 impl Drop for MMCSnapInAbout {
     fn drop(&mut self) {
         // Delete the bitmaps
-        /*
-        if let Some(icon) = self.icon.get() {
-            unsafe { DeleteObject(icon as _) };
-        }
-        */
+        self.bitmaps.get_mut().drain(..).for_each(imagelist::delete_bitmap);
     }
 }
\ No newline at end of file
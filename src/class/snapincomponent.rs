@@ -1,15 +1,79 @@
+use std::collections::HashMap;
+
 use intercom::prelude::*;
+use intercom::attributes::ComInterface;
+use intercom::type_system::{RawTypeSystem, TypeSystemName};
+use windows::Win32::Foundation::{COLORREF, LPARAM};
+use windows::core::PCWSTR;
+
+use crate::actions::{self, ActionId, Verb, MENU_BUTTONS, TOOLBAR};
+use crate::columns::{self, Align};
+use crate::images::{self, Image, IDB_TOOLBAR_16};
+use crate::interfaces::{IComponent, IConsole, IConsole2, IConsoleVerb, IContextMenuCallback, IControlbar, IDataObject, IExtendContextMenu, IExtendControlbar, IExtendPropertySheet, IExtendPropertySheet2, IHeaderCtrl, IHeaderCtrl2, IMenuButton, IPropertySheetCallback, IToolbar, ComCOLORREF, ComHBITMAP, ComPCWSTR, IResultData, MENUBUTTONDATA, MMCBUTTON, RESULTDATAITEM, HRESULTITEM, HSCOPEITEM, LVCFMT_LEFT, LVCFMT_RIGHT, MMC_BUTTON_CHECKED, MMC_BUTTON_ENABLED, MMC_CALLBACK, MMC_MENUBUTTON, MMC_TOOLBAR, MMC_VERB_COPY, MMC_VERB_DELETE, MMC_VERB_OPEN, MMC_VERB_PROPERTIES, MMC_VERB_REFRESH, MMC_VERB_RENAME, TBSTATE_ENABLED, TBSTYLE_BUTTON, TBSTYLE_CHECK};
+
+use crate::registry::{NodeId, NodeKind};
+
+use super::{contextmenu, dataobject_from_param, imagelist, node_id_of, MmcNotifyType, MMCSnapIn, PropertyChange};
+
+// Our own result items use negative lparams so they never collide with a
+// NodeId cookie, which is never negative.
+fn row_lparam(index: usize) -> LPARAM {
+    LPARAM(-(index as isize) - 1)
+}
 
-use crate::interfaces::{IComponent, IConsole, IConsole2, IDataObject, ComPCWSTR, IResultData, RESULTDATAITEM};
+fn row_index(lparam: LPARAM) -> Option<usize> {
+    if lparam.0 < 0 {
+        Some((-(lparam.0 + 1)) as usize)
+    } else {
+        None
+    }
+}
+
+// What get_display_info hands out for a column a row has no text for
+static EMPTY: [u16; 1] = [0];
 
-use super::{MmcNotifyType, MMCSnapIn};
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
 
-#[com_class(IComponent)]
+fn mmc_verb(verb: Verb) -> i32 {
+    match verb {
+        Verb::Refresh => MMC_VERB_REFRESH,
+        Verb::Rename => MMC_VERB_RENAME,
+        Verb::Delete => MMC_VERB_DELETE,
+        Verb::Properties => MMC_VERB_PROPERTIES,
+        Verb::Copy => MMC_VERB_COPY,
+    }
+}
+
+// One of our own result items
+#[derive(Debug, PartialEq)]
+struct Row {
+    cells: Vec<Vec<u16>>,
+    image: i32,
+}
+
+#[com_class(IComponent, IExtendContextMenu, IExtendPropertySheet, IExtendPropertySheet2, IExtendControlbar)]
 #[derive(Debug)]
 pub struct MMCSnapInComponent {
     parent: *mut MMCSnapIn,
     console: Option<ComRc<dyn IConsole2>>,
     resultdata: Option<ComRc<dyn IResultData>>,
+    header: Option<ComRc<dyn IHeaderCtrl2>>,
+    // Cells of the result items we inserted ourselves, see row_index().
+    // Kept as null-terminated UTF-16 so get_display_info can hand out
+    // pointers that stay valid until the next Show.
+    rows: Vec<Row>,
+    // Cells past the name for the shown node's children, which MMC lists
+    // in the result pane by itself
+    children: HashMap<NodeId, Vec<Vec<u16>>>,
+    // The scope node whose result pane we're showing
+    shown: Option<NodeId>,
+    controlbar: Option<ComRc<dyn IControlbar>>,
+    toolbar: Option<ComRc<dyn IToolbar>>,
+    menubutton: Option<ComRc<dyn IMenuButton>>,
+    // The node our controls were last attached for
+    selected: Option<NodeId>,
 }
 
 impl Default for MMCSnapInComponent {
@@ -18,6 +82,14 @@ impl Default for MMCSnapInComponent {
             parent: std::ptr::null_mut(),
             console: None,
             resultdata: None,
+            header: None,
+            rows: Vec::new(),
+            children: HashMap::new(),
+            shown: None,
+            controlbar: None,
+            toolbar: None,
+            menubutton: None,
+            selected: None,
         }
     }
 }
@@ -28,6 +100,383 @@ impl MMCSnapInComponent {
             parent,
             console: None,
             resultdata: None,
+            header: None,
+            rows: Vec::new(),
+            children: HashMap::new(),
+            shown: None,
+            controlbar: None,
+            toolbar: None,
+            menubutton: None,
+            selected: None,
+        }
+    }
+    
+    // Adds the column headers of the node's column set. MMC then puts back
+    // the widths and order the user last left for that set.
+    fn insert_columns(&self, id: NodeId) -> ComResult<()> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                log::error!("No IHeaderCtrl2 to insert columns into");
+                return Err(ComError::E_POINTER);
+            }
+        };
+        let parent = unsafe { &*self.parent };
+        let kind = match parent.registry.get(id) {
+            Some(node) => node.kind,
+            None => return Err(ComError::E_INVALIDARG),
+        };
+        
+        for (index, column) in columns::for_kind(kind).columns.iter().enumerate() {
+            let title = wide(column.title);
+            let format = match column.align {
+                Align::Left => LVCFMT_LEFT,
+                Align::Right => LVCFMT_RIGHT,
+            };
+            if let Err(e) = header.insert_column(index as i32, ComPCWSTR(PCWSTR(title.as_ptr())), format, column.width) {
+                log::error!("IHeaderCtrl::InsertColumn() error: {}", e);
+                return Err(e);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn resultdata(&self) -> ComResult<&ComRc<dyn IResultData>> {
+        match &self.resultdata {
+            Some(resultdata) => Ok(resultdata),
+            None => {
+                log::error!("No IResultData for the result pane");
+                Err(ComError::E_POINTER)
+            }
+        }
+    }
+    
+    // The first of our rows that's selected
+    fn selected_row(&self) -> Option<usize> {
+        let resultdata = self.resultdata.as_ref()?;
+        let mut resultdataitem = RESULTDATAITEM {
+            mask: 0x0008 | 0x0010, // RDI_STATE | RDI_PARAM
+            scope_item: false,
+            itemid: HRESULTITEM(0),
+            // Start looking from the top
+            index: -1,
+            col: 0,
+            str: PCWSTR::null(),
+            image: 0,
+            state: 0x0002, // LVIS_SELECTED
+            lparam: LPARAM(0),
+            indent: 0,
+        };
+        if let Err(e) = resultdata.get_next_item((&mut resultdataitem) as *mut _) {
+            log::error!("IResultData::GetNextItem() error: {}", e);
+            return None;
+        }
+        // The index comes back as -1 when nothing is selected
+        match resultdataitem.index {
+            -1 => None,
+            _ => row_index(resultdataitem.lparam),
+        }
+    }
+    
+    // Replaces every result item we inserted with `rows`
+    fn insert_rows(&mut self, rows: Vec<Row>) -> ComResult<()> {
+        let resultdata = self.resultdata()?.clone();
+        
+        if !self.rows.is_empty() {
+            resultdata.delete_all_rslt_items()?;
+        }
+        self.rows = rows;
+        
+        for (index, row) in self.rows.iter().enumerate() {
+            let mut resultdataitem = RESULTDATAITEM {
+                mask: 0x0002 | 0x0004 | 0x0010, // RDI_STR | RDI_IMAGE | RDI_PARAM
+                scope_item: false,
+                itemid: HRESULTITEM(0),
+                index: 0,
+                col: 0,
+                str: MMC_CALLBACK,
+                image: row.image,
+                state: 0,
+                lparam: row_lparam(index),
+                indent: 0,
+            };
+            
+            if let Err(e) = resultdata.insert_item((&mut resultdataitem) as *mut _) {
+                log::error!("IResultData::InsertItem() error: {}", e);
+                return Err(e);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    // Swaps in new text for rows that are already listed and has MMC redraw
+    // only the ones that changed, so polling doesn't make the list flicker
+    // or lose the selection
+    fn update_rows(&mut self, rows: Vec<Row>) -> ComResult<()> {
+        let resultdata = self.resultdata()?.clone();
+        
+        for (index, row) in rows.into_iter().enumerate() {
+            if self.rows[index] == row {
+                continue;
+            }
+            let image_changed = self.rows[index].image != row.image;
+            self.rows[index] = row;
+            
+            let itemid = resultdata.find_item_by_lparam(row_lparam(index).0)?;
+            if image_changed {
+                // The text is a callback, but the image is stored in the item
+                let mut resultdataitem = RESULTDATAITEM {
+                    mask: 0x0004, // RDI_IMAGE
+                    scope_item: false,
+                    itemid,
+                    index: 0,
+                    col: 0,
+                    str: PCWSTR::null(),
+                    image: self.rows[index].image,
+                    state: 0,
+                    lparam: LPARAM(0),
+                    indent: 0,
+                };
+                if let Err(e) = resultdata.set_item((&mut resultdataitem) as *mut _) {
+                    log::error!("IResultData::SetItem() error: {}", e);
+                }
+            }
+            if let Err(e) = resultdata.update_item(itemid) {
+                log::error!("IResultData::UpdateItem() error: {}", e);
+                return Err(e);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    // Enables the verbs of the node that was just selected
+    fn select(&self, id: NodeId) -> ComResult<()> {
+        let console = match &self.console {
+            Some(console) => console,
+            None => return Err(ComError::E_POINTER),
+        };
+        let console_verb = console.query_console_verb()?;
+        let parent = unsafe { &*self.parent };
+        let verbs = parent.node_verbs(id);
+        
+        for verb in Verb::ALL {
+            let enabled = verbs.allows(verb) as i32;
+            if let Err(e) = console_verb.set_verb_state(mmc_verb(verb), MMC_BUTTON_ENABLED, enabled) {
+                log::error!("IConsoleVerb::SetVerbState({:?}) error: {}", verb, e);
+            }
+        }
+        console_verb.set_default_verb(verbs.default.map(mmc_verb).unwrap_or(MMC_VERB_OPEN))
+    }
+    
+    // Our own IExtendControlbar, for the control bar to report clicks on our
+    // controls to
+    fn extend_controlbar(&self) -> ComResult<ComRc<dyn IExtendControlbar>> {
+        let iid = match <dyn IExtendControlbar as ComInterface>::iid(TypeSystemName::Raw) {
+            Some(iid) => iid,
+            None => return Err(ComError::E_NOINTERFACE),
+        };
+        let mut ptr = std::ptr::null_mut();
+        unsafe {
+            let data = intercom::ComBoxData::of(self);
+            let hr = intercom::ComBoxData::query_interface(data, iid, &mut ptr);
+            if hr.hr != 0 {
+                return Err(ComError::new_hr(hr));
+            }
+            match intercom::raw::InterfacePtr::<RawTypeSystem, dyn IExtendControlbar>::new(ptr) {
+                Some(ptr) => Ok(ComRc::wrap(ptr)),
+                None => Err(ComError::E_NOINTERFACE),
+            }
+        }
+    }
+    
+    // Creates the toolbar and the menu buttons. They stay detached until
+    // one of our nodes is selected.
+    fn create_controls(&mut self, controlbar: &ComRc<dyn IControlbar>) -> ComResult<()> {
+        let extend = self.extend_controlbar()?;
+        
+        let toolbar = controlbar.create(MMC_TOOLBAR, &extend)?;
+        let toolbar: ComRc<dyn IToolbar> = ComItf::query_interface(&toolbar)?;
+        let bitmap = imagelist::load_bitmap(IDB_TOOLBAR_16)?;
+        let added = toolbar.add_bitmap(TOOLBAR.len() as i32, ComHBITMAP(bitmap), 16, 16, ComCOLORREF(COLORREF(images::MASK_COLOR)));
+        imagelist::delete_bitmap(bitmap);
+        if let Err(e) = added {
+            log::error!("IToolbar::AddBitmap() error: {}", e);
+            return Err(e);
+        }
+        
+        // The strings only have to last until AddButtons returns
+        let texts: Vec<(Vec<u16>, Vec<u16>)> = TOOLBAR.iter()
+            .map(|button| (wide(button.text), wide(button.tooltip)))
+            .collect();
+        let mut buttons: Vec<MMCBUTTON> = TOOLBAR.iter().zip(&texts).enumerate()
+            .map(|(index, (button, (text, tooltip)))| MMCBUTTON {
+                bitmap: index as i32,
+                command_id: button.action.command_id(),
+                state: TBSTATE_ENABLED,
+                style: match button.toggle {
+                    true => TBSTYLE_CHECK,
+                    false => TBSTYLE_BUTTON,
+                },
+                button_text: PCWSTR(text.as_ptr()),
+                tooltip_text: PCWSTR(tooltip.as_ptr()),
+            })
+            .collect();
+        if let Err(e) = toolbar.add_buttons(buttons.len() as i32, buttons.as_mut_ptr()) {
+            log::error!("IToolbar::AddButtons() error: {}", e);
+            return Err(e);
+        }
+        
+        let menubutton = controlbar.create(MMC_MENUBUTTON, &extend)?;
+        let menubutton: ComRc<dyn IMenuButton> = ComItf::query_interface(&menubutton)?;
+        for button in &MENU_BUTTONS {
+            let text = wide(button.text);
+            let tooltip = wide(button.tooltip);
+            if let Err(e) = menubutton.add_button(button.action.command_id(), ComPCWSTR(PCWSTR(text.as_ptr())), ComPCWSTR(PCWSTR(tooltip.as_ptr()))) {
+                log::error!("IMenuButton::AddButton() error: {}", e);
+                return Err(e);
+            }
+        }
+        
+        self.toolbar = Some(toolbar);
+        self.menubutton = Some(menubutton);
+        Ok(())
+    }
+    
+    // Shows our controls while one of our nodes is selected, and hides them
+    // when it isn't
+    fn attach_controls(&mut self, id: Option<NodeId>) -> ComResult<()> {
+        let controlbar = match &self.controlbar {
+            Some(controlbar) => controlbar,
+            None => return Err(ComError::E_POINTER),
+        };
+        
+        let controls = [
+            (MMC_TOOLBAR, self.toolbar.as_ref().map(|toolbar| toolbar.as_iunknown())),
+            (MMC_MENUBUTTON, self.menubutton.as_ref().map(|menubutton| menubutton.as_iunknown())),
+        ];
+        for (control_type, control) in controls {
+            let control = match control {
+                Some(control) => control,
+                None => continue,
+            };
+            let result = match id {
+                Some(_) => controlbar.attach(control_type, control),
+                None => controlbar.detach(control),
+            };
+            if let Err(e) = result {
+                log::error!("Couldn't attach or detach control {}: {}", control_type, e);
+            }
+        }
+        
+        self.selected = id;
+        self.update_controls();
+        Ok(())
+    }
+    
+    // Enables and checks the buttons the way the selected node's actions are
+    fn update_controls(&self) {
+        let parent = unsafe { &*self.parent };
+        let state = |action: ActionId| -> (bool, bool) {
+            let kind = self.selected.and_then(|id| parent.registry.get(id)).map(|node| node.kind);
+            let action = match (self.selected, kind) {
+                (Some(id), Some(kind)) => actions::find(kind, &parent.action_state(id), action),
+                _ => None,
+            };
+            match action {
+                Some(action) => (action.enabled, action.checked.unwrap_or(false)),
+                None => (false, false),
+            }
+        };
+        
+        if let Some(toolbar) = &self.toolbar {
+            for button in &TOOLBAR {
+                let (enabled, checked) = state(button.action);
+                let id = button.action.command_id();
+                if let Err(e) = toolbar.set_button_state(id, MMC_BUTTON_ENABLED, enabled as i32) {
+                    log::error!("IToolbar::SetButtonState({:?}) error: {}", button.action, e);
+                }
+                if button.toggle {
+                    if let Err(e) = toolbar.set_button_state(id, MMC_BUTTON_CHECKED, checked as i32) {
+                        log::error!("IToolbar::SetButtonState({:?}) error: {}", button.action, e);
+                    }
+                }
+            }
+        }
+        if let Some(menubutton) = &self.menubutton {
+            for button in &MENU_BUTTONS {
+                let (enabled, _) = state(button.action);
+                if let Err(e) = menubutton.set_button_state(button.action.command_id(), MMC_BUTTON_ENABLED, enabled as i32) {
+                    log::error!("IMenuButton::SetButtonState({:?}) error: {}", button.action, e);
+                }
+            }
+        }
+    }
+    
+    // Runs the action behind a toolbar or menu button, the same way the
+    // context menu would
+    fn run_button(&mut self, command_id: i32, dataobject: i64) -> ComResult<()> {
+        let dataobject = match unsafe { dataobject_from_param(dataobject as isize) } {
+            Some(dataobject) => dataobject,
+            None => return Err(ComError::E_INVALIDARG),
+        };
+        let result = self.command(command_id, &dataobject);
+        self.update_controls();
+        result
+    }
+    
+    fn show(&mut self, id: NodeId) -> ComResult<()> {
+        self.shown = Some(id);
+        // MMC empties the list when it switches nodes
+        self.rows.clear();
+        
+        if let Err(e) = self.insert_columns(id) {
+            log::error!("Couldn't insert the columns of {:?}: {}", id, e);
+        }
+        
+        let parent = unsafe { &*self.parent };
+        let description = parent.registry.get(id)
+            .and_then(|node| node.description())
+            .unwrap_or_default();
+        let description = wide(&description);
+        if let Err(e) = self.resultdata()?.set_desc_bar_text(ComPCWSTR(PCWSTR(description.as_ptr()))) {
+            log::error!("IResultData::SetDescBarText() error: {}", e);
+        }
+        
+        self.fill(id)
+    }
+    
+    // Reads the shown node again. The node's children are listed by MMC;
+    // we only keep the text of their other columns. Readings and register
+    // rows are our own items, updated in place while the number of rows
+    // stays the same.
+    fn fill(&mut self, id: NodeId) -> ComResult<()> {
+        let parent = unsafe { &mut *self.parent };
+        
+        self.children = match parent.registry.get(id) {
+            Some(node) => node.children().iter()
+                .filter_map(|child| parent.registry.get(*child))
+                .map(|child| (child.id, columns::node_row(child).iter().map(|cell| wide(cell)).collect()))
+                .collect(),
+            None => HashMap::new(),
+        };
+        
+        let rows: Vec<Row> = match parent.read_result_rows(id) {
+            Some(rows) => rows.iter()
+                .map(|(cells, image): &(Vec<String>, Image)| Row {
+                    cells: cells.iter().map(|cell| wide(cell)).collect(),
+                    image: image.index(),
+                })
+                .collect(),
+            None => return Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+        };
+        
+        if !self.rows.is_empty() && rows.len() == self.rows.len() {
+            self.update_rows(rows)
+        } else {
+            self.insert_rows(rows)
         }
     }
 }
@@ -38,6 +487,7 @@ impl IComponent for MMCSnapInComponent {
         // Cache the IConsole interface of the MMC
         let console2: ComResult<ComRc<dyn IConsole2>> = ComItf::query_interface(lp_console);
         let resultdata: ComResult<ComRc<dyn IResultData>> = ComItf::query_interface(lp_console);
+        let header: ComResult<ComRc<dyn IHeaderCtrl2>> = ComItf::query_interface(lp_console);
         
         match console2 {
             Ok(console2) => {
@@ -55,6 +505,14 @@ impl IComponent for MMCSnapInComponent {
             Err(e) => { log::error!("Error {:?}: QI for IResultData", e); }
         }
         
+        match header {
+            Ok(header) => {
+                log::debug!("Got {:p} for IHeaderCtrl2", header.as_raw_iunknown());
+                self.header = Some(header.clone());
+            },
+            Err(e) => { log::error!("Error {:?}: QI for IHeaderCtrl2", e); }
+        }
+        
         log::debug!("IComponent::Initialize done");
 
         Ok(())
@@ -71,8 +529,47 @@ impl IComponent for MMCSnapInComponent {
     }
     
     fn get_display_info(&mut self, resultdataitem: *mut RESULTDATAITEM) -> ComResult<()> {
-        let cookie = &(unsafe {*resultdataitem}.lparam.0);
-        let node = unsafe { (*self.parent).nodes.get_mut(cookie) };
+        // Items we inserted ourselves carry an index into self.rows
+        if !unsafe { (*resultdataitem).scope_item } {
+            let lparam = unsafe { (*resultdataitem).lparam };
+            match row_index(lparam).and_then(|index| self.rows.get(index)) {
+                None => {
+                    log::error!("Couldn't match result row: {}", lparam.0);
+                    return Err(ComError::E_POINTER);
+                }
+                Some(row) => {
+                    let mask = unsafe { (*resultdataitem).mask.clone() };
+                    let col = unsafe { (*resultdataitem).col };
+                    if (mask & 0x0002) != 0 {
+                        let cell = row.cells.get(col as usize).map(Vec::as_slice).unwrap_or(&EMPTY);
+                        unsafe {
+                            (*resultdataitem).str.0 = cell.as_ptr();
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        
+        let cookie = unsafe { (*resultdataitem).lparam.0 };
+        
+        // Only the name comes from the node; the other columns are cells
+        // read when its parent was shown
+        let col = unsafe { (*resultdataitem).col };
+        if col > 0 {
+            if unsafe { (*resultdataitem).mask & 0x0002 } != 0 {
+                let cell = self.children.get(&NodeId(cookie))
+                    .and_then(|cells| cells.get(col as usize))
+                    .map(Vec::as_slice)
+                    .unwrap_or(&EMPTY);
+                unsafe {
+                    (*resultdataitem).str.0 = cell.as_ptr();
+                }
+            }
+            return Ok(());
+        }
+        
+        let node = unsafe { (*self.parent).nodes.get_mut(&NodeId(cookie)) };
 
         match node {
             None => {
@@ -95,7 +592,10 @@ impl IComponent for MMCSnapInComponent {
                     }
                 }
                 if (mask & 0x0004) != 0 {
-                    log::debug!("MMC wants the image, but I have no image to provide");
+                    let (image, _) = unsafe { (*self.parent).node_images(NodeId(cookie)) };
+                    unsafe {
+                        (*resultdataitem).image = image;
+                    }
                 }
                 Ok(())
             }
@@ -113,55 +613,208 @@ impl IComponent for MMCSnapInComponent {
         */
     }
     
-    fn notify(&self, _lp_dataobject: &ComItf<dyn IDataObject>, event:u32, _arg:i64, _param:i64) -> ComResult<()> {
+    fn notify(&mut self, lp_dataobject: Option<&ComItf<dyn IDataObject>>, event:u32, arg:i64, param:i64) -> ComResult<()> {
         let mmc_event: MmcNotifyType = unsafe { std::mem::transmute(event) };
         log::info!("Received event: {:#06X} ({:?})", event, mmc_event);
         
-        Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }))
+        let parent = unsafe { &mut *self.parent };
+        if let Some(result) = parent.notify_verb(&mmc_event, lp_dataobject, arg, param) {
+            return result;
+        }
         
-        /*
-        // Test insert_item()
         match mmc_event {
             MmcNotifyType::Show => {
-
-                match &self.resultdata {
-                    Some(resultdata) => {
-                        let mut resultdataitem = RESULTDATAITEM {
-                            mask: 0x0002,
-                            scope_item: false,
-                            itemid: HRESULTITEM(0),
-                            index: 0,
-                            col: 0,
-                            str: MMC_CALLBACK,
-                            image: 0,
-                            state: 0,
-                            lparam: LPARAM(1),
-                            indent: 0,
-                        };
-                        
-                        match resultdata.insert_item((&mut resultdataitem) as *mut _) {
-                            Ok(_) => {
-                                log::info!("Wow, inserting the item worked!\n{:?}", resultdataitem);
-                                return Ok(());
-                            }
-                            Err(e) => {
-                                log::error!("IResultData::InsertItem() error: {}", e);
-                                return Ok(());
-                            }
-                        }
-                        
+                // arg is TRUE when the scope item is selected, FALSE when
+                // it's deselected. param is the scope item's HSCOPEITEM.
+                if arg == 0 {
+                    self.rows.clear();
+                    self.children.clear();
+                    self.shown = None;
+                    return Ok(());
+                }
+                
+                let parent = unsafe { &*self.parent };
+                match parent.node_for_scope_item(HSCOPEITEM(param as isize)) {
+                    Some(id) => self.show(id),
+                    None => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+                }
+            }
+            MmcNotifyType::Select => {
+                // The high word of arg is TRUE when the item is selected,
+                // FALSE when it's deselected
+                let selected = (arg >> 16) & 0xFFFF != 0;
+                match lp_dataobject.and_then(node_id_of) {
+                    Some(id) if selected => self.select(id),
+                    _ => Ok(()),
+                }
+            }
+            MmcNotifyType::AddImages => {
+                // arg is the result pane's IImageList, which the console
+                // hands out as well
+                match &self.console {
+                    Some(console) => imagelist::set_strip(&console.query_result_image_list()?),
+                    None => Err(ComError::E_POINTER),
+                }
+            }
+            MmcNotifyType::PropertyChange => {
+                // Sheets opened from the result pane report to us, but the
+                // writing is the parent's job
+                let parent = unsafe { &mut *self.parent };
+                match unsafe { PropertyChange::from_param(param as isize) } {
+                    Some(change) => parent.apply_property_change(&change),
+                    None => Ok(()),
+                }
+            }
+            MmcNotifyType::ViewChange => {
+                // arg is the cookie passed to UpdateAllViews
+                match self.shown {
+                    Some(id) if id.cookie() == arg as isize => self.fill(id),
+                    _ => Ok(()),
+                }
+            }
+            MmcNotifyType::DblClick => {
+                // Only our register rows come with the shown node's data
+                // object. Anything else gets MMC's default, S_FALSE.
+                let parent = unsafe { &mut *self.parent };
+                let editable = |id: NodeId| parent.node_actions(id).iter()
+                    .any(|action| action.id == ActionId::EditRegister && action.enabled);
+                match lp_dataobject.and_then(node_id_of) {
+                    Some(id) if Some(id) == self.shown && editable(id) => {
+                        let row = self.selected_row();
+                        parent.edit_register(id, row)
                     }
-                    None => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }))
+                    _ => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+                }
+            }
+            _ => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+        }
+    }
+    
+    fn query_data_object(&mut self, cookie:isize, _type:i32) -> ComResult<ComRc<dyn IDataObject>> {
+        let parent = unsafe { &*self.parent };
+        if row_index(LPARAM(cookie)).is_some() {
+            // A register row stands for its Registers node, so its context
+            // menu and double-clicking it can edit the register. Other rows
+            // don't have data objects of their own.
+            return match self.shown {
+                Some(id) if matches!(parent.registry.get(id).map(|node| node.kind), Some(NodeKind::Registers(_))) => {
+                    parent.data_object(id).ok_or(ComError::E_POINTER)
                 }
+                _ => Err(ComError::E_NOTIMPL),
+            };
+        }
+        
+        match parent.data_object(NodeId(cookie)) {
+            Some(node) => Ok(node),
+            None => {
+                log::error!("MMC tried to get node with cookie: {}", cookie);
+                Err(ComError::E_POINTER)
             }
-            _ => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 }))
+        }
+    }
+}
 
+impl IExtendContextMenu for MMCSnapInComponent {
+    fn add_menu_items(&mut self, dataobject: &ComItf<dyn IDataObject>, callback: &ComItf<dyn IContextMenuCallback>, insertion_allowed: *mut i32) -> ComResult<()> {
+        let id = match node_id_of(dataobject) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let parent = unsafe { &*self.parent };
+        let allowed = unsafe { *insertion_allowed };
+        contextmenu::add_actions(&parent.node_actions(id), callback, allowed)
+    }
+    
+    fn command(&mut self, command_id: i32, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let id = node_id_of(dataobject);
+        match (id, ActionId::from_command_id(command_id)) {
+            (Some(id), Some(action)) => {
+                let parent = unsafe { &mut *self.parent };
+                let result = match action {
+                    // Start at the register row the menu was opened on
+                    ActionId::EditRegister if Some(id) == self.shown => parent.edit_register(id, self.selected_row()),
+                    _ => parent.run_action(id, action, dataobject),
+                };
+                self.update_controls();
+                result
+            }
+            _ => {
+                log::error!("Unknown command {} for {:?}", command_id, id);
+                Err(ComError::E_INVALIDARG)
+            }
         }
-        */
+    }
+}
+
+impl IExtendPropertySheet for MMCSnapInComponent {
+    fn create_property_pages(&mut self, callback: &ComItf<dyn IPropertySheetCallback>, handle: isize, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let parent = unsafe { &mut *self.parent };
+        parent.create_property_pages(callback, handle, dataobject)
     }
     
-    fn query_data_object(&mut self, _cookie:isize, _type:i32) -> ComResult<ComRc<dyn IDataObject>> {
-        log::error!("Not implemented");
+    fn query_pages_for(&self, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()> {
+        let parent = unsafe { &*self.parent };
+        parent.query_pages_for(dataobject)
+    }
+}
+
+impl IExtendPropertySheet2 for MMCSnapInComponent {
+    fn get_watermarks(&self, _dataobject: &ComItf<dyn IDataObject>) -> ComResult<(ComHBITMAP, ComHBITMAP, isize, i32)> {
         Err(ComError::E_NOTIMPL)
     }
-}
\ No newline at end of file
+}
+
+impl IExtendControlbar for MMCSnapInComponent {
+    fn set_controlbar(&mut self, controlbar: Option<&ComItf<dyn IControlbar>>) -> ComResult<()> {
+        // MMC passes None when the view closes
+        let controlbar: ComRc<dyn IControlbar> = match controlbar {
+            Some(controlbar) => controlbar.into(),
+            None => {
+                self.toolbar = None;
+                self.menubutton = None;
+                self.controlbar = None;
+                self.selected = None;
+                return Ok(());
+            }
+        };
+        
+        if let Err(e) = self.create_controls(&controlbar) {
+            log::error!("Couldn't create the toolbar: {}", e);
+        }
+        self.controlbar = Some(controlbar);
+        Ok(())
+    }
+    
+    fn controlbar_notify(&mut self, event: u32, arg: i64, param: i64) -> ComResult<()> {
+        let mmc_event: MmcNotifyType = unsafe { std::mem::transmute(event) };
+        log::info!("Received control bar event: {:#06X} ({:?})", event, mmc_event);
+        
+        match mmc_event {
+            MmcNotifyType::Select => {
+                // The high word of arg is TRUE when the item is selected,
+                // and param is its data object
+                let selected = (arg >> 16) & 0xFFFF != 0;
+                let id = unsafe { dataobject_from_param(param as isize) }
+                    .and_then(|dataobject| node_id_of(&dataobject));
+                match selected {
+                    true => self.attach_controls(id),
+                    false => self.attach_controls(None),
+                }
+            }
+            MmcNotifyType::BtnClick => {
+                // arg is the selected item's data object, param the button's
+                // command id
+                self.run_button(param as i32, arg)
+            }
+            MmcNotifyType::MenuBtnClick => {
+                // arg is the data object, param points to a MENUBUTTONDATA
+                let data = match param {
+                    0 => return Err(ComError::E_POINTER),
+                    param => unsafe { *(param as *const MENUBUTTONDATA) },
+                };
+                self.run_button(data.command_id, arg)
+            }
+            _ => Err(ComError::new_hr(intercom::raw::HRESULT { hr: 1 })),
+        }
+    }
+}
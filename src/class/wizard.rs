@@ -0,0 +1,280 @@
+// The page MMC's Add Snap-in wizard shows for us: which computer to manage
+// and where to read devices from. The snap-in manager runs the wizard, so
+// the page hands the user's choice back through a shared slot that the
+// snap-in picks up the next time MMC calls it. "Connect to another
+// computer" shows the same page as a wizard of its own.
+
+use std::sync::{Arc, Mutex};
+
+use intercom::prelude::*;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::UI::Controls::{
+    CheckRadioButton, CreatePropertySheetPageW, DestroyPropertySheetPage, IsDlgButtonChecked, PropertySheetW,
+    BST_CHECKED, HPROPSHEETPAGE, NMHDR, PROPSHEETHEADERW_V2, PROPSHEETHEADERW_V2_2, PROPSHEETPAGEW, PROPSHEETPAGEW_0,
+    PSH_HEADER, PSH_WIZARD97, PSM_SETWIZBUTTONS, PSN_SETACTIVE, PSN_WIZFINISH, PSPCB_MESSAGE, PSPCB_RELEASE,
+    PSP_DLGINDIRECT, PSP_USECALLBACK, PSP_USEHEADERSUBTITLE, PSP_USEHEADERTITLE, PSWIZB_FINISH,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::EnableWindow;
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetDlgItem, GetDlgItemTextW, GetParent, GetWindowLongPtrW, GetWindowTextLengthW, SendMessageW,
+    SetDlgItemTextW, SetWindowLongPtrW, BS_AUTORADIOBUTTON, DLGTEMPLATE, ES_AUTOHSCROLL, WM_COMMAND,
+    WM_INITDIALOG, WM_NOTIFY, WS_BORDER, WS_CHILD, WS_GROUP, WS_TABSTOP, WS_VISIBLE,
+};
+
+use crate::connect::{Computer, Connection, DataSource};
+use crate::interfaces::{ComHPROPSHEETPAGE, IPropertySheetCallback};
+
+use super::propertysheet::{
+    message_box, push_header, push_item, to_template, wide, CLASS_BUTTON, CLASS_EDIT, CLASS_STATIC, DWLP_MSGRESULT,
+    DWLP_USER, MARGIN,
+};
+
+/// Where the wizard leaves the connection the user picked.
+pub type WizardChoice = Arc<Mutex<Option<Connection>>>;
+
+// The inside of a Wizard97 page, in dialog units
+const PAGE_WIDTH: i16 = 317;
+const PAGE_HEIGHT: i16 = 143;
+const INDENT: i16 = 90;
+
+const ID_LOCAL: i32 = 100;
+const ID_REMOTE: i32 = 101;
+const ID_REMOTE_NAME: i32 = 102;
+const ID_HARDWARE: i32 = 110;
+const ID_SIMULATION: i32 = 111;
+const ID_SNAPSHOT: i32 = 112;
+const ID_SNAPSHOT_PATH: i32 = 113;
+
+// What the page's dialog proc finds at DWLP_USER. Freed when the page is
+// released.
+struct PageState {
+    initial: Connection,
+    choice: WizardChoice,
+    template: Vec<u32>,
+    title: Vec<u16>,
+    subtitle: Vec<u16>,
+}
+
+/// Adds the page to the Add Snap-in wizard, starting from `initial`.
+/// Finishing the wizard puts the user's choice in `choice`.
+pub fn add_page(callback: &ComItf<dyn IPropertySheetCallback>, initial: Connection, choice: WizardChoice) -> ComResult<()> {
+    let hpage = create_page(initial, choice)?;
+    if let Err(e) = callback.add_page(ComHPROPSHEETPAGE(hpage)) {
+        log::error!("IPropertySheetCallback::AddPage() error: {}", e);
+        unsafe { DestroyPropertySheetPage(hpage); }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Shows the page as a wizard owned by `owner`, starting from `initial`.
+/// Returns the user's choice, or `None` if they cancelled.
+pub fn run(owner: HWND, initial: Connection) -> Option<Connection> {
+    let choice = WizardChoice::default();
+    let mut hpage = create_page(initial, choice.clone()).ok()?;
+    let caption = wide("Connect to Another Computer");
+
+    let mut header = PROPSHEETHEADERW_V2 {
+        dwSize: std::mem::size_of::<PROPSHEETHEADERW_V2>() as u32,
+        dwFlags: PSH_WIZARD97 | PSH_HEADER,
+        hwndParent: owner,
+        pszCaption: PCWSTR::from_raw(caption.as_ptr()),
+        nPages: 1,
+        Anonymous3: PROPSHEETHEADERW_V2_2 { phpage: &mut hpage },
+        ..Default::default()
+    };
+    // The sheet destroys the page, even if it fails
+    if unsafe { PropertySheetW(&mut header) } == -1 {
+        log::error!("PropertySheetW() failed");
+        return None;
+    }
+
+    let picked = match choice.lock() {
+        Ok(mut choice) => choice.take(),
+        Err(_) => None,
+    };
+    picked
+}
+
+// The page, which owns its state until the property sheet releases it
+fn create_page(initial: Connection, choice: WizardChoice) -> ComResult<HPROPSHEETPAGE> {
+    let page = Box::into_raw(Box::new(PageState {
+        initial,
+        choice,
+        template: dialog_template(),
+        title: wide("Choose the computer and data source"),
+        subtitle: wide("The snap-in can read this computer, another one, or devices that aren't on a real bus."),
+    }));
+
+    let mut psp = PROPSHEETPAGEW {
+        dwSize: std::mem::size_of::<PROPSHEETPAGEW>() as u32,
+        dwFlags: PSP_DLGINDIRECT | PSP_USECALLBACK | PSP_USEHEADERTITLE | PSP_USEHEADERSUBTITLE,
+        Anonymous1: PROPSHEETPAGEW_0 {
+            pResource: unsafe { (*page).template.as_ptr() } as *mut DLGTEMPLATE,
+        },
+        pfnDlgProc: Some(page_proc),
+        lParam: LPARAM(page as isize),
+        pfnCallback: Some(page_callback),
+        pszHeaderTitle: PCWSTR::from_raw(unsafe { (*page).title.as_ptr() }),
+        pszHeaderSubTitle: PCWSTR::from_raw(unsafe { (*page).subtitle.as_ptr() }),
+        ..Default::default()
+    };
+
+    let hpage = unsafe { CreatePropertySheetPageW(&mut psp) };
+    if hpage.is_invalid() {
+        log::error!("CreatePropertySheetPageW() failed");
+        drop(unsafe { Box::from_raw(page) });
+        return Err(ComError::E_FAIL);
+    }
+    Ok(hpage)
+}
+
+unsafe extern "system" fn page_callback(_hwnd: HWND, msg: PSPCB_MESSAGE, psp: *mut PROPSHEETPAGEW) -> u32 {
+    if msg == PSPCB_RELEASE && !psp.is_null() {
+        let page = (*psp).lParam.0 as *mut PageState;
+        if !page.is_null() {
+            drop(Box::from_raw(page));
+        }
+    }
+    1
+}
+
+unsafe extern "system" fn page_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            let psp = lparam.0 as *const PROPSHEETPAGEW;
+            SetWindowLongPtrW(hwnd, DWLP_USER, (*psp).lParam.0);
+            if let Some(page) = page_state(hwnd) {
+                fill(hwnd, &page.initial);
+            }
+            1
+        }
+        WM_COMMAND => {
+            // Any of the radio buttons
+            let id = (wparam.0 & 0xffff) as i32;
+            if matches!(id, ID_LOCAL | ID_REMOTE | ID_HARDWARE | ID_SIMULATION | ID_SNAPSHOT) {
+                update_enabled(hwnd);
+            }
+            0
+        }
+        WM_NOTIFY => {
+            let page = match page_state(hwnd) {
+                Some(page) => page,
+                None => return 0,
+            };
+            let result = match (*(lparam.0 as *const NMHDR)).code {
+                PSN_SETACTIVE => {
+                    SendMessageW(GetParent(hwnd), PSM_SETWIZBUTTONS, WPARAM(0), LPARAM(PSWIZB_FINISH as isize));
+                    0
+                }
+                // Nonzero keeps the wizard open
+                PSN_WIZFINISH => match finish(hwnd, page) {
+                    true => 0,
+                    false => 1,
+                },
+                _ => return 0,
+            };
+            SetWindowLongPtrW(hwnd, DWLP_MSGRESULT, result);
+            1
+        }
+        _ => 0,
+    }
+}
+
+unsafe fn page_state<'a>(hwnd: HWND) -> Option<&'a PageState> {
+    (GetWindowLongPtrW(hwnd, DWLP_USER) as *const PageState).as_ref()
+}
+
+unsafe fn fill(hwnd: HWND, connection: &Connection) {
+    let (computer, name) = match &connection.computer {
+        Computer::Local => (ID_LOCAL, String::new()),
+        Computer::Remote(name) => (ID_REMOTE, name.clone()),
+    };
+    let (source, path) = match &connection.source {
+        DataSource::Hardware => (ID_HARDWARE, String::new()),
+        DataSource::Simulation => (ID_SIMULATION, String::new()),
+        DataSource::Snapshot(path) => (ID_SNAPSHOT, path.display().to_string()),
+    };
+    CheckRadioButton(hwnd, ID_LOCAL, ID_REMOTE, computer);
+    CheckRadioButton(hwnd, ID_HARDWARE, ID_SNAPSHOT, source);
+    let name = wide(&name);
+    SetDlgItemTextW(hwnd, ID_REMOTE_NAME, PCWSTR::from_raw(name.as_ptr()));
+    let path = wide(&path);
+    SetDlgItemTextW(hwnd, ID_SNAPSHOT_PATH, PCWSTR::from_raw(path.as_ptr()));
+    update_enabled(hwnd);
+}
+
+// Only hardware is read from a particular computer, and each text box only
+// matters while its radio button is picked
+unsafe fn update_enabled(hwnd: HWND) {
+    let hardware = checked(hwnd, ID_HARDWARE);
+    EnableWindow(GetDlgItem(hwnd, ID_LOCAL), hardware);
+    EnableWindow(GetDlgItem(hwnd, ID_REMOTE), hardware);
+    EnableWindow(GetDlgItem(hwnd, ID_REMOTE_NAME), hardware && checked(hwnd, ID_REMOTE));
+    EnableWindow(GetDlgItem(hwnd, ID_SNAPSHOT_PATH), checked(hwnd, ID_SNAPSHOT));
+}
+
+// Takes the user's choice if it holds up. Returns false, after saying why,
+// if it doesn't.
+unsafe fn finish(hwnd: HWND, page: &PageState) -> bool {
+    let computer = match checked(hwnd, ID_REMOTE) {
+        true => Computer::Remote(text(hwnd, ID_REMOTE_NAME).trim().to_owned()),
+        false => Computer::Local,
+    };
+    let source = match (checked(hwnd, ID_SIMULATION), checked(hwnd, ID_SNAPSHOT)) {
+        (true, _) => DataSource::Simulation,
+        (_, true) => DataSource::Snapshot(text(hwnd, ID_SNAPSHOT_PATH).trim().into()),
+        _ => DataSource::Hardware,
+    };
+    let connection = Connection { computer, source };
+
+    if let Err(error) = connection.validate() {
+        message_box(hwnd, &error);
+        return false;
+    }
+    log::info!("Connect wizard picked {:?}", connection);
+    match page.choice.lock() {
+        Ok(mut choice) => *choice = Some(connection),
+        Err(_) => return false,
+    }
+    true
+}
+
+unsafe fn checked(hwnd: HWND, id: i32) -> bool {
+    IsDlgButtonChecked(hwnd, id) == BST_CHECKED.0
+}
+
+unsafe fn text(hwnd: HWND, id: i32) -> String {
+    let len = GetWindowTextLengthW(GetDlgItem(hwnd, id)).max(0) as usize;
+    let mut buffer = vec![0u16; len + 1];
+    let copied = GetDlgItemTextW(hwnd, id, &mut buffer) as usize;
+    String::from_utf16_lossy(&buffer[..copied])
+}
+
+// Two groups of radio buttons, the last of each with a text box beside it.
+// The text boxes start a group of their own, which keeps each set of radio
+// buttons apart.
+fn dialog_template() -> Vec<u32> {
+    let mut words: Vec<u16> = Vec::new();
+    push_header(&mut words, 9, PAGE_WIDTH, PAGE_HEIGHT);
+
+    let visible = WS_CHILD.0 | WS_VISIBLE.0;
+    let radio = visible | WS_TABSTOP.0 | BS_AUTORADIOBUTTON as u32;
+    let edit = visible | WS_GROUP.0 | WS_TABSTOP.0 | WS_BORDER.0 | ES_AUTOHSCROLL as u32;
+    let width = PAGE_WIDTH - 2 * MARGIN;
+    let edit_width = PAGE_WIDTH - INDENT - MARGIN;
+
+    push_item(&mut words, visible, (MARGIN, 7, width, 8), u16::MAX, CLASS_STATIC, "Read devices from:");
+    push_item(&mut words, radio | WS_GROUP.0, (MARGIN, 21, width, 10), ID_HARDWARE as u16, CLASS_BUTTON, "&Hardware on a computer");
+    push_item(&mut words, radio, (MARGIN, 35, width, 10), ID_SIMULATION as u16, CLASS_BUTTON, "&Simulated devices");
+    push_item(&mut words, radio, (MARGIN, 49, INDENT - MARGIN, 10), ID_SNAPSHOT as u16, CLASS_BUTTON, "S&napshot file:");
+    push_item(&mut words, edit, (INDENT, 48, edit_width, 12), ID_SNAPSHOT_PATH as u16, CLASS_EDIT, "");
+
+    push_item(&mut words, visible | WS_GROUP.0, (MARGIN, 70, width, 8), u16::MAX, CLASS_STATIC, "Manage this computer:");
+    push_item(&mut words, radio | WS_GROUP.0, (MARGIN, 84, width, 10), ID_LOCAL as u16, CLASS_BUTTON, "&Local computer (the computer this console is running on)");
+    push_item(&mut words, radio, (MARGIN, 98, INDENT - MARGIN, 10), ID_REMOTE as u16, CLASS_BUTTON, "&Another computer:");
+    push_item(&mut words, edit, (INDENT, 97, edit_width, 12), ID_REMOTE_NAME as u16, CLASS_EDIT, "");
+
+    to_template(words)
+}
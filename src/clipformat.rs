@@ -0,0 +1,243 @@
+// MMC clipboard formats and the bytes we answer them with.
+//
+// Everything here is plain data: the COM data object fills in a `NodeData`
+// for the node it represents and copies whatever `serialize` returns into
+// the caller's medium. Layouts follow the structures in mmc.h.
+
+use std::fmt::Write;
+
+use crate::render;
+use crate::smbus::Property;
+
+/// What a data object knows about its node, already in the shape the
+/// clipboard formats need. GUIDs are in their in-memory layout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeData {
+    pub cookie: isize,
+    pub display_name: String,
+    pub node_type: [u8; 16],
+    pub node_type_string: String,
+    pub snapin_clsid: [u8; 16],
+    pub window_title: String,
+    /// Identifies the node across console sessions, unlike its cookie.
+    pub persistent_id: String,
+    pub column_set_id: String,
+    pub description: Option<String>,
+    pub html_details: Option<String>,
+    /// Only the static node answers CCF_SNAPIN_PRELOADS.
+    pub preload: Option<bool>,
+    /// What gets copied as text or CSV.
+    pub properties: Vec<Property>,
+}
+
+type Serializer = fn(&NodeData) -> Option<Vec<u8>>;
+
+/// Every format we serve, by registered name. A serializer returns `None`
+/// when the node has nothing to offer in that format.
+pub const SERIALIZERS: [(&str, Serializer); 14] = [
+    // Copy and paste into other applications
+    ("CF_UNICODETEXT", |data| Some(wide(&crlf(&render::text(&data.display_name, &data.properties))))),
+    ("Csv", |data| Some(narrow(&render::csv(&data.properties)))),
+    // MMC's own formats
+    ("CCF_DISPLAY_NAME", |data| Some(wide(&data.display_name))),
+    ("CCF_NODETYPE", |data| Some(data.node_type.to_vec())),
+    ("CCF_SZNODETYPE", |data| Some(wide(&data.node_type_string))),
+    ("CCF_SNAPIN_CLASSID", |data| Some(data.snapin_clsid.to_vec())),
+    ("CCF_WINDOW_TITLE", |data| Some(wide(&data.window_title))),
+    ("CCF_NODEID", |data| Some(node_id(data.persistent_id.as_bytes()))),
+    ("CCF_NODEID2", |data| Some(node_id2(data.persistent_id.as_bytes(), 0))),
+    ("CCF_COLUMN_SET_ID", |data| Some(column_set_id(data.column_set_id.as_bytes(), 0))),
+    ("CCF_DESCRIPTION", |data| data.description.as_deref().map(wide)),
+    ("CCF_HTML_DETAILS", |data| data.html_details.as_deref().map(wide)),
+    ("CCF_SNAPIN_PRELOADS", |data| data.preload.map(win32_bool)),
+    // Private: lets us find the node behind a data object MMC hands back
+    (NODE_COOKIE, |data| Some(data.cookie.to_le_bytes().to_vec())),
+];
+
+pub const NODE_COOKIE: &str = "SMBUS_SNAPIN_NODE_COOKIE";
+
+/// Standard formats have fixed ids instead of being registered by name.
+pub fn predefined(format_name: &str) -> Option<u32> {
+    match format_name {
+        "CF_UNICODETEXT" => Some(CF_UNICODETEXT),
+        _ => None,
+    }
+}
+
+pub const CF_UNICODETEXT: u32 = 13;
+
+pub fn is_supported(format_name: &str) -> bool {
+    SERIALIZERS.iter().any(|(name, _)| *name == format_name)
+}
+
+/// Builds the payload for a clipboard format, or `None` if we don't serve
+/// that format for this node.
+pub fn serialize(format_name: &str, data: &NodeData) -> Option<Vec<u8>> {
+    SERIALIZERS.iter()
+        .find(|(name, _)| *name == format_name)
+        .and_then(|(_, serializer)| serializer(data))
+}
+
+/// Null-terminated UTF-16LE.
+pub fn wide(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+/// Null-terminated UTF-8. Excel reads Csv in the ANSI code page, so this
+/// only round-trips ASCII exactly.
+pub fn narrow(text: &str) -> Vec<u8> {
+    text.bytes().chain(std::iter::once(0)).collect()
+}
+
+// The clipboard expects Windows line endings
+fn crlf(text: &str) -> String {
+    text.lines().collect::<Vec<_>>().join("\r\n")
+}
+
+pub fn win32_bool(value: bool) -> Vec<u8> {
+    (value as i32).to_le_bytes().to_vec()
+}
+
+// SNodeID: DWORD cBytes; BYTE id[]
+pub fn node_id(id: &[u8]) -> Vec<u8> {
+    let mut bytes = (id.len() as u32).to_le_bytes().to_vec();
+    bytes.extend_from_slice(id);
+    bytes
+}
+
+// SNodeID2: DWORD dwFlags; DWORD cBytes; BYTE id[]
+pub fn node_id2(id: &[u8], flags: u32) -> Vec<u8> {
+    let mut bytes = flags.to_le_bytes().to_vec();
+    bytes.extend(node_id(id));
+    bytes
+}
+
+// SColumnSetID has the same layout as SNodeID2
+pub fn column_set_id(id: &[u8], flags: u32) -> Vec<u8> {
+    node_id2(id, flags)
+}
+
+/// Escapes text for CCF_HTML_DETAILS.
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A small HTML fragment with the node's name and description.
+pub fn html_details(title: &str, description: &str) -> String {
+    let mut html = String::new();
+    let _ = write!(html, "<b>{}</b><br>{}", html_escape(title), html_escape(description));
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> NodeData {
+        NodeData {
+            cookie: 3,
+            display_name: "EMC2101".to_owned(),
+            node_type: [7; 16],
+            node_type_string: "{07070707-0707-0707-0707-070707070707}".to_owned(),
+            snapin_clsid: [9; 16],
+            window_title: "SMBus - EMC2101".to_owned(),
+            persistent_id: "device-4c".to_owned(),
+            column_set_id: "Properties".to_owned(),
+            description: None,
+            html_details: None,
+            preload: None,
+            properties: vec![Property::text("Temperature", "41.0 \u{b0}C"), Property::text("Fan", "1200 RPM")],
+        }
+    }
+
+    fn utf16(bytes: &[u8]) -> String {
+        let units: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(units.last(), Some(&0));
+        String::from_utf16(&units[..units.len() - 1]).unwrap()
+    }
+
+    #[test]
+    fn strings_are_null_terminated() {
+        assert_eq!(wide("Ab"), vec![b'A', 0, b'b', 0, 0, 0]);
+        assert_eq!(narrow("Ab"), vec![b'A', b'b', 0]);
+        assert_eq!(utf16(&wide("41.0 \u{b0}C")), "41.0 \u{b0}C");
+    }
+
+    #[test]
+    fn node_ids_are_length_prefixed() {
+        assert_eq!(node_id(b"abc"), vec![3, 0, 0, 0, b'a', b'b', b'c']);
+        assert_eq!(node_id2(b"ab", 1), vec![1, 0, 0, 0, 2, 0, 0, 0, b'a', b'b']);
+        assert_eq!(column_set_id(b"ab", 0), node_id2(b"ab", 0));
+        assert_eq!(win32_bool(true), vec![1, 0, 0, 0]);
+        assert_eq!(win32_bool(false), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn mmc_formats_come_from_the_node_data() {
+        let data = data();
+        assert_eq!(utf16(&serialize("CCF_DISPLAY_NAME", &data).unwrap()), "EMC2101");
+        assert_eq!(serialize("CCF_NODETYPE", &data).unwrap(), vec![7; 16]);
+        assert_eq!(utf16(&serialize("CCF_SZNODETYPE", &data).unwrap()), data.node_type_string);
+        assert_eq!(serialize("CCF_SNAPIN_CLASSID", &data).unwrap(), vec![9; 16]);
+        assert_eq!(utf16(&serialize("CCF_WINDOW_TITLE", &data).unwrap()), "SMBus - EMC2101");
+        assert_eq!(serialize("CCF_NODEID", &data).unwrap(), node_id(b"device-4c"));
+        assert_eq!(serialize("CCF_NODEID2", &data).unwrap(), node_id2(b"device-4c", 0));
+        assert_eq!(serialize("CCF_COLUMN_SET_ID", &data).unwrap(), column_set_id(b"Properties", 0));
+        assert_eq!(serialize(NODE_COOKIE, &data).unwrap(), 3isize.to_le_bytes().to_vec());
+    }
+
+    #[test]
+    fn optional_formats_are_only_served_when_set() {
+        let mut data = data();
+        assert!(serialize("CCF_DESCRIPTION", &data).is_none());
+        assert!(serialize("CCF_HTML_DETAILS", &data).is_none());
+        assert!(serialize("CCF_SNAPIN_PRELOADS", &data).is_none());
+
+        data.description = Some("Fan controller".to_owned());
+        data.html_details = Some(html_details("EMC2101", "Fan controller"));
+        data.preload = Some(false);
+        assert_eq!(utf16(&serialize("CCF_DESCRIPTION", &data).unwrap()), "Fan controller");
+        assert_eq!(utf16(&serialize("CCF_HTML_DETAILS", &data).unwrap()), "<b>EMC2101</b><br>Fan controller");
+        assert_eq!(serialize("CCF_SNAPIN_PRELOADS", &data).unwrap(), win32_bool(false));
+    }
+
+    #[test]
+    fn text_formats_carry_the_properties() {
+        let data = data();
+        let text = utf16(&serialize("CF_UNICODETEXT", &data).unwrap());
+        assert_eq!(text, "EMC2101\r\n  Temperature  41.0 \u{b0}C\r\n  Fan          1200 RPM");
+
+        let csv = serialize("Csv", &data).unwrap();
+        assert_eq!(csv, narrow("Name,Value,Unit\r\nTemperature,41.0 \u{b0}C,\r\nFan,1200 RPM,\r\n"));
+    }
+
+    #[test]
+    fn only_table_formats_are_supported() {
+        for (name, _) in SERIALIZERS.iter() {
+            assert!(is_supported(name));
+        }
+        assert!(!is_supported("CF_TEXT"));
+        assert!(serialize("CF_TEXT", &data()).is_none());
+        assert_eq!(predefined("CF_UNICODETEXT"), Some(CF_UNICODETEXT));
+        assert_eq!(predefined("CCF_NODEID"), None);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(html_escape("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+        assert_eq!(html_details("A<B", "x&y"), "<b>A&lt;B</b><br>x&amp;y");
+    }
+}
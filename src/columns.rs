@@ -0,0 +1,243 @@
+// The result pane's columns. Each kind of node shows one column set; the
+// set's id is what MMC stores column widths and order under, so nodes
+// showing the same columns share the user's layout.
+//
+// Lowest, Average and Highest are over the history the snap-in keeps,
+// unlike Min and Max, which are the limits the device checks against.
+
+use crate::history::{HistoryRow, Summary};
+use crate::registry::{NodeKind, NodeModel};
+use crate::smbus::drivers::eeprom::MODULE_PROPERTIES;
+use crate::smbus::drivers::{DeviceKind, SensorReading};
+use crate::smbus::safety::SPD_EEPROM_RANGE;
+use crate::smbus::{Property, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub title: &'static str,
+    /// Initial width in pixels, until the user resizes the column.
+    pub width: i32,
+    pub align: Align,
+}
+
+const fn column(title: &'static str, width: i32, align: Align) -> Column {
+    Column { title, width, align }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ColumnSet {
+    pub id: &'static str,
+    pub columns: &'static [Column],
+}
+
+pub const NODES: ColumnSet = ColumnSet {
+    id: "smbus.nodes",
+    columns: &[
+        column("Name", 160, Align::Left),
+        column("Type", 120, Align::Left),
+        column("Description", 260, Align::Left),
+    ],
+};
+
+pub const PROPERTIES: ColumnSet = ColumnSet {
+    id: "smbus.properties",
+    columns: &[
+        column("Property", 180, Align::Left),
+        column("Value", 120, Align::Right),
+        column("Unit", 60, Align::Left),
+        column("Lowest", 70, Align::Right),
+        column("Average", 70, Align::Right),
+        column("Highest", 70, Align::Right),
+    ],
+};
+
+pub const SENSORS: ColumnSet = ColumnSet {
+    id: "smbus.sensors",
+    columns: &[
+        column("Sensor", 180, Align::Left),
+        column("Value", 80, Align::Right),
+        column("Unit", 50, Align::Left),
+        column("Min", 70, Align::Right),
+        column("Max", 70, Align::Right),
+        column("Status", 100, Align::Left),
+        column("Lowest", 70, Align::Right),
+        column("Average", 70, Align::Right),
+        column("Highest", 70, Align::Right),
+    ],
+};
+
+pub const MEMORY: ColumnSet = ColumnSet {
+    id: "smbus.memory",
+    columns: &[
+        column("Slot", 80, Align::Left),
+        column("Size", 70, Align::Right),
+        column("Speed", 100, Align::Left),
+        column("Part Number", 180, Align::Left),
+    ],
+};
+
+pub const REGISTERS: ColumnSet = ColumnSet {
+    id: "smbus.registers",
+    columns: &[
+        column("Offset", 60, Align::Left),
+        column("Values", 420, Align::Left),
+    ],
+};
+
+/// About how many rows each sensor gets in the history view. Buckets are
+/// merged to fit, however long the history is kept.
+pub const HISTORY_ROWS: usize = 60;
+
+pub const HISTORY: ColumnSet = ColumnSet {
+    id: "smbus.history",
+    columns: &[
+        column("Time (UTC)", 140, Align::Left),
+        column("Sensor", 160, Align::Left),
+        column("Lowest", 70, Align::Right),
+        column("Average", 70, Align::Right),
+        column("Highest", 70, Align::Right),
+        column("Unit", 50, Align::Left),
+        column("Samples", 60, Align::Right),
+    ],
+};
+
+/// The columns a node of this kind shows in the result pane.
+pub fn for_kind(kind: NodeKind) -> &'static ColumnSet {
+    match kind {
+        NodeKind::Root | NodeKind::Folder => &NODES,
+        NodeKind::Device(_, DeviceKind::TemperatureSensor) => &SENSORS,
+        NodeKind::Device(addr, DeviceKind::Eeprom) if SPD_EEPROM_RANGE.contains(&addr) => &MEMORY,
+        NodeKind::Device(..) => &PROPERTIES,
+        NodeKind::Registers(_) => &REGISTERS,
+        NodeKind::History(_) => &HISTORY,
+    }
+}
+
+/// A child node listed in its parent's result pane.
+pub fn node_row(node: &NodeModel) -> Vec<String> {
+    let kind = match node.kind {
+        NodeKind::Root => "SMBus".to_owned(),
+        NodeKind::Folder => "Folder".to_owned(),
+        NodeKind::Device(_, kind) => kind.to_string(),
+        NodeKind::Registers(_) => "Register dump".to_owned(),
+        NodeKind::History(_) => "Sensor history".to_owned(),
+    };
+    vec![node.label.clone(), kind, node.description().unwrap_or_default()]
+}
+
+fn number(value: Option<f64>) -> String {
+    value.map(|v| Value::Scaled(v).to_string()).unwrap_or_default()
+}
+
+// Readings with no history yet leave the history columns empty
+fn summary_cells(summary: Option<&Summary>) -> [String; 3] {
+    [
+        number(summary.map(|summary| summary.min)),
+        number(summary.map(|summary| summary.avg)),
+        number(summary.map(|summary| summary.max)),
+    ]
+}
+
+pub fn property_row(property: &Property, summary: Option<&Summary>) -> Vec<String> {
+    let mut row = vec![
+        property.name.clone(),
+        property.value.to_string(),
+        property.unit.clone().unwrap_or_default(),
+    ];
+    row.extend(summary_cells(summary));
+    row
+}
+
+pub fn sensor_row(sensor: &SensorReading, summary: Option<&Summary>) -> Vec<String> {
+    let mut row = vec![
+        sensor.name.clone(),
+        sensor.value.to_string(),
+        sensor.unit.clone().unwrap_or_default(),
+        number(sensor.min),
+        number(sensor.max),
+        sensor.status().to_string(),
+    ];
+    row.extend(summary_cells(summary));
+    row
+}
+
+/// The memory module whose SPD is at `addr`, numbered by slot the way the
+/// SPD addresses are, from its EEPROM's properties. Modules whose SPD
+/// can't be decoded only fill in the slot.
+pub fn memory_row(addr: u8, properties: &[Property]) -> Vec<String> {
+    let mut row = vec![format!("DIMM {}", addr.wrapping_sub(*SPD_EEPROM_RANGE.start()))];
+    row.extend(MODULE_PROPERTIES.iter().map(|name| {
+        properties.iter()
+            .find(|property| property.name == *name)
+            .map(Property::value_text)
+            .unwrap_or_default()
+    }));
+    row
+}
+
+pub fn history_row(row: &HistoryRow) -> Vec<String> {
+    vec![
+        crate::history::timestamp(row.bucket.start).replace('T', " ").trim_end_matches('Z').to_owned(),
+        row.sensor.clone(),
+        number(Some(row.bucket.min)),
+        number(Some(row.bucket.avg())),
+        number(Some(row.bucket.max)),
+        row.unit.clone().unwrap_or_default(),
+        row.bucket.count.to_string(),
+    ]
+}
+
+/// Splits an i2cdump-style line, `00: 12 34 ...`, into its offset and
+/// values.
+pub fn register_row(line: &str) -> Vec<String> {
+    match line.split_once(": ") {
+        Some((offset, values)) => vec![offset.to_owned(), values.to_owned()],
+        None => vec![line.to_owned()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::drivers::eeprom::MemoryModule;
+
+    #[test]
+    fn memory_modules_get_the_memory_columns() {
+        assert_eq!(for_kind(NodeKind::Device(0x52, DeviceKind::Eeprom)), &MEMORY);
+        assert_eq!(for_kind(NodeKind::Device(0x57, DeviceKind::Eeprom)), &MEMORY);
+        assert_eq!(for_kind(NodeKind::Device(0x58, DeviceKind::Eeprom)), &PROPERTIES);
+        assert_eq!(for_kind(NodeKind::Device(0x18, DeviceKind::TemperatureSensor)), &SENSORS);
+        assert_eq!(for_kind(NodeKind::Device(0x4c, DeviceKind::FanController)), &PROPERTIES);
+        assert_eq!(for_kind(NodeKind::Root), &NODES);
+        assert_eq!(NodeKind::Device(0x50, DeviceKind::Eeprom).column_set(), "smbus.memory");
+    }
+
+    #[test]
+    fn memory_rows_fill_every_column() {
+        let module = MemoryModule {
+            generation: "DDR4",
+            size_mib: 16384,
+            data_rate: 3200,
+            part_number: "M378A2K43EB1-CWE".to_owned(),
+        };
+        let mut properties = vec![Property::text("Part", "EE1004")];
+        properties.extend(module.properties());
+        assert_eq!(memory_row(0x52, &properties), vec!["DIMM 2", "16 GB", "DDR4-3200", "M378A2K43EB1-CWE"]);
+        assert_eq!(memory_row(0x50, &properties[..1]), vec!["DIMM 0", "", "", ""]);
+        assert_eq!(memory_row(0x50, &[]).len(), MEMORY.columns.len());
+    }
+
+    #[test]
+    fn column_set_ids_are_unique() {
+        let sets = [&NODES, &PROPERTIES, &SENSORS, &MEMORY, &REGISTERS, &HISTORY];
+        for (index, set) in sets.iter().enumerate() {
+            assert!(sets[index + 1..].iter().all(|other| other.id != set.id));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Which computer the snap-in manages and where it reads devices from. The
+// user picks both in the wizard shown when the snap-in is added, and the
+// console file remembers them.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::profile::Profile;
+use crate::service::client::{ServiceBus, ServiceClient};
+use crate::service::ipc::DEFAULT_ENDPOINT;
+use crate::service::remote::RemoteConfig;
+use crate::service::server::{self, BusService};
+use crate::smbus::hardware;
+use crate::smbus::safety::WritePolicy;
+use crate::smbus::sim::SimulatedBus;
+use crate::smbus::{SmbusError, SmbusTransport};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Computer {
+    /// The computer the console runs on.
+    #[default]
+    Local,
+    /// Another computer, by name.
+    Remote(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DataSource {
+    /// The computer's own SMBus controllers.
+    #[default]
+    Hardware,
+    /// Made-up devices, see `SimulatedBus::demo`.
+    Simulation,
+    /// Registers saved from a bus earlier, see `crate::smbus::sim` for the
+    /// file's layout.
+    Snapshot(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Connection {
+    pub computer: Computer,
+    pub source: DataSource,
+}
+
+impl Connection {
+    /// The root node's name. Like Computer Management, it says which
+    /// computer is being looked at.
+    pub fn root_label(&self) -> String {
+        let target = match (&self.source, &self.computer) {
+            (DataSource::Hardware, Computer::Local) => "Local".to_owned(),
+            (DataSource::Hardware, Computer::Remote(name)) => name.trim_start_matches('\\').to_uppercase(),
+            (DataSource::Simulation, _) => "Simulation".to_owned(),
+            (DataSource::Snapshot(path), _) => path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        };
+        format!("SMBus ({})", target)
+    }
+
+    /// Says what's wrong with a choice before it's taken, e.g. from the
+    /// wizard's Finish button.
+    pub fn validate(&self) -> Result<(), String> {
+        if let (DataSource::Hardware, Computer::Remote(name)) = (&self.source, &self.computer) {
+            if !is_computer_name(name) {
+                return Err(format!("\"{}\" isn't a computer name.", name));
+            }
+            // Or the snap-in would open without a bus and not say why
+            RemoteConfig::load_default()
+                .and_then(|config| config.target(name))
+                .map_err(|e| format!("Can't connect to {}: {}.", name, e))?;
+        }
+        if let DataSource::Snapshot(path) = &self.source {
+            if path.as_os_str().is_empty() {
+                return Err("Enter the snapshot file to read.".to_owned());
+            }
+            if !Path::new(path).is_file() {
+                return Err(format!("{} doesn't exist.", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the bus this connection reads. This computer's hardware is
+    /// reached through the bus service, which has the privileges for it;
+    /// other computers' through their services, as remote.toml says.
+    pub fn open(&self) -> Result<Box<dyn SmbusTransport>, SmbusError> {
+        match (&self.source, &self.computer) {
+            (DataSource::Hardware, Computer::Local) => Ok(Box::new(ServiceBus::connect(DEFAULT_ENDPOINT, "smbus-snapin")?)),
+            (DataSource::Hardware, Computer::Remote(name)) => {
+                let target = RemoteConfig::load_default()
+                    .and_then(|config| config.target(name))
+                    .map_err(|e| SmbusError::Other(format!("Can't connect to {}: {}", name, e)))?;
+                Ok(Box::new(ServiceBus::connect_remote(&target, "smbus-snapin")?))
+            }
+            (source, _) => source.open(),
+        }
+    }
+
+    /// Connects to the service for this connection's devices, as the
+    /// snap-in does. Sources in this process get a service of their own,
+    /// so a client reads every source the same way. Only its client
+    /// writes to that bus, so the client's own write policy is the one
+    /// that counts.
+    pub fn open_service(&self, client: &str) -> Result<ServiceBus, SmbusError> {
+        match (&self.source, &self.computer) {
+            (DataSource::Hardware, Computer::Local) => ServiceBus::connect(DEFAULT_ENDPOINT, client),
+            (DataSource::Hardware, Computer::Remote(name)) => {
+                let target = RemoteConfig::load_default()
+                    .and_then(|config| config.target(name))
+                    .map_err(|e| SmbusError::Other(format!("Can't connect to {}: {}", name, e)))?;
+                ServiceBus::connect_remote(&target, client)
+            }
+            (source, _) => {
+                let policy = WritePolicy { protected: Vec::new(), ..WritePolicy::default() };
+                let service = BusService::new(source.open()?, policy, Profile::builtin());
+                let (reader, writer) = server::serve_in_process(Arc::new(Mutex::new(service)))
+                    .map_err(|e| SmbusError::Other(format!("Couldn't start a service for {:?}: {}", source, e)))?;
+                ServiceClient::handshake(reader, writer, client, None).map(ServiceBus::new)
+            }
+        }
+    }
+}
+
+impl DataSource {
+    /// Opens the source in this process. The bus service uses this for
+    /// its own bus, see `crate::smbus::hardware` for which controller it
+    /// drives.
+    pub fn open(&self) -> Result<Box<dyn SmbusTransport>, SmbusError> {
+        match self {
+            DataSource::Hardware => hardware::open(),
+            DataSource::Simulation => Ok(Box::new(SimulatedBus::demo())),
+            DataSource::Snapshot(path) => Ok(Box::new(SimulatedBus::load(path)?)),
+        }
+    }
+}
+
+// NetBIOS and DNS names: letters, digits, hyphens and dots, optionally
+// with the leading backslashes people copy from UNC paths
+fn is_computer_name(name: &str) -> bool {
+    let name = name.trim_start_matches('\\');
+    !name.is_empty() && name.len() <= 253
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::scan::SCAN_RANGE;
+
+    #[test]
+    fn simulations_get_a_service_of_their_own() {
+        let connection = Connection { computer: Computer::Local, source: DataSource::Simulation };
+        let mut service = connection.open_service("test").unwrap();
+        let found: Vec<u8> = service.client().scan(SCAN_RANGE).unwrap().iter().map(|device| device.addr).collect();
+        assert_eq!(found, vec![0x4c, 0x50, 0x70]);
+        assert!(service.client().read(0x4c).is_ok());
+
+        // Raw writes are the client's to check, SPD or not
+        service.write_byte_data(0x50, 0x00, 0x12).unwrap();
+        assert_eq!(service.read_byte_data(0x50, 0x00), Ok(0x12));
+    }
+
+    #[test]
+    fn snapshots_that_cannot_be_read_fail_to_open() {
+        let connection = Connection { computer: Computer::Local, source: DataSource::Snapshot("testdata/missing.snap".into()) };
+        assert!(connection.open_service("test").is_err());
+    }
+}
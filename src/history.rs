@@ -0,0 +1,482 @@
+// What devices read over time, so a reading can be judged against the
+// last hour or day and not just the last poll.
+//
+// Each sensor keeps a ring buffer of buckets `resolution` wide, holding the
+// lowest, highest and total of the values read in them. Reads within a
+// bucket are folded into it, so however often devices are polled a sensor
+// never holds more than `retention / resolution` buckets, and a spike
+// between two polls of the view still shows as its bucket's highest.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::render::csv_field;
+use crate::smbus::drivers::DeviceKind;
+use crate::smbus::{Property, Value};
+
+/// How long readings are kept by default.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How wide a bucket is by default.
+pub const DEFAULT_RESOLUTION: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryConfig {
+    pub retention: Duration,
+    pub resolution: Duration,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { retention: DEFAULT_RETENTION, resolution: DEFAULT_RESOLUTION }
+    }
+}
+
+impl HistoryConfig {
+    /// The most buckets a sensor keeps.
+    pub fn capacity(&self) -> usize {
+        (self.retention.as_secs() / self.resolution.as_secs().max(1)).max(1) as usize
+    }
+}
+
+/// Whether devices of this kind read anything worth a history. Memory
+/// and RGB controllers don't change by themselves, so they aren't polled
+/// for one.
+pub fn keeps_history(kind: DeviceKind) -> bool {
+    match kind {
+        DeviceKind::TemperatureSensor | DeviceKind::FanController | DeviceKind::Battery => true,
+        DeviceKind::Eeprom | DeviceKind::RgbController | DeviceKind::Unknown => false,
+    }
+}
+
+/// The values read during one stretch of time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub start: SystemTime,
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: u32,
+}
+
+impl Bucket {
+    fn new(start: SystemTime, value: f64) -> Self {
+        Self { start, min: value, max: value, sum: value, count: 1 }
+    }
+
+    fn merge(&mut self, other: &Bucket) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+/// Lowest, average and highest over a whole series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Series {
+    pub unit: Option<String>,
+    buckets: VecDeque<Bucket>,
+    // When the last value was recorded, which the buckets only know to
+    // the resolution
+    last: Option<SystemTime>,
+}
+
+impl Series {
+    fn record(&mut self, at: SystemTime, value: f64, config: &HistoryConfig) {
+        let start = align(at, config.resolution);
+        self.last = self.last.max(Some(at));
+        match self.buckets.back_mut() {
+            Some(last) if last.start == start => last.merge(&Bucket::new(start, value)),
+            // A clock set back lands in the newest bucket rather than
+            // before it
+            Some(last) if last.start > start => last.merge(&Bucket::new(last.start, value)),
+            _ => self.buckets.push_back(Bucket::new(start, value)),
+        }
+        self.prune(at, config);
+    }
+
+    // Drops buckets past the retention, or beyond the capacity if the
+    // resolution was made finer
+    fn prune(&mut self, now: SystemTime, config: &HistoryConfig) {
+        let oldest = now.checked_sub(config.retention).unwrap_or(UNIX_EPOCH);
+        while self.buckets.len() > config.capacity()
+            || self.buckets.front().is_some_and(|bucket| bucket.start < oldest)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Oldest first.
+    pub fn buckets(&self) -> impl DoubleEndedIterator<Item = &Bucket> {
+        self.buckets.iter()
+    }
+
+    pub fn summary(&self) -> Option<Summary> {
+        let mut buckets = self.buckets.iter();
+        let mut total = *buckets.next()?;
+        buckets.for_each(|bucket| total.merge(bucket));
+        Some(Summary { min: total.min, avg: total.avg(), max: total.max, count: total.count })
+    }
+
+    /// The buckets merged into ones `width` wide, oldest first.
+    pub fn downsample(&self, width: Duration) -> Vec<Bucket> {
+        let mut merged: Vec<Bucket> = Vec::new();
+        for bucket in &self.buckets {
+            let start = align(bucket.start, width);
+            match merged.last_mut() {
+                Some(last) if last.start == start => last.merge(bucket),
+                _ => merged.push(Bucket { start, ..*bucket }),
+            }
+        }
+        merged
+    }
+}
+
+// The start of the `width`-wide stretch holding `at`, counted from the
+// Unix epoch so buckets line up across sensors
+fn align(at: SystemTime, width: Duration) -> SystemTime {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let width = width.as_secs().max(1);
+    UNIX_EPOCH + Duration::from_secs(secs - secs % width)
+}
+
+/// One row of a device's history view.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRow {
+    pub sensor: String,
+    pub unit: Option<String>,
+    pub bucket: Bucket,
+}
+
+/// Every device's sensors, by address and then by sensor name.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    config: HistoryConfig,
+    devices: BTreeMap<u8, BTreeMap<String, Series>>,
+}
+
+impl History {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self { config, devices: BTreeMap::new() }
+    }
+
+    pub fn config(&self) -> HistoryConfig {
+        self.config
+    }
+
+    /// Takes effect for what's read from now on. Buckets already kept
+    /// stay as wide as they were.
+    pub fn set_config(&mut self, config: HistoryConfig) {
+        self.config = config;
+        let now = SystemTime::now();
+        for series in self.devices.values_mut().flat_map(|sensors| sensors.values_mut()) {
+            series.prune(now, &config);
+        }
+    }
+
+    pub fn record(&mut self, addr: u8, name: &str, unit: Option<&str>, value: f64, at: SystemTime) {
+        let series = self.devices.entry(addr).or_default().entry(name.to_owned()).or_default();
+        series.unit = unit.map(str::to_owned);
+        series.record(at, value, &self.config);
+    }
+
+    /// Records every reading that's a number with a unit. Counts, ids and
+    /// text aren't worth a history.
+    pub fn record_properties(&mut self, addr: u8, properties: &[Property], at: SystemTime) {
+        for property in properties {
+            if let (Some(value), Some(unit)) = (property.value.number(), property.unit.as_deref()) {
+                self.record(addr, &property.name, Some(unit), value, at);
+            }
+        }
+    }
+
+    pub fn series(&self, addr: u8, name: &str) -> Option<&Series> {
+        self.devices.get(&addr)?.get(name)
+    }
+
+    pub fn summary(&self, addr: u8, name: &str) -> Option<Summary> {
+        self.series(addr, name)?.summary()
+    }
+
+    /// One line per sensor of the device, e.g. `EXT_TEMP = 31 to 58 °C,
+    /// average 40.2 °C`.
+    pub fn summaries(&self, addr: u8) -> Vec<Property> {
+        let sensors = match self.devices.get(&addr) {
+            Some(sensors) => sensors,
+            None => return Vec::new(),
+        };
+        sensors.iter()
+            .filter_map(|(name, series)| {
+                let summary = series.summary()?;
+                let unit = series.unit.as_deref();
+                let text = format!(
+                    "{} to {}, average {}",
+                    Value::Scaled(summary.min),
+                    Value::Scaled(summary.max).with_unit(unit),
+                    Value::Scaled(summary.avg).with_unit(unit),
+                );
+                Some(Property::text(name, text))
+            })
+            .collect()
+    }
+
+    /// When the device was last recorded.
+    pub fn last_recorded(&self, addr: u8) -> Option<SystemTime> {
+        self.devices.get(&addr)?.values().filter_map(|series| series.last).max()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// A device's history for the result pane, newest first, with each
+    /// sensor's buckets merged so it has about `max_rows` of them.
+    pub fn rows(&self, addr: u8, max_rows: usize) -> Vec<HistoryRow> {
+        let sensors = match self.devices.get(&addr) {
+            Some(sensors) => sensors,
+            None => return Vec::new(),
+        };
+
+        let mut rows = Vec::new();
+        for (name, series) in sensors {
+            let (first, last) = match (series.buckets.front(), series.buckets.back()) {
+                (Some(first), Some(last)) => (first.start, last.start),
+                _ => continue,
+            };
+            let span = last.duration_since(first).unwrap_or_default() + self.config.resolution;
+            let per_row = (span.as_secs() / max_rows.max(1) as u64).max(1);
+            // Rounded up to a whole number of buckets
+            let resolution = self.config.resolution.as_secs().max(1);
+            let width = Duration::from_secs(per_row.div_ceil(resolution) * resolution);
+            rows.extend(series.downsample(width).into_iter().map(|bucket| HistoryRow {
+                sensor: name.clone(),
+                unit: series.unit.clone(),
+                bucket,
+            }));
+        }
+        rows.sort_by(|a, b| b.bucket.start.cmp(&a.bucket.start).then_with(|| a.sensor.cmp(&b.sensor)));
+        rows
+    }
+
+    pub fn forget(&mut self, addr: u8) {
+        self.devices.remove(&addr);
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+
+    /// Every bucket of the device at `addr`, or of every device, as CSV
+    /// with a header line. Times are UTC.
+    pub fn to_csv(&self, addr: Option<u8>) -> String {
+        let mut out = String::from("time,address,sensor,unit,min,avg,max,samples\r\n");
+        let devices = self.devices.iter().filter(|(device, _)| addr.is_none_or(|addr| addr == **device));
+        for (device, sensors) in devices {
+            for (name, series) in sensors {
+                for bucket in &series.buckets {
+                    let _ = write!(
+                        out,
+                        "{},{:#04x},{},{},{},{},{},{}\r\n",
+                        timestamp(bucket.start),
+                        device,
+                        csv_field(name),
+                        csv_field(series.unit.as_deref().unwrap_or_default()),
+                        Value::Scaled(bucket.min),
+                        Value::Scaled(bucket.avg()),
+                        Value::Scaled(bucket.max),
+                        bucket.count,
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `at` in RFC 3339, to the second, in UTC.
+pub fn timestamp(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Days since the epoch to a civil date, after Howard Hinnant's
+    // days_from_civil, run backwards
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-11-14T22:00:00Z, on the boundary of any bucket up to an hour
+    const T: u64 = 1_699_999_200;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(T + secs)
+    }
+
+    fn config(retention: u64, resolution: u64) -> HistoryConfig {
+        HistoryConfig { retention: Duration::from_secs(retention), resolution: Duration::from_secs(resolution) }
+    }
+
+    fn starts(history: &History, addr: u8, name: &str) -> Vec<u64> {
+        history.series(addr, name).unwrap().buckets()
+            .map(|bucket| bucket.start.duration_since(UNIX_EPOCH).unwrap().as_secs() - T)
+            .collect()
+    }
+
+    #[test]
+    fn reads_within_a_bucket_are_folded_into_it() {
+        let mut history = History::new(config(3600, 10));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 40.0, at(0));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 58.0, at(3));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 31.0, at(9));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 35.0, at(10));
+        assert_eq!(starts(&history, 0x4c, "EXT_TEMP"), vec![0, 10]);
+
+        let first = *history.series(0x4c, "EXT_TEMP").unwrap().buckets().next().unwrap();
+        assert_eq!(first, Bucket { start: at(0), min: 31.0, max: 58.0, sum: 129.0, count: 3 });
+        assert_eq!(first.avg(), 43.0);
+        assert_eq!(history.last_recorded(0x4c), Some(at(10)));
+
+        // A clock set back lands in the newest bucket
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 70.0, at(0));
+        assert_eq!(starts(&history, 0x4c, "EXT_TEMP"), vec![0, 10]);
+        assert_eq!(history.series(0x4c, "EXT_TEMP").unwrap().buckets().last().unwrap().max, 70.0);
+        assert_eq!(history.last_recorded(0x4c), Some(at(10)));
+    }
+
+    #[test]
+    fn buckets_past_the_retention_are_dropped() {
+        let mut history = History::new(config(60, 10));
+        assert_eq!(history.config().capacity(), 6);
+        for i in 0..10 {
+            history.record(0x4c, "EXT_TEMP", None, i as f64, at(i * 10));
+        }
+        assert_eq!(starts(&history, 0x4c, "EXT_TEMP"), vec![40, 50, 60, 70, 80, 90]);
+
+        // After a gap longer than the retention only the new reading is left
+        history.record(0x4c, "EXT_TEMP", None, 1.0, at(1000));
+        assert_eq!(starts(&history, 0x4c, "EXT_TEMP"), vec![1000]);
+    }
+
+    #[test]
+    fn a_coarser_resolution_keeps_to_the_capacity() {
+        let mut history = History::new(config(3600, 10));
+        for i in 0..30 {
+            history.record(0x4c, "EXT_TEMP", None, i as f64, at(i * 10));
+        }
+        // Still within the retention, but there's only room for 6 buckets
+        history.config = config(3600, 600);
+        history.record(0x4c, "EXT_TEMP", None, 0.0, at(600));
+        assert_eq!(starts(&history, 0x4c, "EXT_TEMP"), vec![250, 260, 270, 280, 290, 600]);
+    }
+
+    #[test]
+    fn summaries_cover_the_whole_series() {
+        let mut history = History::new(config(3600, 10));
+        for (i, value) in [30.0, 50.0, 40.0, 40.0].into_iter().enumerate() {
+            history.record(0x4c, "EXT_TEMP", Some("°C"), value, at(i as u64 * 10));
+        }
+        assert_eq!(history.summary(0x4c, "EXT_TEMP"), Some(Summary { min: 30.0, avg: 40.0, max: 50.0, count: 4 }));
+        assert_eq!(history.summaries(0x4c), vec![Property::text("EXT_TEMP", "30 to 50 °C, average 40 °C")]);
+        assert_eq!(history.summary(0x4c, "INT_TEMP"), None);
+        assert!(history.summaries(0x2d).is_empty());
+    }
+
+    #[test]
+    fn the_trend_is_downsampled_to_the_rows_shown() {
+        let mut history = History::new(config(3600, 10));
+        for i in 0..60 {
+            history.record(0x4c, "EXT_TEMP", Some("°C"), i as f64, at(i * 10));
+        }
+        history.record(0x4c, "FAN1_TACH", Some("RPM"), 1200.0, at(590));
+
+        let series = history.series(0x4c, "EXT_TEMP").unwrap();
+        let merged = series.downsample(Duration::from_secs(30));
+        assert_eq!(merged.len(), 20);
+        assert_eq!(merged[1], Bucket { start: at(30), min: 3.0, max: 5.0, sum: 12.0, count: 3 });
+
+        // 600 s over 6 rows is a row every 100 s
+        let rows = history.rows(0x4c, 6);
+        let temps: Vec<&HistoryRow> = rows.iter().filter(|row| row.sensor == "EXT_TEMP").collect();
+        assert_eq!(temps.len(), 6);
+        assert_eq!(temps[0].bucket, Bucket { start: at(500), min: 50.0, max: 59.0, sum: 545.0, count: 10 });
+        assert_eq!(temps.iter().map(|row| row.bucket.count).sum::<u32>(), 60);
+        // Newest first across sensors, each downsampled over its own span
+        assert_eq!((rows[0].sensor.as_str(), rows[0].bucket.start), ("FAN1_TACH", at(590)));
+        assert_eq!(rows[0].unit.as_deref(), Some("RPM"));
+        assert_eq!((rows[1].sensor.as_str(), rows[1].bucket.start), ("EXT_TEMP", at(500)));
+        assert!(rows.windows(2).all(|pair| pair[0].bucket.start >= pair[1].bucket.start));
+    }
+
+    #[test]
+    fn only_numbers_with_units_are_recorded() {
+        let mut history = History::new(HistoryConfig::default());
+        history.record_properties(0x4c, &[
+            Property::new("EXT_TEMP", Value::Scaled(52.0), Some("°C")),
+            Property::new("Revision", Value::Integer(3), None),
+            Property::text("Status", "OK"),
+            Property::new("FAN1_TACH", Value::Undefined, Some("RPM")),
+        ], at(0));
+        assert!(history.series(0x4c, "EXT_TEMP").is_some());
+        assert!(history.series(0x4c, "Revision").is_none());
+        assert!(history.series(0x4c, "Status").is_none());
+        assert!(history.series(0x4c, "FAN1_TACH").is_none());
+
+        history.forget(0x4c);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn csv_has_a_line_per_bucket() {
+        let mut history = History::new(config(3600, 10));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 40.0, at(0));
+        history.record(0x4c, "EXT_TEMP", Some("°C"), 41.5, at(5));
+        history.record(0x4c, "Fan, \"main\"", Some("RPM"), 1200.0, at(10));
+        history.record(0x18, "TEMP", Some("°C"), 30.0, at(10));
+
+        assert_eq!(history.to_csv(Some(0x4c)), concat!(
+            "time,address,sensor,unit,min,avg,max,samples\r\n",
+            "2023-11-14T22:00:00Z,0x4c,EXT_TEMP,°C,40,40.75,41.5,2\r\n",
+            "2023-11-14T22:00:10Z,0x4c,\"Fan, \"\"main\"\"\",RPM,1200,1200,1200,1\r\n",
+        ));
+        let all = history.to_csv(None);
+        assert_eq!(all.lines().count(), 4);
+        assert!(all.lines().nth(1).unwrap().contains(",0x18,TEMP,"));
+        assert_eq!(History::default().to_csv(None), "time,address,sensor,unit,min,avg,max,samples\r\n");
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)), "2000-02-29T12:34:56Z");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(4_107_542_399)), "2100-02-28T23:59:59Z");
+    }
+}
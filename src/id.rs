@@ -1,9 +1,91 @@
 use windows::core::GUID;
 
+use crate::registry::NodeKind;
+use crate::smbus::drivers::DeviceKind;
+use crate::smbus::safety::SPD_EEPROM_RANGE;
+
 pub const SNAPIN_NAME: &'static str = "MMCSnapIn";
 pub const SNAPIN_CLSID: GUID = GUID { data1: 0xd39d9c35, data2: 0x6106, data3: 0x4735, data4: [0xb9, 0x44, 0x7e, 0x92, 0x9d, 0x60, 0x70, 0x00]};
 
 pub const SNAPINABOUT_NAME: &'static str = "MMCSnapInAbout";
 pub const SNAPINABOUT_CLSID: GUID = GUID { data1: 0xd39d9c35, data2: 0x6106, data3: 0x4735, data4: [0xb9, 0x44, 0x7e, 0x92, 0x9d, 0x60, 0x70, 0x01]};
 
-pub const SNAPIN_VERSION: &'static str = env!("CARGO_PKG_VERSION");
\ No newline at end of file
+pub const SNAPIN_VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+// Node types, returned through CCF_NODETYPE and CCF_SZNODETYPE. Extension
+// snap-ins register against these, so once published they must not change.
+// Controller and mux channel nodes are reserved for upcoming node kinds.
+pub const NODETYPE_ROOT: GUID = node_type_guid(0x00);
+pub const NODETYPE_FOLDER: GUID = node_type_guid(0x01);
+pub const NODETYPE_CONTROLLER: GUID = node_type_guid(0x02);
+pub const NODETYPE_MUX_CHANNEL: GUID = node_type_guid(0x03);
+pub const NODETYPE_DEVICE: GUID = node_type_guid(0x10);
+pub const NODETYPE_DIMM: GUID = node_type_guid(0x11);
+pub const NODETYPE_EEPROM: GUID = node_type_guid(0x12);
+pub const NODETYPE_BATTERY: GUID = node_type_guid(0x13);
+pub const NODETYPE_FAN: GUID = node_type_guid(0x14);
+pub const NODETYPE_SENSOR: GUID = node_type_guid(0x15);
+pub const NODETYPE_RGB: GUID = node_type_guid(0x16);
+pub const NODETYPE_REGISTERS: GUID = node_type_guid(0x20);
+pub const NODETYPE_HISTORY: GUID = node_type_guid(0x21);
+
+// Every node type with the name it's registered under
+pub const NODETYPES: [(&'static str, GUID); 13] = [
+    ("SMBus Snap-in", NODETYPE_ROOT),
+    ("Folder", NODETYPE_FOLDER),
+    ("SMBus controller", NODETYPE_CONTROLLER),
+    ("Mux channel", NODETYPE_MUX_CHANNEL),
+    ("SMBus device", NODETYPE_DEVICE),
+    ("DIMM", NODETYPE_DIMM),
+    ("EEPROM", NODETYPE_EEPROM),
+    ("Battery", NODETYPE_BATTERY),
+    ("Fan controller", NODETYPE_FAN),
+    ("Temperature sensor", NODETYPE_SENSOR),
+    ("RGB controller", NODETYPE_RGB),
+    ("Registers", NODETYPE_REGISTERS),
+    ("History", NODETYPE_HISTORY),
+];
+
+// Node types share the snap-in's CLSID except for the last two bytes
+const fn node_type_guid(n: u8) -> GUID {
+    GUID { data1: 0xd39d9c35, data2: 0x6106, data3: 0x4735, data4: [0xb9, 0x44, 0x7e, 0x92, 0x9d, 0x60, 0x71, n]}
+}
+
+pub fn node_type(kind: NodeKind) -> GUID {
+    match kind {
+        NodeKind::Root => NODETYPE_ROOT,
+        NodeKind::Folder => NODETYPE_FOLDER,
+        NodeKind::Registers(_) => NODETYPE_REGISTERS,
+        NodeKind::History(_) => NODETYPE_HISTORY,
+        NodeKind::Device(addr, device) => match device {
+            // An EEPROM where SPD lives is a memory module
+            DeviceKind::Eeprom if SPD_EEPROM_RANGE.contains(&addr) => NODETYPE_DIMM,
+            DeviceKind::Eeprom => NODETYPE_EEPROM,
+            DeviceKind::Battery => NODETYPE_BATTERY,
+            DeviceKind::FanController => NODETYPE_FAN,
+            DeviceKind::TemperatureSensor => NODETYPE_SENSOR,
+            DeviceKind::RgbController => NODETYPE_RGB,
+            DeviceKind::Unknown => NODETYPE_DEVICE,
+        },
+    }
+}
+
+// In-memory layout, as written to a clipboard medium
+pub fn guid_bytes(guid: &GUID) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[0..4].copy_from_slice(&guid.data1.to_le_bytes());
+    bytes[4..6].copy_from_slice(&guid.data2.to_le_bytes());
+    bytes[6..8].copy_from_slice(&guid.data3.to_le_bytes());
+    bytes[8..16].copy_from_slice(&guid.data4);
+    bytes
+}
+
+// Registry form, e.g. "{d39d9c35-6106-4735-b944-7e929d607100}"
+pub fn guid_string(guid: &GUID) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}}}",
+        guid.data1, guid.data2, guid.data3,
+        guid.data4[0], guid.data4[1], guid.data4[2], guid.data4[3],
+        guid.data4[4], guid.data4[5], guid.data4[6], guid.data4[7],
+    )
+}
@@ -0,0 +1,127 @@
+// Icons for scope and result items. The resources hold one bitmap strip
+// per size, the icons below in order, repeated once per state: first all
+// of them plain, then with a warning badge, a critical badge and greyed
+// out. MMC gets the whole strip, so an item's image is just an index.
+
+use crate::registry::NodeKind;
+use crate::smbus::drivers::{DeviceKind, SensorStatus};
+
+/// Resource ids of the strips, the static node's bitmaps and the toolbar's
+/// buttons, see
+/// resources.rc.
+pub const IDB_STRIP_16: u16 = 201;
+pub const IDB_STRIP_32: u16 = 202;
+pub const IDB_STATIC_16: u16 = 203;
+pub const IDB_STATIC_OPEN_16: u16 = 204;
+pub const IDB_STATIC_32: u16 = 205;
+pub const IDB_TOOLBAR_16: u16 = 206;
+
+/// The colour the bitmaps use for transparent pixels, as a COLORREF.
+pub const MASK_COLOR: u32 = 0x00FF00FF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icon {
+    Controller,
+    Mux,
+    Dimm,
+    Fan,
+    Temperature,
+    Voltage,
+    Rgb,
+    Unknown,
+    Folder,
+    FolderOpen,
+    Registers,
+}
+
+impl Icon {
+    /// How many icons each state's run of the strip holds.
+    pub const COUNT: i32 = 11;
+
+    /// The icon for a node, and the one it shows while expanded.
+    pub fn for_kind(kind: NodeKind) -> (Icon, Icon) {
+        let icon = match kind {
+            NodeKind::Root => Icon::Controller,
+            NodeKind::Folder => return (Icon::Folder, Icon::FolderOpen),
+            NodeKind::Device(_, kind) => match kind {
+                DeviceKind::Unknown => Icon::Unknown,
+                DeviceKind::Battery => Icon::Voltage,
+                DeviceKind::Eeprom => Icon::Dimm,
+                DeviceKind::FanController => Icon::Fan,
+                DeviceKind::TemperatureSensor => Icon::Temperature,
+                DeviceKind::RgbController => Icon::Rgb,
+            },
+            // The strip has no chart icon, so history shares the dump's
+            NodeKind::Registers(_) | NodeKind::History(_) => Icon::Registers,
+        };
+        (icon, icon)
+    }
+
+    /// The icon for a reading, going by its unit. Readings without a
+    /// telling unit get their device's icon.
+    pub fn for_unit(unit: Option<&str>) -> Option<Icon> {
+        match unit? {
+            "°C" => Some(Icon::Temperature),
+            "V" | "mV" | "A" | "mA" => Some(Icon::Voltage),
+            "RPM" => Some(Icon::Fan),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IconState {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+    /// The device didn't answer the last time it was read.
+    Offline,
+}
+
+impl IconState {
+    pub fn of_sensor(status: SensorStatus) -> Self {
+        match status {
+            SensorStatus::Ok | SensorStatus::Unknown => IconState::Normal,
+            SensorStatus::Low | SensorStatus::High => IconState::Warning,
+            SensorStatus::Critical => IconState::Critical,
+        }
+    }
+
+    /// The state to show for several readings together: the worst of them.
+    pub fn worst(states: impl IntoIterator<Item = IconState>) -> Self {
+        states.into_iter().max_by_key(|state| state.severity()).unwrap_or_default()
+    }
+
+    fn severity(self) -> u8 {
+        match self {
+            IconState::Normal => 0,
+            IconState::Warning => 1,
+            IconState::Critical => 2,
+            IconState::Offline => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    pub icon: Icon,
+    pub state: IconState,
+}
+
+impl Image {
+    pub fn new(icon: Icon, state: IconState) -> Self {
+        Self { icon, state }
+    }
+
+    /// The image's place in the strips.
+    pub fn index(self) -> i32 {
+        let state = match self.state {
+            IconState::Normal => 0,
+            IconState::Warning => 1,
+            IconState::Critical => 2,
+            IconState::Offline => 3,
+        };
+        state * Icon::COUNT + self.icon as i32
+    }
+}
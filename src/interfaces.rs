@@ -6,6 +6,7 @@ use windows::Win32::System::Com::{FORMATETC, STGMEDIUM};
 use windows::Win32::Foundation::{COLORREF, LPARAM, HWND};
 use windows::Win32::UI::WindowsAndMessaging::HICON;
 use windows::Win32::Graphics::Gdi::HBITMAP;
+use windows::Win32::UI::Controls::HPROPSHEETPAGE;
 use windows::core::PCWSTR;
 
 
@@ -14,17 +15,17 @@ use windows::core::PCWSTR;
 #[repr(transparent)]
 pub struct ComPCWSTR(pub PCWSTR);
 
-#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternOutput)]
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput, intercom::ExternOutput)]
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 pub struct ComHBITMAP(pub HBITMAP);
 
-#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternOutput)]
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput, intercom::ExternOutput)]
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 pub struct ComCOLORREF(pub COLORREF);
 
-#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternOutput)]
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput, intercom::ExternOutput)]
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 pub struct ComHICON(pub HICON);
@@ -34,6 +35,11 @@ pub struct ComHICON(pub HICON);
 #[repr(transparent)]
 pub struct ComHWND(pub HWND);
 
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput)]
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+pub struct ComHPROPSHEETPAGE(pub HPROPSHEETPAGE);
+
 #[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput)]
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
@@ -77,6 +83,30 @@ pub struct RESULTDATAITEM {
     pub indent: i32,
 }
 
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput, intercom::ExternOutput)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct CONTEXTMENUITEM {
+    pub name: PCWSTR,
+    pub status_bar_text: PCWSTR,
+    pub command_id: i32,
+    pub insertion_point_id: i32,
+    pub flags: i32,
+    pub special_flags: i32,
+}
+
+// CONTEXTMENUITEM insertion points and the matching bits MMC sets in
+// AddMenuItems' pInsertionAllowed
+pub const CCM_INSERTIONPOINTID_PRIMARY_TOP: i32 = 0xA0000000u32 as i32;
+pub const CCM_INSERTIONPOINTID_PRIMARY_NEW: i32 = 0xA0000001u32 as i32;
+pub const CCM_INSERTIONPOINTID_PRIMARY_TASK: i32 = 0xA0000002u32 as i32;
+pub const CCM_INSERTIONPOINTID_PRIMARY_VIEW: i32 = 0xA0000003u32 as i32;
+pub const CCM_INSERTIONALLOWED_TOP: i32 = 0x1;
+pub const CCM_INSERTIONALLOWED_NEW: i32 = 0x2;
+pub const CCM_INSERTIONALLOWED_TASK: i32 = 0x4;
+pub const CCM_INSERTIONALLOWED_VIEW: i32 = 0x8;
+
 // Should be correct
 pub const MMC_CALLBACK: PCWSTR = PCWSTR::from_raw(usize::MAX as *const u16);
 
@@ -95,17 +125,29 @@ pub struct HRESULTITEM(pub isize);
 
 #[com_interface(com_iid = "0000010e-0000-0000-C000-000000000046")]
 pub trait IDataObject: IUnknown {
-    fn get_data(&self, pformatetc: *const ComFORMATETC) -> ComResult<*mut ComSTGMEDIUM>;
+    // The caller owns *pmedium and the callee fills it in, so it's an in
+    // parameter as far as intercom is concerned
+    fn get_data(&self, pformatetc: *const ComFORMATETC, pmedium: *mut ComSTGMEDIUM) -> ComResult<()>;
     fn get_data_here(&self, pformatetc: *const ComFORMATETC, pmedium: *mut ComSTGMEDIUM) -> ComResult<()>;
-    fn query_get_data(&self, ) -> ComResult<()>;
+    fn query_get_data(&self, pformatetc: *const ComFORMATETC) -> ComResult<()>;
     fn get_canonical_format(&self, ) -> ComResult<()>;
     fn set_data(&self, ) -> ComResult<()>;
-    fn enum_format_etc(&self, ) -> ComResult<()>;
+    fn enum_format_etc(&self, direction: u32) -> ComResult<ComRc<dyn IEnumFORMATETC>>;
     fn d_advise(&self, ) -> ComResult<()>;
     fn d_unadvise(&self, ) -> ComResult<()>;
     fn enum_d_advise(&self) -> ComResult<()>;
 }
 
+/// Enumerates the formats a data object can render. `next` returns S_FALSE
+/// when fewer than `celt` formats were left.
+#[com_interface(com_iid = "00000103-0000-0000-C000-000000000046")]
+pub trait IEnumFORMATETC: IUnknown {
+    fn next(&mut self, celt: u32, rgelt: *mut ComFORMATETC, pcelt_fetched: *mut u32) -> ComResult<()>;
+    fn skip(&mut self, celt: u32) -> ComResult<()>;
+    fn reset(&mut self) -> ComResult<()>;
+    fn clone_enum(&self) -> ComResult<ComRc<dyn IEnumFORMATETC>>;
+}
+
 #[com_interface(com_iid = "955AB28A-5218-11D0-A985-00C04FD8D565")]
 pub trait IComponentData: IUnknown {
     // Snap-in entry point. Can QI for IConsole & IConsoleNameSpace
@@ -114,8 +156,9 @@ pub trait IComponentData: IUnknown {
     // Create a Component for this ComponentData
     fn create_component(&mut self) -> ComResult<ComRc<dyn IComponent>>;
 
-    // User actions
-    fn notify(&mut self, lp_dataobject: &ComItf<dyn IDataObject>, event: u32, arg: i64, param: i64) -> ComResult<()>;
+    // User actions. Some notifications, such as MMCN_PROPERTY_CHANGE, come
+    // without a data object
+    fn notify(&mut self, lp_dataobject: Option<&ComItf<dyn IDataObject>>, event: u32, arg: i64, param: i64) -> ComResult<()>;
 
     // Release cookies associated with the children of a specific node
     fn destroy(&self) -> ComResult<()>;
@@ -135,8 +178,9 @@ pub trait IComponent: IUnknown {
     // provides an entry point to the console.
     fn initialize(&mut self, lp_console: &ComItf<dyn IConsole>) -> ComResult<()>;
 
-    // notifies the snap-in of actions taken by the user.
-    fn notify(&self, lp_dataobject: &ComItf<dyn IDataObject>, event: u32, arg: i64, param: i64) -> ComResult<()>;
+    // notifies the snap-in of actions taken by the user. lp_dataobject is
+    // NULL for some notifications.
+    fn notify(&mut self, lp_dataobject: Option<&ComItf<dyn IDataObject>>, event: u32, arg: i64, param: i64) -> ComResult<()>;
 
     // releases all references to the console that are held by this component.
     fn destroy(&self) -> ComResult<()>;
@@ -169,19 +213,20 @@ pub trait IConsole: IUnknown {
     fn query_result_view(&self, ) -> ComResult<i32>;
 
     // Queries the IConsole provided image list for the scope pane.
-    fn query_scope_image_list(&self, ) -> ComResult<i32>;
+    fn query_scope_image_list(&self) -> ComResult<ComRc<dyn IImageList>>;
 
     // Queries the IConsole provided image list for the result pane.
-    fn query_result_image_list(&self, ) -> ComResult<i32>;
+    fn query_result_image_list(&self) -> ComResult<ComRc<dyn IImageList>>;
 
-    // Generates a notification to update view(s) because of content change
-    fn update_all_views(&self, ) -> ComResult<i32>;
+    // Generates a notification to update view(s) because of content change.
+    // Every IComponent gets MMCN_VIEW_CHANGE with `data` and `hint`.
+    fn update_all_views(&self, dataobject: &ComItf<dyn IDataObject>, data: isize, hint: isize) -> ComResult<()>;
 
     // Displays a message box
     fn message_box(&self, text: ComPCWSTR, title: ComPCWSTR, style: u32) -> ComResult<i32>;
 
     // Query for the IConsoleVerb.
-    fn query_console_verb(&self, ) -> ComResult<i32>;
+    fn query_console_verb(&self) -> ComResult<ComRc<dyn IConsoleVerb>>;
 
     // Selects the given scope item.
     fn select_scope_item(&self, ) -> ComResult<i32>;
@@ -295,7 +340,45 @@ pub trait IRequiredExtensions: IUnknown {
     fn get_next_extension(&self) -> ComResult<()>;
 }
 
-// The next step is to get IResultData working
+#[com_interface(com_iid = "4F3B7A4F-CFAC-11CF-B8E3-00C04FD8D5B0")]
+pub trait IExtendContextMenu: IUnknown {
+    // Adds our items for the selected data object. insertion_allowed says
+    // which CCM_INSERTIONALLOWED_* groups we may add to.
+    fn add_menu_items(&mut self, dataobject: &ComItf<dyn IDataObject>, callback: &ComItf<dyn IContextMenuCallback>, insertion_allowed: *mut i32) -> ComResult<()>;
+
+    // One of our items was picked
+    fn command(&mut self, command_id: i32, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "43136EB7-D36C-11CF-ADBC-00AA00A80033")]
+pub trait IContextMenuCallback: IUnknown {
+    fn add_item(&self, item: *mut CONTEXTMENUITEM) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "85DE64DC-EF21-11cf-A285-00C04FD8DBE6")]
+pub trait IExtendPropertySheet: IUnknown {
+    // Adds our pages to the sheet for the data object. handle is for
+    // MMCPropertyChangeNotify and has to be freed with MMCFreeNotifyHandle.
+    fn create_property_pages(&mut self, callback: &ComItf<dyn IPropertySheetCallback>, handle: isize, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()>;
+
+    // S_OK if the data object has pages, S_FALSE if it doesn't
+    fn query_pages_for(&self, dataobject: &ComItf<dyn IDataObject>) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "B7A87232-4A51-11D1-A7EA-00C04FD909DD")]
+pub trait IExtendPropertySheet2: IExtendPropertySheet {
+    // Wizard 97 watermark and header bitmaps, palette and whether to
+    // stretch the watermark
+    fn get_watermarks(&self, dataobject: &ComItf<dyn IDataObject>) -> ComResult<(ComHBITMAP, ComHBITMAP, isize, i32)>;
+}
+
+#[com_interface(com_iid = "85DE64DD-EF21-11cf-A285-00C04FD8DBE6")]
+pub trait IPropertySheetCallback: IUnknown {
+    fn add_page(&self, page: ComHPROPSHEETPAGE) -> ComResult<()>;
+    fn remove_page(&self, page: ComHPROPSHEETPAGE) -> ComResult<()>;
+}
+
+// How a snap-in drives the result pane's list view:
 // https://learn.microsoft.com/en-us/previous-versions/windows/desktop/mmc/using-list-views-implementation-details
 #[com_interface(com_iid = "31DA5FA0-E0EB-11cf-9F21-00AA003CA9F6")]
 pub trait IResultData: IUnknown {
@@ -306,56 +389,250 @@ pub trait IResultData: IUnknown {
     fn delete_item(&self, itemid: HRESULTITEM, _reserved: std::ffi::c_int) -> ComResult<()>;
 
     // Allows the snap-in to find an item/subitem based on its user inserted lParam.
-    // HRESULT FindItemByLParam([in] LPARAM lParam, [out] HRESULTITEM *pItemID);
-    fn find_item_by_lparam(&self, ) -> ComResult<()>;
+    fn find_item_by_lparam(&self, lparam: isize) -> ComResult<HRESULTITEM>;
 
     // Allows the snap-in to delete all the items.
-    // HRESULT DeleteAllRsltItems();
-    fn delete_all_rslt_items(&self, ) -> ComResult<()>;
+    fn delete_all_rslt_items(&self) -> ComResult<()>;
 
     // Allows the snap-in to set a single item.
-    // HRESULT SetItem([in] LPRESULTDATAITEM item);
-    fn set_item(&self, ) -> ComResult<()>;
+    fn set_item(&self, resultdataitem: *mut RESULTDATAITEM) -> ComResult<()>;
 
     // Allows the snap-in to get a single item.
-    // HRESULT GetItem([in,out] LPRESULTDATAITEM item);
-    fn get_item(&self, ) -> ComResult<()>;
+    fn get_item(&self, resultdataitem: *mut RESULTDATAITEM) -> ComResult<()>;
 
     // Returns the lParam of the first item, which matches the given state.
-    // HRESULT GetNextItem([in,out] LPRESULTDATAITEM item);
-    fn get_next_item(&self, ) -> ComResult<()>;
+    fn get_next_item(&self, resultdataitem: *mut RESULTDATAITEM) -> ComResult<()>;
 
     // Allows the snap-in to modify the state of an item.
-    // HRESULT ModifyItemState([in] int nIndex, [in] HRESULTITEM itemID,
-    //                      [in] UINT uAdd, [in] UINT uRemove);
-    fn modify_item_state(&self, ) -> ComResult<()>;
+    fn modify_item_state(&self, index: i32, itemid: HRESULTITEM, add: u32, remove: u32) -> ComResult<()>;
 
     // Allows the snap-in to set the result view style.
-    // HRESULT ModifyViewStyle([in] MMC_RESULT_VIEW_STYLE add,
-    //                   [in] MMC_RESULT_VIEW_STYLE remove);
-    fn modify_view_style(&self, ) -> ComResult<()>;
+    fn modify_view_style(&self, add: i32, remove: i32) -> ComResult<()>;
 
     // Allows the snap-in to set the result view mode.
-    // HRESULT SetViewMode([in] long lViewMode);
-    fn set_view_mode(&self, ) -> ComResult<()>;
+    fn set_view_mode(&self, view_mode: i32) -> ComResult<()>;
 
     // Allows the snap-in to get the result view mode.
-    // HRESULT GetViewMode([out] long* lViewMode);
-    fn get_view_mode(&self, ) -> ComResult<()>;
+    fn get_view_mode(&self) -> ComResult<i32>;
 
-    // Allows the snap-in to update a single item.
-    // HRESULT UpdateItem([in] HRESULTITEM itemID);
-    fn update_item(&self, ) -> ComResult<()>;
+    // Allows the snap-in to update a single item. MMC asks for its display
+    // info again and redraws just that item.
+    fn update_item(&self, itemid: HRESULTITEM) -> ComResult<()>;
 
     // Sort all items in result pane
-    // HRESULT Sort([in] int nColumn, [in] DWORD dwSortOptions, [in] LPARAM lUserParam);
-    fn sort(&self, ) -> ComResult<()>;
+    fn sort(&self, column: i32, options: u32, user_param: isize) -> ComResult<()>;
 
     // Set the description bar text for the result view
-    // HRESULT SetDescBarText([in] LPOLESTR DescText);
-    fn set_desc_bar_text(&self, ) -> ComResult<()>;
+    fn set_desc_bar_text(&self, text: ComPCWSTR) -> ComResult<()>;
 
     // Set number of items in result pane list
-    // HRESULT SetItemCount([in] int nItemCount, [in] DWORD dwOptions);
-    fn set_item_count(&self, ) -> ComResult<()>;
-}
\ No newline at end of file
+    fn set_item_count(&self, count: i32, options: u32) -> ComResult<()>;
+}
+// LVCFMT_* alignments for IHeaderCtrl::InsertColumn's nFormat
+pub const LVCFMT_LEFT: i32 = 0x0000;
+pub const LVCFMT_RIGHT: i32 = 0x0001;
+
+#[com_interface(com_iid = "43136EB3-D36C-11CF-ADBC-00AA00A80033")]
+pub trait IHeaderCtrl: IUnknown {
+    // Adds a column to the result pane's list view.
+    fn insert_column(&self, col: i32, title: ComPCWSTR, format: i32, width: i32) -> ComResult<()>;
+
+    // Removes a column.
+    fn delete_column(&self, col: i32) -> ComResult<()>;
+
+    // Changes a column's title.
+    fn set_column_text(&self, col: i32, title: ComPCWSTR) -> ComResult<()>;
+
+    // Gets a column's title. MMC allocates it; free it with CoTaskMemFree.
+    // HRESULT GetColumnText([in] int nCol, [out] LPOLESTR* pText);
+    fn get_column_text(&self, ) -> ComResult<()>;
+
+    // Changes a column's width.
+    fn set_column_width(&self, col: i32, width: i32) -> ComResult<()>;
+
+    // Gets a column's width.
+    fn get_column_width(&self, col: i32) -> ComResult<i32>;
+}
+
+#[com_interface(com_iid = "9757ABB8-1B32-11D1-A7CE-00C04FD8D565")]
+pub trait IHeaderCtrl2: IHeaderCtrl {
+    // Sets the delay before a changed column filter is applied.
+    fn set_change_time_out(&self, timeout: u32) -> ComResult<()>;
+
+    // Sets a column's filter.
+    // HRESULT SetColumnFilter([in] UINT nColumn, [in] DWORD dwType, [in] MMC_FILTERDATA* pFilterData);
+    fn set_column_filter(&self, ) -> ComResult<()>;
+
+    // Gets a column's filter.
+    // HRESULT GetColumnFilter([in] UINT nColumn, [in,out] LPDWORD pdwType, [in,out] MMC_FILTERDATA* pFilterData);
+    fn get_column_filter(&self, ) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "43136EB8-D36C-11CF-ADBC-00AA00A80033")]
+pub trait IImageList: IUnknown {
+    // Adds an icon at index `loc`.
+    // HRESULT ImageListSetIcon([in] LONG_PTR* pIcon, [in] long nLoc);
+    fn image_list_set_icon(&self, icon: ComHICON, loc: i32) -> ComResult<()>;
+
+    // Adds a strip of icons starting at index `start`, small and large
+    // versions in separate bitmaps. MMC copies them, so the snap-in can
+    // delete the bitmaps afterwards.
+    // HRESULT ImageListSetStrip([in] LONG_PTR* pBMapSm, [in] LONG_PTR* pBMapLg,
+    //                           [in] long nStartLoc, [in] COLORREF cMask);
+    fn image_list_set_strip(&self, small: ComHBITMAP, large: ComHBITMAP, start: i32, mask: ComCOLORREF) -> ComResult<()>;
+}
+
+// MMC_CONSOLE_VERB values for the verbs we use
+pub const MMC_VERB_OPEN: i32 = 0x8000;
+pub const MMC_VERB_COPY: i32 = 0x8001;
+pub const MMC_VERB_DELETE: i32 = 0x8003;
+pub const MMC_VERB_PROPERTIES: i32 = 0x8004;
+pub const MMC_VERB_RENAME: i32 = 0x8005;
+pub const MMC_VERB_REFRESH: i32 = 0x8006;
+
+// MMC_BUTTON_STATE
+pub const MMC_BUTTON_ENABLED: i32 = 0x01;
+pub const MMC_BUTTON_CHECKED: i32 = 0x02;
+
+#[com_interface(com_iid = "E49F7A60-74AF-11D0-A286-00C04FD8FE93")]
+pub trait IConsoleVerb: IUnknown {
+    // Gets one state of a verb, e.g. whether it's enabled. The BOOLs are
+    // i32s on the wire.
+    fn get_verb_state(&self, verb: i32, state: i32) -> ComResult<i32>;
+
+    // Sets one state of a verb.
+    fn set_verb_state(&self, verb: i32, state: i32, value: i32) -> ComResult<()>;
+
+    // Sets what double-clicking the selected item does.
+    fn set_default_verb(&self, verb: i32) -> ComResult<()>;
+
+    // Gets what double-clicking the selected item does.
+    fn get_default_verb(&self) -> ComResult<i32>;
+}
+
+// MMC_CONTROL_TYPE
+pub const MMC_TOOLBAR: i32 = 0;
+pub const MMC_MENUBUTTON: i32 = 1;
+
+// TBSTATE_* and TBSTYLE_* for MMCBUTTON
+pub const TBSTATE_ENABLED: u8 = 0x04;
+pub const TBSTYLE_BUTTON: u8 = 0x00;
+pub const TBSTYLE_CHECK: u8 = 0x02;
+
+#[derive(intercom::ExternType, intercom::ForeignType, intercom::ExternInput, intercom::ExternOutput)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct MMCBUTTON {
+    pub bitmap: i32,
+    pub command_id: i32,
+    pub state: u8,
+    pub style: u8,
+    pub button_text: PCWSTR,
+    pub tooltip_text: PCWSTR,
+}
+
+// What MMCN_MENU_BTNCLICK's param points to: the menu button's command id
+// and where to drop its menu, in screen coordinates.
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct MENUBUTTONDATA {
+    pub command_id: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[com_interface(com_iid = "49506520-6F40-11D0-A98B-00C04FD8D565")]
+pub trait IExtendControlbar: IUnknown {
+    // Hands the snap-in the control bar, or None when the view closes and
+    // the snap-in should let go of it.
+    fn set_controlbar(&mut self, controlbar: Option<&ComItf<dyn IControlbar>>) -> ComResult<()>;
+
+    // Selection changes and clicks on the snap-in's controls. Like
+    // IComponent::Notify, except arg and param swap roles between events.
+    fn controlbar_notify(&mut self, event: u32, arg: i64, param: i64) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "69FB811E-6C1C-11D0-A2CB-00C04FD909DD")]
+pub trait IControlbar: IUnknown {
+    // Creates a toolbar or menu button for the snap-in. MMC reports clicks
+    // on it to `extend`.
+    fn create(&self, control_type: i32, extend: &ComItf<dyn IExtendControlbar>) -> ComResult<ComRc<dyn IUnknown>>;
+
+    // Shows a control created with Create.
+    fn attach(&self, control_type: i32, control: &ComItf<dyn IUnknown>) -> ComResult<()>;
+
+    // Hides a control again.
+    fn detach(&self, control: &ComItf<dyn IUnknown>) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "43136EB9-D36C-11CF-ADBC-00AA00A80033")]
+pub trait IToolbar: IUnknown {
+    // Adds `count` images of `width` by `height` from a strip. MMC copies
+    // them, so the snap-in can delete the bitmap afterwards.
+    fn add_bitmap(&self, count: i32, bitmap: ComHBITMAP, width: i32, height: i32, mask: ComCOLORREF) -> ComResult<()>;
+
+    // Adds buttons to the end of the toolbar.
+    fn add_buttons(&self, count: i32, buttons: *mut MMCBUTTON) -> ComResult<()>;
+
+    // Inserts a button at `index`.
+    fn insert_button(&self, index: i32, button: *mut MMCBUTTON) -> ComResult<()>;
+
+    // Removes the button at `index`.
+    fn delete_button(&self, index: i32) -> ComResult<()>;
+
+    // Gets one MMC_BUTTON_STATE of a button. The BOOL is an i32 on the wire.
+    fn get_button_state(&self, command_id: i32, state: i32) -> ComResult<i32>;
+
+    // Sets one MMC_BUTTON_STATE of a button.
+    fn set_button_state(&self, command_id: i32, state: i32, value: i32) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "951ED750-D080-11D0-B197-000000000000")]
+pub trait IMenuButton: IUnknown {
+    // Adds a menu button.
+    fn add_button(&self, command_id: i32, text: ComPCWSTR, tooltip: ComPCWSTR) -> ComResult<()>;
+
+    // Changes a menu button's text.
+    fn set_button(&self, command_id: i32, text: ComPCWSTR, tooltip: ComPCWSTR) -> ComResult<()>;
+
+    // Sets one MMC_BUTTON_STATE of a menu button.
+    fn set_button_state(&self, command_id: i32, state: i32, value: i32) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "0000000C-0000-0000-C000-000000000046")]
+pub trait IStream: IUnknown {
+    // ISequentialStream. Both return how many bytes were transferred,
+    // which for Read is less than asked at the end of the stream.
+    fn read(&self, buffer: *mut u8, count: u32) -> ComResult<u32>;
+    fn write(&self, buffer: *const u8, count: u32) -> ComResult<u32>;
+
+    fn seek(&self) -> ComResult<()>;
+    fn set_size(&self) -> ComResult<()>;
+    fn copy_to(&self) -> ComResult<()>;
+    fn commit(&self) -> ComResult<()>;
+    fn revert(&self) -> ComResult<()>;
+    fn lock_region(&self) -> ComResult<()>;
+    fn unlock_region(&self) -> ComResult<()>;
+    fn stat(&self) -> ComResult<()>;
+    fn clone_stream(&self) -> ComResult<()>;
+}
+
+#[com_interface(com_iid = "00000109-0000-0000-C000-000000000046")]
+pub trait IPersistStream: IUnknown {
+    // IPersist
+    fn get_class_id(&self) -> ComResult<intercom::GUID>;
+
+    // S_OK when there are changes to save, S_FALSE when there aren't
+    fn is_dirty(&self) -> ComResult<()>;
+
+    fn load(&mut self, stream: &ComItf<dyn IStream>) -> ComResult<()>;
+
+    // clear_dirty is a BOOL: whether the console file is being saved, as
+    // opposed to copied
+    fn save(&mut self, stream: &ComItf<dyn IStream>, clear_dirty: i32) -> ComResult<()>;
+
+    // How many bytes Save might write
+    fn get_size_max(&self) -> ComResult<u64>;
+}
@@ -1,14 +1,38 @@
+// The COM side only exists on Windows. The node registry and the SMBus
+// domain layer have no platform dependencies so they build (and can be
+// tested) anywhere.
+#[cfg(windows)]
 use intercom::prelude::*;
 
+#[cfg(windows)]
 mod class;
+#[cfg(windows)]
 use class::*;
 
+#[cfg(windows)]
 mod interfaces;
+#[cfg(windows)]
 mod registration;
+#[cfg(windows)]
 pub mod id;
+pub mod actions;
+pub mod clipformat;
+pub mod columns;
+pub mod connect;
+pub mod history;
+pub mod images;
+pub mod profile;
+pub mod propsheet;
+pub mod render;
+pub mod registry;
+pub mod service;
+pub mod settings;
+pub mod smbus;
 
+#[cfg(windows)]
 use registration::{register, unregister};
 
+#[cfg(windows)]
 com_library!(
     on_load = on_load,
     on_register = register,
@@ -18,6 +42,7 @@ com_library!(
     class Node,
 );
 
+#[cfg(windows)]
 fn on_load() {
     // Set up logging to project directory
     use log::LevelFilter;
@@ -0,0 +1,141 @@
+// Profiles: named sets of device settings applied in one go, e.g. slower
+// fans for the night. A profile is a TOML document:
+//
+//     [profile]
+//     name = "Quiet"
+//     description = "Run fans slowly"
+//
+//     [[setting]]
+//     address = 0x4c
+//     key = "FAN1_PWM"
+//     value = 19
+//
+// `key` and `value` are what a device's Properties dialog would write, so a
+// profile can set anything the dialog can.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::smbus::{SmbusError, SmbusTransport};
+use crate::smbus::drivers::Driver;
+
+/// Profiles shipped with the snap-in.
+pub const BUILTIN_PROFILES: &[(&str, &str)] = &[
+    ("quiet.toml", include_str!("../profiles/quiet.toml")),
+    ("full-speed.toml", include_str!("../profiles/full-speed.toml")),
+];
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "couldn't read profile: {}", e),
+            ProfileError::Parse(e) => write!(f, "couldn't parse profile: {}", e),
+            ProfileError::Invalid(e) => write!(f, "invalid profile: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(e: std::io::Error) -> Self {
+        ProfileError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileSetting {
+    #[serde(rename = "address")]
+    pub addr: u8,
+    pub key: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub description: Option<String>,
+    pub settings: Vec<ProfileSetting>,
+}
+
+#[derive(Deserialize)]
+struct RawProfile {
+    profile: RawHeader,
+    #[serde(default, rename = "setting")]
+    settings: Vec<ProfileSetting>,
+}
+
+#[derive(Deserialize)]
+struct RawHeader {
+    name: String,
+    description: Option<String>,
+}
+
+impl Profile {
+    pub fn from_toml(text: &str) -> Result<Self, ProfileError> {
+        let raw: RawProfile = toml::from_str(text).map_err(|e| ProfileError::Parse(e.to_string()))?;
+        if raw.profile.name.trim().is_empty() {
+            return Err(ProfileError::Invalid("the profile has no name".to_owned()));
+        }
+        if raw.settings.is_empty() {
+            return Err(ProfileError::Invalid(format!("\"{}\" doesn't set anything", raw.profile.name)));
+        }
+        Ok(Self {
+            name: raw.profile.name,
+            description: raw.profile.description,
+            settings: raw.settings,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text)
+    }
+
+    pub fn builtin() -> Vec<Profile> {
+        BUILTIN_PROFILES.iter()
+            .filter_map(|(file, text)| match Self::from_toml(text) {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    log::error!("Built-in profile {}: {}", file, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Writes the profile's settings through the drivers of the devices
+    /// they name. Pass a `GuardedBus` so the writes obey the safety policy.
+    /// Carries on past failures, including devices that weren't found, and
+    /// returns all of them.
+    pub fn apply(&self, drivers: &HashMap<u8, Box<dyn Driver>>, bus: &mut dyn SmbusTransport) -> Result<(), Vec<(ProfileSetting, SmbusError)>> {
+        log::info!("Applying profile \"{}\"", self.name);
+
+        let mut failures = Vec::new();
+        for setting in &self.settings {
+            let result = match drivers.get(&setting.addr) {
+                Some(driver) => driver.write_setting(bus, &setting.key, setting.value),
+                None => Err(SmbusError::Other(format!("no known device at {:#04x}", setting.addr))),
+            };
+            if let Err(e) = result {
+                log::error!("Couldn't set {} at {:#04x}: {}", setting.key, setting.addr, e);
+                failures.push((setting.clone(), e));
+            }
+        }
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(failures),
+        }
+    }
+}
@@ -0,0 +1,548 @@
+// What a device's Properties dialog shows and edits. The dialog only copies
+// text in and out of these pages; reading the device, checking what the
+// user typed and writing it back all happen here.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::history::HistoryConfig;
+use crate::smbus::{arp, Property, SmbusError, SmbusTransport};
+use crate::smbus::drivers::{eeprom, DeviceKind, Driver, Setting};
+use crate::smbus::safety::SPD_EEPROM_RANGE;
+
+/// How many fields fit on one page. Longer lists are split over several
+/// pages.
+pub const FIELDS_PER_PAGE: usize = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    Identification,
+    Settings,
+    Thresholds,
+    History,
+}
+
+impl PageKind {
+    pub fn title(self) -> &'static str {
+        match self {
+            PageKind::Identification => "Identification",
+            PageKind::Settings => "Settings",
+            PageKind::Thresholds => "Thresholds",
+            PageKind::History => "History",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub label: String,
+    /// What the control shows: the value as read, or what the user typed.
+    pub text: String,
+    pub unit: Option<String>,
+    /// The setting the field edits. `None` for read-only fields.
+    pub setting: Option<Setting>,
+}
+
+impl Field {
+    fn read_only(property: &Property) -> Self {
+        Self {
+            label: property.name.clone(),
+            text: property.value_text(),
+            unit: None,
+            setting: None,
+        }
+    }
+
+    fn editable(setting: Setting) -> Self {
+        Self {
+            label: setting.label.clone(),
+            text: setting.text(),
+            unit: setting.unit.clone(),
+            setting: Some(setting),
+        }
+    }
+
+    /// Whether the text no longer says the value the device has. Text that
+    /// doesn't parse counts, so validation gets to complain about it.
+    pub fn is_modified(&self) -> bool {
+        match &self.setting {
+            Some(setting) => match setting.parse(&self.text) {
+                Ok(value) => value != setting.value,
+                Err(_) => true,
+            },
+            None => false,
+        }
+    }
+}
+
+/// A value the user changed, checked and ready to be written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub key: String,
+    pub label: String,
+    pub value: f64,
+}
+
+/// Why a field's text can't be written, with where to find the field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub page: usize,
+    pub field: usize,
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub kind: PageKind,
+    pub title: String,
+    pub fields: Vec<Field>,
+}
+
+impl Page {
+    // Splits fields over as many pages as they need, numbering the titles
+    // of all but the first
+    fn split(kind: PageKind, fields: Vec<Field>) -> Vec<Page> {
+        fields.chunks(FIELDS_PER_PAGE)
+            .enumerate()
+            .map(|(index, fields)| Page {
+                kind,
+                title: match index {
+                    0 => kind.title().to_owned(),
+                    _ => format!("{} ({})", kind.title(), index + 1),
+                },
+                fields: fields.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Stores what the user typed into a field. Returns false if there's no
+    /// such field or it's read-only.
+    pub fn set_text(&mut self, field: usize, text: &str) -> bool {
+        match self.fields.get_mut(field) {
+            Some(field) if field.setting.is_some() => {
+                field.text = text.to_owned();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.fields.iter().any(Field::is_modified)
+    }
+
+    /// Checks every modified field, stopping at the first one that can't
+    /// be written. `index` is the page's place in its sheet, for the error.
+    pub fn changes(&self, index: usize) -> Result<Vec<Change>, FieldError> {
+        let mut changes = Vec::new();
+        for (field_index, field) in self.fields.iter().enumerate() {
+            let setting = match &field.setting {
+                Some(setting) if field.is_modified() => setting,
+                _ => continue,
+            };
+            match setting.parse(&field.text) {
+                Ok(value) => changes.push(Change {
+                    key: setting.key.clone(),
+                    label: setting.label.clone(),
+                    value,
+                }),
+                Err(message) => return Err(FieldError {
+                    page: index,
+                    field: field_index,
+                    label: field.label.clone(),
+                    message,
+                }),
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// Every page of one device's Properties dialog, or of the root's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSheet {
+    /// `None` for the root, whose sheet holds the console's own settings.
+    pub addr: Option<u8>,
+    pub pages: Vec<Page>,
+}
+
+impl DeviceSheet {
+    /// Reads everything the sheet shows. Without a bus only what the
+    /// snap-in already knows goes on the Identification page.
+    pub fn build(addr: u8, kind: DeviceKind, driver: Option<&dyn Driver>, mut bus: Option<&mut dyn SmbusTransport>) -> Self {
+        let identification = identification(addr, kind, driver, bus.as_mut().map(|bus| &mut **bus as &mut dyn SmbusTransport));
+        let mut pages = Page::split(PageKind::Identification, identification.iter().map(Field::read_only).collect());
+
+        if let (Some(driver), Some(bus)) = (driver, bus) {
+            pages.extend(Page::split(PageKind::Settings, setting_fields(driver.settings(bus))));
+            pages.extend(Page::split(PageKind::Thresholds, setting_fields(driver.thresholds(bus))));
+        }
+
+        Self { addr: Some(addr), pages }
+    }
+
+    /// A device's sheet from what the bus service says about it: its
+    /// identification and, for devices a driver recognised, its settings
+    /// and thresholds, which the service sends together.
+    pub fn from_service(addr: u8, identification: &[Property], settings: Option<Result<Vec<Setting>, SmbusError>>) -> Self {
+        let mut pages = Page::split(PageKind::Identification, identification.iter().map(Field::read_only).collect());
+        if let Some(settings) = settings {
+            pages.extend(Page::split(PageKind::Settings, setting_fields(settings)));
+        }
+        Self { addr: Some(addr), pages }
+    }
+
+    /// The root's sheet: how long readings are kept, and how finely.
+    pub fn console(history: HistoryConfig) -> Self {
+        let fields = vec![
+            Field::editable(Setting::number(RETENTION, "Keep readings for", (history.retention.as_secs() / 3600) as f64, 1.0, 720.0, 1.0, Some("h"))),
+            Field::editable(Setting::number(RESOLUTION, "Bucket width", history.resolution.as_secs() as f64, 1.0, 3600.0, 1.0, Some("s"))),
+        ];
+        Self { addr: None, pages: Page::split(PageKind::History, fields) }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.pages.iter().any(Page::is_modified)
+    }
+
+    /// Checks the modified fields of every page.
+    pub fn changes(&self) -> Result<Vec<Change>, FieldError> {
+        let mut changes = Vec::new();
+        for (index, page) in self.pages.iter().enumerate() {
+            changes.extend(page.changes(index)?);
+        }
+        Ok(changes)
+    }
+
+    /// Takes `changes` as the device's values from now on, once they've
+    /// been handed off to be written, and tidies the fields' text.
+    pub fn commit(&mut self, changes: &[Change]) {
+        for field in self.pages.iter_mut().flat_map(|page| page.fields.iter_mut()) {
+            let setting = match &mut field.setting {
+                Some(setting) => setting,
+                None => continue,
+            };
+            if let Some(change) = changes.iter().find(|change| change.key == setting.key) {
+                setting.value = change.value;
+                field.text = setting.text();
+            }
+        }
+    }
+}
+
+// Keys of the root sheet's settings
+const RETENTION: &str = "HISTORY_RETENTION";
+const RESOLUTION: &str = "HISTORY_RESOLUTION";
+
+/// `history` with the changes from the root's sheet made. Buckets wider
+/// than the time they're kept for are refused.
+pub fn history_config(history: HistoryConfig, changes: &[Change]) -> Result<HistoryConfig, String> {
+    let mut config = history;
+    for change in changes {
+        match change.key.as_str() {
+            RETENTION => config.retention = Duration::from_secs(change.value as u64 * 3600),
+            RESOLUTION => config.resolution = Duration::from_secs(change.value as u64),
+            _ => return Err(format!("{} isn't a console setting", change.label)),
+        }
+    }
+    match config.resolution <= config.retention {
+        true => Ok(config),
+        false => Err(format!(
+            "Buckets {} s wide can't be kept for only {} h",
+            config.resolution.as_secs(),
+            config.retention.as_secs() / 3600,
+        )),
+    }
+}
+
+// Read errors become a single read-only field so the page says why it's
+// empty
+fn setting_fields(settings: Result<Vec<Setting>, SmbusError>) -> Vec<Field> {
+    match settings {
+        Ok(settings) => settings.into_iter().map(Field::editable).collect(),
+        Err(e) => vec![Field::read_only(&Property::text("Read failed", e.to_string()))],
+    }
+}
+
+/// What the Identification page shows: the address and driver, whatever
+/// the driver can read about the part, the UDID of ARP-capable devices,
+/// and an SPD summary for EEPROMs where memory modules keep theirs.
+pub fn identification(addr: u8, kind: DeviceKind, driver: Option<&dyn Driver>, bus: Option<&mut dyn SmbusTransport>) -> Vec<Property> {
+    let mut properties = vec![
+        Property::text("Address", format!("{:#04x}", addr)),
+        Property::text("Type", kind.to_string()),
+        Property::text("Driver", driver.map(|driver| driver.name()).unwrap_or_else(|| "None".to_owned())),
+    ];
+
+    let bus = match bus {
+        Some(bus) => bus,
+        None => return properties,
+    };
+
+    if let Some(driver) = driver {
+        properties.extend(driver.identification(bus));
+    }
+    match arp::get_udid(bus, addr) {
+        Ok(udid) => properties.extend(udid.iter().flat_map(arp::Udid::properties)),
+        Err(e) => log::debug!("Couldn't read the UDID of {:#04x}: {}", addr, e),
+    }
+    if matches!(kind, DeviceKind::Unknown | DeviceKind::Eeprom) && SPD_EEPROM_RANGE.contains(&addr) {
+        match eeprom::spd_summary(bus, addr) {
+            Ok(summary) => properties.extend(summary),
+            Err(e) => log::debug!("Couldn't read SPD at {:#04x}: {}", addr, e),
+        }
+    }
+    properties
+}
+
+/// Writes changes from a sheet. Pass a `GuardedBus` so the writes obey the
+/// safety policy. Carries on past failures and returns all of them.
+pub fn apply(driver: &dyn Driver, bus: &mut dyn SmbusTransport, changes: &[Change]) -> Result<(), Vec<(Change, SmbusError)>> {
+    let mut failures = Vec::new();
+    for change in changes {
+        log::info!("Setting {} of {} at {:#04x} to {}", change.label, driver.name(), driver.addr(), change.value);
+        if let Err(e) = driver.write_setting(bus, &change.key, change.value) {
+            log::error!("Couldn't set {}: {}", change.label, e);
+            failures.push((change.clone(), e));
+        }
+    }
+
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(failures),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::drivers::{self, SettingKind};
+    use crate::smbus::regmap::RegisterMap;
+    use crate::smbus::safety::{GuardedBus, WritePolicy};
+    use crate::smbus::sim::SimulatedBus;
+
+    fn fan_sheet(bus: &mut SimulatedBus) -> (Box<dyn Driver>, DeviceSheet) {
+        let driver = drivers::identify(bus, 0x4c, &RegisterMap::builtin()).unwrap();
+        let sheet = DeviceSheet::build(0x4c, driver.kind(), Some(driver.as_ref()), Some(bus));
+        (driver, sheet)
+    }
+
+    fn field_index(page: &Page, label: &str) -> usize {
+        page.fields.iter().position(|field| field.label == label).unwrap()
+    }
+
+    fn number(key: &str) -> Setting {
+        Setting::number(key, key, 0.0, 0.0, 100.0, 1.0, None)
+    }
+
+    #[test]
+    fn without_a_bus_only_what_is_known_is_shown() {
+        let sheet = DeviceSheet::build(0x48, DeviceKind::TemperatureSensor, None, None);
+        assert_eq!(sheet.pages.len(), 1);
+        let page = &sheet.pages[0];
+        assert_eq!(page.kind, PageKind::Identification);
+        let texts: Vec<(&str, &str)> = page.fields.iter().map(|field| (field.label.as_str(), field.text.as_str())).collect();
+        assert_eq!(texts, vec![("Address", "0x48"), ("Type", "Temperature sensor"), ("Driver", "None")]);
+        assert!(page.fields.iter().all(|field| field.setting.is_none()));
+    }
+
+    #[test]
+    fn pages_come_from_the_driver() {
+        let mut bus = SimulatedBus::demo();
+        let (_, sheet) = fan_sheet(&mut bus);
+        let kinds: Vec<PageKind> = sheet.pages.iter().map(|page| page.kind).collect();
+        assert_eq!(kinds, vec![PageKind::Identification, PageKind::Settings]);
+
+        let identification = &sheet.pages[0];
+        assert_eq!(identification.fields[field_index(identification, "Driver")].text, "EMC2101");
+        assert_eq!(identification.fields[field_index(identification, "Vendor")].text, "Microchip");
+
+        let settings = &sheet.pages[1];
+        let mode = &settings.fields[field_index(settings, "FAN_MODE")];
+        assert_eq!(mode.text, "Manual");
+        assert!(matches!(mode.setting.as_ref().unwrap().kind, SettingKind::Choice(_)));
+        assert!(!sheet.is_modified());
+    }
+
+    #[test]
+    fn only_editable_fields_take_text() {
+        let mut bus = SimulatedBus::demo();
+        let (_, mut sheet) = fan_sheet(&mut bus);
+        assert!(!sheet.pages[0].set_text(0, "0x4d"));
+        assert!(!sheet.pages[1].set_text(99, "1"));
+
+        let mode = field_index(&sheet.pages[1], "FAN_MODE");
+        assert!(sheet.pages[1].set_text(mode, "manual"));
+        assert!(!sheet.is_modified());
+        assert!(sheet.pages[1].set_text(mode, "Automatic"));
+        assert!(sheet.pages[1].is_modified());
+        assert!(sheet.is_modified());
+    }
+
+    #[test]
+    fn changes_are_checked_and_point_at_bad_fields() {
+        let mut bus = SimulatedBus::demo();
+        let (_, mut sheet) = fan_sheet(&mut bus);
+        assert_eq!(sheet.changes(), Ok(Vec::new()));
+
+        let mode = field_index(&sheet.pages[1], "FAN_MODE");
+        sheet.pages[1].set_text(mode, "automatic");
+        assert_eq!(sheet.changes(), Ok(vec![Change { key: "FAN_MODE".to_owned(), label: "FAN_MODE".to_owned(), value: 0.0 }]));
+
+        sheet.pages[1].set_text(mode, "Turbo");
+        let error = sheet.changes().unwrap_err();
+        assert_eq!((error.page, error.field), (1, mode));
+        assert_eq!(error.to_string(), "FAN_MODE: must be one of Automatic, Manual");
+    }
+
+    #[test]
+    fn committing_takes_the_new_values() {
+        let mut bus = SimulatedBus::demo();
+        let (_, mut sheet) = fan_sheet(&mut bus);
+        let mode = field_index(&sheet.pages[1], "FAN_MODE");
+        sheet.pages[1].set_text(mode, "automatic");
+        let changes = sheet.changes().unwrap();
+        sheet.commit(&changes);
+        assert_eq!(sheet.pages[1].fields[mode].text, "Automatic");
+        assert_eq!(sheet.pages[1].fields[mode].setting.as_ref().unwrap().value, 0.0);
+        assert!(!sheet.is_modified());
+    }
+
+    #[test]
+    fn long_pages_are_split() {
+        let fields: Vec<Field> = (0..FIELDS_PER_PAGE + 1).map(|index| Field::editable(number(&index.to_string()))).collect();
+        let pages = Page::split(PageKind::Settings, fields);
+        let titles: Vec<&str> = pages.iter().map(|page| page.title.as_str()).collect();
+        assert_eq!(titles, vec!["Settings", "Settings (2)"]);
+        assert_eq!(pages[1].fields.len(), 1);
+        assert!(Page::split(PageKind::Thresholds, Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn read_failures_explain_the_empty_page() {
+        let fields = setting_fields(Err(SmbusError::Timeout));
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].label, "Read failed");
+        assert_eq!(fields[0].text, "bus timeout");
+        assert!(fields[0].setting.is_none());
+    }
+
+    #[test]
+    fn changes_are_written_through_the_safety_layer() {
+        let mut bus = SimulatedBus::demo();
+        let (driver, _) = fan_sheet(&mut bus);
+        let changes = [Change { key: "FAN_MODE".to_owned(), label: "FAN_MODE".to_owned(), value: 0.0 }];
+
+        let locked = WritePolicy { writes_enabled: false, ..Default::default() };
+        let failures = apply(driver.as_ref(), &mut GuardedBus::new(&mut bus, &locked), &changes).unwrap_err();
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0].1, SmbusError::Blocked { .. }));
+        assert_eq!(bus.read_byte_data(0x4c, 0x4a).unwrap(), 0x20);
+
+        let policy = WritePolicy::default();
+        apply(driver.as_ref(), &mut GuardedBus::new(&mut bus, &policy), &changes).unwrap();
+        assert_eq!(bus.read_byte_data(0x4c, 0x4a).unwrap(), 0x00);
+    }
+
+    // The demo bus with an ARP-capable device at 0x4c
+    #[derive(Debug)]
+    struct ArpBus(SimulatedBus);
+
+    impl SmbusTransport for ArpBus {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            self.0.read_byte_data(addr, command)
+        }
+
+        fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            self.0.write_byte_data(addr, command, value)
+        }
+
+        fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+            self.0.read_word_data(addr, command)
+        }
+
+        fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+            self.0.write_word_data(addr, command, value)
+        }
+
+        fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+            match (addr, command) {
+                (arp::ARP_ADDRESS, 0x99) => {
+                    let mut reply = vec![0x81, 0x08, 0x10, 0x55, 0x00, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07];
+                    reply.push(0x99);
+                    Ok(reply)
+                }
+                _ => Err(SmbusError::Nack { addr }),
+            }
+        }
+    }
+
+    #[test]
+    fn arp_capable_devices_show_their_udid() {
+        let mut bus = ArpBus(SimulatedBus::demo());
+        let properties = identification(0x4c, DeviceKind::FanController, None, Some(&mut bus));
+        let udid = properties.iter().find(|property| property.name == "UDID").unwrap();
+        assert_eq!(udid.value_text(), "8108-1055000A-0000-00000000-00000007");
+        assert!(properties.iter().any(|property| property.name == "Vendor ID" && property.value_text() == "1055"));
+
+        let properties = identification(0x50, DeviceKind::Eeprom, None, Some(&mut bus));
+        assert!(properties.iter().all(|property| property.name != "UDID"));
+    }
+
+    #[test]
+    fn service_sheets_put_settings_after_identification() {
+        let identification = [Property::text("Address", "0x4c"), Property::text("Vendor", "Microchip")];
+        let sheet = DeviceSheet::from_service(0x4c, &identification, Some(Ok(vec![number("FAN_SPEED")])));
+        assert_eq!(sheet.addr, Some(0x4c));
+        let kinds: Vec<PageKind> = sheet.pages.iter().map(|page| page.kind).collect();
+        assert_eq!(kinds, vec![PageKind::Identification, PageKind::Settings]);
+        assert_eq!(sheet.pages[0].fields[1].text, "Microchip");
+        assert!(sheet.pages[1].fields[0].setting.is_some());
+
+        // Unknown devices have nothing to set, and failed reads say so
+        let sheet = DeviceSheet::from_service(0x2d, &identification, None);
+        assert_eq!(sheet.pages.len(), 1);
+        let sheet = DeviceSheet::from_service(0x4c, &identification, Some(Err(SmbusError::Timeout)));
+        assert_eq!(sheet.pages[1].fields[0].label, "Read failed");
+    }
+
+    #[test]
+    fn the_console_sheet_sets_the_history() {
+        let history = HistoryConfig { retention: Duration::from_secs(86400), resolution: Duration::from_secs(10) };
+        let mut sheet = DeviceSheet::console(history);
+        assert_eq!(sheet.addr, None);
+        assert_eq!(sheet.pages.len(), 1);
+        let texts: Vec<&str> = sheet.pages[0].fields.iter().map(|field| field.text.as_str()).collect();
+        assert_eq!(texts, vec!["24", "10"]);
+        assert_eq!(history_config(history, &sheet.changes().unwrap()), Ok(history));
+
+        assert!(sheet.pages[0].set_text(0, "168"));
+        assert!(sheet.pages[0].set_text(1, "300"));
+        assert_eq!(history_config(history, &sheet.changes().unwrap()), Ok(HistoryConfig {
+            retention: Duration::from_secs(7 * 86400),
+            resolution: Duration::from_secs(300),
+        }));
+
+        // Each field is checked, then the two together
+        assert!(sheet.pages[0].set_text(1, "0"));
+        assert_eq!(sheet.changes().unwrap_err().label, "Bucket width");
+        assert!(sheet.pages[0].set_text(0, "1"));
+        assert!(sheet.pages[0].set_text(1, "3600"));
+        assert_eq!(history_config(history, &sheet.changes().unwrap()), Ok(HistoryConfig {
+            retention: Duration::from_secs(3600),
+            resolution: Duration::from_secs(3600),
+        }));
+        let wider = HistoryConfig { retention: Duration::from_secs(1800), ..history };
+        let changes = [Change { key: RESOLUTION.to_owned(), label: "Bucket width".to_owned(), value: 3600.0 }];
+        assert!(history_config(wider, &changes).is_err());
+    }
+}
@@ -6,7 +6,7 @@ use winreg::RegKey;
 use log::{error, trace};
 
 use crate::{CLSID_MMCSnapIn, CLSID_MMCSnapInAbout};
-use crate::id::{SNAPIN_NAME, SNAPINABOUT_NAME, SNAPIN_VERSION};
+use crate::id::{self, SNAPIN_NAME, SNAPINABOUT_NAME, SNAPIN_VERSION, NODETYPES, NODETYPE_ROOT};
 
 fn register_snapin() -> Result<(), Box<dyn std::error::Error>> {
     trace!("crate::registration::register_snapin()");
@@ -18,8 +18,19 @@ fn register_snapin() -> Result<(), Box<dyn std::error::Error>> {
     snapin_key.set_value("NameString", &SNAPIN_NAME)?;
     snapin_key.set_value("About", &CLSID_MMCSnapInAbout.to_string())?;
     snapin_key.set_value("Version", &SNAPIN_VERSION)?;
+    snapin_key.set_value("NodeType", &id::guid_string(&NODETYPE_ROOT))?;
             
     snapin_key.create_subkey("StandAlone")?;
+    
+    // Publish our node types so extension snap-ins can find them
+    let (snapin_nodetypes, _) = snapin_key.create_subkey("NodeTypes")?;
+    let nodetypes = hklm.open_subkey_with_flags("SOFTWARE\\Microsoft\\MMC\\NodeTypes", KEY_ALL_ACCESS)?;
+    for (name, guid) in NODETYPES.iter() {
+        let guid = id::guid_string(guid);
+        snapin_nodetypes.create_subkey(&guid)?;
+        let (nodetype_key, _) = nodetypes.create_subkey(&guid)?;
+        nodetype_key.set_value("", name)?;
+    }
 
     Ok(())
 }
@@ -41,6 +52,14 @@ fn unregister_snapin() -> Result<(), Box<dyn std::error::Error>> {
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
     let path = Path::new("SOFTWARE\\Microsoft\\MMC\\SnapIns").join(CLSID_MMCSnapIn.to_string());
     hklm.delete_subkey_all(path)?;
+    
+    for (_, guid) in NODETYPES.iter() {
+        let path = Path::new("SOFTWARE\\Microsoft\\MMC\\NodeTypes").join(id::guid_string(guid));
+        if let Err(e) = hklm.delete_subkey_all(path) {
+            // Already gone is fine
+            trace!("Couldn't remove node type {}: {}", id::guid_string(guid), e);
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,380 @@
+// The snap-in's node tree.
+//
+// MMC identifies our items by an opaque cookie, the LPARAM of SCOPEDATAITEM
+// and RESULTDATAITEM. The registry hands those cookies out as `NodeId`s and
+// keeps the parent/child links between them. It knows nothing about COM, so
+// the tree logic builds and runs on any platform; the COM data objects that
+// MMC sees are kept separately and refer back to nodes by id.
+
+use std::collections::HashMap;
+
+use crate::smbus::drivers::DeviceKind;
+
+/// A node's cookie. The root is always 0, since that's the cookie MMC uses
+/// for the static node.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub isize);
+
+impl NodeId {
+    pub const ROOT: NodeId = NodeId(0);
+
+    pub fn cookie(self) -> isize {
+        self.0
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum NodeKind {
+    #[default]
+    Folder,
+    Root,
+    Device(u8, DeviceKind),
+    Registers(u8),
+    History(u8),
+}
+
+impl NodeKind {
+    /// Nodes whose children aren't known until they are first expanded.
+    pub fn discovers_children(self) -> bool {
+        matches!(self, NodeKind::Root)
+    }
+
+    /// Nodes showing the same columns in the result pane share a column set,
+    /// so MMC remembers column widths and order per set.
+    pub fn column_set(self) -> &'static str {
+        crate::columns::for_kind(self).id
+    }
+}
+
+/// Everything the snap-in knows about a node, independent of MMC.
+#[derive(Debug, Clone)]
+pub struct NodeModel {
+    pub id: NodeId,
+    pub kind: NodeKind,
+    pub label: String,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    discovered: bool,
+}
+
+impl NodeModel {
+    fn new(id: NodeId, kind: NodeKind, label: &str, parent: Option<NodeId>) -> Self {
+        Self {
+            id,
+            kind,
+            label: label.to_owned(),
+            parent,
+            children: Vec::new(),
+            discovered: !kind.discovers_children(),
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// False until a node that discovers its children has been expanded.
+    pub fn is_discovered(&self) -> bool {
+        self.discovered
+    }
+
+    /// Whether the scope pane should offer to expand this node: it either
+    /// has children or hasn't looked for them yet.
+    pub fn may_have_children(&self) -> bool {
+        !self.children.is_empty() || !self.discovered
+    }
+
+    pub fn description(&self) -> Option<String> {
+        match self.kind {
+            NodeKind::Root => Some("SMBus devices on this computer".to_owned()),
+            NodeKind::Folder => None,
+            NodeKind::Device(addr, kind) => Some(format!("{} at address {:#04x}", kind, addr)),
+            NodeKind::Registers(addr) => Some(format!("Raw registers of the device at address {:#04x}", addr)),
+            NodeKind::History(addr) => Some(format!("Readings over time of the device at address {:#04x}", addr)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NodeRegistry {
+    nodes: HashMap<NodeId, NodeModel>,
+    // Cookies of removed nodes, handed out again before new ones
+    free: Vec<NodeId>,
+    next_cookie: isize,
+}
+
+impl NodeRegistry {
+    /// Creates a registry holding only the root node.
+    pub fn new(root_label: &str) -> Self {
+        let root = NodeModel::new(NodeId::ROOT, NodeKind::Root, root_label, None);
+
+        Self {
+            nodes: HashMap::from([(NodeId::ROOT, root)]),
+            free: Vec::new(),
+            next_cookie: 1,
+        }
+    }
+
+    pub fn root(&self) -> &NodeModel {
+        &self.nodes[&NodeId::ROOT]
+    }
+
+    fn alloc_id(&mut self) -> NodeId {
+        match self.free.pop() {
+            Some(id) => id,
+            None => {
+                let id = NodeId(self.next_cookie);
+                self.next_cookie += 1;
+                id
+            }
+        }
+    }
+
+    /// Adds a node as the last child of `parent`. Returns `None` if the
+    /// parent doesn't exist.
+    pub fn insert(&mut self, parent: NodeId, label: &str, kind: NodeKind) -> Option<NodeId> {
+        if !self.nodes.contains_key(&parent) {
+            return None;
+        }
+
+        let id = self.alloc_id();
+        self.nodes.insert(id, NodeModel::new(id, kind, label, Some(parent)));
+        self.nodes.get_mut(&parent).unwrap().children.push(id);
+
+        Some(id)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&NodeModel> {
+        self.nodes.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut NodeModel> {
+        self.nodes.get_mut(&id)
+    }
+
+    /// Looks up a cookie handed back by MMC.
+    pub fn resolve(&self, cookie: isize) -> Option<&NodeModel> {
+        self.nodes.get(&NodeId(cookie))
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.nodes.get(&id).map(|node| node.children()).unwrap_or(&[])
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes.get(&id).and_then(|node| node.parent)
+    }
+
+    /// Marks a node's children as discovered. Returns true the first time,
+    /// when the caller should go and find them.
+    pub fn begin_discovery(&mut self, id: NodeId) -> bool {
+        match self.nodes.get_mut(&id) {
+            Some(node) if !node.discovered => {
+                node.discovered = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The labels from the root down to `id`, root first.
+    pub fn path(&self, id: NodeId) -> Vec<&str> {
+        let mut labels = Vec::new();
+        let mut next = self.get(id);
+        while let Some(node) = next {
+            labels.push(node.label.as_str());
+            next = node.parent.and_then(|parent| self.get(parent));
+        }
+        labels.reverse();
+        labels
+    }
+
+    /// Names `id` the same way in every console session, whatever its label
+    /// or cookie: by what kind of node it is and the address it shows.
+    /// Folders have neither, so they're named by their label under their
+    /// parent.
+    pub fn persistent_id(&self, id: NodeId) -> String {
+        let node = match self.get(id) {
+            Some(node) => node,
+            None => return String::new(),
+        };
+        match node.kind {
+            NodeKind::Root => "root".to_owned(),
+            NodeKind::Device(addr, _) => format!("device-{:02x}", addr),
+            NodeKind::Registers(addr) => format!("registers-{:02x}", addr),
+            NodeKind::History(addr) => format!("history-{:02x}", addr),
+            NodeKind::Folder => {
+                let parent = node.parent.map(|parent| self.persistent_id(parent)).unwrap_or_default();
+                format!("{}\\{}", parent, node.label)
+            }
+        }
+    }
+
+    /// First node, in no particular order, that matches `predicate`.
+    pub fn find(&self, predicate: impl Fn(&NodeModel) -> bool) -> Option<NodeId> {
+        self.nodes.values().find(|node| predicate(node)).map(|node| node.id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NodeModel> {
+        self.nodes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Every node below `id`, depth first, parents before children.
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        let mut stack: Vec<NodeId> = self.children(id).iter().rev().copied().collect();
+        while let Some(next) = stack.pop() {
+            result.push(next);
+            stack.extend(self.children(next).iter().rev());
+        }
+        result
+    }
+
+    /// Removes `id` and everything below it and returns the removed ids so
+    /// callers can release whatever they keep per node. The root can't be
+    /// removed; use `remove_children` to empty it.
+    pub fn remove(&mut self, id: NodeId) -> Vec<NodeId> {
+        if id == NodeId::ROOT || !self.nodes.contains_key(&id) {
+            return Vec::new();
+        }
+
+        if let Some(parent) = self.parent(id) {
+            if let Some(parent) = self.nodes.get_mut(&parent) {
+                parent.children.retain(|&child| child != id);
+            }
+        }
+
+        let mut removed = vec![id];
+        removed.extend(self.descendants(id));
+        for node in &removed {
+            self.nodes.remove(node);
+            self.free.push(*node);
+        }
+        removed
+    }
+
+    /// Removes every descendant of `id` but keeps `id` itself.
+    pub fn remove_children(&mut self, id: NodeId) -> Vec<NodeId> {
+        let children = self.children(id).to_vec();
+        children.into_iter().flat_map(|child| self.remove(child)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Root > Device > (Registers, History), and a second device
+    fn tree() -> (NodeRegistry, NodeId, NodeId, NodeId, NodeId) {
+        let mut registry = NodeRegistry::new("SMBus");
+        let device = registry.insert(NodeId::ROOT, "EMC2101", NodeKind::Device(0x4c, DeviceKind::FanController)).unwrap();
+        let registers = registry.insert(device, "Registers", NodeKind::Registers(0x4c)).unwrap();
+        let history = registry.insert(device, "History", NodeKind::History(0x4c)).unwrap();
+        let other = registry.insert(NodeId::ROOT, "24C02", NodeKind::Device(0x50, DeviceKind::Eeprom)).unwrap();
+        (registry, device, registers, history, other)
+    }
+
+    #[test]
+    fn removed_cookies_are_handed_out_again() {
+        let (mut registry, _, _, _, other) = tree();
+        assert_eq!(registry.remove(other), vec![other]);
+        assert!(registry.resolve(other.cookie()).is_none());
+
+        let again = registry.insert(NodeId::ROOT, "24C02", NodeKind::Device(0x50, DeviceKind::Eeprom)).unwrap();
+        assert_eq!(again, other);
+        let fresh = registry.insert(NodeId::ROOT, "LM75", NodeKind::Device(0x48, DeviceKind::TemperatureSensor)).unwrap();
+        assert_eq!(fresh, NodeId(5));
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree() {
+        let (mut registry, device, registers, history, other) = tree();
+        let mut removed = registry.remove(device);
+        removed.sort();
+
+        assert_eq!(removed, vec![device, registers, history]);
+        assert_eq!(registry.children(NodeId::ROOT), &[other]);
+        assert!(!registry.contains(registers) && !registry.contains(history));
+        assert_eq!(registry.len(), 2);
+        assert!(registry.remove(device).is_empty());
+    }
+
+    #[test]
+    fn the_root_stays_when_emptied() {
+        let (mut registry, ..) = tree();
+        assert!(registry.remove(NodeId::ROOT).is_empty());
+
+        assert_eq!(registry.remove_children(NodeId::ROOT).len(), 4);
+        assert_eq!(registry.len(), 1);
+        assert!(registry.root().children().is_empty());
+        assert_eq!(registry.root().label, "SMBus");
+    }
+
+    #[test]
+    fn children_are_discovered_once() {
+        let (mut registry, device, ..) = tree();
+        assert!(!registry.root().is_discovered());
+        assert!(registry.root().may_have_children());
+
+        assert!(registry.begin_discovery(NodeId::ROOT));
+        assert!(!registry.begin_discovery(NodeId::ROOT));
+        assert!(registry.root().is_discovered());
+        // Only the root discovers its children
+        assert!(!registry.begin_discovery(device));
+        assert!(!registry.begin_discovery(NodeId(99)));
+    }
+
+    #[test]
+    fn path_runs_from_the_root() {
+        let (registry, device, registers, ..) = tree();
+        assert_eq!(registry.path(registers), vec!["SMBus", "EMC2101", "Registers"]);
+        assert_eq!(registry.path(device), vec!["SMBus", "EMC2101"]);
+        assert_eq!(registry.path(NodeId::ROOT), vec!["SMBus"]);
+        assert!(registry.path(NodeId(99)).is_empty());
+    }
+
+    #[test]
+    fn persistent_ids_survive_relabelling_and_rescans() {
+        let (mut registry, device, registers, history, other) = tree();
+        assert_eq!(registry.persistent_id(NodeId::ROOT), "root");
+        assert_eq!(registry.persistent_id(device), "device-4c");
+        assert_eq!(registry.persistent_id(registers), "registers-4c");
+        assert_eq!(registry.persistent_id(history), "history-4c");
+        assert_eq!(registry.persistent_id(other), "device-50");
+
+        registry.get_mut(device).unwrap().label = "CPU fan".to_owned();
+        assert_eq!(registry.persistent_id(device), "device-4c");
+
+        // Found again under a new cookie after a rescan
+        registry.remove_children(NodeId::ROOT);
+        registry.insert(NodeId::ROOT, "LM75", NodeKind::Device(0x48, DeviceKind::TemperatureSensor)).unwrap();
+        let again = registry.insert(NodeId::ROOT, "EMC2101", NodeKind::Device(0x4c, DeviceKind::FanController)).unwrap();
+        assert_ne!(again, device);
+        assert_eq!(registry.persistent_id(again), "device-4c");
+    }
+
+    #[test]
+    fn folders_are_named_by_their_path() {
+        let mut registry = NodeRegistry::new("SMBus");
+        let folder = registry.insert(NodeId::ROOT, "Sensors", NodeKind::Folder).unwrap();
+        assert_eq!(registry.persistent_id(folder), "root\\Sensors");
+        assert_eq!(registry.persistent_id(NodeId(99)), "");
+    }
+}
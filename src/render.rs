@@ -0,0 +1,41 @@
+// Plain text and CSV renderings of a node's properties, shared by the
+// clipboard and anything else that needs to hand readings to the user.
+
+use crate::smbus::Property;
+
+/// A title line followed by one aligned `name  value` line per property.
+pub fn text(title: &str, properties: &[Property]) -> String {
+    let width = properties.iter().map(|p| p.name.chars().count()).max().unwrap_or(0);
+
+    let mut lines = vec![title.to_owned()];
+    for property in properties {
+        lines.push(format!("  {:<width$}  {}", property.name, property.value_text(), width = width));
+    }
+    lines.join("\n")
+}
+
+/// RFC 4180 CSV with a header row. Values and units get their own columns
+/// so spreadsheets see numbers as numbers.
+pub fn csv(properties: &[Property]) -> String {
+    let mut csv = String::from("Name,Value,Unit\r\n");
+    for property in properties {
+        let fields = [
+            property.name.as_str(),
+            &property.value.to_string(),
+            property.unit.as_deref().unwrap_or(""),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes a field if it contains a separator, quote or line break.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,317 @@
+// The client side: `ServiceClient` sends requests and waits for their
+// responses, and `ServiceBus` makes the service look like any other
+// transport to code that drives devices itself, such as smbusctl or the
+// snap-in's register browser.
+
+use std::fmt;
+use std::io::BufReader;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::smbus::drivers::Setting;
+use crate::smbus::{Property, SmbusError, SmbusTransport, BLOCK_MAX};
+
+use super::ipc::{self, Reader, Writer};
+use super::protocol::{
+    parse_frame, read_line, write_frame, DeviceInfo, DeviceReading, Event, ProfileFailure, ProfileInfo, Reply,
+    Request, RequestFrame, Role, ServerFrame, Transaction, WireError, Version, PROTOCOL_VERSION,
+};
+use super::remote::{self, Credentials, RemoteTarget};
+
+/// How long a request may take before the service is given up on.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct ServiceClient {
+    writer: Writer,
+    responses: Receiver<(u64, Result<Reply, WireError>)>,
+    events: Receiver<Event>,
+    next_id: u64,
+    version: Version,
+    role: Role,
+}
+
+impl fmt::Debug for ServiceClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceClient").field("version", &self.version).field("role", &self.role).finish()
+    }
+}
+
+impl ServiceClient {
+    /// Connects to the service at `endpoint` and says hello as `name`.
+    pub fn connect(endpoint: &str, name: &str) -> Result<Self, SmbusError> {
+        let (reader, writer) = ipc::connect(endpoint)
+            .map_err(|e| SmbusError::Other(format!("Couldn't reach the SMBus service at {}: {}", endpoint, e)))?;
+        Self::handshake(reader, writer, name, None)
+    }
+
+    /// Connects to the service on another computer and authenticates
+    /// with the target's credentials.
+    pub fn connect_remote(target: &RemoteTarget, name: &str) -> Result<Self, SmbusError> {
+        let (reader, writer) = target.connect()
+            .map_err(|e| SmbusError::Other(format!("Couldn't reach the SMBus service at {}: {}", target.address, e)))?;
+        Self::handshake(reader, writer, name, Some(&target.credentials))
+    }
+
+    /// Says hello over a connection made some other way, then proves
+    /// who we are with `credentials` if the service asks.
+    pub fn handshake(reader: Reader, writer: Writer, name: &str, credentials: Option<&Credentials>) -> Result<Self, SmbusError> {
+        let (responses_tx, responses) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+
+        // Sorts what the service sends until it hangs up. Dropping the
+        // senders then fails whatever is waiting.
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let frame = match read_line(&mut reader).and_then(|line| line.map(|line| parse_frame(&line)).transpose()) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::error!("Couldn't read from the SMBus service: {}", e);
+                        break;
+                    }
+                };
+                let sent = match frame {
+                    ServerFrame::Response { id, result } => responses_tx.send((id, result)).is_ok(),
+                    // Nobody listening for events is fine
+                    ServerFrame::Event(event) => {
+                        let _ = events_tx.send(event);
+                        true
+                    }
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self { writer, responses, events, next_id: 1, version: PROTOCOL_VERSION, role: Role::ReadOnly };
+        let (role, challenge) = match client.call(Request::Hello { version: PROTOCOL_VERSION, client: name.to_owned() })? {
+            Reply::Hello { version, server, role, challenge } => {
+                log::info!("Connected to {} speaking version {}", server, version);
+                client.version = version;
+                (role, challenge)
+            }
+            reply => return Err(unexpected(reply)),
+        };
+
+        client.role = match (role, challenge, credentials) {
+            (Some(role), _, _) => role,
+            (None, Some(challenge), Some(Credentials::Key { id, secret })) => {
+                let proof = remote::proof(secret, &challenge);
+                match client.call(Request::Authenticate { key_id: id.clone(), proof })? {
+                    Reply::Authenticated { role } => role,
+                    reply => return Err(unexpected(reply)),
+                }
+            }
+            (None, _, _) => {
+                return Err(SmbusError::Other("The SMBus service didn't accept our credentials".to_owned()))
+            }
+        };
+        log::info!("Connected as {}", client.role);
+        Ok(client)
+    }
+
+    /// The version the service speaks. Requests added after its minor
+    /// version won't be understood.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// What the service lets us do.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn call(&mut self, request: Request) -> Result<Reply, SmbusError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        write_frame(self.writer.as_mut(), &RequestFrame { id, request })
+            .map_err(|e| SmbusError::Other(format!("Couldn't send to the SMBus service: {}", e)))?;
+
+        loop {
+            match self.responses.recv_timeout(REQUEST_TIMEOUT) {
+                Ok((response_id, result)) if response_id == id => return result.map_err(SmbusError::from),
+                // Left over from a request that timed out
+                Ok((response_id, _)) => log::debug!("Dropping response {} while waiting for {}", response_id, id),
+                Err(RecvTimeoutError::Timeout) => return Err(SmbusError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(SmbusError::Other("The SMBus service closed the connection".to_owned()))
+                }
+            }
+        }
+    }
+
+    pub fn scan(&mut self, range: RangeInclusive<u8>) -> Result<Vec<DeviceInfo>, SmbusError> {
+        match self.call(Request::Scan { first: *range.start(), last: *range.end() })? {
+            Reply::Devices(devices) => Ok(devices),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn devices(&mut self) -> Result<Vec<DeviceInfo>, SmbusError> {
+        match self.call(Request::Devices)? {
+            Reply::Devices(devices) => Ok(devices),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Since 1.2. The device at `addr` and what can be read about it,
+    /// adding it to the service's devices if the scan missed it.
+    pub fn identify(&mut self, addr: u8) -> Result<(DeviceInfo, Vec<Property>), SmbusError> {
+        match self.call(Request::Identify { addr })? {
+            Reply::Identification { device, properties } => Ok((device, properties)),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn read(&mut self, addr: u8) -> Result<DeviceReading, SmbusError> {
+        match self.call(Request::Read { addr })? {
+            Reply::Reading(reading) => Ok(reading),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn settings(&mut self, addr: u8) -> Result<Vec<Setting>, SmbusError> {
+        match self.call(Request::Settings { addr })? {
+            Reply::Settings(settings) => Ok(settings),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Returns false if the write was only logged, as a dry run.
+    pub fn write(&mut self, addr: u8, key: &str, value: f64, dry_run: bool) -> Result<bool, SmbusError> {
+        match self.call(Request::Write { addr, key: key.to_owned(), value, dry_run })? {
+            Reply::Written { performed } => Ok(performed),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn transfer(&mut self, addr: u8, transaction: Transaction) -> Result<Reply, SmbusError> {
+        self.call(Request::Transfer { addr, transaction })
+    }
+
+    /// Asks for readings of `addrs`, or of every device if it's empty,
+    /// every `interval_ms`. They arrive through `next_event`.
+    pub fn subscribe(&mut self, addrs: &[u8], interval_ms: u32) -> Result<(), SmbusError> {
+        self.call(Request::Subscribe { addrs: addrs.to_vec(), interval_ms }).map(|_| ())
+    }
+
+    pub fn unsubscribe(&mut self) -> Result<(), SmbusError> {
+        self.call(Request::Unsubscribe).map(|_| ())
+    }
+
+    /// Waits up to `timeout` for the next subscribed reading.
+    pub fn next_event(&self, timeout: Duration) -> Option<Event> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    pub fn profiles(&mut self) -> Result<Vec<ProfileInfo>, SmbusError> {
+        match self.call(Request::Profiles)? {
+            Reply::Profiles(profiles) => Ok(profiles),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Returns the settings that couldn't be applied.
+    pub fn apply_profile(&mut self, name: &str, dry_run: bool) -> Result<Vec<ProfileFailure>, SmbusError> {
+        match self.call(Request::ApplyProfile { name: name.to_owned(), dry_run })? {
+            Reply::ProfileApplied { failures } => Ok(failures),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+fn unexpected(reply: Reply) -> SmbusError {
+    SmbusError::Other(format!("Unexpected reply from the SMBus service: {:?}", reply))
+}
+
+/// The bus behind the service, one transaction per request.
+#[derive(Debug)]
+pub struct ServiceBus {
+    client: ServiceClient,
+}
+
+impl ServiceBus {
+    pub fn new(client: ServiceClient) -> Self {
+        Self { client }
+    }
+
+    pub fn connect(endpoint: &str, name: &str) -> Result<Self, SmbusError> {
+        ServiceClient::connect(endpoint, name).map(Self::new)
+    }
+
+    pub fn connect_remote(target: &RemoteTarget, name: &str) -> Result<Self, SmbusError> {
+        ServiceClient::connect_remote(target, name).map(Self::new)
+    }
+
+    pub fn client(&mut self) -> &mut ServiceClient {
+        &mut self.client
+    }
+
+    fn byte(&mut self, addr: u8, transaction: Transaction) -> Result<u8, SmbusError> {
+        match self.client.transfer(addr, transaction)? {
+            Reply::Byte(value) => Ok(value),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn bytes(&mut self, addr: u8, transaction: Transaction) -> Result<Vec<u8>, SmbusError> {
+        match self.client.transfer(addr, transaction)? {
+            Reply::Bytes(data) => Ok(data),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn done(&mut self, addr: u8, transaction: Transaction) -> Result<(), SmbusError> {
+        match self.client.transfer(addr, transaction)? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+impl SmbusTransport for ServiceBus {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+        self.byte(addr, Transaction::ReadByteData { command })
+    }
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+        self.done(addr, Transaction::WriteByteData { command, value })
+    }
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+        match self.client.transfer(addr, Transaction::ReadWordData { command })? {
+            Reply::Word(value) => Ok(value),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+        self.done(addr, Transaction::WriteWordData { command, value })
+    }
+
+    fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+        self.byte(addr, Transaction::ReceiveByte)
+    }
+
+    fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+        self.bytes(addr, Transaction::ReadBlockData { command })
+    }
+
+    // The service takes up to 32 bytes at a time, like the kernel
+    fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+        let mut bytes = Vec::new();
+        while bytes.len() < len {
+            let chunk = (len - bytes.len()).min(BLOCK_MAX);
+            let command = command.wrapping_add(bytes.len() as u8);
+            bytes.extend(self.bytes(addr, Transaction::ReadI2cBlockData { command, len: chunk })?);
+        }
+        Ok(bytes)
+    }
+
+    fn write_i2c_block_data(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+        self.done(addr, Transaction::WriteI2cBlockData { command, data: data.to_vec() })
+    }
+}
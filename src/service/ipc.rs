@@ -0,0 +1,192 @@
+// The local channel between clients and the bus service: a Unix socket
+// where there are Unix sockets, a named pipe on Windows. Either way an
+// endpoint is a name in the file system's sense, e.g.
+// "/run/smbus-service.sock" or r"\\.\pipe\smbus-service".
+//
+// Each connection is split into a reader and a writer so one thread can
+// wait for requests while another sends events.
+
+use std::io::{Read, Write};
+
+pub type Reader = Box<dyn Read + Send>;
+pub type Writer = Box<dyn Write + Send>;
+
+#[cfg(unix)]
+pub const DEFAULT_ENDPOINT: &str = "/run/smbus-service.sock";
+#[cfg(windows)]
+pub const DEFAULT_ENDPOINT: &str = r"\\.\pipe\smbus-service";
+#[cfg(not(any(unix, windows)))]
+pub const DEFAULT_ENDPOINT: &str = "";
+
+pub use imp::{connect, Listener};
+
+#[cfg(unix)]
+mod imp {
+    use std::io;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    use super::{Reader, Writer};
+
+    pub struct Listener {
+        listener: UnixListener,
+    }
+
+    impl Listener {
+        pub fn bind(endpoint: &str) -> io::Result<Self> {
+            // A socket left behind by a service that didn't shut down
+            // cleanly refuses connections; one that's in use doesn't
+            if Path::new(endpoint).exists() && UnixStream::connect(endpoint).is_err() {
+                log::info!("Removing stale socket {}", endpoint);
+                std::fs::remove_file(endpoint)?;
+            }
+            Ok(Self { listener: UnixListener::bind(endpoint)? })
+        }
+
+        pub fn accept(&self) -> io::Result<(Reader, Writer)> {
+            let (stream, _) = self.listener.accept()?;
+            split(stream)
+        }
+    }
+
+    pub fn connect(endpoint: &str) -> io::Result<(Reader, Writer)> {
+        split(UnixStream::connect(endpoint)?)
+    }
+
+    fn split(stream: UnixStream) -> io::Result<(Reader, Writer)> {
+        Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+    }
+}
+
+// A synchronous pipe handle runs one operation at a time, so a ReadFile
+// waiting for the next request would hold up every event written in the
+// meantime. Reads only start once PeekNamedPipe says there's something to
+// read, and poll until then.
+#[cfg(windows)]
+mod imp {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{GetLastError, ERROR_BROKEN_PIPE, ERROR_PIPE_CONNECTED, HANDLE};
+    use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+        PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    use super::{Reader, Writer};
+
+    const BUFFER_SIZE: u32 = 64 * 1024;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub struct Listener {
+        name: Vec<u16>,
+    }
+
+    impl Listener {
+        pub fn bind(endpoint: &str) -> io::Result<Self> {
+            Ok(Self { name: endpoint.encode_utf16().chain(std::iter::once(0)).collect() })
+        }
+
+        // Every connection gets a pipe instance of its own. The default
+        // security descriptor lets administrators and SYSTEM in, and
+        // everyone else only read, which isn't enough to send a request.
+        pub fn accept(&self) -> io::Result<(Reader, Writer)> {
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    PCWSTR::from_raw(self.name.as_ptr()),
+                    PIPE_ACCESS_DUPLEX,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+                    PIPE_UNLIMITED_INSTANCES,
+                    BUFFER_SIZE,
+                    BUFFER_SIZE,
+                    0,
+                    None,
+                )
+            };
+            if handle.is_invalid() {
+                return Err(io::Error::last_os_error());
+            }
+            let file = unsafe { File::from_raw_handle(handle.0 as _) };
+
+            // A client that connected between CreateNamedPipe and here is
+            // reported as an error but is connected all the same
+            if !unsafe { ConnectNamedPipe(handle, None) }.as_bool() && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(split(file))
+        }
+    }
+
+    pub fn connect(endpoint: &str) -> io::Result<(Reader, Writer)> {
+        let file = OpenOptions::new().read(true).write(true).open(endpoint)?;
+        Ok(split(file))
+    }
+
+    fn split(file: File) -> (Reader, Writer) {
+        let file = Arc::new(file);
+        (Box::new(PipeReader(file.clone())), Box::new(PipeWriter(file)))
+    }
+
+    struct PipeReader(Arc<File>);
+
+    impl Read for PipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let handle = HANDLE(self.0.as_raw_handle() as isize);
+            loop {
+                let mut available = 0u32;
+                if !unsafe { PeekNamedPipe(handle, None, 0, None, Some(&mut available), None) }.as_bool() {
+                    return match unsafe { GetLastError() } {
+                        // The other end has gone
+                        ERROR_BROKEN_PIPE => Ok(0),
+                        _ => Err(io::Error::last_os_error()),
+                    };
+                }
+                if available > 0 {
+                    let len = buf.len().min(available as usize);
+                    return (&*self.0).read(&mut buf[..len]);
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    struct PipeWriter(Arc<File>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (&*self.0).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            (&*self.0).flush()
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io;
+
+    use super::{Reader, Writer};
+
+    pub struct Listener;
+
+    impl Listener {
+        pub fn bind(_endpoint: &str) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "no local IPC on this platform"))
+        }
+
+        pub fn accept(&self) -> io::Result<(Reader, Writer)> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "no local IPC on this platform"))
+        }
+    }
+
+    pub fn connect(_endpoint: &str) -> io::Result<(Reader, Writer)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "no local IPC on this platform"))
+    }
+}
@@ -0,0 +1,339 @@
+// Serves what the service reads in the OpenMetrics text format, so
+// Prometheus can scrape the bus instead of a second tool probing it.
+//
+//     GET /metrics
+//
+// Each scrape reads the identified devices again unless they were read
+// within the last `MAX_AGE_MS`, by a scrape or by a subscription. Values
+// with a unit the exporter knows go into a family of their own, in base
+// units; the rest go into `smbus_reading` with the unit as a label.
+//
+// Nothing on this port is authenticated, so it should only be bound where
+// anyone who can reach it may see the readings.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use super::protocol::{DeviceInfo, DeviceReading};
+use super::server::SharedService;
+
+/// Readings younger than this are served again rather than read again.
+pub const MAX_AGE_MS: u64 = 1000;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Longest request head taken, and how long a client gets to send it
+const MAX_REQUEST_LEN: u64 = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the service counts as it goes.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    pub scans: u64,
+    pub reads: HashMap<u8, u64>,
+    pub read_errors: HashMap<u8, u64>,
+}
+
+/// Everything one scrape shows.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub devices: Vec<DeviceInfo>,
+    pub readings: Vec<(u8, DeviceReading)>,
+    pub counters: Counters,
+}
+
+/// A metric family for values in one unit.
+struct Family {
+    name: &'static str,
+    unit: &'static str,
+    help: &'static str,
+    // What a value in the device's unit is multiplied by to get the base unit
+    scale: f64,
+}
+
+// Several device units can share a family
+static FAMILIES: [(&str, Family); 9] = [
+    ("°C", Family { name: "smbus_temperature_celsius", unit: "celsius", help: "Temperatures.", scale: 1.0 }),
+    ("RPM", Family { name: "smbus_fan_speed_rpm", unit: "rpm", help: "Fan speeds.", scale: 1.0 }),
+    ("%", Family { name: "smbus_level_ratio", unit: "ratio", help: "Percentages such as PWM duty, as a fraction of one.", scale: 0.01 }),
+    ("V", Family { name: "smbus_voltage_volts", unit: "volts", help: "Voltages.", scale: 1.0 }),
+    ("mV", Family { name: "smbus_voltage_volts", unit: "volts", help: "Voltages.", scale: 0.001 }),
+    ("A", Family { name: "smbus_current_amperes", unit: "amperes", help: "Currents.", scale: 1.0 }),
+    ("mA", Family { name: "smbus_current_amperes", unit: "amperes", help: "Currents.", scale: 0.001 }),
+    ("W", Family { name: "smbus_power_watts", unit: "watts", help: "Power.", scale: 1.0 }),
+    ("mW", Family { name: "smbus_power_watts", unit: "watts", help: "Power.", scale: 0.001 }),
+];
+
+static OTHER_FAMILY: Family = Family { name: "smbus_reading", unit: "", help: "Readings in other units, named by the unit label.", scale: 1.0 };
+
+fn family(unit: &str) -> &'static Family {
+    FAMILIES.iter()
+        .find(|(device_unit, _)| *device_unit == unit)
+        .map_or(&OTHER_FAMILY, |(_, family)| family)
+}
+
+// Label values escape backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".to_owned(),
+        v if v == f64::INFINITY => "+Inf".to_owned(),
+        v if v == f64::NEG_INFINITY => "-Inf".to_owned(),
+        v => v.to_string(),
+    }
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs.iter().map(|(name, value)| format!("{}=\"{}\"", name, escape(value))).collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn header(out: &mut String, name: &str, kind: &str, unit: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    if !unit.is_empty() {
+        let _ = writeln!(out, "# UNIT {} {}", name, unit);
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+}
+
+/// Writes `sample` as an OpenMetrics exposition, labelled with the
+/// controller the service owns.
+pub fn render(controller: &str, sample: &Sample) -> String {
+    let models: HashMap<u8, &str> = sample.devices.iter().map(|device| (device.addr, device.name.as_str())).collect();
+    let model = |addr: u8| models.get(&addr).copied().unwrap_or("Unknown device");
+
+    // Families in the order they're first met, each with its samples.
+    // Sensors come before other properties, and a property with a
+    // sensor's name isn't repeated.
+    let mut families: Vec<(&Family, Vec<String>)> = Vec::new();
+    for (addr, reading) in &sample.readings {
+        let address = format!("{:#04x}", addr);
+        let sensors = reading.sensors.iter().map(|sensor| (&sensor.name, &sensor.value, &sensor.unit));
+        let properties = reading.properties.iter()
+            .filter(|property| !reading.sensors.iter().any(|sensor| sensor.name == property.name))
+            .map(|property| (&property.name, &property.value, &property.unit));
+
+        for (name, value, unit) in sensors.chain(properties) {
+            let (Some(value), Some(unit)) = (value.number(), unit.as_deref()) else {
+                continue;
+            };
+            let family = family(unit);
+            let mut pairs = vec![("controller", controller), ("address", address.as_str()), ("model", model(*addr)), ("sensor", name.as_str())];
+            if std::ptr::eq(family, &OTHER_FAMILY) {
+                pairs.push(("unit", unit));
+            }
+            let line = format!("{}{} {}", family.name, labels(&pairs), number(value * family.scale));
+            match families.iter_mut().find(|(known, _)| known.name == family.name) {
+                Some((_, lines)) => lines.push(line),
+                None => families.push((family, vec![line])),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (family, lines) in &families {
+        header(&mut out, family.name, "gauge", family.unit, family.help);
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+
+    let bus = labels(&[("controller", controller)]);
+    header(&mut out, "smbus_devices", "gauge", "", "Devices that answered the last scan.");
+    let _ = writeln!(out, "smbus_devices{} {}", bus, sample.devices.len());
+    header(&mut out, "smbus_scans", "counter", "", "Bus scans since the service started.");
+    let _ = writeln!(out, "smbus_scans_total{} {}", bus, sample.counters.scans);
+
+    let mut addrs: Vec<u8> = sample.counters.reads.keys().chain(sample.counters.read_errors.keys()).copied().collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+    for (name, help, counts) in [
+        ("smbus_reads", "Device reads since the service started.", &sample.counters.reads),
+        ("smbus_read_errors", "Device reads that failed since the service started.", &sample.counters.read_errors),
+    ] {
+        header(&mut out, name, "counter", "", help);
+        for addr in &addrs {
+            let address = format!("{:#04x}", addr);
+            let device = labels(&[("controller", controller), ("address", &address), ("model", model(*addr))]);
+            let _ = writeln!(out, "{}_total{} {}", name, device, counts.get(addr).copied().unwrap_or(0));
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Answers scrapes until the listener fails, each on a thread of its own.
+pub fn serve_metrics(listener: TcpListener, service: SharedService, controller: String) -> io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept()?;
+        let service = service.clone();
+        let controller = controller.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = answer(stream, &service, &controller) {
+                log::debug!("Metrics request from {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+fn answer(stream: TcpStream, service: &SharedService, controller: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(Read::take(stream.try_clone()?, MAX_REQUEST_LEN));
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The rest of the head isn't needed, but it's read so the client
+    // isn't reset while still sending it
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line)? {
+            0 => break,
+            _ if line.trim_end().is_empty() => break,
+            _ => {}
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let sample = match service.lock() {
+                Ok(mut service) => service.sample(Duration::from_millis(MAX_AGE_MS)),
+                Err(_) => return respond(stream, "500 Internal Server Error", "text/plain", "the service failed\n"),
+            };
+            ("200 OK", CONTENT_TYPE, render(controller, &sample))
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "only /metrics is served here\n".to_owned()),
+        (Some(_), Some(_)) => ("405 Method Not Allowed", "text/plain", "only GET is served here\n".to_owned()),
+        _ => ("400 Bad Request", "text/plain", "couldn't parse the request\n".to_owned()),
+    };
+    respond(stream, status, content_type, &body)
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body,
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::drivers::{DeviceKind, SensorReading};
+    use crate::smbus::{Property, Value};
+
+    fn sensor(name: &str, value: f64, unit: &str) -> SensorReading {
+        SensorReading { name: name.to_owned(), value: Value::Scaled(value), unit: Some(unit.to_owned()), min: None, max: None, critical: None }
+    }
+
+    fn device(addr: u8, name: &str) -> DeviceInfo {
+        DeviceInfo { addr, name: name.to_owned(), kind: DeviceKind::Unknown, identified: true }
+    }
+
+    // A fan controller, and a board monitor whose name needs escaping
+    fn sample() -> Sample {
+        let fan = DeviceReading {
+            sensors: vec![sensor("EXT_TEMP", 52.0, "°C"), sensor("FAN1", 1200.0, "RPM")],
+            properties: vec![
+                Property::new("EXT_TEMP", Value::Scaled(52.0), Some("°C")),
+                Property::new("PWM", Value::Integer(25), Some("%")),
+                Property::new("FAN_MODE", Value::Enum("Manual".to_owned()), None),
+            ],
+        };
+        let board = DeviceReading {
+            sensors: vec![sensor("VCORE", 1500.0, "mV"), sensor("12V", 12.0, "V")],
+            properties: vec![
+                Property::new("Uptime", Value::Integer(90), Some("s")),
+                Property::new("Tach", Value::Undefined, Some("RPM")),
+            ],
+        };
+        Sample {
+            devices: vec![device(0x4c, "EMC2101"), device(0x2d, "Board \"B\"\\rev\n2")],
+            readings: vec![(0x4c, fan), (0x2d, board)],
+            counters: Counters {
+                scans: 2,
+                reads: HashMap::from([(0x4c, 5), (0x2d, 1)]),
+                read_errors: HashMap::from([(0x2d, 3)]),
+            },
+        }
+    }
+
+    fn lines_of<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+        text.lines().filter(|line| line.starts_with(&format!("{}{{", name))).collect()
+    }
+
+    #[test]
+    fn units_go_into_families_in_base_units() {
+        let text = render("i801", &sample());
+        // Sensors first, and a property isn't repeated for its sensor
+        assert_eq!(lines_of(&text, "smbus_temperature_celsius"), [
+            r#"smbus_temperature_celsius{controller="i801",address="0x4c",model="EMC2101",sensor="EXT_TEMP"} 52"#,
+        ]);
+        assert_eq!(lines_of(&text, "smbus_level_ratio"), [
+            r#"smbus_level_ratio{controller="i801",address="0x4c",model="EMC2101",sensor="PWM"} 0.25"#,
+        ]);
+        // Millivolts and volts share a family, in volts
+        let volts = lines_of(&text, "smbus_voltage_volts");
+        assert_eq!(volts.len(), 2);
+        assert!(volts[0].ends_with(r#"sensor="VCORE"} 1.5"#));
+        assert!(volts[1].ends_with(r#"sensor="12V"} 12"#));
+        // Each family's header comes once, before its samples
+        assert_eq!(text.matches("# TYPE smbus_voltage_volts gauge\n# UNIT smbus_voltage_volts volts\n# HELP").count(), 1);
+        let header = text.find("# TYPE smbus_voltage_volts").unwrap();
+        assert!(text.find("smbus_voltage_volts{").unwrap() > header);
+        // Enums and undefined values have no number to show
+        assert!(!text.contains("FAN_MODE") && !text.contains("Tach"));
+    }
+
+    #[test]
+    fn other_units_are_labelled_readings() {
+        let text = render("i801", &sample());
+        assert_eq!(lines_of(&text, "smbus_reading").len(), 1);
+        assert!(lines_of(&text, "smbus_reading")[0].ends_with(r#"sensor="Uptime",unit="s"} 90"#));
+        assert!(!text.contains("# UNIT smbus_reading"));
+    }
+
+    #[test]
+    fn labels_are_escaped_and_counters_are_totals() {
+        let text = render("i801", &sample());
+        let model = r#"model="Board \"B\"\\rev\n2""#;
+        assert!(lines_of(&text, "smbus_reading")[0].contains(model));
+
+        assert!(text.contains("# TYPE smbus_scans counter\n"));
+        assert!(text.contains("smbus_scans_total{controller=\"i801\"} 2\n"));
+        assert!(text.contains("smbus_devices{controller=\"i801\"} 2\n"));
+        // Every device counted in either is in both, in address order
+        assert_eq!(lines_of(&text, "smbus_reads_total"), [
+            format!(r#"smbus_reads_total{{controller="i801",address="0x2d",{}}} 1"#, model),
+            r#"smbus_reads_total{controller="i801",address="0x4c",model="EMC2101"} 5"#.to_owned(),
+        ]);
+        assert_eq!(lines_of(&text, "smbus_read_errors_total"), [
+            format!(r#"smbus_read_errors_total{{controller="i801",address="0x2d",{}}} 3"#, model),
+            r#"smbus_read_errors_total{controller="i801",address="0x4c",model="EMC2101"} 0"#.to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn expositions_end_with_eof() {
+        let text = render("i801", &sample());
+        assert!(text.ends_with("\n# EOF\n"));
+        assert_eq!(text.matches("# EOF").count(), 1);
+
+        let empty = Sample { devices: Vec::new(), readings: Vec::new(), counters: Counters::default() };
+        let text = render("i801", &empty);
+        assert!(text.starts_with("# TYPE smbus_devices gauge\n"));
+        assert!(text.ends_with("smbus_devices{controller=\"i801\"} 0\n# TYPE smbus_scans counter\n# HELP smbus_scans Bus scans since the service started.\nsmbus_scans_total{controller=\"i801\"} 0\n# TYPE smbus_reads counter\n# HELP smbus_reads Device reads since the service started.\n# TYPE smbus_read_errors counter\n# HELP smbus_read_errors Device reads that failed since the service started.\n# EOF\n"));
+    }
+}
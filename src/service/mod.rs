@@ -0,0 +1,16 @@
+// The bus service: a separate process that owns the bus, so raw access
+// needs privileges only there and not in every console that shows it.
+//
+// Clients speak the protocol in `protocol` over the local channel in
+// `ipc`, or over TLS from other computers (`remote`); `metrics` serves
+// readings to Prometheus, and `openrgb` lighting to OpenRGB's clients.
+// Nothing here depends on MMC, so the service, its clients and their
+// tests build anywhere.
+
+pub mod client;
+pub mod ipc;
+pub mod metrics;
+pub mod openrgb;
+pub mod protocol;
+pub mod remote;
+pub mod server;
@@ -0,0 +1,946 @@
+// The OpenRGB SDK protocol, so OpenRGB and the clients and plugins written
+// for its SDK can drive the RGB controllers the service found on its bus.
+//
+// Every packet, both ways, is a 16-byte header, "ORGB" and then the
+// device index, packet id and data length as little-endian u32s, followed
+// by the data. Strings are a u16 length that counts a trailing NUL, then
+// the bytes and the NUL; colours are red, green, blue and a zero byte.
+//
+// Controllers are numbered in address order. Each one's mode list starts
+// with Direct, which shows the LED colours as clients set them, followed
+// by the driver's own modes. What clients set is kept here between
+// connections, and saved profiles are those states in a JSON file.
+//
+// Like the metrics port, nothing on this one is authenticated, so it
+// should only be bound where anyone who can reach it may change the
+// lighting.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::smbus::drivers::rgb::{find_mode, Color, RgbDevice, RgbMode};
+use crate::smbus::SmbusError;
+
+use super::server::{BusService, SharedService};
+
+/// The port OpenRGB's clients look for a server on.
+pub const DEFAULT_PORT: u16 = 6742;
+
+/// The newest SDK protocol served. Version 4 adds zone segments, which
+/// nothing here has.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// The mode that shows the LED colours as they're set.
+pub const DIRECT: &str = "Direct";
+
+const MAGIC: &[u8; 4] = b"ORGB";
+const HEADER_LEN: usize = 16;
+// Longest packet taken; a full controller's colours are a few hundred bytes
+const MAX_PACKET_LEN: u32 = 1 << 20;
+
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const REQUEST_PROTOCOL_VERSION: u32 = 40;
+const SET_CLIENT_NAME: u32 = 50;
+const REQUEST_PROFILE_LIST: u32 = 150;
+const REQUEST_SAVE_PROFILE: u32 = 151;
+const REQUEST_LOAD_PROFILE: u32 = 152;
+const REQUEST_DELETE_PROFILE: u32 = 153;
+const RESIZE_ZONE: u32 = 1000;
+const UPDATE_LEDS: u32 = 1050;
+const UPDATE_ZONE_LEDS: u32 = 1051;
+const UPDATE_SINGLE_LED: u32 = 1052;
+const SET_CUSTOM_MODE: u32 = 1100;
+const UPDATE_MODE: u32 = 1101;
+const SAVE_MODE: u32 = 1102;
+
+const MODE_FLAG_HAS_SPEED: u32 = 1 << 0;
+const MODE_FLAG_HAS_DIRECTION_LR: u32 = 1 << 1;
+const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+
+const MODE_DIRECTION_LEFT: u32 = 0;
+const MODE_DIRECTION_RIGHT: u32 = 1;
+const MODE_DIRECTION_DOWN: u32 = 3;
+
+const MODE_COLORS_NONE: u32 = 0;
+const MODE_COLORS_PER_LED: u32 = 1;
+
+const DEVICE_TYPE_MOTHERBOARD: i32 = 0;
+const DEVICE_TYPE_DRAM: i32 = 1;
+
+const ZONE_TYPE_SINGLE: i32 = 0;
+const ZONE_TYPE_LINEAR: i32 = 1;
+
+/// What a controller shows, as clients last set it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightingState {
+    /// The mode's name, or `Direct`.
+    pub mode: String,
+    pub speed: u8,
+    pub reverse: bool,
+    /// One per LED, across every zone in order.
+    pub colors: Vec<Color>,
+}
+
+/// A controller's state in a saved profile. It's only restored to a
+/// controller at the same address with the same name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedController {
+    pub addr: u8,
+    pub name: String,
+    #[serde(flatten)]
+    pub state: LightingState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightingProfile {
+    pub name: String,
+    pub controllers: Vec<SavedController>,
+}
+
+#[derive(Debug)]
+enum Failure {
+    Bus(SmbusError),
+    NoController(u32),
+    NoProfile(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Bus(e) => write!(f, "{}", e),
+            Failure::NoController(index) => write!(f, "there's no controller {}", index),
+            Failure::NoProfile(name) => write!(f, "there's no profile called \"{}\"", name),
+            Failure::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SmbusError> for Failure {
+    fn from(e: SmbusError) -> Self {
+        Failure::Bus(e)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+pub struct OpenRgbServer {
+    service: SharedService,
+    // Taken before the service whenever both are
+    states: Mutex<HashMap<u8, LightingState>>,
+    profiles: Mutex<Vec<LightingProfile>>,
+    // Where profiles are saved; without one they last as long as the server
+    profile_file: Option<PathBuf>,
+}
+
+impl OpenRgbServer {
+    /// Loads the profiles saved in `profile_file`, if it exists yet.
+    pub fn new(service: SharedService, profile_file: Option<PathBuf>) -> io::Result<Self> {
+        let profiles = match &profile_file {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)?;
+                serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self { service, states: Mutex::new(HashMap::new()), profiles: Mutex::new(profiles), profile_file })
+    }
+
+    fn service(&self) -> Result<MutexGuard<'_, BusService>, SmbusError> {
+        self.service.lock().map_err(|_| SmbusError::Other("the service failed".to_owned()))
+    }
+
+    fn states(&self) -> MutexGuard<'_, HashMap<u8, LightingState>> {
+        self.states.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn controllers(&self) -> Vec<u8> {
+        self.service().map(|service| service.rgb_controllers()).unwrap_or_default()
+    }
+
+    fn addr(&self, index: u32) -> Result<u8, Failure> {
+        self.controllers().get(index as usize).copied().ok_or(Failure::NoController(index))
+    }
+
+    // What was last set, or failing that what the controller shows now
+    fn state(&self, states: &mut HashMap<u8, LightingState>, addr: u8) -> Result<LightingState, SmbusError> {
+        if let Some(state) = states.get(&addr) {
+            return Ok(state.clone());
+        }
+        let state = self.service()?.with_rgb(addr, |driver, rgb, bus| {
+            let leds = rgb.leds();
+            Ok(match rgb.state(bus) {
+                Ok(shown) => {
+                    let mode = match shown.direct {
+                        true => None,
+                        false => rgb.modes().iter().find(|mode| mode.value == shown.mode),
+                    };
+                    let mut colors = shown.colors;
+                    colors.resize(leds, Color::BLACK);
+                    LightingState { mode: mode.map_or(DIRECT, |mode| mode.name).to_owned(), speed: shown.speed, reverse: shown.reverse, colors }
+                }
+                // Clients can still set it; it just starts out blank
+                Err(e) => {
+                    log::warn!("Couldn't read what {} shows: {}", driver.name(), e);
+                    LightingState { mode: DIRECT.to_owned(), speed: *rgb.speeds().start(), reverse: false, colors: vec![Color::BLACK; leds] }
+                }
+            })
+        })?;
+        states.insert(addr, state.clone());
+        Ok(state)
+    }
+
+    // Shows `state` and keeps it if that worked
+    fn apply(&self, states: &mut HashMap<u8, LightingState>, addr: u8, state: LightingState) -> Result<(), SmbusError> {
+        self.service()?.with_rgb(addr, |driver, rgb, bus| {
+            match state.mode.eq_ignore_ascii_case(DIRECT) {
+                true => rgb.set_leds(bus, &state.colors),
+                false => {
+                    let mode = find_mode(rgb.modes(), &state.mode)
+                        .ok_or_else(|| SmbusError::Other(format!("{} has no mode \"{}\"", driver.name(), state.mode)))?;
+                    let speed = state.speed.clamp(*rgb.speeds().start(), *rgb.speeds().end());
+                    rgb.set_mode(bus, mode.value, speed, state.reverse, &state.colors)
+                }
+            }
+        })?;
+        states.insert(addr, state);
+        Ok(())
+    }
+
+    fn description(&self, index: u32, version: u32) -> Result<Vec<u8>, Failure> {
+        let addr = self.addr(index)?;
+        let mut states = self.states();
+        let state = self.state(&mut states, addr)?;
+        let blob = self.service()?.with_rgb(addr, |driver, rgb, _| Ok(describe(addr, &driver.name(), rgb, &state, version)))?;
+        Ok(blob)
+    }
+
+    // Sets the colours of the LEDs from `first` on
+    fn update_leds(&self, index: u32, first: usize, colors: &[Color]) -> Result<(), Failure> {
+        let addr = self.addr(index)?;
+        let mut states = self.states();
+        let mut state = self.state(&mut states, addr)?;
+        for (slot, color) in state.colors.iter_mut().skip(first).zip(colors) {
+            *slot = *color;
+        }
+        // Modes that make their own colours keep these for later
+        let uses_colors = self.service()?.with_rgb(addr, |_, rgb, _| {
+            Ok(find_mode(rgb.modes(), &state.mode).is_none_or(|mode| mode.colors))
+        })?;
+        match uses_colors {
+            true => self.apply(&mut states, addr, state)?,
+            false => {
+                states.insert(addr, state);
+            }
+        }
+        Ok(())
+    }
+
+    fn zone_start(&self, index: u32, zone: usize) -> Result<usize, Failure> {
+        let addr = self.addr(index)?;
+        let zones = self.service()?.with_rgb(addr, |_, rgb, _| Ok(rgb.zones()))?;
+        match zone < zones.len() {
+            true => Ok(zones[..zone].iter().map(|zone| zone.leds).sum()),
+            false => Err(Failure::Bus(SmbusError::Other(format!("there's no zone {}", zone)))),
+        }
+    }
+
+    // `mode` indexes the mode list clients were sent, Direct first
+    fn update_mode(&self, index: u32, mode: usize, speed: u32, direction: u32) -> Result<(), Failure> {
+        let addr = self.addr(index)?;
+        let mut states = self.states();
+        let mut state = self.state(&mut states, addr)?;
+        let modes = self.service()?.with_rgb(addr, |_, rgb, _| Ok(mode_list(rgb)))?;
+        let chosen = modes.get(mode)
+            .ok_or_else(|| Failure::Bus(SmbusError::Other(format!("there's no mode {}", mode))))?;
+        state.mode = chosen.map_or(DIRECT, |mode| mode.name).to_owned();
+        if chosen.is_some_and(|mode| mode.speed) {
+            state.speed = speed.min(u8::MAX as u32) as u8;
+        }
+        if chosen.is_some_and(|mode| mode.direction) {
+            state.reverse = matches!(direction, MODE_DIRECTION_RIGHT | MODE_DIRECTION_DOWN);
+        }
+        self.apply(&mut states, addr, state)?;
+        Ok(())
+    }
+
+    fn set_custom_mode(&self, index: u32) -> Result<(), Failure> {
+        let addr = self.addr(index)?;
+        let mut states = self.states();
+        let mut state = self.state(&mut states, addr)?;
+        state.mode = DIRECT.to_owned();
+        self.apply(&mut states, addr, state)?;
+        Ok(())
+    }
+
+    fn profile_list(&self) -> Vec<u8> {
+        let profiles = self.profiles.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut blob = Blob::sized();
+        blob.u16(profiles.len() as u16);
+        for profile in profiles.iter() {
+            blob.string(&profile.name);
+        }
+        blob.finish()
+    }
+
+    fn save_profile(&self, name: &str) -> Result<(), Failure> {
+        let mut controllers = Vec::new();
+        {
+            let mut states = self.states();
+            for addr in self.controllers() {
+                let state = self.state(&mut states, addr)?;
+                let name = self.service()?.with_rgb(addr, |driver, _, _| Ok(driver.name()))?;
+                controllers.push(SavedController { addr, name, state });
+            }
+        }
+        let mut profiles = self.profiles.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        profiles.retain(|profile| !profile.name.eq_ignore_ascii_case(name));
+        profiles.push(LightingProfile { name: name.to_owned(), controllers });
+        self.write_profiles(&profiles)
+    }
+
+    // Controllers that are gone or have changed are skipped
+    fn load_profile(&self, name: &str) -> Result<(), Failure> {
+        let profile = self.profiles.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| Failure::NoProfile(name.to_owned()))?;
+        let present = self.controllers();
+        let mut states = self.states();
+        for saved in profile.controllers {
+            let current = match present.contains(&saved.addr) {
+                true => self.service()?.with_rgb(saved.addr, |driver, _, _| Ok(driver.name()))?,
+                false => String::new(),
+            };
+            if current != saved.name {
+                log::info!("Profile \"{}\" skips {} at {:#04x}, which isn't there", profile.name, saved.name, saved.addr);
+                continue;
+            }
+            if let Err(e) = self.apply(&mut states, saved.addr, saved.state) {
+                log::warn!("Profile \"{}\" couldn't set {}: {}", profile.name, saved.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_profile(&self, name: &str) -> Result<(), Failure> {
+        let mut profiles = self.profiles.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = profiles.len();
+        profiles.retain(|profile| !profile.name.eq_ignore_ascii_case(name));
+        match profiles.len() < count {
+            true => self.write_profiles(&profiles),
+            false => Err(Failure::NoProfile(name.to_owned())),
+        }
+    }
+
+    fn write_profiles(&self, profiles: &[LightingProfile]) -> Result<(), Failure> {
+        if let Some(path) = &self.profile_file {
+            let text = serde_json::to_string_pretty(profiles).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            std::fs::write(path, text)?;
+        }
+        Ok(())
+    }
+}
+
+// Direct, then the driver's modes
+fn mode_list(rgb: &dyn RgbDevice) -> Vec<Option<RgbMode>> {
+    std::iter::once(None).chain(rgb.modes().iter().copied().map(Some)).collect()
+}
+
+// A controller as REQUEST_CONTROLLER_DATA answers it
+fn describe(addr: u8, name: &str, rgb: &dyn RgbDevice, state: &LightingState, version: u32) -> Vec<u8> {
+    let modes = mode_list(rgb);
+    let active = modes.iter()
+        .position(|mode| mode.map_or(DIRECT, |mode| mode.name).eq_ignore_ascii_case(&state.mode))
+        .unwrap_or(0);
+    let (fastest, slowest) = (*rgb.speeds().start(), *rgb.speeds().end());
+
+    let mut blob = Blob::sized();
+    // Memory modules' controllers are moved up to 0x70-0x77
+    blob.i32(match addr {
+        0x70..=0x77 => DEVICE_TYPE_DRAM,
+        _ => DEVICE_TYPE_MOTHERBOARD,
+    });
+    blob.string(name);
+    if version >= 1 {
+        blob.string(rgb.vendor());
+    }
+    blob.string("SMBus RGB controller");
+    blob.string("");
+    blob.string("");
+    blob.string(&format!("SMBus address {:#04x}", addr));
+
+    blob.u16(modes.len() as u16);
+    blob.i32(active as i32);
+    for mode in &modes {
+        let (speed, direction, colors) = mode.map_or((false, false, true), |mode| (mode.speed, mode.direction, mode.colors));
+        blob.string(mode.map_or(DIRECT, |mode| mode.name));
+        // Direct isn't one of the controller's own modes
+        blob.i32(mode.map_or(0xffff, |mode| mode.value as i32));
+        blob.u32(
+            if speed { MODE_FLAG_HAS_SPEED } else { 0 }
+                | if direction { MODE_FLAG_HAS_DIRECTION_LR } else { 0 }
+                | if colors { MODE_FLAG_HAS_PER_LED_COLOR } else { 0 },
+        );
+        // OpenRGB's minimum speed is the slowest
+        match speed {
+            true => {
+                blob.u32(slowest as u32);
+                blob.u32(fastest as u32);
+            }
+            false => {
+                blob.u32(0);
+                blob.u32(0);
+            }
+        }
+        if version >= 3 {
+            // No brightness, minimum and maximum
+            blob.u32(0);
+            blob.u32(0);
+        }
+        // No colours of the mode's own, minimum and maximum
+        blob.u32(0);
+        blob.u32(0);
+        blob.u32(if speed { state.speed as u32 } else { 0 });
+        if version >= 3 {
+            blob.u32(0);
+        }
+        blob.u32(match state.reverse && direction {
+            true => MODE_DIRECTION_RIGHT,
+            false => MODE_DIRECTION_LEFT,
+        });
+        blob.u32(if colors { MODE_COLORS_PER_LED } else { MODE_COLORS_NONE });
+        blob.u16(0);
+    }
+
+    let zones = rgb.zones();
+    blob.u16(zones.len() as u16);
+    for zone in &zones {
+        blob.string(&zone.name);
+        blob.i32(if zone.leds == 1 { ZONE_TYPE_SINGLE } else { ZONE_TYPE_LINEAR });
+        // Minimum, maximum and count; nothing here can be resized
+        blob.u32(zone.leds as u32);
+        blob.u32(zone.leds as u32);
+        blob.u32(zone.leds as u32);
+        // No matrix map
+        blob.u16(0);
+    }
+
+    blob.u16(state.colors.len() as u16);
+    let mut led = 0;
+    for zone in &zones {
+        for i in 0..zone.leds {
+            blob.string(&format!("{} {}", zone.name, i + 1));
+            blob.u32(led);
+            led += 1;
+        }
+    }
+    blob.u16(state.colors.len() as u16);
+    for color in &state.colors {
+        blob.color(*color);
+    }
+    blob.finish()
+}
+
+// Packet data being written
+struct Blob(Vec<u8>);
+
+impl Blob {
+    // Starts with room for the total length, which `finish` fills in
+    fn sized() -> Self {
+        Blob(vec![0; 4])
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, text: &str) {
+        self.u16(text.len() as u16 + 1);
+        self.0.extend_from_slice(text.as_bytes());
+        self.0.push(0);
+    }
+
+    fn color(&mut self, color: Color) {
+        self.0.extend_from_slice(&[color.red, color.green, color.blue, 0]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&len.to_le_bytes());
+        self.0
+    }
+}
+
+// Packet data being read
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "packet ended early"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap_or_default()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        Ok(text(self.take(len)?))
+    }
+
+    fn color(&mut self) -> io::Result<Color> {
+        let bytes = self.take(4)?;
+        Ok(Color::new(bytes[0], bytes[1], bytes[2]))
+    }
+
+    fn colors(&mut self) -> io::Result<Vec<Color>> {
+        let count = self.u16()?;
+        (0..count).map(|_| self.color()).collect()
+    }
+}
+
+// A string without its length, as names are sent on their own
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches('\0').to_owned()
+}
+
+/// Takes clients until the listener fails, each on a thread of its own.
+pub fn serve_openrgb(listener: TcpListener, server: Arc<OpenRgbServer>) -> io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept()?;
+        let server = server.clone();
+        std::thread::spawn(move || {
+            let mut connection = Connection { server: &server, stream, version: 0, client: address.to_string() };
+            match connection.run() {
+                Ok(()) => log::info!("OpenRGB client {} disconnected", connection.client),
+                Err(e) => log::info!("OpenRGB client {} disconnected: {}", connection.client, e),
+            }
+        });
+    }
+}
+
+struct Connection<'a> {
+    server: &'a OpenRgbServer,
+    stream: TcpStream,
+    // Clients that don't say which version they speak get the first one
+    version: u32,
+    client: String,
+}
+
+impl Connection<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some((device, id, data)) = self.receive()? {
+            match self.handle(device, id, &data) {
+                // A packet that doesn't parse is dropped, not the client
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    log::debug!("Bad packet {} from OpenRGB client {}: {}", id, self.client, e);
+                }
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(u32, u32, Vec<u8>)>> {
+        let mut header = [0u8; HEADER_LEN];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        if &header[..4] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an OpenRGB packet"));
+        }
+        let mut cursor = Cursor::new(&header[4..]);
+        let (device, id, len) = (cursor.u32()?, cursor.u32()?, cursor.u32()?);
+        if len > MAX_PACKET_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a {}-byte packet is too long", len)));
+        }
+        let mut data = vec![0; len as usize];
+        self.stream.read_exact(&mut data)?;
+        Ok(Some((device, id, data)))
+    }
+
+    fn send(&mut self, device: u32, id: u32, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(HEADER_LEN + data.len());
+        packet.extend_from_slice(MAGIC);
+        packet.extend_from_slice(&device.to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(data);
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    // Requests that change something get no answer, so their failures
+    // can only be logged
+    fn report(&self, what: &str, result: Result<(), Failure>) {
+        if let Err(e) = result {
+            log::warn!("OpenRGB client {} couldn't {}: {}", self.client, what, e);
+        }
+    }
+
+    fn handle(&mut self, device: u32, id: u32, data: &[u8]) -> io::Result<()> {
+        let mut cursor = Cursor::new(data);
+        match id {
+            REQUEST_CONTROLLER_COUNT => {
+                let count = self.server.controllers().len() as u32;
+                self.send(0, id, &count.to_le_bytes())
+            }
+            REQUEST_CONTROLLER_DATA => {
+                // Newer clients say which version they want it in
+                let version = match data.len() >= 4 {
+                    true => cursor.u32()?.min(PROTOCOL_VERSION),
+                    false => self.version,
+                };
+                // Clients wait for an answer, so a controller that can't be
+                // described still gets one, with no data
+                match self.server.description(device, version) {
+                    Ok(blob) => self.send(device, id, &blob),
+                    Err(e) => {
+                        log::warn!("Couldn't describe controller {} to OpenRGB client {}: {}", device, self.client, e);
+                        self.send(device, id, &[])
+                    }
+                }
+            }
+            REQUEST_PROTOCOL_VERSION => {
+                let version = match data.len() >= 4 {
+                    true => cursor.u32()?,
+                    false => 0,
+                };
+                self.version = version.min(PROTOCOL_VERSION);
+                self.send(0, id, &PROTOCOL_VERSION.to_le_bytes())
+            }
+            SET_CLIENT_NAME => {
+                let name = text(data);
+                log::info!("OpenRGB client {} is {}", self.client, name);
+                self.client = format!("{} ({})", name, self.client);
+                Ok(())
+            }
+            REQUEST_PROFILE_LIST => {
+                let list = self.server.profile_list();
+                self.send(0, id, &list)
+            }
+            REQUEST_SAVE_PROFILE => {
+                self.report("save a profile", self.server.save_profile(&text(data)));
+                Ok(())
+            }
+            REQUEST_LOAD_PROFILE => {
+                self.report("load a profile", self.server.load_profile(&text(data)));
+                Ok(())
+            }
+            REQUEST_DELETE_PROFILE => {
+                self.report("delete a profile", self.server.delete_profile(&text(data)));
+                Ok(())
+            }
+            RESIZE_ZONE => {
+                log::debug!("OpenRGB client {} tried to resize a zone; they're all fixed", self.client);
+                Ok(())
+            }
+            UPDATE_LEDS => {
+                cursor.u32()?;
+                let colors = cursor.colors()?;
+                self.report("set LEDs", self.server.update_leds(device, 0, &colors));
+                Ok(())
+            }
+            UPDATE_ZONE_LEDS => {
+                cursor.u32()?;
+                let zone = cursor.u32()? as usize;
+                let colors = cursor.colors()?;
+                let result = self.server.zone_start(device, zone)
+                    .and_then(|first| self.server.update_leds(device, first, &colors));
+                self.report("set a zone's LEDs", result);
+                Ok(())
+            }
+            UPDATE_SINGLE_LED => {
+                let led = cursor.u32()? as usize;
+                let color = cursor.color()?;
+                self.report("set an LED", self.server.update_leds(device, led, &[color]));
+                Ok(())
+            }
+            SET_CUSTOM_MODE => {
+                self.report("switch to direct mode", self.server.set_custom_mode(device));
+                Ok(())
+            }
+            // Nothing here keeps a mode through a power cycle, so saving
+            // one is the same as setting it
+            UPDATE_MODE | SAVE_MODE => {
+                cursor.u32()?;
+                let mode = cursor.u32()? as usize;
+                let (speed, direction) = self.mode_settings(&mut cursor)?;
+                self.report("set a mode", self.server.update_mode(device, mode, speed, direction));
+                Ok(())
+            }
+            _ => {
+                log::debug!("OpenRGB client {} sent packet {}, which isn't served", self.client, id);
+                Ok(())
+            }
+        }
+    }
+
+    // The speed and direction from a mode as a client sends it back
+    fn mode_settings(&self, cursor: &mut Cursor) -> io::Result<(u32, u32)> {
+        cursor.string()?;
+        // Value, flags, minimum and maximum speed
+        for _ in 0..4 {
+            cursor.u32()?;
+        }
+        if self.version >= 3 {
+            cursor.u32()?;
+            cursor.u32()?;
+        }
+        // Minimum and maximum colours
+        cursor.u32()?;
+        cursor.u32()?;
+        let speed = cursor.u32()?;
+        if self.version >= 3 {
+            cursor.u32()?;
+        }
+        let direction = cursor.u32()?;
+        Ok((speed, direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use crate::smbus::safety::WritePolicy;
+    use crate::smbus::sim::SimulatedBus;
+
+    // An OpenRGB server on the demo bus, whose only RGB controller is the
+    // ENE one at 0x70, on a loopback port
+    fn start() -> (SharedService, SocketAddr) {
+        let mut service = BusService::new(Box::new(SimulatedBus::demo()), WritePolicy::default(), Vec::new());
+        service.scan(0x03..=0x77);
+        let service = Arc::new(Mutex::new(service));
+        let server = Arc::new(OpenRgbServer::new(service.clone(), None).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve_openrgb(listener, server));
+        (service, address)
+    }
+
+    fn send(stream: &mut TcpStream, device: u32, id: u32, data: &[u8]) {
+        let mut packet = MAGIC.to_vec();
+        for field in [device, id, data.len() as u32] {
+            packet.extend_from_slice(&field.to_le_bytes());
+        }
+        packet.extend_from_slice(data);
+        stream.write_all(&packet).unwrap();
+    }
+
+    fn receive(stream: &mut TcpStream) -> (u32, u32, Vec<u8>) {
+        let mut header = [0u8; HEADER_LEN];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..4], MAGIC);
+        let mut cursor = Cursor::new(&header[4..]);
+        let (device, id, len) = (cursor.u32().unwrap(), cursor.u32().unwrap(), cursor.u32().unwrap());
+        let mut data = vec![0; len as usize];
+        stream.read_exact(&mut data).unwrap();
+        (device, id, data)
+    }
+
+    // Says hello the way OpenRGB does and returns the version agreed on
+    fn connect(address: SocketAddr) -> (TcpStream, u32) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        send(&mut stream, 0, REQUEST_PROTOCOL_VERSION, &4u32.to_le_bytes());
+        let (_, id, data) = receive(&mut stream);
+        assert_eq!(id, REQUEST_PROTOCOL_VERSION);
+        let version = u32::from_le_bytes(data.try_into().unwrap());
+        send(&mut stream, 0, SET_CLIENT_NAME, b"smbus-snapin tests\0");
+        (stream, version)
+    }
+
+    fn controller_data(stream: &mut TcpStream, device: u32, version: u32) -> Vec<u8> {
+        send(stream, device, REQUEST_CONTROLLER_DATA, &version.to_le_bytes());
+        let (replied, id, data) = receive(stream);
+        assert_eq!((replied, id), (device, REQUEST_CONTROLLER_DATA));
+        data
+    }
+
+    struct Mode {
+        name: String,
+        flags: u32,
+        speed: u32,
+    }
+
+    // The parts of a version 3 description the tests look at
+    struct Description {
+        device_type: i32,
+        name: String,
+        vendor: String,
+        location: String,
+        modes: Vec<Mode>,
+        active: usize,
+        zones: Vec<(String, u32)>,
+        leds: Vec<String>,
+        colors: Vec<Color>,
+    }
+
+    fn parse(data: &[u8]) -> io::Result<Description> {
+        let mut cursor = Cursor::new(data);
+        assert_eq!(cursor.u32()? as usize, data.len());
+        let device_type = cursor.u32()? as i32;
+        let name = cursor.string()?;
+        let vendor = cursor.string()?;
+        cursor.string()?;
+        cursor.string()?;
+        cursor.string()?;
+        let location = cursor.string()?;
+
+        let count = cursor.u16()?;
+        let active = cursor.u32()? as usize;
+        let mut modes = Vec::new();
+        for _ in 0..count {
+            let name = cursor.string()?;
+            cursor.u32()?;
+            let flags = cursor.u32()?;
+            // Speeds, brightnesses and colours, minimum and maximum
+            for _ in 0..6 {
+                cursor.u32()?;
+            }
+            let speed = cursor.u32()?;
+            // Brightness, direction and colour mode
+            for _ in 0..3 {
+                cursor.u32()?;
+            }
+            cursor.colors()?;
+            modes.push(Mode { name, flags, speed });
+        }
+
+        let mut zones = Vec::new();
+        for _ in 0..cursor.u16()? {
+            let name = cursor.string()?;
+            cursor.u32()?;
+            cursor.u32()?;
+            cursor.u32()?;
+            let leds = cursor.u32()?;
+            assert_eq!(cursor.u16()?, 0);
+            zones.push((name, leds));
+        }
+        let mut leds = Vec::new();
+        for _ in 0..cursor.u16()? {
+            leds.push(cursor.string()?);
+            cursor.u32()?;
+        }
+        let colors = cursor.colors()?;
+        assert_eq!(cursor.pos, data.len());
+        Ok(Description { device_type, name, vendor, location, modes, active, zones, leds, colors })
+    }
+
+    #[test]
+    fn clients_get_the_ene_controller() {
+        let (_, address) = start();
+        let (mut stream, version) = connect(address);
+        assert_eq!(version, PROTOCOL_VERSION);
+
+        send(&mut stream, 0, REQUEST_CONTROLLER_COUNT, &[]);
+        let (_, id, data) = receive(&mut stream);
+        assert_eq!(id, REQUEST_CONTROLLER_COUNT);
+        assert_eq!(data, 1u32.to_le_bytes());
+
+        let description = parse(&controller_data(&mut stream, 0, version)).unwrap();
+        assert_eq!(description.device_type, DEVICE_TYPE_DRAM);
+        assert_eq!(description.name, "ENE RGB controller");
+        assert_eq!(description.vendor, "ENE");
+        assert_eq!(description.location, "SMBus address 0x70");
+        // Direct, then the ENE's 14
+        assert_eq!(description.modes.len(), 15);
+        assert_eq!(description.modes[0].name, DIRECT);
+        assert_eq!(description.modes[0].flags, MODE_FLAG_HAS_PER_LED_COLOR);
+        // The demo's static orange at speed 2
+        assert_eq!(description.modes[description.active].name, "Static");
+        let breathing = description.modes.iter().find(|mode| mode.name == "Breathing").unwrap();
+        assert_eq!(breathing.flags & MODE_FLAG_HAS_SPEED, MODE_FLAG_HAS_SPEED);
+        assert_eq!(breathing.speed, 2);
+        assert_eq!(description.zones, vec![("LEDs".to_owned(), 5)]);
+        assert_eq!(description.leds, ["LEDs 1", "LEDs 2", "LEDs 3", "LEDs 4", "LEDs 5"]);
+        assert_eq!(description.colors, vec![Color::new(0xff, 0x60, 0x00); 5]);
+
+    }
+
+    #[test]
+    fn unknown_controllers_get_an_empty_answer() {
+        let (_, address) = start();
+        let (mut stream, version) = connect(address);
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+
+        assert_eq!(controller_data(&mut stream, 1, version), Vec::<u8>::new());
+        assert_eq!(controller_data(&mut stream, u32::MAX, version), Vec::<u8>::new());
+        // And the connection carries on
+        assert!(!controller_data(&mut stream, 0, version).is_empty());
+    }
+
+    #[test]
+    fn older_clients_get_older_descriptions() {
+        let (_, address) = start();
+        let mut stream = TcpStream::connect(address).unwrap();
+        // Version 0 has no vendor and fewer fields in each mode
+        let v0 = controller_data(&mut stream, 0, 0);
+        let v3 = controller_data(&mut stream, 0, PROTOCOL_VERSION);
+        let mut cursor = Cursor::new(&v0[8..]);
+        assert_eq!(cursor.string().unwrap(), "ENE RGB controller");
+        assert_eq!(cursor.string().unwrap(), "SMBus RGB controller");
+        assert_eq!(v3.len() - v0.len(), "ENE\0".len() + 2 + 15 * 3 * 4);
+    }
+
+    #[test]
+    fn led_updates_reach_the_bus() {
+        let (service, address) = start();
+        let (mut stream, version) = connect(address);
+        let green = Color::new(0x00, 0xc0, 0x20);
+        // OpenRGB switches to Direct before it sends colours
+        send(&mut stream, 0, SET_CUSTOM_MODE, &[]);
+        let mut data = Blob::sized();
+        data.u16(5);
+        for _ in 0..5 {
+            data.color(green);
+        }
+        send(&mut stream, 0, UPDATE_LEDS, &data.finish());
+
+        // Packets are handled in order, so this one sees the update
+        let description = parse(&controller_data(&mut stream, 0, version)).unwrap();
+        assert_eq!(description.modes[description.active].name, DIRECT);
+        assert_eq!(description.colors, vec![green; 5]);
+
+        let shown = service.lock().unwrap().with_rgb(0x70, |_, rgb, bus| rgb.state(bus)).unwrap();
+        assert!(shown.direct);
+        assert_eq!(shown.colors, vec![green; 5]);
+    }
+}
@@ -0,0 +1,288 @@
+// What clients and the bus service say to each other.
+//
+// Each message is one line of JSON. The client sends `RequestFrame`s and
+// the service answers each with a `ServerFrame::Response` carrying the
+// same id. Once a client subscribes, `ServerFrame::Event`s arrive between
+// responses whenever the service has read the devices again.
+//
+//     > {"id":1,"request":{"hello":{"version":{"major":1,"minor":0},"client":"smbus-snapin"}}}
+//     < {"response":{"id":1,"result":{"Ok":{"hello":{"version":{"major":1,"minor":0},"server":"smbus-service"}}}}}
+//     > {"id":2,"request":{"read":{"addr":76}}}
+//     < {"response":{"id":2,"result":{"Ok":{"reading":{"properties":[...],"sensors":[...]}}}}}
+//
+// The first request must be `hello`. The service refuses clients with a
+// different major version. Minor versions only add requests, so a client
+// can use anything from the lower of the two minor versions.
+//
+// Local clients are trusted with everything. Clients on other computers
+// (since 1.1) are given a role by their TLS certificate, or else answer
+// the challenge in the service's hello with `authenticate`.
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::profile::ProfileSetting;
+use crate::smbus::drivers::{DeviceKind, SensorReading, Setting};
+use crate::smbus::{Property, SmbusError};
+
+pub const PROTOCOL_VERSION: Version = Version { major: 1, minor: 2 };
+
+/// Longest line either side accepts, so a confused peer can't make the
+/// other buffer without end.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// What a client may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Read devices and run dry runs, but not write to the bus.
+    ReadOnly,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::ReadOnly => "read-only",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl Role {
+    pub fn allows(self, request: &Request) -> bool {
+        match (self, request) {
+            (Role::Admin, _) => true,
+            (Role::ReadOnly, Request::Write { dry_run, .. } | Request::ApplyProfile { dry_run, .. }) => *dry_run,
+            (Role::ReadOnly, Request::Transfer { transaction, .. }) => !transaction.is_write(),
+            (Role::ReadOnly, _) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestFrame {
+    pub id: u64,
+    pub request: Request,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    Hello { version: Version, client: String },
+    /// Probes `first..=last`, identifies what answers and replaces the
+    /// devices the service knows.
+    Scan { first: u8, last: u8 },
+    /// The devices found by the last scan, without touching the bus.
+    Devices,
+    /// Since 1.2. Identifies the device at `addr`, which is known from
+    /// then on even if the scan's probes missed it, and says what can be
+    /// read about the part.
+    Identify { addr: u8 },
+    Read { addr: u8 },
+    Settings { addr: u8 },
+    /// Writes one of the values from `Settings`, like a device's
+    /// Properties dialog.
+    Write { addr: u8, key: String, value: f64, dry_run: bool },
+    /// A single SMBus transaction, for clients that drive devices
+    /// themselves.
+    Transfer { addr: u8, transaction: Transaction },
+    /// Sends an event with a reading of each device in `addrs`, or every
+    /// known device if it's empty, every `interval_ms`. Replaces an
+    /// earlier subscription.
+    Subscribe { addrs: Vec<u8>, interval_ms: u32 },
+    Unsubscribe,
+    Profiles,
+    ApplyProfile { name: String, dry_run: bool },
+    /// Since 1.1. Proves the client holds the pre-shared key `key_id`:
+    /// `proof` is the hex HMAC-SHA256 of the hello's challenge, keyed with
+    /// the secret.
+    Authenticate { key_id: String, proof: String },
+}
+
+/// The transactions of `SmbusTransport`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transaction {
+    ReadByteData { command: u8 },
+    WriteByteData { command: u8, value: u8 },
+    ReadWordData { command: u8 },
+    WriteWordData { command: u8, value: u16 },
+    ReceiveByte,
+    ReadBlockData { command: u8 },
+    ReadI2cBlockData { command: u8, len: usize },
+    WriteI2cBlockData { command: u8, data: Vec<u8> },
+}
+
+impl Transaction {
+    pub fn is_write(&self) -> bool {
+        matches!(self, Transaction::WriteByteData { .. } | Transaction::WriteWordData { .. } | Transaction::WriteI2cBlockData { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerFrame {
+    Response { id: u64, result: Result<Reply, WireError> },
+    Event(Event),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Hello {
+        version: Version,
+        server: String,
+        /// Since 1.1. `None` until the client authenticates.
+        #[serde(default)]
+        role: Option<Role>,
+        /// Since 1.1. What `authenticate` signs, if it's needed.
+        #[serde(default)]
+        challenge: Option<String>,
+    },
+    Authenticated { role: Role },
+    Devices(Vec<DeviceInfo>),
+    /// Since 1.2.
+    Identification { device: DeviceInfo, properties: Vec<Property> },
+    Reading(DeviceReading),
+    Settings(Vec<Setting>),
+    /// `performed` is false for a dry run.
+    Written { performed: bool },
+    Byte(u8),
+    Word(u16),
+    Bytes(Vec<u8>),
+    Profiles(Vec<ProfileInfo>),
+    ProfileApplied { failures: Vec<ProfileFailure> },
+    Done,
+}
+
+/// A subscribed device read again. Failed reads are sent too, so clients
+/// can tell a device that stopped answering from a quiet service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub addr: u8,
+    pub reading: Result<DeviceReading, WireError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub addr: u8,
+    pub name: String,
+    pub kind: DeviceKind,
+    /// False for devices that answered but that no driver recognised.
+    pub identified: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceReading {
+    pub properties: Vec<Property>,
+    pub sensors: Vec<SensorReading>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileFailure {
+    pub setting: ProfileSetting,
+    pub error: WireError,
+}
+
+/// `SmbusError` as it crosses the wire, plus what can go wrong with the
+/// conversation itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireError {
+    Nack { addr: u8 },
+    Timeout,
+    Blocked { addr: u8, reason: String },
+    Unsupported(String),
+    Other(String),
+    /// The request couldn't be understood or isn't allowed yet, e.g.
+    /// anything before `hello`.
+    Protocol(String),
+    /// Since 1.1. The client's role doesn't allow the request.
+    Denied(String),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Nack { addr } => SmbusError::Nack { addr: *addr }.fmt(f),
+            WireError::Timeout => SmbusError::Timeout.fmt(f),
+            WireError::Blocked { addr, reason } => SmbusError::Blocked { addr: *addr, reason: reason.clone() }.fmt(f),
+            WireError::Unsupported(what) => write!(f, "transaction not supported: {}", what),
+            WireError::Other(msg) => f.write_str(msg),
+            WireError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            WireError::Denied(msg) => write!(f, "access denied: {}", msg),
+        }
+    }
+}
+
+impl From<SmbusError> for WireError {
+    fn from(e: SmbusError) -> Self {
+        match e {
+            SmbusError::Nack { addr } => WireError::Nack { addr },
+            SmbusError::Timeout => WireError::Timeout,
+            SmbusError::Blocked { addr, reason } => WireError::Blocked { addr, reason },
+            SmbusError::Unsupported(what) => WireError::Unsupported(what.to_owned()),
+            SmbusError::Other(msg) => WireError::Other(msg),
+        }
+    }
+}
+
+// `SmbusError::Unsupported` only holds static strings, so the service's
+// reason is kept as text
+impl From<WireError> for SmbusError {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Nack { addr } => SmbusError::Nack { addr },
+            WireError::Timeout => SmbusError::Timeout,
+            WireError::Blocked { addr, reason } => SmbusError::Blocked { addr, reason },
+            e @ (WireError::Unsupported(_) | WireError::Protocol(_) | WireError::Denied(_)) => SmbusError::Other(e.to_string()),
+            WireError::Other(msg) => SmbusError::Other(msg),
+        }
+    }
+}
+
+pub fn write_frame<T: Serialize>(out: &mut dyn Write, frame: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    line.push(b'\n');
+    out.write_all(&line)?;
+    out.flush()
+}
+
+/// Reads the next line. Returns `None` at the end of the stream.
+pub fn read_line(input: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let read = Read::take(input, MAX_FRAME_LEN as u64 + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    Ok(Some(line))
+}
+
+pub fn parse_frame<T: DeserializeOwned>(line: &[u8]) -> io::Result<T> {
+    serde_json::from_slice(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,568 @@
+// Reaching the bus service from another computer: TLS over TCP, who may
+// connect, and where a client finds the credentials for each computer.
+//
+// The service lists who may connect in an access file:
+//
+//     [[key]]
+//     id = "lab-console"
+//     secret = "a long random string"
+//     role = "admin"
+//
+//     [[certificate]]
+//     # SHA-256 of the client certificate, as `openssl x509 -fingerprint
+//     # -sha256` prints it. Client certificates must be issued by the CA
+//     # given with --client-ca.
+//     sha256 = "3F:A2:..."
+//     role = "read-only"
+//
+// A client's remote.toml says how to reach each computer:
+//
+//     [[computer]]
+//     name = "RIG-07"              # as typed in "Connect to another computer"
+//     address = "rig-07.lab:6750"  # defaults to the name and DEFAULT_PORT
+//     ca = "C:\\lab\\ca.pem"       # issued the service's certificate
+//     key_id = "lab-console"
+//     secret = "a long random string"
+//     # or certificate = "...", certificate_key = "..."
+//
+// An entry named "*" covers computers without one of their own.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ring::rand::SecureRandom;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, ConnectionCommon, RootCertStore, ServerConfig, ServerConnection, SideData, StreamOwned};
+use serde::Deserialize;
+
+use super::ipc::{Reader, Writer};
+use super::protocol::Role;
+
+/// The service's TCP port unless told otherwise.
+pub const DEFAULT_PORT: u16 = 6750;
+
+// A client gets this long to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Longest a read holds the connection while waiting for the rest of a
+// record
+const READ_SLICE: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum RemoteError {
+    Io(io::Error),
+    /// An access file or remote.toml that doesn't make sense.
+    Config(String),
+    Tls(rustls::Error),
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::Io(e) => write!(f, "{}", e),
+            RemoteError::Config(e) => write!(f, "{}", e),
+            RemoteError::Tls(e) => write!(f, "TLS: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<io::Error> for RemoteError {
+    fn from(e: io::Error) -> Self {
+        RemoteError::Io(e)
+    }
+}
+
+impl From<rustls::Error> for RemoteError {
+    fn from(e: rustls::Error) -> Self {
+        RemoteError::Tls(e)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresharedKey {
+    pub id: String,
+    pub secret: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PinnedCertificate {
+    pub sha256: String,
+    pub role: Role,
+}
+
+/// Who may connect to the service from another computer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessList {
+    #[serde(default, rename = "key")]
+    pub keys: Vec<PresharedKey>,
+    #[serde(default, rename = "certificate")]
+    pub certificates: Vec<PinnedCertificate>,
+}
+
+impl AccessList {
+    pub fn load(path: &Path) -> Result<Self, RemoteError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RemoteError::Config(format!("couldn't read {}: {}", path.display(), e)))?;
+        let access: Self = toml::from_str(&text)
+            .map_err(|e| RemoteError::Config(format!("{}: {}", path.display(), e)))?;
+        if let Some(key) = access.keys.iter().find(|key| key.secret.len() < 16) {
+            return Err(RemoteError::Config(format!(
+                "{}: the secret of key \"{}\" is shorter than 16 characters", path.display(), key.id)));
+        }
+        Ok(access)
+    }
+
+    /// The role of a client that presented `certificate`, if it's pinned.
+    pub fn certificate_role(&self, certificate: &[u8]) -> Option<Role> {
+        let fingerprint = fingerprint(certificate);
+        self.certificates.iter()
+            .find(|pinned| normalize_fingerprint(&pinned.sha256) == fingerprint)
+            .map(|pinned| pinned.role)
+    }
+
+    /// The role of a client that signed `challenge` with key `key_id`.
+    pub fn key_role(&self, key_id: &str, challenge: &str, proof: &str) -> Option<Role> {
+        let key = self.keys.iter().find(|key| key.id == key_id)?;
+        let proof = from_hex(proof)?;
+        let hmac = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key.secret.as_bytes());
+        ring::hmac::verify(&hmac, challenge.as_bytes(), &proof).ok().map(|_| key.role)
+    }
+}
+
+/// A fresh challenge for `authenticate`.
+pub fn challenge() -> String {
+    let mut bytes = [0u8; 32];
+    // The system's random source only fails if it's missing altogether
+    ring::rand::SystemRandom::new().fill(&mut bytes).expect("no system random source");
+    to_hex(&bytes)
+}
+
+/// What a client sends to `authenticate` with `secret`.
+pub fn proof(secret: &str, challenge: &str) -> String {
+    let hmac = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    to_hex(ring::hmac::sign(&hmac, challenge.as_bytes()).as_ref())
+}
+
+fn fingerprint(certificate: &[u8]) -> String {
+    to_hex(ring::digest::digest(&ring::digest::SHA256, certificate).as_ref())
+}
+
+// Fingerprints are compared as lowercase hex without separators
+fn normalize_fingerprint(text: &str) -> String {
+    text.chars().filter(|c| c.is_ascii_hexdigit()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, RemoteError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| RemoteError::Config(format!("{}: {}", path.display(), e)))?;
+    match certificates.is_empty() {
+        true => Err(RemoteError::Config(format!("{} holds no certificates", path.display()))),
+        false => Ok(certificates),
+    }
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, RemoteError> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| RemoteError::Config(format!("{}: {}", path.display(), e)))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, RemoteError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots.add(certificate)?;
+    }
+    Ok(roots)
+}
+
+/// Listens for clients on other computers.
+pub struct RemoteListener {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+}
+
+/// A client that has connected but not finished the TLS handshake.
+pub struct PendingConnection {
+    stream: TcpStream,
+    config: Arc<ServerConfig>,
+}
+
+/// A client on another computer, once TLS is up.
+pub struct RemoteConnection {
+    pub reader: Reader,
+    pub writer: Writer,
+    pub address: SocketAddr,
+    /// The client's certificate, if it presented one.
+    pub certificate: Option<Vec<u8>>,
+}
+
+impl RemoteListener {
+    /// Listens on `address` as the holder of `certificate`. With
+    /// `client_ca`, clients may present certificates it issued; they're
+    /// optional either way, since keys work too.
+    pub fn bind(address: &str, certificate: &Path, key: &Path, client_ca: Option<&Path>) -> Result<Self, RemoteError> {
+        let builder = ServerConfig::builder();
+        let builder = match client_ca {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(path)?))
+                    .allow_unauthenticated()
+                    .build()
+                    .map_err(|e| RemoteError::Config(format!("{}: {}", path.display(), e)))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certificates(certificate)?, load_key(key)?)?;
+
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn accept(&self) -> io::Result<PendingConnection> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(PendingConnection { stream, config: self.config.clone() })
+    }
+}
+
+impl PendingConnection {
+    pub fn handshake(mut self) -> Result<RemoteConnection, RemoteError> {
+        let address = self.stream.peer_addr()?;
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut tls = ServerConnection::new(self.config)?;
+        while tls.is_handshaking() {
+            tls.complete_io(&mut self.stream)?;
+        }
+        let certificate = tls.peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| certificate.to_vec());
+
+        let (reader, writer) = split(StreamOwned::new(tls, self.stream))?;
+        Ok(RemoteConnection { reader, writer, address, certificate })
+    }
+}
+
+/// How to prove who we are to a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Key { id: String, secret: String },
+    Certificate { certificate: PathBuf, key: PathBuf },
+}
+
+/// A service on another computer and how to connect to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteTarget {
+    /// host:port
+    pub address: String,
+    /// Checked against the service's certificate.
+    pub server_name: String,
+    pub ca: PathBuf,
+    pub credentials: Credentials,
+}
+
+impl RemoteTarget {
+    pub fn connect(&self) -> Result<(Reader, Writer), RemoteError> {
+        let builder = ClientConfig::builder().with_root_certificates(load_roots(&self.ca)?);
+        let config = match &self.credentials {
+            Credentials::Key { .. } => builder.with_no_client_auth(),
+            Credentials::Certificate { certificate, key } => {
+                builder.with_client_auth_cert(load_certificates(certificate)?, load_key(key)?)?
+            }
+        };
+        let server_name = ServerName::try_from(self.server_name.clone())
+            .map_err(|_| RemoteError::Config(format!("\"{}\" isn't a host name", self.server_name)))?;
+
+        let address = self.address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| RemoteError::Config(format!("{} didn't resolve", self.address)))?;
+        let mut stream = TcpStream::connect_timeout(&address, HANDSHAKE_TIMEOUT)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut tls = ClientConnection::new(Arc::new(config), server_name)?;
+        while tls.is_handshaking() {
+            tls.complete_io(&mut stream)?;
+        }
+        Ok(split(StreamOwned::new(tls, stream))?)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ComputerEntry {
+    name: String,
+    address: Option<String>,
+    ca: PathBuf,
+    key_id: Option<String>,
+    secret: Option<String>,
+    certificate: Option<PathBuf>,
+    certificate_key: Option<PathBuf>,
+}
+
+/// A client's remote.toml.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    #[serde(default, rename = "computer")]
+    computers: Vec<ComputerEntry>,
+}
+
+impl RemoteConfig {
+    /// %APPDATA%\SMBus Snap-in\remote.toml on Windows, and the XDG config
+    /// directory elsewhere.
+    pub fn default_path() -> Option<PathBuf> {
+        if cfg!(windows) {
+            return std::env::var_os("APPDATA").map(|dir| Path::new(&dir).join("SMBus Snap-in").join("remote.toml"));
+        }
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("smbus-snapin").join("remote.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RemoteError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RemoteError::Config(format!("couldn't read {}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| RemoteError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn load_default() -> Result<Self, RemoteError> {
+        let path = Self::default_path()
+            .ok_or_else(|| RemoteError::Config("there's no folder for remote.toml".to_owned()))?;
+        Self::load(&path)
+    }
+
+    /// How to reach `computer`. Names are matched without case or leading
+    /// backslashes.
+    pub fn target(&self, computer: &str) -> Result<RemoteTarget, RemoteError> {
+        let computer = computer.trim_start_matches('\\');
+        let entry = self.computers.iter()
+            .find(|entry| entry.name.trim_start_matches('\\').eq_ignore_ascii_case(computer))
+            .or_else(|| self.computers.iter().find(|entry| entry.name == "*"))
+            .ok_or_else(|| RemoteError::Config(format!("remote.toml has no entry for {}", computer)))?;
+
+        let credentials = match (&entry.key_id, &entry.secret, &entry.certificate, &entry.certificate_key) {
+            (Some(id), Some(secret), None, None) => Credentials::Key { id: id.clone(), secret: secret.clone() },
+            (None, None, Some(certificate), Some(key)) => Credentials::Certificate { certificate: certificate.clone(), key: key.clone() },
+            _ => return Err(RemoteError::Config(format!(
+                "the entry for {} needs either key_id and secret, or certificate and certificate_key", entry.name))),
+        };
+        let address = entry.address.clone().unwrap_or_else(|| format!("{}:{}", computer, DEFAULT_PORT));
+        let server_name = host_of(&address).to_owned();
+        Ok(RemoteTarget { address, server_name, ca: entry.ca.clone(), credentials })
+    }
+}
+
+// "host:port" or "[v6]:port" without the port
+fn host_of(address: &str) -> &str {
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+// TLS streams can't be cloned, so the reader and writer share one. The
+// reader waits for the socket without holding it, so events can be
+// written while it waits for the next request.
+fn split<C, S>(stream: StreamOwned<C, TcpStream>) -> io::Result<(Reader, Writer)>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>> + Send + 'static,
+    S: SideData,
+{
+    stream.sock.set_read_timeout(Some(READ_SLICE))?;
+    let socket = stream.sock.try_clone()?;
+    let stream = Arc::new(Mutex::new(stream));
+    Ok((Box::new(TlsReader { stream: stream.clone(), socket }), Box::new(TlsWriter(stream))))
+}
+
+struct TlsReader<C> {
+    stream: Arc<Mutex<StreamOwned<C, TcpStream>>>,
+    // Watched for data before the stream is locked
+    socket: TcpStream,
+}
+
+impl<C, S> Read for TlsReader<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // What an earlier record left over comes first, as the socket
+            // won't say there's more of it
+            {
+                let mut stream = self.stream.lock().map_err(|_| io::Error::other("TLS stream poisoned"))?;
+                match stream.conn.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return eof_as_end(result),
+                }
+            }
+
+            match self.socket.peek(&mut [0]) {
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                Err(e) => return Err(e),
+                // Data, or the end of the stream
+                Ok(_) => {}
+            }
+            let mut stream = self.stream.lock().map_err(|_| io::Error::other("TLS stream poisoned"))?;
+            match stream.read(buf) {
+                // Only part of a record has arrived
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                result => return eof_as_end(result),
+            }
+        }
+    }
+}
+
+// A peer that hangs up without saying goodbye is still gone
+fn eof_as_end(result: io::Result<usize>) -> io::Result<usize> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+        result => result,
+    }
+}
+
+struct TlsWriter<C>(Arc<Mutex<StreamOwned<C, TcpStream>>>);
+
+impl<C, S> Write for TlsWriter<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().map_err(|_| io::Error::other("TLS stream poisoned"))?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().map_err(|_| io::Error::other("TLS stream poisoned"))?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::client::ServiceClient;
+    use crate::service::protocol::{Reply, Transaction};
+    use crate::service::server::{self, BusService};
+    use crate::smbus::drivers::FAN_MODE_SETTING;
+    use crate::smbus::safety::WritePolicy;
+    use crate::smbus::sim::SimulatedBus;
+    use crate::smbus::SmbusError;
+
+    const SECRET: &str = "sixteen or more characters";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("remote").join(name)
+    }
+
+    // A service on the demo bus that takes clients on a loopback port,
+    // with certificates from testdata/remote's CA
+    fn start(access: AccessList) -> SocketAddr {
+        let listener = RemoteListener::bind("127.0.0.1:0", &fixture("server.pem"), &fixture("server.key"), Some(&fixture("ca.pem"))).unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut service = BusService::new(Box::new(SimulatedBus::demo()), WritePolicy::default(), Vec::new());
+        service.scan(0x03..=0x77);
+        let service = Arc::new(Mutex::new(service));
+        std::thread::spawn(move || server::serve_remote(listener, service, Arc::new(access)));
+        address
+    }
+
+    fn target(address: SocketAddr, credentials: Credentials) -> RemoteTarget {
+        RemoteTarget { address: address.to_string(), server_name: "localhost".to_owned(), ca: fixture("ca.pem"), credentials }
+    }
+
+    fn key(id: &str, secret: &str) -> Credentials {
+        Credentials::Key { id: id.to_owned(), secret: secret.to_owned() }
+    }
+
+    fn certificate(name: &str) -> Credentials {
+        Credentials::Certificate { certificate: fixture(&format!("{}.pem", name)), key: fixture(&format!("{}.key", name)) }
+    }
+
+    fn pinned(name: &str, role: Role) -> AccessList {
+        let certificate = load_certificates(&fixture(&format!("{}.pem", name))).unwrap();
+        let sha256 = fingerprint(&certificate[0]).to_uppercase();
+        AccessList { certificates: vec![PinnedCertificate { sha256, role }], ..AccessList::default() }
+    }
+
+    fn keyed(id: &str, role: Role) -> AccessList {
+        AccessList { keys: vec![PresharedKey { id: id.to_owned(), secret: SECRET.to_owned(), role }], ..AccessList::default() }
+    }
+
+    #[test]
+    fn pinned_certificates_get_their_role() {
+        let address = start(pinned("client", Role::Admin));
+        let mut client = ServiceClient::connect_remote(&target(address, certificate("client")), "test").unwrap();
+        assert_eq!(client.role(), Role::Admin);
+        assert_eq!(client.transfer(0x4c, Transaction::WriteByteData { command: 0x4a, value: 0x00 }).unwrap(), Reply::Done);
+    }
+
+    #[test]
+    fn certificates_from_another_ca_are_rejected() {
+        // Even pinned, a certificate has to come from the client CA
+        let address = start(pinned("rogue-client", Role::Admin));
+        assert!(ServiceClient::connect_remote(&target(address, certificate("rogue-client")), "test").is_err());
+        // And one from the CA has to be pinned
+        assert!(ServiceClient::connect_remote(&target(address, certificate("client")), "test").is_err());
+    }
+
+    #[test]
+    fn keys_authenticate_with_their_secret() {
+        let address = start(keyed("console", Role::Admin));
+        let client = ServiceClient::connect_remote(&target(address, key("console", SECRET)), "test").unwrap();
+        assert_eq!(client.role(), Role::Admin);
+        assert!(ServiceClient::connect_remote(&target(address, key("console", "not the secret at all")), "test").is_err());
+        assert!(ServiceClient::connect_remote(&target(address, key("someone", SECRET)), "test").is_err());
+    }
+
+    #[test]
+    fn read_only_clients_are_refused_writes() {
+        let address = start(keyed("viewer", Role::ReadOnly));
+        let mut client = ServiceClient::connect_remote(&target(address, key("viewer", SECRET)), "test").unwrap();
+        assert_eq!(client.role(), Role::ReadOnly);
+
+        assert!(client.read(0x4c).is_ok());
+        assert!(client.transfer(0x4c, Transaction::ReadByteData { command: 0x4a }).is_ok());
+        let refused = client.transfer(0x4c, Transaction::WriteByteData { command: 0x4a, value: 0x00 });
+        assert!(matches!(refused, Err(SmbusError::Other(e)) if e.contains("read-only")));
+        assert!(client.write(0x4c, FAN_MODE_SETTING, 0.0, false).is_err());
+        // Dry runs don't touch the bus, so they're allowed
+        assert!(!client.write(0x4c, FAN_MODE_SETTING, 0.0, true).unwrap());
+    }
+
+    #[test]
+    fn access_files_need_long_secrets() {
+        let path = std::env::temp_dir().join(format!("smbus-access-{}.toml", std::process::id()));
+        std::fs::write(&path, "[[key]]\nid = \"short\"\nsecret = \"too short\"\nrole = \"admin\"\n").unwrap();
+        assert!(matches!(AccessList::load(&path), Err(RemoteError::Config(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
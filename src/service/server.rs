@@ -0,0 +1,592 @@
+// The service side: one `BusService` owns the bus and the drivers of the
+// devices on it, and every connection takes turns with it.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::profile::Profile;
+use crate::propsheet;
+use crate::smbus::drivers::rgb::RgbDevice;
+use crate::smbus::drivers::{self, DeviceKind, Driver};
+use crate::smbus::regmap::RegisterMap;
+use crate::smbus::safety::{GuardedBus, WritePolicy};
+use crate::smbus::scan::scan;
+use crate::smbus::{Property, SmbusError, SmbusTransport, BLOCK_MAX};
+
+use super::ipc::{Listener, Reader, Writer};
+use super::metrics::{Counters, Sample};
+use super::protocol::{
+    parse_frame, read_line, write_frame, DeviceInfo, DeviceReading, Event, ProfileFailure, ProfileInfo, Reply,
+    Request, RequestFrame, Role, ServerFrame, Transaction, WireError, PROTOCOL_VERSION,
+};
+use super::remote::{self, AccessList, RemoteListener};
+
+pub const SERVER_NAME: &str = "smbus-service";
+
+/// Subscriptions aren't read more often than this.
+pub const MIN_SUBSCRIBE_INTERVAL_MS: u32 = 250;
+
+pub struct BusService {
+    bus: Box<dyn SmbusTransport>,
+    policy: WritePolicy,
+    maps: Vec<RegisterMap>,
+    // Every device found by the last scan, and the drivers of the ones
+    // that were identified
+    found: Vec<u8>,
+    drivers: HashMap<u8, Box<dyn Driver>>,
+    profiles: Vec<Profile>,
+    // The last good reading of each device, and when it was taken
+    latest: HashMap<u8, (Instant, DeviceReading)>,
+    counters: Counters,
+}
+
+pub type SharedService = Arc<Mutex<BusService>>;
+
+impl BusService {
+    pub fn new(bus: Box<dyn SmbusTransport>, policy: WritePolicy, profiles: Vec<Profile>) -> Self {
+        Self {
+            bus,
+            policy,
+            maps: RegisterMap::builtin(),
+            found: Vec::new(),
+            drivers: HashMap::new(),
+            profiles,
+            latest: HashMap::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn scan(&mut self, range: RangeInclusive<u8>) -> Vec<DeviceInfo> {
+        self.found = scan(self.bus.as_mut(), range);
+        self.counters.scans += 1;
+        self.drivers.clear();
+        self.latest.clear();
+        for addr in &self.found {
+            if let Some(driver) = drivers::identify(self.bus.as_mut(), *addr, &self.maps) {
+                self.drivers.insert(*addr, driver);
+            }
+        }
+        log::info!("Scan found {} device(s)", self.found.len());
+        self.devices()
+    }
+
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.found.iter().map(|addr| self.device(*addr)).collect()
+    }
+
+    fn device(&self, addr: u8) -> DeviceInfo {
+        match self.drivers.get(&addr) {
+            Some(driver) => DeviceInfo { addr, name: driver.name(), kind: driver.kind(), identified: true },
+            None => DeviceInfo { addr, name: "Unknown device".to_owned(), kind: DeviceKind::Unknown, identified: false },
+        }
+    }
+
+    /// Identifies the device at `addr` if the scan didn't find it, since
+    /// some parts ignore its probes, and reads what its Properties dialog
+    /// shows about it. The device is known until the next scan.
+    pub fn identify(&mut self, addr: u8) -> (DeviceInfo, Vec<Property>) {
+        if !self.found.contains(&addr) {
+            log::info!("Adding the device at {:#04x}", addr);
+            self.found.push(addr);
+            self.found.sort_unstable();
+            if let Some(driver) = drivers::identify(self.bus.as_mut(), addr, &self.maps) {
+                self.drivers.insert(addr, driver);
+            }
+        }
+        let device = self.device(addr);
+        let driver = self.drivers.get(&addr).map(|driver| driver.as_ref());
+        let properties = propsheet::identification(addr, device.kind, driver, Some(self.bus.as_mut()));
+        (device, properties)
+    }
+
+    pub fn read(&mut self, addr: u8) -> Result<DeviceReading, SmbusError> {
+        let driver = driver(&self.drivers, addr)?;
+        let result = driver.read(self.bus.as_mut())
+            .and_then(|properties| Ok(DeviceReading { properties, sensors: driver.sensors(self.bus.as_mut())? }));
+        *self.counters.reads.entry(addr).or_default() += 1;
+        match &result {
+            Ok(reading) => {
+                self.latest.insert(addr, (Instant::now(), reading.clone()));
+            }
+            Err(_) => {
+                *self.counters.read_errors.entry(addr).or_default() += 1;
+                self.latest.remove(&addr);
+            }
+        }
+        result
+    }
+
+    /// Every identified device's reading, read again if it's older than
+    /// `max_age`. Devices that fail to read are left out.
+    pub fn sample(&mut self, max_age: Duration) -> Sample {
+        let mut addrs: Vec<u8> = self.drivers.keys().copied().collect();
+        addrs.sort_unstable();
+        let readings = addrs.into_iter()
+            .filter_map(|addr| match self.latest.get(&addr) {
+                Some((taken, reading)) if taken.elapsed() < max_age => Some((addr, reading.clone())),
+                _ => self.read(addr).ok().map(|reading| (addr, reading)),
+            })
+            .collect();
+        Sample { devices: self.devices(), readings, counters: self.counters.clone() }
+    }
+
+    /// The addresses of the identified RGB controllers, lowest first.
+    pub fn rgb_controllers(&self) -> Vec<u8> {
+        let mut addrs: Vec<u8> = self.drivers.iter()
+            .filter(|(_, driver)| driver.rgb().is_some())
+            .map(|(addr, _)| *addr)
+            .collect();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    /// Runs `f` with the RGB controller at `addr`. Writes on the bus it's
+    /// given go through the service's policy.
+    pub fn with_rgb<T>(
+        &mut self,
+        addr: u8,
+        f: impl FnOnce(&dyn Driver, &dyn RgbDevice, &mut dyn SmbusTransport) -> Result<T, SmbusError>,
+    ) -> Result<T, SmbusError> {
+        let policy = self.policy(false);
+        let driver = driver(&self.drivers, addr)?;
+        let rgb = driver.rgb()
+            .ok_or_else(|| SmbusError::Other(format!("{} has no RGB lighting", driver.name())))?;
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        f(driver, rgb, &mut guarded)
+    }
+
+    // Writes go through the service's own policy, whatever the client
+    // checked first
+    fn policy(&self, dry_run: bool) -> WritePolicy {
+        let mut policy = self.policy.clone();
+        policy.dry_run |= dry_run;
+        policy
+    }
+
+    pub fn handle(&mut self, request: Request) -> Result<Reply, WireError> {
+        match request {
+            Request::Hello { .. } => Err(WireError::Protocol("already said hello".to_owned())),
+            Request::Authenticate { .. } => Err(WireError::Protocol("authentication belongs to a connection".to_owned())),
+            Request::Scan { first, last } => Ok(Reply::Devices(self.scan(first..=last))),
+            Request::Devices => Ok(Reply::Devices(self.devices())),
+            Request::Identify { addr } => {
+                let (device, properties) = self.identify(addr);
+                Ok(Reply::Identification { device, properties })
+            }
+            Request::Read { addr } => Ok(Reply::Reading(self.read(addr)?)),
+            Request::Settings { addr } => {
+                let driver = driver(&self.drivers, addr)?;
+                let mut settings = driver.settings(self.bus.as_mut())?;
+                settings.extend(driver.thresholds(self.bus.as_mut())?);
+                Ok(Reply::Settings(settings))
+            }
+            Request::Write { addr, key, value, dry_run } => {
+                let policy = self.policy(dry_run);
+                let driver = driver(&self.drivers, addr)?;
+                let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+                driver.write_setting(&mut guarded, &key, value)?;
+                Ok(Reply::Written { performed: !policy.dry_run })
+            }
+            // Longer reads would hold the bus for as long as the client likes
+            Request::Transfer { transaction: Transaction::ReadI2cBlockData { len, .. }, .. } if len > BLOCK_MAX => {
+                Err(WireError::Protocol(format!("an I2C block read is at most {} bytes, not {}", BLOCK_MAX, len)))
+            }
+            Request::Transfer { addr, transaction } => self.transfer(addr, transaction).map_err(WireError::from),
+            Request::Subscribe { .. } | Request::Unsubscribe => {
+                Err(WireError::Protocol("subscriptions belong to a connection".to_owned()))
+            }
+            Request::Profiles => Ok(Reply::Profiles(self.profiles.iter()
+                .map(|profile| ProfileInfo { name: profile.name.clone(), description: profile.description.clone() })
+                .collect())),
+            Request::ApplyProfile { name, dry_run } => {
+                let profile = self.profiles.iter()
+                    .find(|profile| profile.name.eq_ignore_ascii_case(&name))
+                    .ok_or_else(|| WireError::Other(format!("no profile called \"{}\"", name)))?;
+                let policy = self.policy(dry_run);
+                let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+                let failures = match profile.apply(&self.drivers, &mut guarded) {
+                    Ok(()) => Vec::new(),
+                    Err(failures) => failures.into_iter()
+                        .map(|(setting, e)| ProfileFailure { setting, error: e.into() })
+                        .collect(),
+                };
+                Ok(Reply::ProfileApplied { failures })
+            }
+        }
+    }
+
+    fn transfer(&mut self, addr: u8, transaction: Transaction) -> Result<Reply, SmbusError> {
+        let policy = self.policy(false);
+        let mut guarded = GuardedBus::new(self.bus.as_mut(), &policy);
+        let bus: &mut dyn SmbusTransport = &mut guarded;
+        match transaction {
+            Transaction::ReadByteData { command } => bus.read_byte_data(addr, command).map(Reply::Byte),
+            Transaction::WriteByteData { command, value } => bus.write_byte_data(addr, command, value).map(|_| Reply::Done),
+            Transaction::ReadWordData { command } => bus.read_word_data(addr, command).map(Reply::Word),
+            Transaction::WriteWordData { command, value } => bus.write_word_data(addr, command, value).map(|_| Reply::Done),
+            Transaction::ReceiveByte => bus.receive_byte(addr).map(Reply::Byte),
+            Transaction::ReadBlockData { command } => bus.read_block_data(addr, command).map(Reply::Bytes),
+            Transaction::ReadI2cBlockData { command, len } => bus.read_i2c_block_data(addr, command, len).map(Reply::Bytes),
+            Transaction::WriteI2cBlockData { command, data } => bus.write_i2c_block_data(addr, command, &data).map(|_| Reply::Done),
+        }
+    }
+}
+
+fn driver(drivers: &HashMap<u8, Box<dyn Driver>>, addr: u8) -> Result<&dyn Driver, SmbusError> {
+    drivers.get(&addr)
+        .map(|driver| driver.as_ref())
+        .ok_or_else(|| SmbusError::Other(format!("no known device at {:#04x}", addr)))
+}
+
+/// Accepts connections until the listener fails, serving each on a thread
+/// of its own.
+pub fn serve(listener: Listener, service: SharedService) -> std::io::Result<()> {
+    loop {
+        let (reader, writer) = listener.accept()?;
+        let service = service.clone();
+        std::thread::spawn(move || {
+            log::info!("Client connected");
+            Connection::new(service, writer, Peer::Local).run(reader);
+            log::info!("Client disconnected");
+        });
+    }
+}
+
+/// Serves one client in this process, over a pair of pipes. Returns the
+/// client's ends, which hang up on the service when they're dropped.
+pub fn serve_in_process(service: SharedService) -> std::io::Result<(Reader, Writer)> {
+    let (client_reader, service_writer) = std::io::pipe()?;
+    let (service_reader, client_writer) = std::io::pipe()?;
+    std::thread::spawn(move || {
+        Connection::new(service, Box::new(service_writer), Peer::Local).run(Box::new(service_reader));
+    });
+    Ok((Box::new(client_reader), Box::new(client_writer)))
+}
+
+/// Accepts clients on other computers until the listener fails. Each
+/// has to be in `access` to get anywhere.
+pub fn serve_remote(listener: RemoteListener, service: SharedService, access: Arc<AccessList>) -> std::io::Result<()> {
+    loop {
+        let pending = listener.accept()?;
+        let service = service.clone();
+        let access = access.clone();
+        std::thread::spawn(move || {
+            let connection = match pending.handshake() {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("TLS handshake failed: {}", e);
+                    return;
+                }
+            };
+            let address = connection.address;
+            log::info!("Client connected from {}", address);
+            let peer = Peer::Remote { access, certificate: connection.certificate };
+            Connection::new(service, connection.writer, peer).run(connection.reader);
+            log::info!("Client at {} disconnected", address);
+        });
+    }
+}
+
+/// Who's at the other end of a connection.
+enum Peer {
+    /// A client on this computer. The channel only lets administrators
+    /// in, so they may do anything.
+    Local,
+    /// A client on another computer, which gets a role from `access`.
+    Remote { access: Arc<AccessList>, certificate: Option<Vec<u8>> },
+}
+
+type SharedWriter = Arc<Mutex<Writer>>;
+
+struct Connection {
+    service: SharedService,
+    writer: SharedWriter,
+    peer: Peer,
+    greeted: bool,
+    // `None` until the client has proved who it is
+    role: Option<Role>,
+    challenge: Option<String>,
+    // Set when the client has to go, once it's been told why
+    hang_up: bool,
+    // Set to stop the current subscription's thread
+    subscription: Option<Arc<AtomicBool>>,
+}
+
+impl Connection {
+    fn new(service: SharedService, writer: Writer, peer: Peer) -> Self {
+        Self {
+            service,
+            writer: Arc::new(Mutex::new(writer)),
+            peer,
+            greeted: false,
+            role: None,
+            challenge: None,
+            hang_up: false,
+            subscription: None,
+        }
+    }
+
+    fn run(mut self, reader: Reader) {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let line = match read_line(&mut reader) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Couldn't read a request: {}", e);
+                    break;
+                }
+            };
+
+            // The id is picked out first so even a request this version
+            // doesn't know gets an answer
+            let (id, result) = match parse_frame::<RequestFrame>(&line) {
+                Ok(frame) => (frame.id, self.handle(frame.request)),
+                Err(e) => {
+                    let id = parse_frame::<serde_json::Value>(&line).ok()
+                        .and_then(|value| value.get("id").and_then(|id| id.as_u64()))
+                        .unwrap_or(0);
+                    (id, Err(WireError::Protocol(format!("couldn't parse the request: {}", e))))
+                }
+            };
+            if let Err(e) = send(&self.writer, &ServerFrame::Response { id, result }) {
+                log::error!("Couldn't send a response: {}", e);
+                break;
+            }
+            if self.hang_up {
+                break;
+            }
+        }
+        self.unsubscribe();
+    }
+
+    fn handle(&mut self, request: Request) -> Result<Reply, WireError> {
+        match (self.greeted, self.role, request) {
+            (false, _, Request::Hello { version, client }) => {
+                if version.major != PROTOCOL_VERSION.major {
+                    log::warn!("Refusing {} speaking version {}", client, version);
+                    self.hang_up = true;
+                    return Err(WireError::Protocol(format!("version {} isn't supported, this is {}", version, PROTOCOL_VERSION)));
+                }
+                log::info!("Hello from {} speaking version {}", client, version);
+                self.greeted = true;
+                self.role = match &self.peer {
+                    Peer::Local => Some(Role::Admin),
+                    Peer::Remote { access, certificate } => certificate.as_ref()
+                        .and_then(|certificate| access.certificate_role(certificate)),
+                };
+                if self.role.is_none() {
+                    self.challenge = Some(remote::challenge());
+                }
+                Ok(Reply::Hello {
+                    version: PROTOCOL_VERSION,
+                    server: SERVER_NAME.to_owned(),
+                    role: self.role,
+                    challenge: self.challenge.clone(),
+                })
+            }
+            (false, _, _) => {
+                self.hang_up = true;
+                Err(WireError::Protocol("the first request must be hello".to_owned()))
+            }
+            (true, None, Request::Authenticate { key_id, proof }) => {
+                let role = match (&self.peer, &self.challenge) {
+                    (Peer::Remote { access, .. }, Some(challenge)) => access.key_role(&key_id, challenge, &proof),
+                    _ => None,
+                };
+                match role {
+                    Some(role) => {
+                        log::info!("Key {} authenticated as {}", key_id, role);
+                        self.role = Some(role);
+                        Ok(Reply::Authenticated { role })
+                    }
+                    // One try per connection
+                    None => {
+                        log::warn!("Key {} failed to authenticate", key_id);
+                        self.hang_up = true;
+                        Err(WireError::Denied("authentication failed".to_owned()))
+                    }
+                }
+            }
+            (true, None, _) => Err(WireError::Denied("authenticate first".to_owned())),
+            (true, Some(_), Request::Authenticate { .. }) => Err(WireError::Protocol("already authenticated".to_owned())),
+            (true, Some(role), request) if !role.allows(&request) => {
+                Err(WireError::Denied(format!("the {} role can't write to devices", role)))
+            }
+            (true, Some(_), Request::Subscribe { addrs, interval_ms }) => {
+                self.subscribe(addrs, interval_ms.max(MIN_SUBSCRIBE_INTERVAL_MS));
+                Ok(Reply::Done)
+            }
+            (true, Some(_), Request::Unsubscribe) => {
+                self.unsubscribe();
+                Ok(Reply::Done)
+            }
+            (true, Some(_), request) => match self.service.lock() {
+                Ok(mut service) => service.handle(request),
+                Err(_) => Err(WireError::Other("the service failed".to_owned())),
+            },
+        }
+    }
+
+    fn subscribe(&mut self, addrs: Vec<u8>, interval_ms: u32) {
+        self.unsubscribe();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.subscription = Some(stop.clone());
+
+        let service = self.service.clone();
+        let writer = self.writer.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(interval_ms as u64));
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let events: Vec<Event> = match service.lock() {
+                Ok(mut service) => {
+                    let addrs = match addrs.is_empty() {
+                        true => service.found.clone(),
+                        false => addrs.clone(),
+                    };
+                    addrs.into_iter()
+                        .map(|addr| Event { addr, reading: service.read(addr).map_err(WireError::from) })
+                        .collect()
+                }
+                Err(_) => break,
+            };
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            if let Err(e) = events.into_iter().try_for_each(|event| send(&writer, &ServerFrame::Event(event))) {
+                log::debug!("Subscription ended: {}", e);
+                break;
+            }
+        });
+    }
+
+    fn unsubscribe(&mut self) {
+        if let Some(stop) = self.subscription.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn send(writer: &SharedWriter, frame: &ServerFrame) -> std::io::Result<()> {
+    let mut writer = writer.lock().map_err(|_| std::io::Error::other("writer poisoned"))?;
+    write_frame(writer.as_mut() as &mut dyn Write, frame)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::service::client::{ServiceBus, ServiceClient};
+    use crate::smbus::sim::SimulatedBus;
+
+    // A service on the demo bus, listening on a socket of its own
+    fn start(name: &str) -> String {
+        let endpoint = std::env::temp_dir().join(format!("smbus-service-{}-{}.sock", name, std::process::id()));
+        let endpoint = endpoint.to_string_lossy().into_owned();
+        let mut service = BusService::new(Box::new(SimulatedBus::demo()), WritePolicy::default(), Vec::new());
+        service.scan(0x03..=0x77);
+        let listener = Listener::bind(&endpoint).unwrap();
+        let service = Arc::new(Mutex::new(service));
+        std::thread::spawn(move || serve(listener, service));
+        endpoint
+    }
+
+    #[test]
+    fn requests_round_trip_over_the_socket() {
+        let endpoint = start("round-trip");
+        let mut client = ServiceClient::connect(&endpoint, "test").unwrap();
+        assert_eq!(client.version(), PROTOCOL_VERSION);
+        assert_eq!(client.role(), Role::Admin);
+
+        let devices = client.devices().unwrap();
+        assert_eq!(devices.iter().map(|device| device.addr).collect::<Vec<_>>(), vec![0x4c, 0x50, 0x70]);
+        assert_eq!(devices[0].kind, DeviceKind::FanController);
+        assert_eq!(client.scan(0x40..=0x4f).unwrap().len(), 1);
+
+        let reading = client.read(0x4c).unwrap();
+        assert!(!reading.properties.is_empty());
+        assert!(client.settings(0x4c).unwrap().iter().any(|setting| setting.key == drivers::FAN_MODE_SETTING));
+        assert!(!client.write(0x4c, drivers::FAN_MODE_SETTING, 0.0, true).unwrap());
+
+        let mut demo = SimulatedBus::demo();
+        assert_eq!(client.transfer(0x4c, Transaction::ReadByteData { command: 0x00 }).unwrap(), Reply::Byte(demo.read_byte_data(0x4c, 0x00).unwrap()));
+        assert_eq!(client.transfer(0x4c, Transaction::WriteByteData { command: 0x4a, value: 0x00 }).unwrap(), Reply::Done);
+        assert_eq!(client.transfer(0x4c, Transaction::ReadByteData { command: 0x4a }).unwrap(), Reply::Byte(0x00));
+        let _ = std::fs::remove_file(endpoint);
+    }
+
+    #[test]
+    fn errors_and_events_cross_the_socket() {
+        let endpoint = start("errors");
+        let mut client = ServiceClient::connect(&endpoint, "test").unwrap();
+
+        assert_eq!(client.transfer(0x2d, Transaction::ReadByteData { command: 0x00 }), Err(SmbusError::Nack { addr: 0x2d }));
+        assert!(matches!(
+            client.transfer(0x50, Transaction::WriteByteData { command: 0x00, value: 0x00 }),
+            Err(SmbusError::Blocked { addr: 0x50, .. })
+        ));
+        assert!(client.read(0x2d).is_err());
+
+        // An oversized block read is refused rather than run, and the
+        // service carries on
+        assert!(matches!(
+            client.transfer(0x50, Transaction::ReadI2cBlockData { command: 0x00, len: usize::MAX }),
+            Err(SmbusError::Other(e)) if e.contains("at most 32 bytes")
+        ));
+        assert_eq!(client.transfer(0x50, Transaction::ReadI2cBlockData { command: 0x00, len: BLOCK_MAX }), Ok(Reply::Bytes(vec![0xff; BLOCK_MAX])));
+        let mut bus = ServiceBus::connect(&endpoint, "test").unwrap();
+        assert_eq!(bus.read_i2c_block_data(0x50, 0x00, 256).unwrap(), vec![0xff; 256]);
+
+        client.subscribe(&[0x4c], MIN_SUBSCRIBE_INTERVAL_MS).unwrap();
+        let event = client.next_event(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.addr, 0x4c);
+        assert!(event.reading.is_ok());
+        client.unsubscribe().unwrap();
+        let _ = std::fs::remove_file(endpoint);
+    }
+
+    #[test]
+    fn identifying_adds_devices_the_scan_missed() {
+        let mut service = BusService::new(Box::new(SimulatedBus::demo()), WritePolicy::default(), Vec::new());
+        service.scan(0x40..=0x4f);
+
+        let (device, properties) = service.identify(0x70);
+        assert_eq!(device, DeviceInfo { addr: 0x70, name: device.name.clone(), kind: DeviceKind::RgbController, identified: true });
+        assert!(properties.iter().any(|property| property.name == "Driver" && property.value_text() == device.name));
+        assert!(service.read(0x70).is_ok());
+
+        // Nothing answers at 0x2d, but it's shown as the user asked
+        let (device, _) = service.identify(0x2d);
+        assert!(!device.identified);
+        let addrs: Vec<u8> = service.devices().iter().map(|device| device.addr).collect();
+        assert_eq!(addrs, vec![0x2d, 0x4c, 0x70]);
+
+        // Known devices are only described
+        let (device, properties) = service.identify(0x4c);
+        assert_eq!(device.kind, DeviceKind::FanController);
+        assert!(properties.iter().any(|property| property.name == "Vendor"));
+        assert_eq!(service.devices().len(), 3);
+    }
+
+    #[test]
+    fn in_process_clients_talk_like_any_other() {
+        let service = BusService::new(Box::new(SimulatedBus::demo()), WritePolicy::default(), Profile::builtin());
+        let (reader, writer) = serve_in_process(Arc::new(Mutex::new(service))).unwrap();
+        let mut client = ServiceClient::handshake(reader, writer, "test", None).unwrap();
+        assert_eq!(client.role(), Role::Admin);
+
+        assert_eq!(client.scan(0x03..=0x77).unwrap().len(), 3);
+        assert_eq!(client.identify(0x4c).unwrap().0.kind, DeviceKind::FanController);
+        assert!(!client.read(0x4c).unwrap().properties.is_empty());
+        assert!(!client.profiles().unwrap().is_empty());
+        assert_eq!(client.transfer(0x4c, Transaction::ReadByteData { command: 0x4a }), Ok(Reply::Byte(0x20)));
+
+        client.subscribe(&[0x4c], MIN_SUBSCRIBE_INTERVAL_MS).unwrap();
+        assert_eq!(client.next_event(Duration::from_secs(5)).unwrap().addr, 0x4c);
+    }
+}
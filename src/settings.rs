@@ -0,0 +1,390 @@
+// What a saved console file remembers about the snap-in.
+//
+// MMC hands IPersistStream a stream of its own inside the .msc file; we
+// write `ConsoleSettings` into it in the layout below. Everything is
+// little-endian.
+//
+//     magic      b"SMBS"
+//     major      u8     bumped when a record changes meaning; files with
+//                       a newer major version are refused
+//     minor      u8     bumped when records are added
+//     records    until the end of the stream, each
+//         tag    u16
+//         length u32    of the payload, so readers can skip records they
+//                       don't know
+//         payload
+//
+// Strings are a u32 byte count followed by UTF-8. A record that is missing
+// leaves its setting at the default, which is how files written before
+// the record existed load.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::connect::{Computer, Connection, DataSource};
+use crate::history::HistoryConfig;
+use crate::smbus::scan::SCAN_RANGE;
+
+const MAGIC: &[u8; 4] = b"SMBS";
+
+pub const MAJOR_VERSION: u8 = 1;
+pub const MINOR_VERSION: u8 = 2;
+
+// Record tags. Never reuse a retired tag.
+// Retired: the bus's controller and port, which older files still have.
+// The bus service owns the computer's one SMBus controller.
+const TAG_BUS: u16 = 1;
+const TAG_SCAN_RANGE: u16 = 2;
+const TAG_POLLING: u16 = 3;
+const TAG_ALIAS: u16 = 4;
+const TAG_MANUAL_DEVICE: u16 = 5;
+// Since 1.1
+const TAG_COMPUTER: u16 = 6;
+const TAG_SOURCE: u16 = 7;
+// Since 1.2
+const TAG_HISTORY: u16 = 8;
+
+// TAG_SOURCE's kinds
+const SOURCE_HARDWARE: u8 = 0;
+const SOURCE_SIMULATION: u8 = 1;
+const SOURCE_SNAPSHOT: u8 = 2;
+
+/// How often devices are read by default.
+pub const DEFAULT_POLL_INTERVAL_MS: u32 = 5000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsError {
+    /// The stream doesn't start with our magic.
+    BadMagic,
+    /// Written by a newer snap-in whose records mean something else.
+    UnsupportedVersion { major: u8, minor: u8 },
+    /// A record or the header ends early.
+    Truncated,
+    /// A record holds a value that can't be right, e.g. an empty scan range.
+    Invalid(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::BadMagic => write!(f, "not saved by this snap-in"),
+            SettingsError::UnsupportedVersion { major, minor } => write!(f, "saved by a newer version ({}.{})", major, minor),
+            SettingsError::Truncated => write!(f, "the saved settings are cut short"),
+            SettingsError::Invalid(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleSettings {
+    pub connection: Connection,
+    pub scan_range: RangeInclusive<u8>,
+    pub poll_interval_ms: u32,
+    pub polling_paused: bool,
+    /// How long readings are kept, and how finely.
+    pub history: HistoryConfig,
+    /// Names the user gave devices, by address.
+    pub aliases: BTreeMap<u8, String>,
+    /// Addresses of devices the user added by hand. They're shown whether
+    /// or not a scan finds them.
+    pub manual_devices: Vec<u8>,
+}
+
+impl Default for ConsoleSettings {
+    fn default() -> Self {
+        Self {
+            connection: Connection::default(),
+            scan_range: SCAN_RANGE,
+            poll_interval_ms: DEFAULT_POLL_INTERVAL_MS,
+            polling_paused: false,
+            history: HistoryConfig::default(),
+            aliases: BTreeMap::new(),
+            manual_devices: Vec::new(),
+        }
+    }
+}
+
+impl ConsoleSettings {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(MAJOR_VERSION);
+        out.push(MINOR_VERSION);
+
+        if let Computer::Remote(name) = &self.connection.computer {
+            let mut payload = Vec::new();
+            put_str(&mut payload, name);
+            put_record(&mut out, TAG_COMPUTER, &payload);
+        }
+        let (kind, path) = match &self.connection.source {
+            DataSource::Hardware => (SOURCE_HARDWARE, String::new()),
+            DataSource::Simulation => (SOURCE_SIMULATION, String::new()),
+            DataSource::Snapshot(path) => (SOURCE_SNAPSHOT, path.to_string_lossy().into_owned()),
+        };
+        let mut payload = vec![kind];
+        put_str(&mut payload, &path);
+        put_record(&mut out, TAG_SOURCE, &payload);
+
+        put_record(&mut out, TAG_SCAN_RANGE, &[*self.scan_range.start(), *self.scan_range.end()]);
+
+        let mut payload = self.poll_interval_ms.to_le_bytes().to_vec();
+        payload.push(self.polling_paused as u8);
+        put_record(&mut out, TAG_POLLING, &payload);
+
+        let mut payload = (self.history.retention.as_secs() as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(&(self.history.resolution.as_secs() as u32).to_le_bytes());
+        put_record(&mut out, TAG_HISTORY, &payload);
+
+        for (addr, alias) in &self.aliases {
+            let mut payload = vec![*addr];
+            put_str(&mut payload, alias);
+            put_record(&mut out, TAG_ALIAS, &payload);
+        }
+        for addr in &self.manual_devices {
+            put_record(&mut out, TAG_MANUAL_DEVICE, &[*addr]);
+        }
+
+        out
+    }
+
+    /// Reads settings written by this or any earlier version, and by later
+    /// versions with the same major version, whose new records are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SettingsError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).map_err(|_| SettingsError::BadMagic)? != MAGIC {
+            return Err(SettingsError::BadMagic);
+        }
+        let major = reader.u8()?;
+        let minor = reader.u8()?;
+        if major > MAJOR_VERSION {
+            return Err(SettingsError::UnsupportedVersion { major, minor });
+        }
+
+        let mut settings = Self::default();
+        while !reader.bytes.is_empty() {
+            let tag = reader.u16()?;
+            let length = reader.u32()? as usize;
+            let mut payload = Reader { bytes: reader.take(length)? };
+
+            match tag {
+                TAG_BUS => log::debug!("Skipping the retired bus record"),
+                TAG_SCAN_RANGE => {
+                    let (first, last) = (payload.u8()?, payload.u8()?);
+                    if first > last {
+                        return Err(SettingsError::Invalid(format!("empty scan range {:#04x}..={:#04x}", first, last)));
+                    }
+                    settings.scan_range = first..=last;
+                }
+                TAG_POLLING => {
+                    settings.poll_interval_ms = payload.u32()?;
+                    settings.polling_paused = payload.u8()? != 0;
+                    if settings.poll_interval_ms == 0 {
+                        return Err(SettingsError::Invalid("a poll interval of 0 ms".to_owned()));
+                    }
+                }
+                TAG_HISTORY => {
+                    let retention = Duration::from_secs(payload.u32()?.into());
+                    let resolution = Duration::from_secs(payload.u32()?.into());
+                    if resolution.is_zero() || retention < resolution {
+                        return Err(SettingsError::Invalid(format!(
+                            "history kept for {} s in {} s steps",
+                            retention.as_secs(),
+                            resolution.as_secs(),
+                        )));
+                    }
+                    settings.history = HistoryConfig { retention, resolution };
+                }
+                TAG_ALIAS => {
+                    let addr = payload.u8()?;
+                    settings.aliases.insert(addr, payload.str()?);
+                }
+                TAG_MANUAL_DEVICE => settings.manual_devices.push(payload.u8()?),
+                TAG_COMPUTER => settings.connection.computer = Computer::Remote(payload.str()?),
+                TAG_SOURCE => {
+                    let kind = payload.u8()?;
+                    let path = payload.str()?;
+                    settings.connection.source = match kind {
+                        SOURCE_HARDWARE => DataSource::Hardware,
+                        SOURCE_SIMULATION => DataSource::Simulation,
+                        SOURCE_SNAPSHOT => DataSource::Snapshot(path.into()),
+                        kind => {
+                            log::error!("Unknown data source {}, using the hardware", kind);
+                            DataSource::Hardware
+                        }
+                    };
+                }
+                _ => log::debug!("Skipping unknown settings record {} ({} bytes)", tag, length),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+fn put_record(out: &mut Vec<u8>, tag: u16, payload: &[u8]) {
+    out.extend_from_slice(&tag.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+fn put_str(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SettingsError> {
+        if self.bytes.len() < len {
+            return Err(SettingsError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SettingsError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SettingsError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SettingsError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn str(&mut self) -> Result<String, SettingsError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| SettingsError::Invalid("a name that isn't UTF-8".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn everything() -> ConsoleSettings {
+        ConsoleSettings {
+            connection: Connection {
+                computer: Computer::Remote("LAB-PC".to_owned()),
+                source: DataSource::Snapshot("C:\\dumps\\board.json".into()),
+            },
+            scan_range: 0x10..=0x6f,
+            poll_interval_ms: 1500,
+            polling_paused: true,
+            history: HistoryConfig { retention: Duration::from_secs(86400), resolution: Duration::from_secs(60) },
+            aliases: BTreeMap::from([(0x4c, "CPU fan".to_owned()), (0x50, "DIMM A1 \u{2013} left".to_owned())]),
+            manual_devices: vec![0x2e, 0x60],
+        }
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let settings = everything();
+        assert_eq!(ConsoleSettings::from_bytes(&settings.to_bytes()), Ok(settings));
+
+        let defaults = ConsoleSettings::default();
+        assert_eq!(ConsoleSettings::from_bytes(&defaults.to_bytes()), Ok(defaults));
+    }
+
+    #[test]
+    fn each_data_source_round_trips() {
+        for source in [DataSource::Hardware, DataSource::Simulation, DataSource::Snapshot("dump.json".into())] {
+            let settings = ConsoleSettings {
+                connection: Connection { computer: Computer::Local, source },
+                ..Default::default()
+            };
+            assert_eq!(ConsoleSettings::from_bytes(&settings.to_bytes()), Ok(settings));
+        }
+    }
+
+    #[test]
+    fn loads_version_1_0() {
+        // Its bus record is skipped
+        let settings = ConsoleSettings::from_bytes(include_bytes!("../testdata/settings/v1.0.bin")).unwrap();
+        assert_eq!(settings, ConsoleSettings {
+            scan_range: 0x10..=0x6f,
+            poll_interval_ms: 2000,
+            polling_paused: true,
+            aliases: BTreeMap::from([(0x4c, "CPU fan".to_owned()), (0x50, "DIMM A1".to_owned())]),
+            manual_devices: vec![0x2e, 0x60],
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn loads_version_1_1() {
+        let settings = ConsoleSettings::from_bytes(include_bytes!("../testdata/settings/v1.1.bin")).unwrap();
+        assert_eq!(settings, ConsoleSettings {
+            connection: Connection {
+                computer: Computer::Remote("LAB-PC".to_owned()),
+                source: DataSource::Snapshot("C:\\dumps\\board.json".into()),
+            },
+            scan_range: 0x08..=0x77,
+            poll_interval_ms: 1000,
+            manual_devices: vec![0x2e],
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn loads_version_1_2() {
+        let settings = ConsoleSettings::from_bytes(include_bytes!("../testdata/settings/v1.2.bin")).unwrap();
+        assert_eq!(settings, ConsoleSettings {
+            connection: Connection { computer: Computer::Local, source: DataSource::Simulation },
+            scan_range: 0x03..=0x77,
+            history: HistoryConfig { retention: Duration::from_secs(7 * 86400), resolution: Duration::from_secs(300) },
+            aliases: BTreeMap::from([(0x18, "Ambient".to_owned())]),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn newer_minor_versions_skip_what_they_added() {
+        // An unknown record, and known records with fields appended
+        let settings = ConsoleSettings::from_bytes(include_bytes!("../testdata/settings/v1.9.bin")).unwrap();
+        assert_eq!(settings, ConsoleSettings {
+            scan_range: 0x08..=0x77,
+            poll_interval_ms: 750,
+            polling_paused: true,
+            aliases: BTreeMap::from([(0x4c, "Pump".to_owned())]),
+            manual_devices: vec![0x2e],
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn newer_major_versions_and_other_streams_are_refused() {
+        let mut bytes = everything().to_bytes();
+        bytes[4] = MAJOR_VERSION + 1;
+        assert_eq!(ConsoleSettings::from_bytes(&bytes), Err(SettingsError::UnsupportedVersion { major: MAJOR_VERSION + 1, minor: MINOR_VERSION }));
+        assert_eq!(ConsoleSettings::from_bytes(b"MSCF\x01\x00"), Err(SettingsError::BadMagic));
+        assert_eq!(ConsoleSettings::from_bytes(b"SM"), Err(SettingsError::BadMagic));
+    }
+
+    #[test]
+    fn damaged_records_are_errors() {
+        let bytes = everything().to_bytes();
+        assert_eq!(ConsoleSettings::from_bytes(&bytes[..bytes.len() - 1]), Err(SettingsError::Truncated));
+
+        let mut empty_range = Vec::from(&b"SMBS\x01\x02"[..]);
+        put_record(&mut empty_range, TAG_SCAN_RANGE, &[0x40, 0x10]);
+        assert!(matches!(ConsoleSettings::from_bytes(&empty_range), Err(SettingsError::Invalid(_))));
+
+        let mut no_interval = Vec::from(&b"SMBS\x01\x02"[..]);
+        put_record(&mut no_interval, TAG_POLLING, &[0, 0, 0, 0, 0]);
+        assert!(matches!(ConsoleSettings::from_bytes(&no_interval), Err(SettingsError::Invalid(_))));
+    }
+}
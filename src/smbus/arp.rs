@@ -0,0 +1,154 @@
+// SMBus 2.0 Address Resolution Protocol, as far as reading the unique
+// device identifier (UDID) an ARP-capable device reports about itself.
+
+use std::fmt;
+
+use super::{Property, SmbusError, SmbusTransport};
+
+/// Where every ARP-capable device answers ARP commands.
+pub const ARP_ADDRESS: u8 = 0x61;
+
+/// The 16-byte UDID, most significant byte first as it comes off the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Udid(pub [u8; 16]);
+
+impl Udid {
+    fn word(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.word(2)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.word(4)
+    }
+
+    pub fn subsystem_vendor_id(&self) -> u16 {
+        self.word(8)
+    }
+
+    pub fn subsystem_device_id(&self) -> u16 {
+        self.word(10)
+    }
+
+    /// What the Identification page shows about the device's UDID.
+    pub fn properties(&self) -> Vec<Property> {
+        vec![
+            Property::text("UDID", self.to_string()),
+            Property::text("Vendor ID", format!("{:04X}", self.vendor_id())),
+            Property::text("Device ID", format!("{:04X}", self.device_id())),
+            Property::text("Subsystem", format!("{:04X}:{:04X}", self.subsystem_vendor_id(), self.subsystem_device_id())),
+        ]
+    }
+}
+
+// Grouped the way the spec lays the fields out
+impl fmt::Display for Udid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if matches!(index, 2 | 6 | 8 | 12) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks the device at `addr` for its UDID with a directed Get UDID. `None`
+/// when it isn't ARP-capable or the controller can't do block reads.
+pub fn get_udid(bus: &mut dyn SmbusTransport, addr: u8) -> Result<Option<Udid>, SmbusError> {
+    let command = (addr << 1) | 1;
+    let bytes = match bus.read_block_data(ARP_ADDRESS, command) {
+        Ok(bytes) => bytes,
+        Err(SmbusError::Nack { .. } | SmbusError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // The UDID, then the address the device answers at, shifted like the
+    // command
+    if bytes.len() != 17 {
+        return Err(SmbusError::Other(format!("Get UDID returned {} bytes instead of 17", bytes.len())));
+    }
+    if bytes[16] >> 1 != addr {
+        log::debug!("Get UDID for {:#04x} was answered by {:#04x}", addr, bytes[16] >> 1);
+        return Ok(None);
+    }
+    let mut udid = [0u8; 16];
+    udid.copy_from_slice(&bytes[..16]);
+    Ok(Some(Udid(udid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::sim::SimulatedBus;
+
+    // Answers Get UDID for one device and passes everything else on
+    #[derive(Debug)]
+    struct ArpBus {
+        addr: u8,
+        reply: Vec<u8>,
+        inner: SimulatedBus,
+    }
+
+    impl SmbusTransport for ArpBus {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            self.inner.read_byte_data(addr, command)
+        }
+
+        fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            self.inner.write_byte_data(addr, command, value)
+        }
+
+        fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+            self.inner.read_word_data(addr, command)
+        }
+
+        fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+            self.inner.write_word_data(addr, command, value)
+        }
+
+        fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+            match addr == ARP_ADDRESS && command == (self.addr << 1) | 1 {
+                true => Ok(self.reply.clone()),
+                false => Err(SmbusError::Nack { addr }),
+            }
+        }
+    }
+
+    const UDID: [u8; 16] = [0x81, 0x08, 0x80, 0x86, 0x1c, 0x22, 0x00, 0x04, 0x10, 0x43, 0x84, 0x3e, 0x00, 0x00, 0x00, 0x01];
+
+    fn bus(reply: Vec<u8>) -> ArpBus {
+        ArpBus { addr: 0x4c, reply, inner: SimulatedBus::new() }
+    }
+
+    #[test]
+    fn reads_a_directed_udid() {
+        let mut reply = UDID.to_vec();
+        reply.push((0x4c << 1) | 1);
+        let udid = get_udid(&mut bus(reply), 0x4c).unwrap().unwrap();
+        assert_eq!(udid.vendor_id(), 0x8086);
+        assert_eq!(udid.device_id(), 0x1c22);
+        assert_eq!(udid.subsystem_vendor_id(), 0x1043);
+        assert_eq!(udid.subsystem_device_id(), 0x843e);
+        assert_eq!(udid.to_string(), "8108-80861C22-0004-1043843E-00000001");
+        assert_eq!(udid.properties()[0], Property::text("UDID", "8108-80861C22-0004-1043843E-00000001"));
+    }
+
+    #[test]
+    fn devices_without_arp_have_no_udid() {
+        assert_eq!(get_udid(&mut bus(Vec::new()), 0x50).unwrap(), None);
+        assert_eq!(get_udid(&mut SimulatedBus::demo(), 0x4c).unwrap(), None);
+    }
+
+    #[test]
+    fn answers_for_another_address_or_of_the_wrong_size_are_rejected() {
+        let mut reply = UDID.to_vec();
+        reply.push((0x2c << 1) | 1);
+        assert_eq!(get_udid(&mut bus(reply), 0x4c).unwrap(), None);
+        assert!(get_udid(&mut bus(UDID.to_vec()), 0x4c).is_err());
+    }
+}
@@ -0,0 +1,1234 @@
+// Generic 24Cxx I2C EEPROM driver, 24C01 through 24C512, and the EE1004
+// that holds DDR4 SPD.
+//
+// Small parts (up to 24C16) take a one-byte word address; parts larger than
+// 256 bytes borrow the low bits of the device address as block select.
+// 24C32 and up take a two-byte word address. The EE1004 answers at one
+// address only and shows one of its two 256 byte pages at a time, picked
+// by a write to 0x36 (page 0) or 0x37 (page 1). Writes are split at page
+// boundaries and each page is followed by ACK polling until the internal
+// write cycle finishes.
+//
+// Writes should be given a `GuardedBus` so the safety policy applies. Reads
+// of two-byte parts set the address pointer with a write, so pass the plain
+// transport for reads of a protected address.
+
+use std::fmt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::smbus::safety::SPD_EEPROM_RANGE;
+use crate::smbus::{Property, SmbusError, SmbusTransport, Value, BLOCK_MAX};
+
+use super::{DeviceKind, Driver};
+
+/// How long to wait for a page write cycle before giving up. Datasheets
+/// give 5 ms or 10 ms as the worst case.
+pub const WRITE_CYCLE_TIMEOUT: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EepromModel {
+    C01,
+    C02,
+    C04,
+    C08,
+    C16,
+    C32,
+    C64,
+    C128,
+    C256,
+    C512,
+    /// DDR4 SPD: 512 bytes in two pages.
+    Ee1004,
+}
+
+impl EepromModel {
+    pub const ALL: [EepromModel; 11] = [
+        EepromModel::C01, EepromModel::C02, EepromModel::C04, EepromModel::C08, EepromModel::C16,
+        EepromModel::C32, EepromModel::C64, EepromModel::C128, EepromModel::C256, EepromModel::C512,
+        EepromModel::Ee1004,
+    ];
+
+    /// Size in bytes.
+    pub fn capacity(self) -> usize {
+        match self {
+            EepromModel::C01 => 128,
+            EepromModel::C02 => 256,
+            EepromModel::C04 | EepromModel::Ee1004 => 512,
+            EepromModel::C08 => 1024,
+            EepromModel::C16 => 2048,
+            EepromModel::C32 => 4096,
+            EepromModel::C64 => 8192,
+            EepromModel::C128 => 16384,
+            EepromModel::C256 => 32768,
+            EepromModel::C512 => 65536,
+        }
+    }
+
+    pub fn page_size(self) -> usize {
+        match self {
+            EepromModel::C01 | EepromModel::C02 => 8,
+            EepromModel::C04 | EepromModel::C08 | EepromModel::C16 | EepromModel::Ee1004 => 16,
+            EepromModel::C32 | EepromModel::C64 => 32,
+            EepromModel::C128 | EepromModel::C256 => 64,
+            EepromModel::C512 => 128,
+        }
+    }
+
+    pub fn two_byte_address(self) -> bool {
+        self.capacity() > 2048
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EepromModel::C01 => "24C01",
+            EepromModel::C02 => "24C02",
+            EepromModel::C04 => "24C04",
+            EepromModel::C08 => "24C08",
+            EepromModel::C16 => "24C16",
+            EepromModel::C32 => "24C32",
+            EepromModel::C64 => "24C64",
+            EepromModel::C128 => "24C128",
+            EepromModel::C256 => "24C256",
+            EepromModel::C512 => "24C512",
+            EepromModel::Ee1004 => "EE1004",
+        }
+    }
+
+    /// Accepts "24C02", "24c02", "c02", "ee1004" and similar.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_uppercase();
+        Self::ALL.iter().copied().find(|m| m.name() == name || m.name().strip_prefix("24") == Some(name.as_str()))
+    }
+
+    /// The 24Cxx part of that size. A 512 byte image is taken as a 24C04;
+    /// the image says when it's an EE1004's.
+    pub fn from_capacity(capacity: usize) -> Option<Self> {
+        Self::ALL.iter().copied().find(|m| m.capacity() == capacity)
+    }
+
+    // Whether the part pages its memory instead of answering at more than
+    // one address
+    fn paged(self) -> bool {
+        self == EepromModel::Ee1004
+    }
+}
+
+impl fmt::Display for EepromModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub enum EepromError {
+    Bus(SmbusError),
+    /// The part reports software write protection (SPD).
+    WriteProtected { addr: u8 },
+    OutOfRange { offset: usize, len: usize, capacity: usize },
+    Verify { offset: usize, expected: u8, found: u8 },
+    Image(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for EepromError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EepromError::Bus(e) => write!(f, "{}", e),
+            EepromError::WriteProtected { addr } => write!(f, "EEPROM at {:#04x} is write protected", addr),
+            EepromError::OutOfRange { offset, len, capacity } => {
+                write!(f, "{} bytes at offset {:#x} don't fit in a {} byte EEPROM", len, offset, capacity)
+            }
+            EepromError::Verify { offset, expected, found } => {
+                write!(f, "verify failed at {:#06x}: wrote {:#04x}, read back {:#04x}", offset, expected, found)
+            }
+            EepromError::Image(e) => write!(f, "bad EEPROM image: {}", e),
+            EepromError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EepromError {}
+
+impl From<SmbusError> for EepromError {
+    fn from(e: SmbusError) -> Self {
+        EepromError::Bus(e)
+    }
+}
+
+impl From<std::io::Error> for EepromError {
+    fn from(e: std::io::Error) -> Self {
+        EepromError::Io(e)
+    }
+}
+
+/// EE1004 commands: a write to SPA0 or SPA1 picks page 0 or page 1.
+pub const EE1004_SET_PAGE: [u8; 2] = [0x36, 0x37];
+
+/// EE1004 commands that ACK when block 0 to 3 isn't write protected.
+pub const EE1004_PROTECTION_STATUS: [u8; 4] = [0x31, 0x34, 0x35, 0x30];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Eeprom {
+    pub addr: u8,
+    pub model: EepromModel,
+    /// Whether the part holds a memory module's SPD, which can be write
+    /// protected in software.
+    pub spd: bool,
+}
+
+impl Eeprom {
+    pub fn new(addr: u8, model: EepromModel) -> Self {
+        Self { addr, model, spd: false }
+    }
+
+    /// Recognises the EEPROM at `addr`. Only the SPD addresses are tried,
+    /// since those are kept for EEPROMs; the memory type byte says whether
+    /// it's a module's SPD and which part holds it. Reads page 0 without
+    /// selecting it, the way the BIOS leaves it.
+    pub fn detect(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        if !SPD_EEPROM_RANGE.contains(&addr) {
+            return None;
+        }
+        let model = match bus.read_byte_data(addr, 2).ok()? {
+            // SDRAM through DDR3 and LPDDR3 fit in 256 bytes
+            0x01..=0x0b | 0x0f => Some(EepromModel::C02),
+            // DDR4, DDR4E, LPDDR4 and LPDDR4X need both EE1004 pages
+            0x0c | 0x0e | 0x10 | 0x11 => Some(EepromModel::Ee1004),
+            // DDR5 modules have an SPD hub, which isn't an EEPROM this
+            // driver knows
+            0x12..=0x15 => return None,
+            _ => None,
+        };
+        Some(match model {
+            Some(model) => Self { addr, model, spd: true },
+            None => Self::new(addr, EepromModel::C02),
+        })
+    }
+
+    /// Every address writes to the part go to: its own, the block-select
+    /// addresses of a part that answers at several, or an EE1004's page
+    /// selects. All of them have to be unlocked to restore an SPD.
+    pub fn write_addresses(&self) -> Vec<u8> {
+        if self.model.paged() {
+            return vec![self.addr, EE1004_SET_PAGE[0], EE1004_SET_PAGE[1]];
+        }
+        match self.model.two_byte_address() {
+            true => vec![self.addr],
+            false => (0..self.model.capacity().div_ceil(256) as u8).map(|block| self.addr | block).collect(),
+        }
+    }
+
+    // Device address and word address for a byte offset
+    fn locate(&self, offset: usize) -> (u8, u16) {
+        if self.model.two_byte_address() {
+            (self.addr, offset as u16)
+        } else if self.model.paged() {
+            (self.addr, (offset & 0xff) as u16)
+        } else {
+            (self.addr | (offset >> 8) as u8, (offset & 0xff) as u16)
+        }
+    }
+
+    // Shows the page holding `offset` on parts that page their memory. The
+    // data byte is ignored.
+    fn select_page(&self, bus: &mut dyn SmbusTransport, offset: usize) -> Result<(), SmbusError> {
+        if !self.model.paged() {
+            return Ok(());
+        }
+        bus.write_byte_data(EE1004_SET_PAGE[offset >> 8], 0, 0)
+    }
+
+    // Leaves page 0 showing for whatever reads the SPD next
+    fn reset_page(&self, bus: &mut dyn SmbusTransport, end: usize) -> Result<(), SmbusError> {
+        match self.model.paged() && end > 256 {
+            true => self.select_page(bus, 0),
+            false => Ok(()),
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), EepromError> {
+        let capacity = self.model.capacity();
+        if offset + len > capacity {
+            return Err(EepromError::OutOfRange { offset, len, capacity });
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes at `offset`. Parts that page their memory are
+    /// left showing page 0, and selecting a page is a write, so pass the
+    /// plain transport.
+    pub fn read(&self, bus: &mut dyn SmbusTransport, offset: usize, len: usize) -> Result<Vec<u8>, EepromError> {
+        self.check_range(offset, len)?;
+
+        let data = self.read_pages(bus, offset, len);
+        self.reset_page(bus, offset + len)?;
+        data
+    }
+
+    fn read_pages(&self, bus: &mut dyn SmbusTransport, offset: usize, len: usize) -> Result<Vec<u8>, EepromError> {
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let position = offset + data.len();
+            let (dev, word) = self.locate(position);
+            if position.is_multiple_of(256) || data.is_empty() {
+                self.select_page(bus, position)?;
+            }
+
+            if self.model.two_byte_address() {
+                // Set the pointer with a dummy write, then read sequentially
+                let [hi, lo] = word.to_be_bytes();
+                bus.write_byte_data(dev, hi, lo)?;
+                let chunk = (len - data.len()).min(BLOCK_MAX);
+                for _ in 0..chunk {
+                    data.push(bus.receive_byte(dev)?);
+                }
+            } else {
+                // Stay inside the current 256 byte block or page
+                let chunk = (len - data.len()).min(BLOCK_MAX).min(256 - word as usize);
+                match bus.read_i2c_block_data(dev, word as u8, chunk) {
+                    Ok(block) => data.extend(block),
+                    // No I2C block reads on this controller, read byte by byte
+                    Err(SmbusError::Unsupported(_)) => {
+                        for i in 0..chunk {
+                            data.push(bus.read_byte_data(dev, word as u8 + i as u8)?);
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    pub fn read_all(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<u8>, EepromError> {
+        self.read(bus, 0, self.model.capacity())
+    }
+
+    /// Reads and decodes the memory module whose SPD this is. `None` for
+    /// EEPROMs that aren't SPD or memory types `MemoryModule` can't decode.
+    pub fn module(&self, bus: &mut dyn SmbusTransport) -> Result<Option<MemoryModule>, EepromError> {
+        match self.spd {
+            true => Ok(MemoryModule::decode(&self.read_all(bus)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Writes `data` at `offset`, one page at a time, waiting out the write
+    /// cycle after each page.
+    pub fn write(&self, bus: &mut dyn SmbusTransport, offset: usize, data: &[u8]) -> Result<(), EepromError> {
+        self.check_range(offset, data.len())?;
+        if self.write_protected(bus)? {
+            return Err(EepromError::WriteProtected { addr: self.addr });
+        }
+
+        let result = self.write_pages(bus, offset, data);
+        self.reset_page(bus, offset + data.len())?;
+        result
+    }
+
+    fn write_pages(&self, bus: &mut dyn SmbusTransport, offset: usize, data: &[u8]) -> Result<(), EepromError> {
+        let page_size = self.model.page_size();
+        let mut written = 0;
+        while written < data.len() {
+            let position = offset + written;
+            if position.is_multiple_of(256) || written == 0 {
+                self.select_page(bus, position)?;
+            }
+            let room_in_page = page_size - position % page_size;
+            // Two-byte parts spend one byte of the block on the address
+            let room_in_block = if self.model.two_byte_address() { BLOCK_MAX - 1 } else { BLOCK_MAX };
+            let chunk = (data.len() - written).min(room_in_page).min(room_in_block);
+
+            self.write_page(bus, position, &data[written..written + chunk])?;
+            written += chunk;
+        }
+
+        Ok(())
+    }
+
+    fn write_page(&self, bus: &mut dyn SmbusTransport, position: usize, chunk: &[u8]) -> Result<(), EepromError> {
+        let (dev, word) = self.locate(position);
+
+        let result = if self.model.two_byte_address() {
+            let [hi, lo] = word.to_be_bytes();
+            let mut block = Vec::with_capacity(chunk.len() + 1);
+            block.push(lo);
+            block.extend_from_slice(chunk);
+            bus.write_i2c_block_data(dev, hi, &block)
+        } else {
+            bus.write_i2c_block_data(dev, word as u8, chunk)
+        };
+
+        match result {
+            Ok(()) => self.wait_for_write_cycle(bus, dev),
+            // No I2C block writes on this controller, fall back to byte writes
+            Err(SmbusError::Unsupported(_)) => {
+                for (i, &byte) in chunk.iter().enumerate() {
+                    let (dev, word) = self.locate(position + i);
+                    if self.model.two_byte_address() {
+                        // Word write sends command, low, high: address high,
+                        // address low, data
+                        let [hi, lo] = word.to_be_bytes();
+                        bus.write_word_data(dev, hi, u16::from_le_bytes([lo, byte]))?;
+                    } else {
+                        bus.write_byte_data(dev, word as u8, byte)?;
+                    }
+                    self.wait_for_write_cycle(bus, dev)?;
+                }
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The part doesn't acknowledge its address while a write cycle is in
+    // progress
+    fn wait_for_write_cycle(&self, bus: &mut dyn SmbusTransport, dev: u8) -> Result<(), EepromError> {
+        let start = Instant::now();
+        loop {
+            match bus.receive_byte(dev) {
+                Ok(_) => return Ok(()),
+                Err(SmbusError::Nack { .. }) | Err(SmbusError::Timeout) => {
+                    if start.elapsed() > WRITE_CYCLE_TIMEOUT {
+                        return Err(SmbusError::Timeout.into());
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                // Can't poll, wait out the worst case instead
+                Err(SmbusError::Unsupported(_)) => {
+                    thread::sleep(WRITE_CYCLE_TIMEOUT);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn verify(&self, bus: &mut dyn SmbusTransport, offset: usize, expected: &[u8]) -> Result<(), EepromError> {
+        let found = self.read(bus, offset, expected.len())?;
+        match expected.iter().zip(found.iter()).position(|(e, f)| e != f) {
+            Some(i) => Err(EepromError::Verify { offset: offset + i, expected: expected[i], found: found[i] }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn write_verified(&self, bus: &mut dyn SmbusTransport, offset: usize, data: &[u8]) -> Result<(), EepromError> {
+        self.write(bus, offset, data)?;
+        self.verify(bus, offset, data)
+    }
+
+    /// SPD EEPROMs with software write protection stop acknowledging their
+    /// protection-status address (0x30-0x37) once protected; an EE1004 has
+    /// one for each of its four blocks. Other parts only have a hardware WP
+    /// pin, which we can't see.
+    pub fn write_protected(&self, bus: &mut dyn SmbusTransport) -> Result<bool, EepromError> {
+        if !self.spd || !SPD_EEPROM_RANGE.contains(&self.addr) {
+            return Ok(false);
+        }
+
+        let status_addrs = match self.model.paged() {
+            true => EE1004_PROTECTION_STATUS.to_vec(),
+            false => vec![0x30 | (self.addr & 0x07)],
+        };
+        for status_addr in status_addrs {
+            match bus.receive_byte(status_addr) {
+                Ok(_) | Err(SmbusError::Unsupported(_)) => {}
+                Err(SmbusError::Nack { .. }) => return Ok(true),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reads the whole part and saves it to `path` as an image file.
+    pub fn backup(&self, bus: &mut dyn SmbusTransport, path: &Path) -> Result<EepromImage, EepromError> {
+        let image = EepromImage { model: self.model, data: self.read_all(bus)? };
+        std::fs::write(path, image.to_bytes())?;
+        log::info!("Saved {} at {:#04x} to {}", self.model, self.addr, path.display());
+        Ok(image)
+    }
+
+    /// Writes an image file back to the part and, if `verify`, reads it
+    /// back to check. Dry runs can't be verified since nothing reaches the
+    /// part.
+    pub fn restore(&self, bus: &mut dyn SmbusTransport, path: &Path, verify: bool) -> Result<(), EepromError> {
+        let image = EepromImage::from_bytes(&std::fs::read(path)?)?;
+        if image.model != self.model {
+            return Err(EepromError::Image(format!(
+                "image is for a {}, this part is a {}", image.model, self.model
+            )));
+        }
+
+        match verify {
+            true => self.write_verified(bus, 0, &image.data)?,
+            false => self.write(bus, 0, &image.data)?,
+        }
+        log::info!("Restored {} at {:#04x} from {}", self.model, self.addr, path.display());
+        Ok(())
+    }
+}
+
+impl Driver for Eeprom {
+    fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    fn name(&self) -> String {
+        match self.spd {
+            true => format!("{} SPD EEPROM", self.model),
+            false => format!("{} EEPROM", self.model),
+        }
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Eeprom
+    }
+
+    // A memory module's SPD also says what the module is
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError> {
+        let bus_error = |e| match e {
+            EepromError::Bus(e) => e,
+            e => SmbusError::Other(e.to_string()),
+        };
+        let protected = self.write_protected(bus).map_err(bus_error)?;
+        let mut properties = vec![
+            Property::text("Part", self.model.name()),
+            Property::new("Size", Value::Integer(self.model.capacity() as i64), Some("bytes")),
+            Property::new("Page size", Value::Integer(self.model.page_size() as i64), Some("bytes")),
+            Property::text("Write protection", if protected { "On" } else { "Off" }),
+        ];
+        if let Some(module) = self.module(bus).map_err(bus_error)? {
+            properties.extend(module.properties());
+        }
+        Ok(properties)
+    }
+}
+
+const IMAGE_MAGIC: &[u8; 4] = b"24CX";
+const IMAGE_VERSION: u16 = 1;
+const IMAGE_HEADER_LEN: usize = 16;
+// Header flag of images read from an EE1004, which are as big as a 24C04's
+const IMAGE_FLAG_EE1004: u16 = 0x0001;
+
+/// Contents of an EEPROM together with the part it came from.
+///
+/// On disk: magic `24CX`, format version (u16), flags (u16), data length
+/// (u32), CRC-32 of the data (u32), then the data. All integers are
+/// little-endian. The only flag is bit 0, set for an EE1004's image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EepromImage {
+    pub model: EepromModel,
+    pub data: Vec<u8>,
+}
+
+impl EepromImage {
+    pub fn checksum(&self) -> u32 {
+        crc32(&self.data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(IMAGE_HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(IMAGE_MAGIC);
+        bytes.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+        let flags = match self.model {
+            EepromModel::Ee1004 => IMAGE_FLAG_EE1004,
+            _ => 0,
+        };
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.checksum().to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EepromError> {
+        if bytes.len() < IMAGE_HEADER_LEN || &bytes[0..4] != IMAGE_MAGIC {
+            return Err(EepromError::Image("not an EEPROM image".to_owned()));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        let version = u16_at(4);
+        if version != IMAGE_VERSION {
+            return Err(EepromError::Image(format!("unsupported image version {}", version)));
+        }
+
+        let len = u32_at(8) as usize;
+        let data = &bytes[IMAGE_HEADER_LEN..];
+        if data.len() != len {
+            return Err(EepromError::Image(format!("expected {} bytes of data, found {}", len, data.len())));
+        }
+
+        let model = match u16_at(6) & IMAGE_FLAG_EE1004 != 0 {
+            true if len == EepromModel::Ee1004.capacity() => EepromModel::Ee1004,
+            true => return Err(EepromError::Image(format!("an EE1004 doesn't hold {} bytes", len))),
+            false => EepromModel::from_capacity(len)
+                .ok_or_else(|| EepromError::Image(format!("no 24Cxx part holds {} bytes", len)))?,
+        };
+
+        let image = EepromImage { model, data: data.to_vec() };
+        let stored = u32_at(12);
+        if image.checksum() != stored {
+            return Err(EepromError::Image(format!(
+                "checksum mismatch: file says {:08x}, data is {:08x}", stored, image.checksum()
+            )));
+        }
+
+        Ok(image)
+    }
+}
+
+/// Reads the first bytes of an SPD EEPROM, which say what kind of memory
+/// module it describes. Byte-at-a-time so it works on any controller and
+/// never writes, even to select a DDR4 page.
+pub fn spd_summary(bus: &mut dyn SmbusTransport, addr: u8) -> Result<Vec<Property>, SmbusError> {
+    let mut header = [0u8; 4];
+    for (offset, byte) in header.iter_mut().enumerate() {
+        *byte = bus.read_byte_data(addr, offset as u8)?;
+    }
+    let [_, revision, dram, module] = header;
+
+    let dram_name = match dram {
+        0x06 => "DDR SDRAM",
+        0x08 => "DDR2 SDRAM",
+        0x0b => "DDR3 SDRAM",
+        0x0c => "DDR4 SDRAM",
+        0x0f => "LPDDR3 SDRAM",
+        0x10 => "LPDDR4 SDRAM",
+        0x12 => "DDR5 SDRAM",
+        0x13 => "LPDDR5 SDRAM",
+        _ => "Unknown",
+    };
+    // The module type nibble means the same for the common form factors
+    // from DDR3 on
+    let module_name = match (dram, module & 0x0f) {
+        (0x06 | 0x08, _) => None,
+        (_, 0x01) => Some("RDIMM"),
+        (_, 0x02) => Some("UDIMM"),
+        (_, 0x03) => Some("SO-DIMM"),
+        (0x0c | 0x12, 0x04) | (0x0b, 0x0b) => Some("LRDIMM"),
+        _ => None,
+    };
+
+    let mut properties = vec![
+        Property::text("Memory type", format!("{} ({:#04x})", dram_name, dram)),
+        Property::text("SPD revision", format!("{}.{}", revision >> 4, revision & 0x0f)),
+    ];
+    if let Some(module_name) = module_name {
+        properties.push(Property::text("Module type", module_name));
+    }
+    Ok(properties)
+}
+
+/// The names of a memory module's properties, in the order the memory
+/// columns show them.
+pub const MODULE_PROPERTIES: [&str; 3] = ["Module size", "Speed", "Part number"];
+
+/// What a memory module's SPD says about it, as the memory columns show
+/// it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryModule {
+    /// "DDR3" or "DDR4".
+    pub generation: &'static str,
+    pub size_mib: u64,
+    /// In MT/s, rounded to the JEDEC speed grade it's closest to.
+    pub data_rate: u32,
+    pub part_number: String,
+}
+
+// JEDEC data rates, which tCK only gives to within a rounding error
+const DATA_RATES: [u32; 16] = [800, 1066, 1333, 1600, 1866, 2133, 2400, 2666, 2933, 3200, 3600, 4000, 4266, 4400, 4800, 5333];
+
+impl MemoryModule {
+    /// Decodes a DDR3 or DDR4 module's SPD contents. `None` for other
+    /// memory types, or when the bytes that matter hold values no module
+    /// uses.
+    pub fn decode(spd: &[u8]) -> Option<Self> {
+        let byte = |offset: usize| spd.get(offset).copied();
+        match byte(2)? {
+            0x0b => {
+                // Capacity per die in Mb, then the organisation
+                let die = 256u64 << (byte(4)? & 0x0f).min(6);
+                let width = 4u64 << (byte(7)? & 0x07);
+                let ranks = u64::from((byte(7)? >> 3) & 0x07) + 1;
+                let bus = 8u64 << (byte(8)? & 0x07);
+                // tCK in medium timebase units of dividend / divisor ns,
+                // corrected by the fine timebase in ps
+                let (dividend, divisor) = (byte(10)?, byte(11)?);
+                if divisor == 0 {
+                    return None;
+                }
+                let mtb_ps = 1000.0 * f64::from(dividend) / f64::from(divisor);
+                let tck_ps = f64::from(byte(12)?) * mtb_ps + f64::from(byte(34)? as i8);
+                Some(Self {
+                    generation: "DDR3",
+                    size_mib: die / 8 * (bus / width) * ranks,
+                    data_rate: data_rate(tck_ps)?,
+                    part_number: ascii(spd.get(128..146)?),
+                })
+            }
+            0x0c => {
+                let die = match byte(4)? & 0x0f {
+                    raw @ 0..=7 => 256u64 << raw,
+                    8 => 12 * 1024,
+                    9 => 24 * 1024,
+                    _ => return None,
+                };
+                let width = 4u64 << (byte(12)? & 0x07);
+                let mut ranks = u64::from((byte(12)? >> 3) & 0x07) + 1;
+                let bus = 8u64 << (byte(13)? & 0x07);
+                // 3DS packages stack dies that each add a logical rank
+                let package = byte(6)?;
+                if package & 0x03 == 0x02 {
+                    ranks *= u64::from((package >> 4) & 0x07) + 1;
+                }
+                // DDR4 fixes the timebases at 125 ps and 1 ps
+                let tck_ps = f64::from(byte(18)?) * 125.0 + f64::from(byte(125)? as i8);
+                Some(Self {
+                    generation: "DDR4",
+                    size_mib: die / 8 * (bus / width) * ranks,
+                    data_rate: data_rate(tck_ps)?,
+                    part_number: ascii(spd.get(329..349)?),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn size_text(&self) -> String {
+        match self.size_mib % 1024 {
+            0 => format!("{} GB", self.size_mib / 1024),
+            _ => format!("{} MB", self.size_mib),
+        }
+    }
+
+    /// The speed grade, e.g. DDR4-3200.
+    pub fn speed_text(&self) -> String {
+        format!("{}-{}", self.generation, self.data_rate)
+    }
+
+    /// What the memory columns show, named as in `MODULE_PROPERTIES`.
+    pub fn properties(&self) -> Vec<Property> {
+        let [size, speed, part_number] = MODULE_PROPERTIES;
+        vec![
+            Property::text(size, self.size_text()),
+            Property::text(speed, self.speed_text()),
+            Property::text(part_number, self.part_number.clone()),
+        ]
+    }
+}
+
+// Two transfers per clock, snapped to the speed grade within 1 %
+fn data_rate(tck_ps: f64) -> Option<u32> {
+    if tck_ps <= 0.0 {
+        return None;
+    }
+    let rate = 2_000_000.0 / tck_ps;
+    let grade = DATA_RATES.iter().copied().find(|grade| (rate - f64::from(*grade)).abs() <= rate / 100.0);
+    Some(grade.unwrap_or(rate.round() as u32))
+}
+
+// SPD strings are space-padded ASCII
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { ' ' })
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// CRC-32 (IEEE 802.3), the same one zip and PNG use.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::safety::{GuardedBus, WritePolicy};
+    use crate::smbus::sim::SimulatedBus;
+
+    // An EE1004 at 0x50 behind a controller that may not do I2C block reads
+    #[derive(Debug)]
+    struct Ee1004Bus {
+        data: Vec<u8>,
+        page: usize,
+        block_reads: bool,
+        protected: bool,
+    }
+
+    impl Ee1004Bus {
+        fn new() -> Self {
+            let mut data: Vec<u8> = (0..512).map(|i| (i ^ ((i >> 8) * 0x5a)) as u8).collect();
+            data[2] = 0x0c;
+            Self { data, page: 0, block_reads: true, protected: false }
+        }
+    }
+
+    impl SmbusTransport for Ee1004Bus {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            match addr {
+                0x50 => Ok(self.data[self.page * 256 + command as usize]),
+                _ => Err(SmbusError::Nack { addr }),
+            }
+        }
+
+        fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            match addr {
+                0x36 | 0x37 => self.page = (addr - 0x36) as usize,
+                0x50 => self.data[self.page * 256 + command as usize] = value,
+                _ => return Err(SmbusError::Nack { addr }),
+            }
+            Ok(())
+        }
+
+        fn read_word_data(&mut self, _addr: u8, _command: u8) -> Result<u16, SmbusError> {
+            Err(SmbusError::Unsupported("word read"))
+        }
+
+        fn write_word_data(&mut self, _addr: u8, _command: u8, _value: u16) -> Result<(), SmbusError> {
+            Err(SmbusError::Unsupported("word write"))
+        }
+
+        fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+            match addr {
+                0x50 => Ok(0),
+                _ if EE1004_PROTECTION_STATUS.contains(&addr) && !self.protected => Ok(0),
+                _ => Err(SmbusError::Nack { addr }),
+            }
+        }
+
+        fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+            if !self.block_reads {
+                return Err(SmbusError::Unsupported("I2C block read"));
+            }
+            (0..len).map(|i| self.read_byte_data(addr, command + i as u8)).collect()
+        }
+
+        fn write_i2c_block_data(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+            for (i, &byte) in data.iter().enumerate() {
+                self.write_byte_data(addr, command + i as u8, byte)?;
+            }
+            Ok(())
+        }
+    }
+
+    // A 24Cxx at 0x50, plus the addresses its blocks answer at, that
+    // keeps track of how it was addressed
+    #[derive(Debug)]
+    struct AtBus {
+        model: EepromModel,
+        data: Vec<u8>,
+        // Where sequential reads of a two-byte part carry on from
+        pointer: usize,
+        block_writes: bool,
+        // How many polls the part ignores after each write, and how many
+        // it's still ignoring
+        write_cycle: u32,
+        busy: u32,
+        // Every pointer set by a dummy write
+        pointers: Vec<usize>,
+        // Every write as offset and length
+        writes: Vec<(usize, usize)>,
+    }
+
+    impl AtBus {
+        fn new(model: EepromModel) -> Self {
+            let data = (0..model.capacity()).map(|i| (i * 7 + (i >> 8)) as u8).collect();
+            Self { model, data, pointer: 0, block_writes: true, write_cycle: 0, busy: 0, pointers: Vec::new(), writes: Vec::new() }
+        }
+
+        // The byte offset a small part's device and word address pick
+        fn offset(&mut self, dev: u8, word: u8) -> Result<usize, SmbusError> {
+            let block = dev.wrapping_sub(0x50) as usize;
+            match self.busy == 0 && block < self.model.capacity().div_ceil(256) {
+                true => Ok(block << 8 | word as usize),
+                false => Err(SmbusError::Nack { addr: dev }),
+            }
+        }
+
+        fn store(&mut self, offset: usize, bytes: &[u8]) {
+            self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            self.writes.push((offset, bytes.len()));
+            self.busy = self.write_cycle;
+        }
+    }
+
+    impl SmbusTransport for AtBus {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            let offset = self.offset(addr, command)?;
+            Ok(self.data[offset])
+        }
+
+        // Two-byte parts take this as the dummy write that sets the pointer
+        fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            match self.model.two_byte_address() {
+                true => {
+                    self.offset(addr, 0)?;
+                    self.pointer = u16::from_be_bytes([command, value]) as usize;
+                    self.pointers.push(self.pointer);
+                }
+                false => {
+                    let offset = self.offset(addr, command)?;
+                    self.store(offset, &[value]);
+                }
+            }
+            Ok(())
+        }
+
+        fn read_word_data(&mut self, _addr: u8, _command: u8) -> Result<u16, SmbusError> {
+            Err(SmbusError::Unsupported("word read"))
+        }
+
+        // Address high, then address low and the data
+        fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+            self.offset(addr, 0)?;
+            let [lo, byte] = value.to_le_bytes();
+            self.store(u16::from_be_bytes([command, lo]) as usize, &[byte]);
+            Ok(())
+        }
+
+        // Also the write cycle poll, which the part ignores while busy
+        fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+            if self.busy > 0 {
+                self.busy -= 1;
+                return Err(SmbusError::Nack { addr });
+            }
+            self.offset(addr, 0)?;
+            let byte = self.data[self.pointer];
+            self.pointer = (self.pointer + 1) % self.data.len();
+            Ok(byte)
+        }
+
+        fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+            let offset = self.offset(addr, command)?;
+            Ok(self.data[offset..offset + len].to_vec())
+        }
+
+        fn write_i2c_block_data(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+            if !self.block_writes {
+                return Err(SmbusError::Unsupported("I2C block write"));
+            }
+            assert!(data.len() <= BLOCK_MAX);
+            match self.model.two_byte_address() {
+                true => {
+                    self.offset(addr, 0)?;
+                    self.store(u16::from_be_bytes([command, data[0]]) as usize, &data[1..]);
+                }
+                false => {
+                    let offset = self.offset(addr, command)?;
+                    self.store(offset, data);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("smbus-eeprom-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn ddr4_spd_is_an_ee1004() {
+        let mut bus = Ee1004Bus::new();
+        let driver = crate::smbus::drivers::identify(&mut bus, 0x50, &[]).unwrap();
+        assert_eq!(driver.kind(), DeviceKind::Eeprom);
+        assert_eq!(driver.name(), "EE1004 SPD EEPROM");
+
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        assert_eq!(part.model, EepromModel::Ee1004);
+        assert!(part.spd);
+    }
+
+    #[test]
+    fn other_parts_at_spd_addresses_are_24c02s() {
+        let mut bus = SimulatedBus::demo();
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        assert_eq!(part.model, EepromModel::C02);
+        assert!(!part.spd);
+        assert!(Eeprom::detect(&mut bus, 0x4c).is_none());
+    }
+
+    #[test]
+    fn reads_span_ee1004_pages_and_leave_page_0() {
+        let mut bus = Ee1004Bus::new();
+        let part = Eeprom { addr: 0x50, model: EepromModel::Ee1004, spd: true };
+
+        assert_eq!(part.read(&mut bus, 0xf0, 0x20).unwrap(), bus.data[0xf0..0x110]);
+        assert_eq!(bus.page, 0);
+        assert_eq!(part.read_all(&mut bus).unwrap(), bus.data);
+        assert_eq!(bus.page, 0);
+    }
+
+    #[test]
+    fn reads_fall_back_to_bytes_without_block_reads() {
+        let mut bus = Ee1004Bus::new();
+        bus.block_reads = false;
+        let part = Eeprom { addr: 0x50, model: EepromModel::Ee1004, spd: true };
+
+        assert_eq!(part.read_all(&mut bus).unwrap(), bus.data);
+    }
+
+    #[test]
+    fn writes_cross_into_the_second_page() {
+        let mut bus = Ee1004Bus::new();
+        let part = Eeprom { addr: 0x50, model: EepromModel::Ee1004, spd: true };
+        let untouched = bus.data[0x08];
+
+        part.write_verified(&mut bus, 0xf8, &[0xa5; 16]).unwrap();
+        assert_eq!(bus.data[0xf8..0x108], [0xa5; 16]);
+        assert_eq!(bus.data[0x08], untouched);
+        assert_eq!(bus.page, 0);
+    }
+
+    #[test]
+    fn protected_ee1004_refuses_writes() {
+        let mut bus = Ee1004Bus::new();
+        bus.protected = true;
+        let part = Eeprom { addr: 0x50, model: EepromModel::Ee1004, spd: true };
+
+        assert!(matches!(part.write(&mut bus, 0, &[0]), Err(EepromError::WriteProtected { addr: 0x50 })));
+    }
+
+    #[test]
+    fn images_keep_the_ee1004_apart_from_a_24c04() {
+        let ee1004 = EepromImage { model: EepromModel::Ee1004, data: vec![7; 512] };
+        assert_eq!(EepromImage::from_bytes(&ee1004.to_bytes()).unwrap(), ee1004);
+        let c04 = EepromImage { model: EepromModel::C04, data: vec![7; 512] };
+        assert_eq!(EepromImage::from_bytes(&c04.to_bytes()).unwrap(), c04);
+
+        let mut bytes = c04.to_bytes();
+        bytes[IMAGE_HEADER_LEN] ^= 1;
+        assert!(matches!(EepromImage::from_bytes(&bytes), Err(EepromError::Image(_))));
+    }
+
+    #[test]
+    fn two_byte_parts_set_the_pointer_before_reading() {
+        let mut bus = AtBus::new(EepromModel::C256);
+        let part = Eeprom::new(0x50, EepromModel::C256);
+
+        assert_eq!(part.read(&mut bus, 0x3ff0, 100).unwrap(), bus.data[0x3ff0..0x4054]);
+        // A dummy write ahead of each run of 32 sequential reads
+        assert_eq!(bus.pointers, vec![0x3ff0, 0x4010, 0x4030, 0x4050]);
+    }
+
+    #[test]
+    fn two_byte_writes_split_at_pages_and_blocks() {
+        let mut bus = AtBus::new(EepromModel::C256);
+        let part = Eeprom::new(0x50, EepromModel::C256);
+        let data: Vec<u8> = (0..100).collect();
+
+        part.write_verified(&mut bus, 0x1230, &data).unwrap();
+        assert_eq!(bus.data[0x1230..0x1294], data);
+        // The address takes a byte of each 32-byte block, and no write
+        // crosses a 64-byte page
+        assert_eq!(bus.writes, vec![(0x1230, 16), (0x1240, 31), (0x125f, 31), (0x127e, 2), (0x1280, 20)]);
+    }
+
+    #[test]
+    fn small_parts_select_blocks_with_the_device_address() {
+        let mut bus = AtBus::new(EepromModel::C16);
+        let part = Eeprom::new(0x50, EepromModel::C16);
+        assert_eq!(part.write_addresses(), (0x50..=0x57).collect::<Vec<u8>>());
+
+        assert_eq!(part.read_all(&mut bus).unwrap(), bus.data);
+        assert_eq!(part.read(&mut bus, 0x2fc, 8).unwrap(), bus.data[0x2fc..0x304]);
+
+        part.write_verified(&mut bus, 0x7f8, &[0x5a; 8]).unwrap();
+        assert_eq!(bus.data[0x7f8..], [0x5a; 8]);
+        part.write_verified(&mut bus, 0x1fc, &[0xa5; 8]).unwrap();
+        assert_eq!(bus.data[0x1fc..0x204], [0xa5; 8]);
+        assert_eq!(bus.writes, vec![(0x7f8, 8), (0x1fc, 4), (0x200, 4)]);
+        assert!(matches!(part.read(&mut bus, 0x7ff, 2), Err(EepromError::OutOfRange { capacity: 2048, .. })));
+    }
+
+    #[test]
+    fn writes_fall_back_to_bytes_and_words() {
+        let mut bus = AtBus::new(EepromModel::C02);
+        bus.block_writes = false;
+        let part = Eeprom::new(0x50, EepromModel::C02);
+        part.write_verified(&mut bus, 0x10, &[1, 2, 3]).unwrap();
+        assert_eq!(bus.data[0x10..0x13], [1, 2, 3]);
+        assert_eq!(bus.writes, vec![(0x10, 1), (0x11, 1), (0x12, 1)]);
+
+        let mut bus = AtBus::new(EepromModel::C256);
+        bus.block_writes = false;
+        let part = Eeprom::new(0x50, EepromModel::C256);
+        part.write_verified(&mut bus, 0x3ffe, &[4, 5, 6]).unwrap();
+        assert_eq!(bus.data[0x3ffe..0x4001], [4, 5, 6]);
+        assert_eq!(bus.writes, vec![(0x3ffe, 1), (0x3fff, 1), (0x4000, 1)]);
+    }
+
+    #[test]
+    fn write_cycles_are_polled_until_the_part_answers() {
+        let mut bus = AtBus::new(EepromModel::C02);
+        bus.write_cycle = 3;
+        let part = Eeprom::new(0x50, EepromModel::C02);
+        part.write(&mut bus, 0, &[9; 16]).unwrap();
+        assert_eq!(bus.writes, vec![(0, 8), (8, 8)]);
+
+        // A part that never finishes times out
+        bus.write_cycle = u32::MAX;
+        let start = Instant::now();
+        assert!(matches!(part.write(&mut bus, 0, &[0]), Err(EepromError::Bus(SmbusError::Timeout))));
+        assert!(start.elapsed() >= WRITE_CYCLE_TIMEOUT);
+    }
+
+    #[test]
+    fn backup_restores_through_the_safety_layer() {
+        let mut bus = SimulatedBus::demo();
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        let path = temp_path("backup.eep");
+        let image = part.backup(&mut bus, &path).unwrap();
+
+        part.write(&mut bus, 0, &[0; 16]).unwrap();
+        let locked = WritePolicy::default();
+        let mut guarded = GuardedBus::new(&mut bus, &locked);
+        assert!(matches!(part.restore(&mut guarded, &path, true), Err(EepromError::Bus(SmbusError::Blocked { .. }))));
+
+        let unlocked = WritePolicy { unlocked: vec![0x50], ..WritePolicy::default() };
+        let mut guarded = GuardedBus::new(&mut bus, &unlocked);
+        part.restore(&mut guarded, &path, true).unwrap();
+        assert_eq!(part.read_all(&mut bus).unwrap(), image.data);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ee1004_restores_with_its_page_selects_unlocked() {
+        let mut bus = Ee1004Bus::new();
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        assert_eq!(part.write_addresses(), vec![0x50, 0x36, 0x37]);
+        let path = temp_path("ee1004.eep");
+        let image = part.backup(&mut bus, &path).unwrap();
+        bus.data[0x100..0x110].fill(0);
+
+        // The part alone isn't enough; the page selects are protected too
+        let part_only = WritePolicy { unlocked: vec![0x50], ..WritePolicy::default() };
+        let mut guarded = GuardedBus::new(&mut bus, &part_only);
+        assert!(matches!(part.restore(&mut guarded, &path, true), Err(EepromError::Bus(SmbusError::Blocked { addr: 0x36, .. }))));
+
+        let unlocked = WritePolicy { unlocked: part.write_addresses(), ..WritePolicy::default() };
+        let mut guarded = GuardedBus::new(&mut bus, &unlocked);
+        part.restore(&mut guarded, &path, true).unwrap();
+        assert!(guarded.journal().iter().any(|write| write.addr == 0x37));
+        assert_eq!(bus.data, image.data);
+        assert_eq!(bus.page, 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // A DDR4-3200 UDIMM: 8 Gb x8 dies, one rank on a 64-bit bus
+    fn ddr4_spd() -> Vec<u8> {
+        let mut spd = vec![0u8; 512];
+        spd[2] = 0x0c;
+        spd[4] = 0x05;
+        spd[12] = 0x01;
+        spd[13] = 0x03;
+        spd[18] = 0x05;
+        spd[329..349].copy_from_slice(b"F4-3200C16-8GVKB    ");
+        spd
+    }
+
+    #[test]
+    fn ddr4_modules_are_decoded() {
+        let module = MemoryModule::decode(&ddr4_spd()).unwrap();
+        assert_eq!(module, MemoryModule {
+            generation: "DDR4",
+            size_mib: 8192,
+            data_rate: 3200,
+            part_number: "F4-3200C16-8GVKB".to_owned(),
+        });
+        assert_eq!(module.size_text(), "8 GB");
+        assert_eq!(module.speed_text(), "DDR4-3200");
+
+        // DDR4-2666 needs the fine correction: 0.750 ns is 6 x 125 ps
+        // exactly, DDR4-2400's 0.833 ns is 7 x 125 ps less 42 ps
+        let mut spd = ddr4_spd();
+        spd[18] = 0x07;
+        spd[125] = (-42i8) as u8;
+        assert_eq!(MemoryModule::decode(&spd).unwrap().data_rate, 2400);
+    }
+
+    #[test]
+    fn ddr4_3ds_dies_add_ranks() {
+        // Two ranks of 4-high 16 Gb x4 stacks
+        let mut spd = ddr4_spd();
+        spd[4] = 0x06;
+        spd[6] = 0b1011_0010;
+        spd[12] = 1 << 3;
+        assert_eq!(MemoryModule::decode(&spd).unwrap().size_mib, 2048 * 16 * 2 * 4);
+    }
+
+    #[test]
+    fn ddr3_modules_are_decoded() {
+        // DDR3-1600 SO-DIMM: 4 Gb x8 dies, two ranks, 1/8 ns timebase
+        let mut spd = vec![0u8; 256];
+        spd[2] = 0x0b;
+        spd[4] = 0x04;
+        spd[7] = (1 << 3) | 0x01;
+        spd[8] = 0x03;
+        spd[10] = 1;
+        spd[11] = 8;
+        spd[12] = 0x0a;
+        spd[128..146].copy_from_slice(b"KHX1600C9S3L/8G   ");
+        let module = MemoryModule::decode(&spd).unwrap();
+        assert_eq!(module.size_text(), "8 GB");
+        assert_eq!(module.speed_text(), "DDR3-1600");
+        assert_eq!(module.part_number, "KHX1600C9S3L/8G");
+    }
+
+    #[test]
+    fn other_memory_is_left_undecoded() {
+        let mut spd = ddr4_spd();
+        spd[2] = 0x08;
+        assert_eq!(MemoryModule::decode(&spd), None);
+        // Too short to hold the part number
+        assert_eq!(MemoryModule::decode(&ddr4_spd()[..256]), None);
+        let mut spd = ddr4_spd();
+        spd[18] = 0;
+        assert_eq!(MemoryModule::decode(&spd), None);
+    }
+
+    #[test]
+    fn modules_are_read_through_both_pages() {
+        let mut bus = Ee1004Bus { data: ddr4_spd(), ..Ee1004Bus::new() };
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        assert_eq!(part.model, EepromModel::Ee1004);
+        assert_eq!(part.module(&mut bus).unwrap().unwrap().part_number, "F4-3200C16-8GVKB");
+
+        let mut demo = SimulatedBus::demo();
+        let plain = Eeprom::detect(&mut demo, 0x50).unwrap();
+        assert_eq!(plain.module(&mut demo).unwrap(), None);
+    }
+
+    #[test]
+    fn spd_readings_say_what_the_module_is() {
+        let mut bus = Ee1004Bus { data: ddr4_spd(), ..Ee1004Bus::new() };
+        let part = Eeprom::detect(&mut bus, 0x50).unwrap();
+        let properties = Driver::read(&part, &mut bus).unwrap();
+        let module: Vec<(&str, String)> = properties.iter()
+            .filter(|property| MODULE_PROPERTIES.contains(&property.name.as_str()))
+            .map(|property| (property.name.as_str(), property.value_text()))
+            .collect();
+        assert_eq!(module, vec![
+            ("Module size", "8 GB".to_owned()),
+            ("Speed", "DDR4-3200".to_owned()),
+            ("Part number", "F4-3200C16-8GVKB".to_owned()),
+        ]);
+
+        let mut demo = SimulatedBus::demo();
+        let plain = Eeprom::detect(&mut demo, 0x50).unwrap();
+        let properties = Driver::read(&plain, &mut demo).unwrap();
+        assert!(properties.iter().all(|property| !MODULE_PROPERTIES.contains(&property.name.as_str())));
+    }
+}
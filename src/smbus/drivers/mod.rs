@@ -0,0 +1,331 @@
+// Device drivers written in code, for parts a register map can't describe.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::regmap::RegisterMap;
+use super::{Property, SmbusError, SmbusTransport, Value};
+
+pub mod eeprom;
+pub mod rgb;
+pub mod sbs;
+pub mod temp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeviceKind {
+    Unknown,
+    Battery,
+    Eeprom,
+    FanController,
+    TemperatureSensor,
+    RgbController,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeviceKind::Unknown => "Unknown device",
+            DeviceKind::Battery => "Battery",
+            DeviceKind::Eeprom => "EEPROM",
+            DeviceKind::FanController => "Fan controller",
+            DeviceKind::TemperatureSensor => "Temperature sensor",
+            DeviceKind::RgbController => "RGB controller",
+        })
+    }
+}
+
+/// A measured value and the limits it's checked against.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub name: String,
+    pub value: Value,
+    pub unit: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Where the device takes action of its own, e.g. shutting down.
+    pub critical: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorStatus {
+    Ok,
+    Low,
+    High,
+    Critical,
+    /// The value isn't a number, so it can't be checked.
+    Unknown,
+}
+
+impl fmt::Display for SensorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SensorStatus::Ok => "OK",
+            SensorStatus::Low => "Below minimum",
+            SensorStatus::High => "Above maximum",
+            SensorStatus::Critical => "Critical",
+            SensorStatus::Unknown => "Unknown",
+        })
+    }
+}
+
+impl SensorReading {
+    pub fn status(&self) -> SensorStatus {
+        match self.value.number() {
+            Some(value) if self.critical.is_some_and(|critical| value >= critical) => SensorStatus::Critical,
+            Some(value) if self.max.is_some_and(|max| value > max) => SensorStatus::High,
+            Some(value) if self.min.is_some_and(|min| value < min) => SensorStatus::Low,
+            Some(_) => SensorStatus::Ok,
+            None => SensorStatus::Unknown,
+        }
+    }
+
+    pub fn to_property(&self) -> Property {
+        Property::new(&self.name, self.value.clone(), self.unit.as_deref())
+    }
+}
+
+/// A value on a device the user can change, such as a configuration
+/// register or an alarm limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Setting {
+    /// Names the setting to `Driver::write_setting`.
+    pub key: String,
+    pub label: String,
+    pub kind: SettingKind,
+    pub value: f64,
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SettingKind {
+    /// A number in `min..=max`, rounded to a multiple of `step`.
+    Number { min: f64, max: f64, step: f64 },
+    /// One of a fixed set of named values.
+    Choice(Vec<(String, f64)>),
+}
+
+impl Setting {
+    pub fn number(key: &str, label: &str, value: f64, min: f64, max: f64, step: f64, unit: Option<&str>) -> Self {
+        Self {
+            key: key.to_owned(),
+            label: label.to_owned(),
+            kind: SettingKind::Number { min, max, step },
+            value,
+            unit: unit.map(str::to_owned),
+        }
+    }
+
+    /// The current value as it would be typed.
+    pub fn text(&self) -> String {
+        self.format(self.value)
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        match &self.kind {
+            SettingKind::Number { step, .. } => {
+                // Enough decimals to show a multiple of step exactly
+                let mut decimals = 0;
+                while decimals < 6 && (step * 10f64.powi(decimals)).fract().abs() > 1e-9 {
+                    decimals += 1;
+                }
+                // Adding zero turns -0 into 0
+                format!("{:.*}", decimals as usize, value + 0.0)
+            }
+            SettingKind::Choice(choices) => choices.iter()
+                .find(|(_, v)| *v == value)
+                .map(|(name, _)| name.clone())
+                .unwrap_or_else(|| value.to_string()),
+        }
+    }
+
+    /// Parses what the user typed, checking it's a value the setting can
+    /// take. Numbers may be decimal or `0x` hex.
+    pub fn parse(&self, text: &str) -> Result<f64, String> {
+        let text = text.trim();
+        match &self.kind {
+            SettingKind::Number { min, max, step } => {
+                let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16).map(|v| v as f64).ok(),
+                    None => text.parse::<f64>().ok().filter(|v| v.is_finite()),
+                };
+                let value = value.ok_or_else(|| format!("\"{}\" isn't a number", text))?;
+                let value = if *step > 0.0 { (value / step).round() * step } else { value };
+                if value < *min || value > *max {
+                    return Err(format!("must be between {} and {}", self.format(*min), self.format(*max)));
+                }
+                Ok(value)
+            }
+            SettingKind::Choice(choices) => choices.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(text))
+                .map(|(_, value)| *value)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = choices.iter().map(|(name, _)| name.as_str()).collect();
+                    format!("must be one of {}", names.join(", "))
+                }),
+        }
+    }
+}
+
+/// The key of the choice setting that switches a fan controller between
+/// automatic and manual fan control.
+pub const FAN_MODE_SETTING: &str = "FAN_MODE";
+
+/// A driver bound to one device on the bus. Drivers are `Send` so the bus
+/// service can hand them between connections.
+pub trait Driver: fmt::Debug + Send {
+    fn addr(&self) -> u8;
+
+    /// Model name shown in the scope pane.
+    fn name(&self) -> String;
+
+    fn kind(&self) -> DeviceKind;
+
+    /// Reads the device's current values.
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError>;
+
+    /// Reads the device's sensors along with their limits. Empty for
+    /// devices that aren't mostly sensors; `read` covers those.
+    fn sensors(&self, _bus: &mut dyn SmbusTransport) -> Result<Vec<SensorReading>, SmbusError> {
+        Ok(Vec::new())
+    }
+
+    /// What identifies this particular part, such as serial numbers and
+    /// manufacturer strings.
+    fn identification(&self, _bus: &mut dyn SmbusTransport) -> Vec<Property> {
+        Vec::new()
+    }
+
+    /// Reads the device's configurable values.
+    fn settings(&self, _bus: &mut dyn SmbusTransport) -> Result<Vec<Setting>, SmbusError> {
+        Ok(Vec::new())
+    }
+
+    /// Reads the device's alarm limits.
+    fn thresholds(&self, _bus: &mut dyn SmbusTransport) -> Result<Vec<Setting>, SmbusError> {
+        Ok(Vec::new())
+    }
+
+    /// Writes one of the values from `settings` or `thresholds`. Pass a
+    /// `GuardedBus` so the write obeys the safety policy.
+    fn write_setting(&self, _bus: &mut dyn SmbusTransport, key: &str, _value: f64) -> Result<(), SmbusError> {
+        Err(SmbusError::Other(format!("{} has no setting \"{}\"", self.name(), key)))
+    }
+
+    /// The device's lighting, for RGB controllers.
+    fn rgb(&self) -> Option<&dyn rgb::RgbDevice> {
+        None
+    }
+}
+
+/// A device driven entirely by its register map.
+#[derive(Debug, Clone)]
+pub struct MappedDevice {
+    pub addr: u8,
+    pub map: RegisterMap,
+}
+
+impl Driver for MappedDevice {
+    fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    fn name(&self) -> String {
+        self.map.name.clone()
+    }
+
+    fn kind(&self) -> DeviceKind {
+        if self.map.registers.iter().any(|r| r.decode.unit.as_deref() == Some("RPM")) {
+            DeviceKind::FanController
+        } else {
+            DeviceKind::Unknown
+        }
+    }
+
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError> {
+        let mut properties = Vec::new();
+        for (name, reading) in self.map.read_all(bus, self.addr) {
+            match reading {
+                Ok(reading) => properties.push(reading.to_property()),
+                Err(e) => log::debug!("{} at {:#04x}: couldn't read {}: {}", self.map.name, self.addr, name, e),
+            }
+        }
+        Ok(properties)
+    }
+
+    fn identification(&self, _bus: &mut dyn SmbusTransport) -> Vec<Property> {
+        let mut properties = Vec::new();
+        if let Some(vendor) = &self.map.vendor {
+            properties.push(Property::text("Vendor", vendor.clone()));
+        }
+        if let Some(description) = &self.map.description {
+            properties.push(Property::text("Description", description.clone()));
+        }
+        properties
+    }
+
+    // Every writable register is a setting, edited as its raw value since
+    // scale formulas can't be inverted
+    fn settings(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Setting>, SmbusError> {
+        let mut settings = Vec::new();
+        for register in self.map.registers.iter().filter(|r| r.access.readable() && r.access.writable()) {
+            let raw = register.read_raw(bus, self.addr)?;
+            let kind = if register.decode.enums.is_empty() {
+                let (min, max) = register.raw_range();
+                SettingKind::Number { min: min as f64, max: max as f64, step: 1.0 }
+            } else {
+                SettingKind::Choice(register.decode.enums.iter()
+                    .map(|(raw, name)| (name.clone(), register.raw_number(*raw) as f64))
+                    .collect())
+            };
+            let (label, unit) = match (&register.decode.scale, &register.decode.unit) {
+                (Some(_), _) => (format!("{} (raw)", register.name), None),
+                (None, unit) => (register.name.clone(), unit.clone()),
+            };
+            settings.push(Setting {
+                key: register.name.clone(),
+                label,
+                kind,
+                value: register.raw_number(raw) as f64,
+                unit,
+            });
+        }
+        Ok(settings)
+    }
+
+    fn write_setting(&self, bus: &mut dyn SmbusTransport, key: &str, value: f64) -> Result<(), SmbusError> {
+        let register = self.map.register(key)
+            .filter(|r| r.access.writable())
+            .ok_or_else(|| SmbusError::Other(format!("{} has no writable register \"{}\"", self.map.name, key)))?;
+        register.write_raw(bus, self.addr, value.round() as i64 as u32)
+    }
+}
+
+/// Finds a driver for the device at `addr`: code drivers that check ID
+/// registers first, then the built-in register maps, then drivers that
+/// can only guess from register contents.
+pub fn identify(bus: &mut dyn SmbusTransport, addr: u8, maps: &[RegisterMap]) -> Option<Box<dyn Driver>> {
+    if sbs::SmartBattery::probe(bus, addr) {
+        return Some(Box::new(sbs::SmartBattery { addr }));
+    }
+    // ENE's 16-byte signature is surer than a temperature sensor's ID
+    // registers at the addresses they share
+    if let Some(controller) = rgb::EneController::detect(bus, addr) {
+        return Some(Box::new(controller));
+    }
+    if let Some(sensor) = temp::TempSensor::detect(bus, addr) {
+        return Some(Box::new(sensor));
+    }
+
+    if let Some(map) = maps.iter().find(|map| map.identifies(bus, addr).unwrap_or(false)) {
+        return Some(Box::new(MappedDevice { addr, map: map.clone() }));
+    }
+    // Anything else at the SPD addresses is an EEPROM, which a guess
+    // from register contents could take for a sensor
+    if let Some(eeprom) = eeprom::Eeprom::detect(bus, addr) {
+        return Some(Box::new(eeprom));
+    }
+
+    temp::TempSensor::detect_heuristic(bus, addr)
+        .map(|sensor| Box::new(sensor) as Box<dyn Driver>)
+}
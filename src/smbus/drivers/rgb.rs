@@ -0,0 +1,372 @@
+// RGB lighting controllers. So far that's ENE's, which ASUS Aura boards
+// and most RGB memory modules use.
+//
+// ENE controllers keep their settings in a 16-bit register space reached
+// through a pointer: a word write to command 0x00 sets the pointer (high
+// byte first), then command 0x01 writes the byte it points at and 0x81
+// reads it. Reading is therefore a write too, so reads fail on a bus that
+// refuses writes.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::smbus::{Property, SmbusError, SmbusTransport, Value};
+
+use super::{DeviceKind, Driver, Setting, SettingKind};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { red: 0, green: 0, blue: 0 };
+
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+/// `#rrggbb` or `rrggbb`.
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let hex = text.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("\"{}\" isn't a colour, expected rrggbb", text));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default();
+        Ok(Self::new(channel(0), channel(2), channel(4)))
+    }
+}
+
+/// One of a controller's built-in effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RgbMode {
+    pub name: &'static str,
+    /// What the controller's mode register holds for it.
+    pub value: u8,
+    pub speed: bool,
+    pub direction: bool,
+    /// Whether the effect uses a colour per LED, set with the mode.
+    pub colors: bool,
+}
+
+/// LEDs that are addressed together, e.g. one memory module's light bar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RgbZone {
+    pub name: String,
+    pub leds: usize,
+}
+
+/// What a controller is showing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RgbState {
+    /// The `value` of the current mode.
+    pub mode: u8,
+    pub speed: u8,
+    pub reverse: bool,
+    /// Whether the LEDs show the colours from `set_leds` instead of the
+    /// mode's.
+    pub direct: bool,
+    /// One per LED, across every zone in order.
+    pub colors: Vec<Color>,
+}
+
+/// What an RGB controller's driver offers besides `Driver`. Writes should
+/// go through a `GuardedBus` so they obey the safety policy.
+pub trait RgbDevice: fmt::Debug + Send {
+    /// Who made the controller, rather than the board or module it's on.
+    fn vendor(&self) -> &'static str;
+
+    fn zones(&self) -> Vec<RgbZone>;
+
+    fn modes(&self) -> &'static [RgbMode];
+
+    /// Speeds from fastest to slowest.
+    fn speeds(&self) -> std::ops::RangeInclusive<u8>;
+
+    fn leds(&self) -> usize {
+        self.zones().iter().map(|zone| zone.leds).sum()
+    }
+
+    fn state(&self, bus: &mut dyn SmbusTransport) -> Result<RgbState, SmbusError>;
+
+    /// Runs the mode whose `value` is `mode`. `colors` are used by modes
+    /// that have them, one per LED; missing ones are black.
+    fn set_mode(&self, bus: &mut dyn SmbusTransport, mode: u8, speed: u8, reverse: bool, colors: &[Color]) -> Result<(), SmbusError>;
+
+    /// Shows `colors`, one per LED, until a mode is set again.
+    fn set_leds(&self, bus: &mut dyn SmbusTransport, colors: &[Color]) -> Result<(), SmbusError>;
+}
+
+pub fn find_mode(modes: &[RgbMode], name: &str) -> Option<RgbMode> {
+    modes.iter().find(|mode| mode.name.eq_ignore_ascii_case(name.trim())).copied()
+}
+
+// Pointer, data and read commands
+const ENE_POINTER: u8 = 0x00;
+const ENE_WRITE: u8 = 0x01;
+const ENE_READ: u8 = 0x81;
+// Read straight, these count up from zero on every ENE part
+const ENE_SIGNATURE: u8 = 0xa0;
+
+const ENE_DEVICE_NAME: u16 = 0x1000;
+const ENE_CONFIG_TABLE: u16 = 0x1c00;
+const ENE_CONFIG_LED_COUNT: u16 = 0x02;
+const ENE_COLORS_DIRECT: u16 = 0x8000;
+const ENE_COLORS_EFFECT: u16 = 0x8010;
+// Parts with more LEDs than fit before the effect colours keep both sets
+// further up
+const ENE_COLORS_DIRECT_V2: u16 = 0x8100;
+const ENE_COLORS_EFFECT_V2: u16 = 0x8160;
+const ENE_DIRECT: u16 = 0x8020;
+const ENE_MODE: u16 = 0x8021;
+const ENE_SPEED: u16 = 0x8022;
+const ENE_DIRECTION: u16 = 0x8023;
+const ENE_APPLY: u16 = 0x80a0;
+const ENE_APPLY_VALUE: u8 = 0x01;
+
+const ENE_V1_LEDS: usize = 5;
+// Longest run of colours either layout has room for
+const ENE_MAX_LEDS: usize = 32;
+
+pub const ENE_MODES: [RgbMode; 14] = [
+    RgbMode { name: "Off", value: 0, speed: false, direction: false, colors: false },
+    RgbMode { name: "Static", value: 1, speed: false, direction: false, colors: true },
+    RgbMode { name: "Breathing", value: 2, speed: true, direction: false, colors: true },
+    RgbMode { name: "Flashing", value: 3, speed: true, direction: false, colors: true },
+    RgbMode { name: "Spectrum Cycle", value: 4, speed: true, direction: true, colors: false },
+    RgbMode { name: "Rainbow", value: 5, speed: true, direction: true, colors: false },
+    RgbMode { name: "Spectrum Cycle Breathing", value: 6, speed: true, direction: true, colors: false },
+    RgbMode { name: "Chase Fade", value: 7, speed: true, direction: true, colors: true },
+    RgbMode { name: "Spectrum Cycle Chase Fade", value: 8, speed: true, direction: true, colors: false },
+    RgbMode { name: "Chase", value: 9, speed: true, direction: true, colors: true },
+    RgbMode { name: "Spectrum Cycle Chase", value: 10, speed: true, direction: true, colors: false },
+    RgbMode { name: "Spectrum Cycle Wave", value: 11, speed: true, direction: true, colors: false },
+    RgbMode { name: "Chase Rainbow Pulse", value: 12, speed: true, direction: true, colors: false },
+    RgbMode { name: "Random Flicker", value: 13, speed: true, direction: false, colors: false },
+];
+
+/// An ENE RGB controller, e.g. on an Aura motherboard or a memory module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EneController {
+    pub addr: u8,
+    /// What the part calls itself, such as `AUDA0-E6K5-0101`.
+    pub device_name: String,
+    pub leds: usize,
+}
+
+impl EneController {
+    /// Memory modules are moved to 0x70-0x77 by the BIOS; boards keep
+    /// theirs at 0x40 or 0x4e-0x4f.
+    pub fn detect(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        if !matches!(addr, 0x40 | 0x4e | 0x4f | 0x70..=0x77) {
+            return None;
+        }
+        for i in 0..16u8 {
+            if bus.read_byte_data(addr, ENE_SIGNATURE + i).ok()? != i {
+                return None;
+            }
+        }
+
+        let mut controller = Self { addr, device_name: String::new(), leds: 0 };
+        let name = controller.read_block(bus, ENE_DEVICE_NAME, 16).ok()?;
+        controller.device_name = String::from_utf8_lossy(&name)
+            .trim_end_matches(['\0', '\u{fffd}', ' '])
+            .to_owned();
+        controller.leds = (controller.read(bus, ENE_CONFIG_TABLE + ENE_CONFIG_LED_COUNT).ok()? as usize).min(ENE_MAX_LEDS);
+        Some(controller)
+    }
+
+    fn read(&self, bus: &mut dyn SmbusTransport, register: u16) -> Result<u8, SmbusError> {
+        bus.write_word_data(self.addr, ENE_POINTER, register.swap_bytes())?;
+        bus.read_byte_data(self.addr, ENE_READ)
+    }
+
+    fn write(&self, bus: &mut dyn SmbusTransport, register: u16, value: u8) -> Result<(), SmbusError> {
+        bus.write_word_data(self.addr, ENE_POINTER, register.swap_bytes())?;
+        bus.write_byte_data(self.addr, ENE_WRITE, value)
+    }
+
+    fn read_block(&self, bus: &mut dyn SmbusTransport, register: u16, len: usize) -> Result<Vec<u8>, SmbusError> {
+        (0..len).map(|i| self.read(bus, register + i as u16)).collect()
+    }
+
+    // One at a time, since not every controller does block writes
+    fn write_block(&self, bus: &mut dyn SmbusTransport, register: u16, data: &[u8]) -> Result<(), SmbusError> {
+        for (i, value) in data.iter().enumerate() {
+            self.write(bus, register + i as u16, *value)?;
+        }
+        Ok(())
+    }
+
+    fn color_registers(&self) -> (u16, u16) {
+        match self.leds > ENE_V1_LEDS {
+            true => (ENE_COLORS_DIRECT_V2, ENE_COLORS_EFFECT_V2),
+            false => (ENE_COLORS_DIRECT, ENE_COLORS_EFFECT),
+        }
+    }
+
+    // ENE stores red, blue, green
+    fn read_colors(&self, bus: &mut dyn SmbusTransport, register: u16) -> Result<Vec<Color>, SmbusError> {
+        let bytes = self.read_block(bus, register, self.leds * 3)?;
+        Ok(bytes.chunks(3).map(|rbg| Color::new(rbg[0], rbg[2], rbg[1])).collect())
+    }
+
+    fn write_colors(&self, bus: &mut dyn SmbusTransport, register: u16, colors: &[Color]) -> Result<(), SmbusError> {
+        let bytes: Vec<u8> = (0..self.leds)
+            .map(|led| colors.get(led).copied().unwrap_or(Color::BLACK))
+            .flat_map(|color| [color.red, color.blue, color.green])
+            .collect();
+        self.write_block(bus, register, &bytes)
+    }
+
+    fn mode_name(mode: u8) -> String {
+        ENE_MODES.iter()
+            .find(|m| m.value == mode)
+            .map(|m| m.name.to_owned())
+            .unwrap_or_else(|| format!("Unknown ({})", mode))
+    }
+}
+
+impl RgbDevice for EneController {
+    fn vendor(&self) -> &'static str {
+        "ENE"
+    }
+
+    fn zones(&self) -> Vec<RgbZone> {
+        vec![RgbZone { name: "LEDs".to_owned(), leds: self.leds }]
+    }
+
+    fn modes(&self) -> &'static [RgbMode] {
+        &ENE_MODES
+    }
+
+    fn speeds(&self) -> std::ops::RangeInclusive<u8> {
+        0..=4
+    }
+
+    fn state(&self, bus: &mut dyn SmbusTransport) -> Result<RgbState, SmbusError> {
+        let direct = self.read(bus, ENE_DIRECT)? != 0;
+        let (direct_colors, effect_colors) = self.color_registers();
+        Ok(RgbState {
+            mode: self.read(bus, ENE_MODE)?,
+            speed: self.read(bus, ENE_SPEED)?,
+            reverse: self.read(bus, ENE_DIRECTION)? != 0,
+            direct,
+            colors: self.read_colors(bus, if direct { direct_colors } else { effect_colors })?,
+        })
+    }
+
+    fn set_mode(&self, bus: &mut dyn SmbusTransport, mode: u8, speed: u8, reverse: bool, colors: &[Color]) -> Result<(), SmbusError> {
+        let mode = ENE_MODES.iter()
+            .find(|m| m.value == mode)
+            .ok_or_else(|| SmbusError::Other(format!("{} has no mode {}", self.device_name, mode)))?;
+        if !self.speeds().contains(&speed) {
+            return Err(SmbusError::Other(format!("speed must be between 0 and {}", self.speeds().end())));
+        }
+        if mode.colors && !colors.is_empty() {
+            self.write_colors(bus, self.color_registers().1, colors)?;
+        }
+        self.write(bus, ENE_MODE, mode.value)?;
+        self.write(bus, ENE_SPEED, speed)?;
+        self.write(bus, ENE_DIRECTION, reverse as u8)?;
+        self.write(bus, ENE_DIRECT, 0)?;
+        self.write(bus, ENE_APPLY, ENE_APPLY_VALUE)
+    }
+
+    fn set_leds(&self, bus: &mut dyn SmbusTransport, colors: &[Color]) -> Result<(), SmbusError> {
+        self.write_colors(bus, self.color_registers().0, colors)?;
+        self.write(bus, ENE_DIRECT, 1)?;
+        self.write(bus, ENE_APPLY, ENE_APPLY_VALUE)
+    }
+}
+
+impl Driver for EneController {
+    fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    fn name(&self) -> String {
+        "ENE RGB controller".to_owned()
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::RgbController
+    }
+
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError> {
+        let state = self.state(bus)?;
+        let colors: Vec<String> = state.colors.iter().map(Color::to_string).collect();
+        Ok(vec![
+            Property::new("Mode", Value::Enum(match state.direct {
+                true => "Direct".to_owned(),
+                false => Self::mode_name(state.mode),
+            }), None),
+            Property::new("Speed", Value::Integer(state.speed.into()), None),
+            Property::new("Direction", Value::Enum(match state.reverse {
+                true => "Reverse".to_owned(),
+                false => "Forward".to_owned(),
+            }), None),
+            Property::new("LEDs", Value::Integer(self.leds as i64), None),
+            Property::text("Colours", colors.join(" ")),
+        ])
+    }
+
+    fn identification(&self, _bus: &mut dyn SmbusTransport) -> Vec<Property> {
+        vec![
+            Property::text("Vendor", "ENE Technology"),
+            Property::text("Device name", self.device_name.clone()),
+        ]
+    }
+
+    // The effect, without its colours; those don't fit a single value
+    fn settings(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Setting>, SmbusError> {
+        let state = self.state(bus)?;
+        let speeds = self.speeds();
+        Ok(vec![
+            Setting {
+                key: "MODE".to_owned(),
+                label: "Mode".to_owned(),
+                kind: SettingKind::Choice(ENE_MODES.iter().map(|m| (m.name.to_owned(), m.value as f64)).collect()),
+                value: state.mode as f64,
+                unit: None,
+            },
+            Setting::number("SPEED", "Speed (0 is fastest)", state.speed as f64, *speeds.start() as f64, *speeds.end() as f64, 1.0, None),
+            Setting {
+                key: "DIRECTION".to_owned(),
+                label: "Direction".to_owned(),
+                kind: SettingKind::Choice(vec![("Forward".to_owned(), 0.0), ("Reverse".to_owned(), 1.0)]),
+                value: state.reverse as u8 as f64,
+                unit: None,
+            },
+        ])
+    }
+
+    fn write_setting(&self, bus: &mut dyn SmbusTransport, key: &str, value: f64) -> Result<(), SmbusError> {
+        let state = self.state(bus)?;
+        let value = value.round().clamp(0.0, 255.0) as u8;
+        match key {
+            "MODE" => self.set_mode(bus, value, state.speed, state.reverse, &state.colors),
+            "SPEED" => self.set_mode(bus, state.mode, value, state.reverse, &state.colors),
+            "DIRECTION" => self.set_mode(bus, state.mode, state.speed, value != 0, &state.colors),
+            _ => Err(SmbusError::Other(format!("{} has no setting \"{}\"", self.name(), key))),
+        }
+    }
+
+    fn rgb(&self) -> Option<&dyn RgbDevice> {
+        Some(self)
+    }
+}
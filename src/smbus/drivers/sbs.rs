@@ -0,0 +1,389 @@
+// Smart Battery System (SBS 1.1) driver.
+
+use std::fmt;
+
+use crate::smbus::{Property, SmbusError, SmbusTransport, Value};
+
+use super::{DeviceKind, Driver};
+
+/// Smart batteries always answer at this address.
+pub const SBS_ADDRESS: u8 = 0x0b;
+
+// SBS 1.1 command codes
+const BATTERY_MODE: u8 = 0x03;
+const TEMPERATURE: u8 = 0x08;
+const VOLTAGE: u8 = 0x09;
+const CURRENT: u8 = 0x0a;
+const AVERAGE_CURRENT: u8 = 0x0b;
+const RELATIVE_STATE_OF_CHARGE: u8 = 0x0d;
+const ABSOLUTE_STATE_OF_CHARGE: u8 = 0x0e;
+const REMAINING_CAPACITY: u8 = 0x0f;
+const FULL_CHARGE_CAPACITY: u8 = 0x10;
+const BATTERY_STATUS: u8 = 0x16;
+const CYCLE_COUNT: u8 = 0x17;
+const DESIGN_CAPACITY: u8 = 0x18;
+const DESIGN_VOLTAGE: u8 = 0x19;
+const SPECIFICATION_INFO: u8 = 0x1a;
+const MANUFACTURE_DATE: u8 = 0x1b;
+const SERIAL_NUMBER: u8 = 0x1c;
+const MANUFACTURER_NAME: u8 = 0x20;
+const DEVICE_NAME: u8 = 0x21;
+const DEVICE_CHEMISTRY: u8 = 0x22;
+
+// BatteryMode bit that switches capacities from mAh to 10 mWh
+const CAPACITY_MODE: u16 = 1 << 15;
+
+/// The BatteryStatus word: alarm and status flags plus an error code in the
+/// low nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus(pub u16);
+
+impl BatteryStatus {
+    const FLAGS: [(u16, &'static str); 10] = [
+        (1 << 15, "Over charged alarm"),
+        (1 << 14, "Terminate charge alarm"),
+        (1 << 12, "Over temperature alarm"),
+        (1 << 11, "Terminate discharge alarm"),
+        (1 << 9, "Remaining capacity alarm"),
+        (1 << 8, "Remaining time alarm"),
+        (1 << 7, "Initialized"),
+        (1 << 6, "Discharging"),
+        (1 << 5, "Fully charged"),
+        (1 << 4, "Fully discharged"),
+    ];
+
+    pub fn flags(self) -> Vec<&'static str> {
+        Self::FLAGS.iter()
+            .filter(|(bit, _)| self.0 & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    pub fn has_alarm(self) -> bool {
+        self.0 & 0xdb00 != 0
+    }
+
+    pub fn error(self) -> &'static str {
+        match self.0 & 0x000f {
+            0 => "OK",
+            1 => "Busy",
+            2 => "Reserved command",
+            3 => "Unsupported command",
+            4 => "Access denied",
+            5 => "Overflow/underflow",
+            6 => "Bad size",
+            _ => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Display for BatteryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = self.flags();
+        if flags.is_empty() {
+            write!(f, "{:#06x}", self.0)
+        } else {
+            write!(f, "{}", flags.join(", "))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityUnit {
+    MilliampHours,
+    /// Capacities are reported in units of 10 mWh.
+    TenMilliwattHours,
+}
+
+/// Everything we read from a smart battery in one pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryInfo {
+    /// The strings are `None` when the controller can't do SMBus block
+    /// reads.
+    pub manufacturer: Option<String>,
+    pub device_name: Option<String>,
+    pub chemistry: Option<String>,
+    pub serial_number: u16,
+    /// (year, month, day)
+    pub manufacture_date: (u16, u8, u8),
+    pub voltage_mv: u16,
+    pub current_ma: i16,
+    pub average_current_ma: i16,
+    /// Tenths of a kelvin, as reported
+    pub temperature_dk: u16,
+    pub relative_state_of_charge: u8,
+    pub absolute_state_of_charge: u8,
+    pub capacity_unit: CapacityUnit,
+    pub remaining_capacity: u16,
+    pub full_charge_capacity: u16,
+    pub design_capacity: u16,
+    pub design_voltage_mv: u16,
+    pub cycle_count: u16,
+    pub status: BatteryStatus,
+}
+
+impl BatteryInfo {
+    pub fn temperature_celsius(&self) -> f64 {
+        f64::from(self.temperature_dk) / 10.0 - 273.15
+    }
+
+    /// Full charge capacity as a percentage of design capacity.
+    pub fn health(&self) -> Option<f64> {
+        if self.design_capacity == 0 {
+            return None;
+        }
+        Some(f64::from(self.full_charge_capacity) * 100.0 / f64::from(self.design_capacity))
+    }
+
+    fn capacity(&self, raw: u16) -> (Value, &'static str) {
+        match self.capacity_unit {
+            CapacityUnit::MilliampHours => (Value::Integer(i64::from(raw)), "mAh"),
+            CapacityUnit::TenMilliwattHours => (Value::Integer(i64::from(raw) * 10), "mWh"),
+        }
+    }
+
+    // The strings that could be read
+    fn strings(&self) -> Vec<Property> {
+        [("Manufacturer", &self.manufacturer), ("Device name", &self.device_name), ("Chemistry", &self.chemistry)]
+            .into_iter()
+            .filter_map(|(name, text)| Some(Property::text(name, text.clone()?)))
+            .collect()
+    }
+
+    pub fn properties(&self) -> Vec<Property> {
+        let capacity = |name: &str, raw: u16| {
+            let (value, unit) = self.capacity(raw);
+            Property::new(name, value, Some(unit))
+        };
+        let (year, month, day) = self.manufacture_date;
+
+        let mut properties = self.strings();
+        properties.extend([
+            Property::text("Serial number", format!("{:04X}", self.serial_number)),
+            Property::text("Manufacture date", format!("{:04}-{:02}-{:02}", year, month, day)),
+            Property::new("Voltage", Value::Scaled(f64::from(self.voltage_mv) / 1000.0), Some("V")),
+            Property::new("Current", Value::Integer(i64::from(self.current_ma)), Some("mA")),
+            Property::new("Average current", Value::Integer(i64::from(self.average_current_ma)), Some("mA")),
+            Property::new("Temperature", Value::Scaled(self.temperature_celsius()), Some("°C")),
+            Property::new("Relative state of charge", Value::Integer(i64::from(self.relative_state_of_charge)), Some("%")),
+            Property::new("Absolute state of charge", Value::Integer(i64::from(self.absolute_state_of_charge)), Some("%")),
+            capacity("Remaining capacity", self.remaining_capacity),
+            capacity("Full charge capacity", self.full_charge_capacity),
+            capacity("Design capacity", self.design_capacity),
+            Property::new("Design voltage", Value::Scaled(f64::from(self.design_voltage_mv) / 1000.0), Some("V")),
+            Property::new("Cycle count", Value::Integer(i64::from(self.cycle_count)), None),
+            Property::text("Status", self.status.to_string()),
+            Property::text("Error code", self.status.error()),
+        ]);
+
+        if let Some(health) = self.health() {
+            properties.push(Property::new("Health", Value::Scaled(health), Some("%")));
+        }
+        properties
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SmartBattery {
+    pub addr: u8,
+}
+
+impl Default for SmartBattery {
+    fn default() -> Self {
+        Self { addr: SBS_ADDRESS }
+    }
+}
+
+impl SmartBattery {
+    /// A battery must answer SpecificationInfo with a known SBS revision.
+    pub fn probe(bus: &mut dyn SmbusTransport, addr: u8) -> bool {
+        if addr != SBS_ADDRESS {
+            return false;
+        }
+        match bus.read_word_data(addr, SPECIFICATION_INFO) {
+            // Revision field, bits 3:0: 1 is SBS 1.0/1.1
+            Ok(info) => info & 0x000f == 1,
+            Err(_) => false,
+        }
+    }
+
+    fn word(&self, bus: &mut dyn SmbusTransport, command: u8) -> Result<u16, SmbusError> {
+        bus.read_word_data(self.addr, command)
+    }
+
+    // Strings only come as SMBus block reads. Every byte of one is behind
+    // the same command, so there's nothing to fall back to on controllers
+    // without them, and those get `None`.
+    fn string(&self, bus: &mut dyn SmbusTransport, command: u8) -> Result<Option<String>, SmbusError> {
+        let bytes = match bus.read_block_data(self.addr, command) {
+            Ok(bytes) => bytes,
+            Err(SmbusError::Unsupported(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let text: String = bytes.iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+            .collect();
+        Ok(Some(text.trim().to_owned()))
+    }
+
+    pub fn read_info(&self, bus: &mut dyn SmbusTransport) -> Result<BatteryInfo, SmbusError> {
+        let mode = self.word(bus, BATTERY_MODE)?;
+        let date = self.word(bus, MANUFACTURE_DATE)?;
+
+        Ok(BatteryInfo {
+            manufacturer: self.string(bus, MANUFACTURER_NAME)?,
+            device_name: self.string(bus, DEVICE_NAME)?,
+            chemistry: self.string(bus, DEVICE_CHEMISTRY)?,
+            serial_number: self.word(bus, SERIAL_NUMBER)?,
+            // (year - 1980) * 512 + month * 32 + day
+            manufacture_date: (1980 + (date >> 9), ((date >> 5) & 0x0f) as u8, (date & 0x1f) as u8),
+            voltage_mv: self.word(bus, VOLTAGE)?,
+            current_ma: self.word(bus, CURRENT)? as i16,
+            average_current_ma: self.word(bus, AVERAGE_CURRENT)? as i16,
+            temperature_dk: self.word(bus, TEMPERATURE)?,
+            relative_state_of_charge: self.word(bus, RELATIVE_STATE_OF_CHARGE)? as u8,
+            absolute_state_of_charge: self.word(bus, ABSOLUTE_STATE_OF_CHARGE)? as u8,
+            capacity_unit: if mode & CAPACITY_MODE != 0 {
+                CapacityUnit::TenMilliwattHours
+            } else {
+                CapacityUnit::MilliampHours
+            },
+            remaining_capacity: self.word(bus, REMAINING_CAPACITY)?,
+            full_charge_capacity: self.word(bus, FULL_CHARGE_CAPACITY)?,
+            design_capacity: self.word(bus, DESIGN_CAPACITY)?,
+            design_voltage_mv: self.word(bus, DESIGN_VOLTAGE)?,
+            cycle_count: self.word(bus, CYCLE_COUNT)?,
+            status: BatteryStatus(self.word(bus, BATTERY_STATUS)?),
+        })
+    }
+}
+
+impl Driver for SmartBattery {
+    fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    fn name(&self) -> String {
+        "Smart Battery".to_owned()
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Battery
+    }
+
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError> {
+        self.read_info(bus).map(|info| info.properties())
+    }
+
+    fn identification(&self, bus: &mut dyn SmbusTransport) -> Vec<Property> {
+        match self.read_info(bus) {
+            Ok(info) => {
+                let (year, month, day) = info.manufacture_date;
+                let mut properties = info.strings();
+                properties.extend([
+                    Property::text("Serial number", format!("{:04X}", info.serial_number)),
+                    Property::text("Manufacture date", format!("{:04}-{:02}-{:02}", year, month, day)),
+                ]);
+                properties
+            }
+            Err(e) => {
+                log::debug!("Smart battery at {:#04x}: couldn't read identification: {}", self.addr, e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // A battery that answers word commands, and block commands if the
+    // controller can do block reads
+    #[derive(Debug)]
+    struct Battery {
+        words: HashMap<u8, u16>,
+        strings: HashMap<u8, &'static [u8]>,
+        block_reads: bool,
+    }
+
+    impl Battery {
+        fn new(block_reads: bool) -> Self {
+            let words = HashMap::from([
+                (BATTERY_MODE, 0),
+                (VOLTAGE, 12_300),
+                (CURRENT, (-850i16) as u16),
+                (RELATIVE_STATE_OF_CHARGE, 80),
+                (DESIGN_CAPACITY, 5000),
+                (FULL_CHARGE_CAPACITY, 4500),
+                (SPECIFICATION_INFO, 0x0031),
+            ]);
+            let strings = HashMap::from([
+                (MANUFACTURER_NAME, &b"ACME\0"[..]),
+                (DEVICE_NAME, &b"LP-42"[..]),
+                (DEVICE_CHEMISTRY, &b"LION"[..]),
+            ]);
+            Self { words, strings, block_reads }
+        }
+    }
+
+    impl SmbusTransport for Battery {
+        // Byte reads of the commands after a string's command would be other
+        // registers, which is what a byte-by-byte fallback got wrong
+        fn read_byte_data(&mut self, _addr: u8, command: u8) -> Result<u8, SmbusError> {
+            Ok(self.words.get(&command).copied().unwrap_or(0) as u8)
+        }
+
+        fn write_byte_data(&mut self, _addr: u8, _command: u8, _value: u8) -> Result<(), SmbusError> {
+            Err(SmbusError::Unsupported("byte write"))
+        }
+
+        fn read_word_data(&mut self, _addr: u8, command: u8) -> Result<u16, SmbusError> {
+            Ok(self.words.get(&command).copied().unwrap_or(0))
+        }
+
+        fn write_word_data(&mut self, _addr: u8, _command: u8, _value: u16) -> Result<(), SmbusError> {
+            Err(SmbusError::Unsupported("word write"))
+        }
+
+        fn read_block_data(&mut self, _addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+            match self.block_reads {
+                true => Ok(self.strings.get(&command).map(|s| s.to_vec()).unwrap_or_default()),
+                false => Err(SmbusError::Unsupported("block read")),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_strings_with_block_reads() {
+        let mut bus = Battery::new(true);
+        assert!(SmartBattery::probe(&mut bus, SBS_ADDRESS));
+        let info = SmartBattery::default().read_info(&mut bus).unwrap();
+
+        assert_eq!(info.manufacturer.as_deref(), Some("ACME"));
+        assert_eq!(info.device_name.as_deref(), Some("LP-42"));
+        assert_eq!(info.chemistry.as_deref(), Some("LION"));
+        assert_eq!(info.current_ma, -850);
+        assert_eq!(info.health(), Some(90.0));
+    }
+
+    #[test]
+    fn leaves_out_strings_without_block_reads() {
+        let mut bus = Battery::new(false);
+        let battery = SmartBattery::default();
+        let info = battery.read_info(&mut bus).unwrap();
+
+        assert_eq!(info.manufacturer, None);
+        assert_eq!(info.chemistry, None);
+        assert_eq!(info.voltage_mv, 12_300);
+
+        let properties = battery.read(&mut bus).unwrap();
+        assert!(properties.iter().all(|property| property.name != "Manufacturer"));
+        assert!(properties.iter().any(|property| property.name == "Voltage"));
+        assert!(battery.identification(&mut bus).iter().any(|property| property.name == "Serial number"));
+    }
+}
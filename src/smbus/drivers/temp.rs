@@ -0,0 +1,648 @@
+// Classic SMBus temperature sensors: LM75/LM75A, TMP102, LM63 and the
+// ADM1021/MAX1617 remote-diode family.
+
+use std::fmt;
+
+use crate::smbus::{Property, SmbusError, SmbusTransport, Value};
+
+use super::{DeviceKind, Driver, SensorReading, Setting};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempModel {
+    Lm75,
+    Lm75a,
+    Tmp102,
+    Lm63,
+    Adm1021,
+    Adm1023,
+    Max1617,
+    Max1617a,
+    Thmc10,
+}
+
+impl TempModel {
+    pub fn name(self) -> &'static str {
+        match self {
+            TempModel::Lm75 => "LM75",
+            TempModel::Lm75a => "LM75A",
+            TempModel::Tmp102 => "TMP102",
+            TempModel::Lm63 => "LM63",
+            TempModel::Adm1021 => "ADM1021",
+            TempModel::Adm1023 => "ADM1023",
+            TempModel::Max1617 => "MAX1617",
+            TempModel::Max1617a => "MAX1617A",
+            TempModel::Thmc10 => "THMC10",
+        }
+    }
+
+    pub fn channels(self) -> &'static [Channel] {
+        match self {
+            TempModel::Lm75 | TempModel::Lm75a | TempModel::Tmp102 => &[Channel::Local],
+            _ => &[Channel::Local, Channel::Remote],
+        }
+    }
+
+    pub fn limits(self) -> &'static [Limit] {
+        match self {
+            TempModel::Lm75 | TempModel::Lm75a => &[Limit::High(Channel::Local), Limit::Hysteresis],
+            TempModel::Tmp102 => &[Limit::High(Channel::Local), Limit::Low(Channel::Local)],
+            TempModel::Lm63 => &[
+                Limit::High(Channel::Local),
+                Limit::High(Channel::Remote),
+                Limit::Low(Channel::Remote),
+                Limit::Critical(Channel::Remote),
+            ],
+            _ => &[
+                Limit::High(Channel::Local),
+                Limit::Low(Channel::Local),
+                Limit::High(Channel::Remote),
+                Limit::Low(Channel::Remote),
+            ],
+        }
+    }
+
+    fn is_lm75_style(self) -> bool {
+        matches!(self, TempModel::Lm75 | TempModel::Lm75a | TempModel::Tmp102)
+    }
+}
+
+impl fmt::Display for TempModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Local,
+    /// External diode
+    Remote,
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Local => "Local",
+            Channel::Remote => "Remote",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    High(Channel),
+    Low(Channel),
+    Critical(Channel),
+    /// LM75 THYST: the over-temperature output releases below this
+    Hysteresis,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::High(channel) => write!(f, "{} high limit", channel),
+            Limit::Low(channel) => write!(f, "{} low limit", channel),
+            Limit::Critical(channel) => write!(f, "{} critical limit", channel),
+            Limit::Hysteresis => write!(f, "Hysteresis"),
+        }
+    }
+}
+
+// Register encodings
+
+/// Decodes a left-justified two's complement temperature (LM75, TMP102,
+/// and the MSB/LSB pairs of remote channels) with `bits` of resolution.
+/// `word` is in register order, MSB first.
+pub fn decode_left_justified(word: u16, bits: u32) -> f64 {
+    let masked = word & !((1u16 << (16 - bits)) - 1);
+    f64::from(masked as i16) / 256.0
+}
+
+pub fn encode_left_justified(celsius: f64, bits: u32) -> u16 {
+    let raw = (celsius * 256.0).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16 as u16;
+    raw & !((1u16 << (16 - bits)) - 1)
+}
+
+pub fn decode_byte(raw: u8) -> f64 {
+    f64::from(raw as i8)
+}
+
+pub fn encode_byte(celsius: f64) -> u8 {
+    celsius.round().clamp(-128.0, 127.0) as i8 as u8
+}
+
+// ADM1021 family: separate read and write addresses for config and limits
+const ADM1021_LOCAL_TEMP: u8 = 0x00;
+const ADM1021_REMOTE_TEMP: u8 = 0x01;
+const ADM1021_STATUS: u8 = 0x02;
+const ADM1021_CONFIG_R: u8 = 0x03;
+const ADM1021_REMOTE_FRACTION: u8 = 0x10;
+const ADM1021_MAN_ID: u8 = 0xfe;
+const ADM1021_DEV_ID: u8 = 0xff;
+
+fn adm1021_limit_registers(limit: Limit) -> Option<(u8, u8)> {
+    match limit {
+        Limit::High(Channel::Local) => Some((0x05, 0x0b)),
+        Limit::Low(Channel::Local) => Some((0x06, 0x0c)),
+        Limit::High(Channel::Remote) => Some((0x07, 0x0d)),
+        Limit::Low(Channel::Remote) => Some((0x08, 0x0e)),
+        _ => None,
+    }
+}
+
+// LM63: remote values are MSB/LSB pairs, same address for read and write
+fn lm63_limit_registers(limit: Limit) -> Option<(u8, Option<u8>)> {
+    match limit {
+        Limit::High(Channel::Local) => Some((0x05, None)),
+        Limit::High(Channel::Remote) => Some((0x07, Some(0x13))),
+        Limit::Low(Channel::Remote) => Some((0x08, Some(0x14))),
+        Limit::Critical(Channel::Remote) => Some((0x19, None)),
+        _ => None,
+    }
+}
+
+// LM75 and TMP102
+const LM75_TEMP: u8 = 0x00;
+const LM75_CONFIG: u8 = 0x01;
+const LM75_THYST: u8 = 0x02;
+const LM75_TOS: u8 = 0x03;
+const LM75A_ID: u8 = 0x07;
+
+// TMP102 extended mode (13-bit) bit in the configuration word
+const TMP102_EXTENDED: u16 = 1 << 4;
+
+fn unsupported_limit(limit: Limit) -> SmbusError {
+    SmbusError::Other(format!("{} isn't available on this sensor", limit))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TempSensor {
+    pub addr: u8,
+    pub model: TempModel,
+}
+
+impl TempSensor {
+    pub fn new(addr: u8, model: TempModel) -> Self {
+        Self { addr, model }
+    }
+
+    /// Identifies parts that have ID registers (LM63, ADM1021, MAX1617A,
+    /// THMC10).
+    pub fn detect(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        if !matches!(addr, 0x18..=0x1a | 0x29..=0x2b | 0x4c..=0x4e) {
+            return None;
+        }
+
+        let man_id = bus.read_byte_data(addr, ADM1021_MAN_ID).ok()?;
+        let dev_id = bus.read_byte_data(addr, ADM1021_DEV_ID).ok()?;
+
+        let model = match (man_id, dev_id) {
+            (0x01, 0x41) if addr == 0x4c => TempModel::Lm63,
+            (0x41, id) if id & 0xf0 == 0x30 => TempModel::Adm1023,
+            (0x41, _) => TempModel::Adm1021,
+            (0x4d, 0x01) => TempModel::Max1617a,
+            (0x49, _) => TempModel::Thmc10,
+            _ => return None,
+        };
+        Some(Self { addr, model })
+    }
+
+    /// Guesses at parts without ID registers from their reserved bits.
+    /// Run this after every other identification has failed, since plenty
+    /// of chips at these addresses look similar.
+    pub fn detect_heuristic(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        if matches!(addr, 0x18..=0x1a | 0x29..=0x2b | 0x4c..=0x4e) {
+            if let Some(sensor) = Self::detect_max1617(bus, addr) {
+                return Some(sensor);
+            }
+        }
+        if matches!(addr, 0x48..=0x4f) {
+            return Self::detect_lm75(bus, addr);
+        }
+        None
+    }
+
+    fn detect_max1617(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        // Reserved config and status bits are zero and the local
+        // temperature is plausible
+        let config = bus.read_byte_data(addr, ADM1021_CONFIG_R).ok()?;
+        let status = bus.read_byte_data(addr, ADM1021_STATUS).ok()?;
+        let local = decode_byte(bus.read_byte_data(addr, ADM1021_LOCAL_TEMP).ok()?);
+        if config & 0x3f != 0 || status & 0x03 != 0 || !(-40.0..=125.0).contains(&local) {
+            return None;
+        }
+        Some(Self { addr, model: TempModel::Max1617 })
+    }
+
+    fn detect_lm75(bus: &mut dyn SmbusTransport, addr: u8) -> Option<Self> {
+        // TMP102 has a 16-bit config with the read-only resolution bits set
+        // and the low nibble clear
+        let config = bus.read_word_data(addr, LM75_CONFIG).ok()?.swap_bytes();
+        if config & 0x6000 == 0x6000 && config & 0x000f == 0 {
+            return Some(Self { addr, model: TempModel::Tmp102 });
+        }
+
+        // LM75 config bits 7:5 are reserved and read as zero
+        let config = bus.read_byte_data(addr, LM75_CONFIG).ok()?;
+        if config & 0xe0 != 0 {
+            return None;
+        }
+        let model = match bus.read_byte_data(addr, LM75A_ID) {
+            Ok(0xa1) => TempModel::Lm75a,
+            _ => TempModel::Lm75,
+        };
+        Some(Self { addr, model })
+    }
+
+    fn read_be_word(&self, bus: &mut dyn SmbusTransport, register: u8) -> Result<u16, SmbusError> {
+        bus.read_word_data(self.addr, register).map(u16::swap_bytes)
+    }
+
+    fn write_be_word(&self, bus: &mut dyn SmbusTransport, register: u8, value: u16) -> Result<(), SmbusError> {
+        bus.write_word_data(self.addr, register, value.swap_bytes())
+    }
+
+    // Resolution of LM75-style registers, in bits
+    fn lm75_bits(&self, bus: &mut dyn SmbusTransport) -> Result<u32, SmbusError> {
+        Ok(match self.model {
+            TempModel::Lm75a => 11,
+            TempModel::Tmp102 => {
+                let config = self.read_be_word(bus, LM75_CONFIG)?;
+                if config & TMP102_EXTENDED != 0 { 13 } else { 12 }
+            }
+            _ => 9,
+        })
+    }
+
+    pub fn temperature(&self, bus: &mut dyn SmbusTransport, channel: Channel) -> Result<f64, SmbusError> {
+        if !self.model.channels().contains(&channel) {
+            return Err(SmbusError::Other(format!("{} has no {} channel", self.model, channel)));
+        }
+
+        match (self.model, channel) {
+            (TempModel::Tmp102, _) => {
+                let raw = self.read_be_word(bus, LM75_TEMP)?;
+                if self.lm75_bits(bus)? == 13 {
+                    // Extended mode is right-justified one bit further
+                    Ok(f64::from((raw as i16) >> 3) * 0.0625)
+                } else {
+                    Ok(decode_left_justified(raw, 12))
+                }
+            }
+            (model, _) if model.is_lm75_style() => {
+                let bits = self.lm75_bits(bus)?;
+                Ok(decode_left_justified(self.read_be_word(bus, LM75_TEMP)?, bits))
+            }
+            (_, Channel::Local) => Ok(decode_byte(bus.read_byte_data(self.addr, ADM1021_LOCAL_TEMP)?)),
+            (TempModel::Lm63, Channel::Remote) | (TempModel::Adm1023, Channel::Remote) => {
+                let msb = bus.read_byte_data(self.addr, ADM1021_REMOTE_TEMP)?;
+                let lsb = bus.read_byte_data(self.addr, ADM1021_REMOTE_FRACTION)?;
+                Ok(decode_left_justified(u16::from_be_bytes([msb, lsb]), 11))
+            }
+            (_, Channel::Remote) => Ok(decode_byte(bus.read_byte_data(self.addr, ADM1021_REMOTE_TEMP)?)),
+        }
+    }
+
+    pub fn limit(&self, bus: &mut dyn SmbusTransport, limit: Limit) -> Result<f64, SmbusError> {
+        match self.model {
+            TempModel::Lm75 | TempModel::Lm75a | TempModel::Tmp102 => {
+                let register = match limit {
+                    Limit::High(Channel::Local) => LM75_TOS,
+                    Limit::Hysteresis | Limit::Low(Channel::Local) => LM75_THYST,
+                    _ => return Err(unsupported_limit(limit)),
+                };
+                // Limits keep the base resolution even on LM75A
+                let bits = if self.model == TempModel::Tmp102 { self.lm75_bits(bus)? } else { 9 };
+                let raw = self.read_be_word(bus, register)?;
+                if bits == 13 {
+                    Ok(f64::from((raw as i16) >> 3) * 0.0625)
+                } else {
+                    Ok(decode_left_justified(raw, bits))
+                }
+            }
+            TempModel::Lm63 => {
+                let (msb, lsb) = lm63_limit_registers(limit).ok_or_else(|| unsupported_limit(limit))?;
+                let msb = bus.read_byte_data(self.addr, msb)?;
+                match lsb {
+                    Some(lsb) => {
+                        let lsb = bus.read_byte_data(self.addr, lsb)?;
+                        Ok(decode_left_justified(u16::from_be_bytes([msb, lsb]), 11))
+                    }
+                    None => Ok(decode_byte(msb)),
+                }
+            }
+            _ => {
+                let (read, _) = adm1021_limit_registers(limit).ok_or_else(|| unsupported_limit(limit))?;
+                Ok(decode_byte(bus.read_byte_data(self.addr, read)?))
+            }
+        }
+    }
+
+    /// The resolution a limit is programmed with, in °C.
+    pub fn limit_step(&self, limit: Limit) -> f64 {
+        match self.model {
+            TempModel::Lm75 | TempModel::Lm75a => 0.5,
+            TempModel::Tmp102 => 0.0625,
+            TempModel::Lm63 => match lm63_limit_registers(limit) {
+                Some((_, Some(_))) => 0.125,
+                _ => 1.0,
+            },
+            _ => 1.0,
+        }
+    }
+
+    /// Programs a limit. Pass a `GuardedBus` so the write obeys the safety
+    /// policy.
+    pub fn set_limit(&self, bus: &mut dyn SmbusTransport, limit: Limit, celsius: f64) -> Result<(), SmbusError> {
+        match self.model {
+            TempModel::Lm75 | TempModel::Lm75a | TempModel::Tmp102 => {
+                let register = match limit {
+                    Limit::High(Channel::Local) => LM75_TOS,
+                    Limit::Hysteresis | Limit::Low(Channel::Local) => LM75_THYST,
+                    _ => return Err(unsupported_limit(limit)),
+                };
+                let bits = if self.model == TempModel::Tmp102 { self.lm75_bits(bus)? } else { 9 };
+                let raw = if bits == 13 {
+                    ((((celsius / 0.0625).round() as i16) << 3) as u16) & 0xfff8
+                } else {
+                    encode_left_justified(celsius, bits)
+                };
+                self.write_be_word(bus, register, raw)
+            }
+            TempModel::Lm63 => {
+                let (msb, lsb) = lm63_limit_registers(limit).ok_or_else(|| unsupported_limit(limit))?;
+                match lsb {
+                    Some(lsb) => {
+                        let [hi, lo] = encode_left_justified(celsius, 11).to_be_bytes();
+                        bus.write_byte_data(self.addr, msb, hi)?;
+                        bus.write_byte_data(self.addr, lsb, lo)
+                    }
+                    None => bus.write_byte_data(self.addr, msb, encode_byte(celsius)),
+                }
+            }
+            _ => {
+                let (_, write) = adm1021_limit_registers(limit).ok_or_else(|| unsupported_limit(limit))?;
+                bus.write_byte_data(self.addr, write, encode_byte(celsius))
+            }
+        }
+    }
+}
+
+impl Driver for TempSensor {
+    fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    fn name(&self) -> String {
+        self.model.name().to_owned()
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::TemperatureSensor
+    }
+
+    fn read(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Property>, SmbusError> {
+        let mut properties = Vec::new();
+        for &channel in self.model.channels() {
+            let celsius = self.temperature(bus, channel)?;
+            properties.push(Property::new(&format!("{} temperature", channel), Value::Scaled(celsius), Some("°C")));
+        }
+        for &limit in self.model.limits() {
+            match self.limit(bus, limit) {
+                Ok(celsius) => properties.push(Property::new(&limit.to_string(), Value::Scaled(celsius), Some("°C"))),
+                Err(e) => log::debug!("{} at {:#04x}: couldn't read {}: {}", self.model, self.addr, limit, e),
+            }
+        }
+        Ok(properties)
+    }
+
+    fn sensors(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<SensorReading>, SmbusError> {
+        let mut sensors = Vec::new();
+        for &channel in self.model.channels() {
+            let celsius = self.temperature(bus, channel)?;
+            // A limit that can't be read just isn't checked
+            let mut limit = |limit: Limit| match self.model.limits().contains(&limit) {
+                true => self.limit(bus, limit).ok(),
+                false => None,
+            };
+            let min = limit(Limit::Low(channel));
+            let max = limit(Limit::High(channel));
+            let critical = limit(Limit::Critical(channel));
+            sensors.push(SensorReading {
+                name: format!("{} temperature", channel),
+                value: Value::Scaled(celsius),
+                unit: Some("°C".to_owned()),
+                min,
+                max,
+                critical,
+            });
+        }
+        Ok(sensors)
+    }
+
+    fn thresholds(&self, bus: &mut dyn SmbusTransport) -> Result<Vec<Setting>, SmbusError> {
+        let mut thresholds = Vec::new();
+        for &limit in self.model.limits() {
+            match self.limit(bus, limit) {
+                Ok(celsius) => {
+                    let name = limit.to_string();
+                    thresholds.push(Setting::number(&name, &name, celsius, -128.0, 127.0, self.limit_step(limit), Some("°C")));
+                }
+                Err(e) => log::debug!("{} at {:#04x}: couldn't read {}: {}", self.model, self.addr, limit, e),
+            }
+        }
+        Ok(thresholds)
+    }
+
+    fn write_setting(&self, bus: &mut dyn SmbusTransport, key: &str, value: f64) -> Result<(), SmbusError> {
+        let limit = self.model.limits().iter()
+            .find(|limit| limit.to_string() == key)
+            .ok_or_else(|| SmbusError::Other(format!("{} has no limit \"{}\"", self.model, key)))?;
+        self.set_limit(bus, *limit, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // A sensor at any address with byte registers and 16-bit ones, the
+    // latter kept in register order (MSB first)
+    #[derive(Debug, Default)]
+    struct Registers {
+        bytes: HashMap<u8, u8>,
+        words: HashMap<u8, u16>,
+    }
+
+    impl Registers {
+        fn with_bytes(bytes: &[(u8, u8)]) -> Self {
+            Self { bytes: bytes.iter().copied().collect(), words: HashMap::new() }
+        }
+
+        fn with_words(words: &[(u8, u16)]) -> Self {
+            Self { bytes: HashMap::new(), words: words.iter().copied().collect() }
+        }
+    }
+
+    impl SmbusTransport for Registers {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            self.bytes.get(&command).copied().ok_or(SmbusError::Nack { addr })
+        }
+
+        fn write_byte_data(&mut self, _addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            self.bytes.insert(command, value);
+            Ok(())
+        }
+
+        // SMBus sends the low byte first, and these parts send the MSB first
+        fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+            self.words.get(&command).map(|word| word.swap_bytes()).ok_or(SmbusError::Nack { addr })
+        }
+
+        fn write_word_data(&mut self, _addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+            self.words.insert(command, value.swap_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn left_justified_values_keep_their_sign() {
+        for (word, bits, celsius) in [
+            (0xff80, 9, -0.5),
+            (0xe700, 9, -25.0),
+            (0x7f80, 9, 127.5),
+            (0xff20, 11, -0.875),
+            (0xc900, 11, -55.0),
+            (0xe6f0, 12, -25.0625),
+            (0xfff0, 12, -0.0625),
+            (0xfff8, 13, -0.03125),
+            (0x8000, 13, -128.0),
+        ] {
+            assert_eq!(decode_left_justified(word, bits), celsius, "{:#06x} in {} bits", word, bits);
+            assert_eq!(encode_left_justified(celsius, bits), word, "{} °C in {} bits", celsius, bits);
+        }
+    }
+
+    #[test]
+    fn bits_below_the_resolution_are_dropped() {
+        // The LM75's 9 bits ignore what a later part puts below them
+        assert_eq!(decode_left_justified(0xff8f, 9), -0.5);
+        assert_eq!(decode_left_justified(0x190f, 11), 25.0);
+        // Values between steps round down, and out-of-range ones clamp
+        assert_eq!(encode_left_justified(-0.3, 9), 0xff80);
+        assert_eq!(encode_left_justified(0.3, 9), 0x0000);
+        assert_eq!(encode_left_justified(200.0, 9), 0x7f80);
+        assert_eq!(encode_left_justified(-200.0, 12), 0x8000);
+        assert_eq!(decode_byte(0xe7), -25.0);
+        assert_eq!(encode_byte(-25.4), 0xe7);
+        assert_eq!((encode_byte(300.0), encode_byte(-300.0)), (0x7f, 0x80));
+    }
+
+    #[test]
+    fn tmp102_extended_mode_has_13_bits() {
+        // -40 °C as 13 bits is what 12 bits would take for -20 °C
+        let mut bus = Registers::with_words(&[(LM75_TEMP, 0xec00), (LM75_CONFIG, 0x60a0), (LM75_TOS, 0x5000)]);
+        let sensor = TempSensor::new(0x48, TempModel::Tmp102);
+        assert_eq!(sensor.temperature(&mut bus, Channel::Local), Ok(-20.0));
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Local)), Ok(80.0));
+
+        bus.words.insert(LM75_CONFIG, 0x60a0 | TMP102_EXTENDED);
+        assert_eq!(sensor.temperature(&mut bus, Channel::Local), Ok(-40.0));
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Local)), Ok(160.0));
+        sensor.set_limit(&mut bus, Limit::High(Channel::Local), 150.0625).unwrap();
+        assert_eq!(bus.words[&LM75_TOS], 0x4b08);
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Local)), Ok(150.0625));
+        sensor.set_limit(&mut bus, Limit::Low(Channel::Local), -40.0).unwrap();
+        assert_eq!(bus.words[&LM75_THYST], 0xec00);
+    }
+
+    #[test]
+    fn lm63_remote_values_are_msb_lsb_pairs() {
+        let mut bus = Registers::with_bytes(&[
+            (ADM1021_LOCAL_TEMP, 0x2a),
+            (ADM1021_REMOTE_TEMP, 0xe7),
+            (ADM1021_REMOTE_FRACTION, 0xe0),
+            (0x07, 0x55),
+            (0x13, 0x60),
+            (0x19, 0x6e),
+        ]);
+        let sensor = TempSensor::new(0x4c, TempModel::Lm63);
+        assert_eq!(sensor.temperature(&mut bus, Channel::Local), Ok(42.0));
+        assert_eq!(sensor.temperature(&mut bus, Channel::Remote), Ok(-24.125));
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Remote)), Ok(85.375));
+        assert_eq!(sensor.limit(&mut bus, Limit::Critical(Channel::Remote)), Ok(110.0));
+        assert_eq!(sensor.limit_step(Limit::High(Channel::Remote)), 0.125);
+        assert_eq!(sensor.limit_step(Limit::Critical(Channel::Remote)), 1.0);
+
+        // Read and written at the same addresses
+        sensor.set_limit(&mut bus, Limit::Low(Channel::Remote), -10.25).unwrap();
+        assert_eq!((bus.bytes[&0x08], bus.bytes[&0x14]), (0xf5, 0xc0));
+        assert_eq!(sensor.limit(&mut bus, Limit::Low(Channel::Remote)), Ok(-10.25));
+        assert!(sensor.set_limit(&mut bus, Limit::Low(Channel::Local), 0.0).is_err());
+    }
+
+    #[test]
+    fn adm1023_remote_has_a_fraction_and_adm1021_doesnt() {
+        let mut bus = Registers::with_bytes(&[(ADM1021_REMOTE_TEMP, 0x80), (ADM1021_REMOTE_FRACTION, 0x60)]);
+        assert_eq!(TempSensor::new(0x18, TempModel::Adm1023).temperature(&mut bus, Channel::Remote), Ok(-127.625));
+        assert_eq!(TempSensor::new(0x18, TempModel::Adm1021).temperature(&mut bus, Channel::Remote), Ok(-128.0));
+    }
+
+    #[test]
+    fn adm1021_limits_are_written_elsewhere() {
+        let mut bus = Registers::with_bytes(&[(0x05, 0x46), (0x06, 0xfb), (0x07, 0x7f), (0x08, 0x80)]);
+        let sensor = TempSensor::new(0x18, TempModel::Adm1021);
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Local)), Ok(70.0));
+        assert_eq!(sensor.limit(&mut bus, Limit::Low(Channel::Local)), Ok(-5.0));
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Remote)), Ok(127.0));
+        assert_eq!(sensor.limit(&mut bus, Limit::Low(Channel::Remote)), Ok(-128.0));
+
+        sensor.set_limit(&mut bus, Limit::High(Channel::Local), -10.0).unwrap();
+        sensor.set_limit(&mut bus, Limit::Low(Channel::Remote), -300.0).unwrap();
+        sensor.set_limit(&mut bus, Limit::High(Channel::Remote), 300.0).unwrap();
+        assert_eq!((bus.bytes[&0x0b], bus.bytes[&0x0e], bus.bytes[&0x0d]), (0xf6, 0x80, 0x7f));
+        // The read registers are untouched
+        assert_eq!(sensor.limit(&mut bus, Limit::High(Channel::Local)), Ok(70.0));
+        assert!(sensor.limit(&mut bus, Limit::Critical(Channel::Remote)).is_err());
+    }
+
+    #[test]
+    fn id_registers_name_the_part() {
+        for (addr, man_id, dev_id, model) in [
+            (0x4c, 0x01, 0x41, Some(TempModel::Lm63)),
+            // The LM63 is only ever at 0x4c
+            (0x18, 0x01, 0x41, None),
+            (0x18, 0x41, 0x39, Some(TempModel::Adm1023)),
+            (0x2a, 0x41, 0x03, Some(TempModel::Adm1021)),
+            (0x4e, 0x4d, 0x01, Some(TempModel::Max1617a)),
+            (0x4e, 0x4d, 0x02, None),
+            (0x19, 0x49, 0x00, Some(TempModel::Thmc10)),
+            (0x1a, 0x00, 0x00, None),
+            // Not an address these parts use
+            (0x48, 0x41, 0x03, None),
+        ] {
+            let mut bus = Registers::with_bytes(&[(ADM1021_MAN_ID, man_id), (ADM1021_DEV_ID, dev_id)]);
+            assert_eq!(TempSensor::detect(&mut bus, addr).map(|sensor| sensor.model), model, "{:#04x}: {:02x} {:02x}", addr, man_id, dev_id);
+        }
+    }
+
+    #[test]
+    fn parts_without_ids_are_told_by_their_reserved_bits() {
+        let mut tmp102 = Registers::with_words(&[(LM75_CONFIG, 0x60a0)]);
+        assert_eq!(TempSensor::detect_heuristic(&mut tmp102, 0x48).map(|sensor| sensor.model), Some(TempModel::Tmp102));
+
+        let mut lm75a = Registers::with_bytes(&[(LM75_CONFIG, 0x00), (LM75A_ID, 0xa1)]);
+        lm75a.words.insert(LM75_CONFIG, 0x0000);
+        assert_eq!(TempSensor::detect_heuristic(&mut lm75a, 0x49).map(|sensor| sensor.model), Some(TempModel::Lm75a));
+        lm75a.bytes.remove(&LM75A_ID);
+        assert_eq!(TempSensor::detect_heuristic(&mut lm75a, 0x49).map(|sensor| sensor.model), Some(TempModel::Lm75));
+        lm75a.bytes.insert(LM75_CONFIG, 0x80);
+        assert_eq!(TempSensor::detect_heuristic(&mut lm75a, 0x49).map(|sensor| sensor.model), None);
+
+        let mut max1617 = Registers::with_bytes(&[(ADM1021_CONFIG_R, 0x00), (ADM1021_STATUS, 0x00), (ADM1021_LOCAL_TEMP, 0x19)]);
+        assert_eq!(TempSensor::detect_heuristic(&mut max1617, 0x18).map(|sensor| sensor.model), Some(TempModel::Max1617));
+        max1617.bytes.insert(ADM1021_LOCAL_TEMP, 0x90);
+        assert_eq!(TempSensor::detect_heuristic(&mut max1617, 0x18).map(|sensor| sensor.model), None);
+    }
+}
@@ -0,0 +1,279 @@
+// Raw register dumps, the i2cdump equivalent for any device.
+
+use super::{Property, SmbusError, SmbusTransport};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpMode {
+    #[default]
+    Byte,
+    Word,
+}
+
+impl DumpMode {
+    /// Registers shown on one line of the hex dump.
+    pub fn per_row(self) -> usize {
+        match self {
+            DumpMode::Byte => 16,
+            DumpMode::Word => 8,
+        }
+    }
+}
+
+/// One full read of registers 0x00 to 0xff. Registers that failed to read
+/// are `None` and show as `XX`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterDump {
+    pub addr: u8,
+    pub mode: DumpMode,
+    pub values: Vec<Option<u16>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub register: u8,
+    pub old: Option<u16>,
+    pub new: Option<u16>,
+}
+
+impl RegisterDump {
+    pub fn read(bus: &mut dyn SmbusTransport, addr: u8, mode: DumpMode) -> Self {
+        let values = (0..=u8::MAX)
+            .map(|register| {
+                let value = match mode {
+                    DumpMode::Byte => bus.read_byte_data(addr, register).map(u16::from),
+                    DumpMode::Word => bus.read_word_data(addr, register),
+                };
+                value.ok()
+            })
+            .collect();
+
+        Self { addr, mode, values }
+    }
+
+    pub fn get(&self, register: u8) -> Option<u16> {
+        self.values.get(register as usize).copied().flatten()
+    }
+
+    /// One property per register that could be read, in hex.
+    pub fn properties(&self) -> Vec<Property> {
+        self.values.iter()
+            .enumerate()
+            .filter_map(|(register, value)| {
+                let value = match self.mode {
+                    DumpMode::Byte => format!("{:#04x}", (*value)?),
+                    DumpMode::Word => format!("{:#06x}", (*value)?),
+                };
+                Some(Property::text(&format!("{:#04x}", register), value))
+            })
+            .collect()
+    }
+
+    /// Registers whose value differs from `previous`. Dumps taken in
+    /// different modes aren't comparable and yield no changes.
+    pub fn diff(&self, previous: &RegisterDump) -> Vec<RegisterChange> {
+        if previous.mode != self.mode || previous.addr != self.addr {
+            return Vec::new();
+        }
+
+        self.values.iter()
+            .zip(previous.values.iter())
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(register, (new, old))| RegisterChange { register: register as u8, old: *old, new: *new })
+            .collect()
+    }
+
+    /// Hex dump lines, i2cdump style. Registers listed in `changed` are
+    /// followed by a `*` instead of a space.
+    pub fn rows(&self, changed: &[RegisterChange]) -> Vec<String> {
+        let per_row = self.mode.per_row();
+
+        self.values.chunks(per_row)
+            .enumerate()
+            .map(|(row, values)| {
+                let mut line = format!("{:02x}: ", row * per_row);
+                for (i, value) in values.iter().enumerate() {
+                    let register = (row * per_row + i) as u8;
+                    match (value, self.mode) {
+                        (Some(v), DumpMode::Byte) => line.push_str(&format!("{:02x}", v)),
+                        (Some(v), DumpMode::Word) => line.push_str(&format!("{:04x}", v)),
+                        (None, DumpMode::Byte) => line.push_str("XX"),
+                        (None, DumpMode::Word) => line.push_str("XXXX"),
+                    }
+                    line.push(if changed.iter().any(|c| c.register == register) { '*' } else { ' ' });
+                }
+                line.trim_end().to_owned()
+            })
+            .collect()
+    }
+}
+
+/// Register browser state for one device: the latest dump, the one before
+/// it, and the read mode the user picked.
+#[derive(Debug, Clone, Default)]
+pub struct RegisterBrowser {
+    pub addr: u8,
+    mode: DumpMode,
+    current: Option<RegisterDump>,
+    previous: Option<RegisterDump>,
+}
+
+impl RegisterBrowser {
+    pub fn new(addr: u8) -> Self {
+        Self { addr, ..Default::default() }
+    }
+
+    pub fn mode(&self) -> DumpMode {
+        self.mode
+    }
+
+    /// Switching modes forgets earlier reads since they can't be diffed.
+    pub fn set_mode(&mut self, mode: DumpMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.current = None;
+            self.previous = None;
+        }
+    }
+
+    pub fn refresh(&mut self, bus: &mut dyn SmbusTransport) -> &RegisterDump {
+        let dump = RegisterDump::read(bus, self.addr, self.mode);
+        self.previous = self.current.replace(dump);
+        self.current.as_ref().unwrap()
+    }
+
+    pub fn current(&self) -> Option<&RegisterDump> {
+        self.current.as_ref()
+    }
+
+    pub fn changes(&self) -> Vec<RegisterChange> {
+        match (&self.current, &self.previous) {
+            (Some(current), Some(previous)) => current.diff(previous),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn rows(&self) -> Vec<String> {
+        match &self.current {
+            Some(current) => current.rows(&self.changes()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes one register in the current mode. Pass a `GuardedBus` so the
+    /// write obeys the safety policy.
+    pub fn write(&mut self, bus: &mut dyn SmbusTransport, register: u8, value: u16) -> Result<(), SmbusError> {
+        match self.mode {
+            DumpMode::Byte => {
+                let byte = u8::try_from(value)
+                    .map_err(|_| SmbusError::Other(format!("{:#x} doesn't fit in a byte", value)))?;
+                bus.write_byte_data(self.addr, register, byte)
+            }
+            DumpMode::Word => bus.write_word_data(self.addr, register, value),
+        }
+    }
+}
+
+/// Parses hex typed by the user, with or without a `0x` prefix.
+pub fn parse_hex(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(text, 16).ok()
+}
+
+/// Parses a register write typed into the Edit Register dialog. Both are
+/// hex and the value has to fit `mode`.
+pub fn parse_write(register: &str, value: &str, mode: DumpMode) -> Result<(u8, u16), String> {
+    let register = parse_hex(register)
+        .and_then(|register| u8::try_from(register).ok())
+        .ok_or_else(|| format!("\"{}\" isn't a register from 00 to ff", register.trim()))?;
+    let value = match (parse_hex(value), mode) {
+        (Some(value), DumpMode::Byte) if value > 0xff => None,
+        (value, _) => value,
+    };
+    let value = value.ok_or_else(|| match mode {
+        DumpMode::Byte => "The value has to be a byte from 00 to ff".to_owned(),
+        DumpMode::Word => "The value has to be a word from 0000 to ffff".to_owned(),
+    })?;
+    Ok((register, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::sim::SimulatedBus;
+
+    fn dump(mode: DumpMode, changes: &[(u8, Option<u16>)]) -> RegisterDump {
+        let mut values: Vec<Option<u16>> = (0..=255).map(|register| Some((register as u16 * 0x0101) & 0x0fff)).collect();
+        for (register, value) in changes {
+            values[*register as usize] = *value;
+        }
+        RegisterDump { addr: 0x4c, mode, values }
+    }
+
+    #[test]
+    fn diffs_list_changed_registers() {
+        let before = dump(DumpMode::Byte, &[(0x05, None)]);
+        let after = dump(DumpMode::Byte, &[(0x05, Some(0x46)), (0x4a, Some(0x00))]);
+        assert_eq!(after.diff(&before), [
+            RegisterChange { register: 0x05, old: None, new: Some(0x46) },
+            RegisterChange { register: 0x4a, old: Some(0x0a4a), new: Some(0x00) },
+        ]);
+        assert!(after.diff(&after).is_empty());
+
+        // Other modes and other devices aren't compared
+        assert!(dump(DumpMode::Word, &[]).diff(&before).is_empty());
+        let elsewhere = RegisterDump { addr: 0x4d, ..before.clone() };
+        assert!(after.diff(&elsewhere).is_empty());
+    }
+
+    #[test]
+    fn byte_rows_mark_changes() {
+        let dump = RegisterDump { values: (0..=255).map(|register| Some(register as u16)).collect(), ..dump(DumpMode::Byte, &[]) };
+        let mut values = dump.values.clone();
+        values[0x11] = None;
+        let after = RegisterDump { values, ..dump.clone() };
+        let changes = [RegisterChange { register: 0x12, old: Some(0x12), new: Some(0x12) }];
+
+        let rows = after.rows(&changes);
+        assert_eq!(rows.len(), 16);
+        assert_eq!(rows[0], "00: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f");
+        assert_eq!(rows[1], "10: 10 XX 12*13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        assert_eq!(rows[15], "f0: f0 f1 f2 f3 f4 f5 f6 f7 f8 f9 fa fb fc fd fe ff");
+        // A change on the last register of a row isn't trimmed away
+        let last = [RegisterChange { register: 0x0f, old: None, new: Some(0x0f) }];
+        assert!(after.rows(&last)[0].ends_with("0f*"));
+    }
+
+    #[test]
+    fn word_rows_hold_eight_registers() {
+        let dump = dump(DumpMode::Word, &[(0x09, None)]);
+        let rows = dump.rows(&[RegisterChange { register: 0x0a, old: None, new: Some(0x0a0a) }]);
+        assert_eq!(rows.len(), 32);
+        assert_eq!(rows[0], "00: 0000 0101 0202 0303 0404 0505 0606 0707");
+        assert_eq!(rows[1], "08: 0808 XXXX 0a0a*0b0b 0c0c 0d0d 0e0e 0f0f");
+        assert!(rows[31].starts_with("f8: 08f8"));
+    }
+
+    #[test]
+    fn browsers_diff_their_last_two_reads() {
+        let mut bus = SimulatedBus::demo();
+        let mut browser = RegisterBrowser::new(0x4c);
+        browser.refresh(&mut bus);
+        assert!(browser.changes().is_empty());
+
+        browser.write(&mut bus, 0x4a, 0x00).unwrap();
+        assert!(browser.write(&mut bus, 0x4a, 0x100).is_err());
+        browser.refresh(&mut bus);
+        assert_eq!(browser.changes(), [RegisterChange { register: 0x4a, old: Some(0x20), new: Some(0x00) }]);
+        assert!(browser.rows()[4].starts_with("40: "));
+        assert!(browser.rows()[4].contains("00*"));
+
+        // A new mode starts over
+        browser.set_mode(DumpMode::Word);
+        assert!(browser.current().is_none() && browser.rows().is_empty());
+        browser.refresh(&mut bus);
+        assert_eq!(browser.current().unwrap().get(0x4a), Some(0x0000));
+        assert!(browser.changes().is_empty());
+    }
+}
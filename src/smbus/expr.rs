@@ -0,0 +1,254 @@
+use std::fmt;
+
+/// A scaling formula from a register map, e.g. `x * 100 / 63` or
+/// `5400000 / x`. The raw register value is bound to `x`.
+///
+/// Supports numbers (decimal, `0x` hex or fractional), `+ - * / %`, unary
+/// minus and parentheses. The formula is parsed once when the map is loaded
+/// and evaluated for every read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    source: String,
+    root: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Raw,
+    Number(f64),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Raw,
+    Number(f64),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl Formula {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let root = parser.expr()?;
+        if parser.pos != tokens.len() {
+            return Err(format!("unexpected trailing input in formula \"{}\"", source));
+        }
+
+        Ok(Formula { source: source.to_owned(), root })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluates the formula for a raw value. Returns `None` when the result
+    /// is undefined, for example a tachometer period of zero.
+    pub fn eval(&self, raw: f64) -> Option<f64> {
+        let value = eval(&self.root, raw)?;
+        if value.is_finite() { Some(value) } else { None }
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn eval(expr: &Expr, raw: f64) -> Option<f64> {
+    match expr {
+        Expr::Raw => Some(raw),
+        Expr::Number(n) => Some(*n),
+        Expr::Neg(inner) => eval(inner, raw).map(|v| -v),
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = eval(lhs, raw)?;
+            let rhs = eval(rhs, raw)?;
+            match op {
+                Op::Add => Some(lhs + rhs),
+                Op::Sub => Some(lhs - rhs),
+                Op::Mul => Some(lhs * rhs),
+                Op::Div | Op::Rem if rhs == 0.0 => None,
+                Op::Div => Some(lhs / rhs),
+                Op::Rem => Some(lhs % rhs),
+            }
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' => { i += 1; }
+            'x' | 'X' => { tokens.push(Token::Raw); i += 1; }
+            '+' => { tokens.push(Token::Op(Op::Add)); i += 1; }
+            '-' => { tokens.push(Token::Op(Op::Sub)); i += 1; }
+            '*' => { tokens.push(Token::Op(Op::Mul)); i += 1; }
+            '/' => { tokens.push(Token::Op(Op::Div)); i += 1; }
+            '%' => { tokens.push(Token::Op(Op::Rem)); i += 1; }
+            '(' => { tokens.push(Token::Open); i += 1; }
+            ')' => { tokens.push(Token::Close); i += 1; }
+            '0'..='9' | '.' => {
+                let start = i;
+                let value = if c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X')) {
+                    i += 2;
+                    while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                        i += 1;
+                    }
+                    let digits: String = chars[start + 2..i].iter().collect();
+                    u64::from_str_radix(&digits, 16)
+                        .map(|v| v as f64)
+                        .map_err(|_| format!("bad hex number in formula \"{}\"", source))?
+                } else {
+                    while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                        i += 1;
+                    }
+                    let digits: String = chars[start..i].iter().collect();
+                    digits.parse::<f64>()
+                        .map_err(|_| format!("bad number \"{}\" in formula \"{}\"", digits, source))?
+                };
+                tokens.push(Token::Number(value));
+            }
+            _ => return Err(format!("unexpected '{}' in formula \"{}\"", c, source)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// expr   := term (('+' | '-') term)*
+// term   := unary (('*' | '/' | '%') unary)*
+// unary  := '-' unary | atom
+// atom   := 'x' | number | '(' expr ')'
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ (Op::Add | Op::Sub))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op @ (Op::Mul | Op::Div | Op::Rem))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if let Some(Token::Op(Op::Sub)) = self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Raw) => Ok(Expr::Raw),
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Open) => {
+                let inner = self.expr()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("missing ')' in formula".to_owned()),
+                }
+            }
+            Some(other) => Err(format!("unexpected {:?} in formula", other)),
+            None => Err("formula ends unexpectedly".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_at(source: &str, raw: f64) -> Option<f64> {
+        Formula::parse(source).unwrap().eval(raw)
+    }
+
+    #[test]
+    fn operators_bind_as_in_arithmetic() {
+        for (source, value) in [
+            ("2 + 3 * x", 14.0),
+            ("(2 + 3) * x", 20.0),
+            ("x * 100 / 8", 50.0),
+            ("10 - x - 3", 3.0),
+            ("96 / x / 2", 12.0),
+            ("x + 10 % 3", 5.0),
+            ("0x10 + 0.5 * X", 18.0),
+        ] {
+            assert_eq!(eval_at(source, 4.0), Some(value), "{}", source);
+        }
+    }
+
+    #[test]
+    fn unary_minus_binds_tightest() {
+        for (source, value) in [
+            ("-x", -4.0),
+            ("-x * 2", -8.0),
+            ("2 * -x", -8.0),
+            ("--x", 4.0),
+            ("-(x - 10)", 6.0),
+            ("1 - -x", 5.0),
+        ] {
+            assert_eq!(eval_at(source, 4.0), Some(value), "{}", source);
+        }
+    }
+
+    #[test]
+    fn dividing_by_zero_is_undefined() {
+        assert_eq!(eval_at("5400000 / x", 0.0), None);
+        assert_eq!(eval_at("5400000 / x", 2700.0), Some(2000.0));
+        assert_eq!(eval_at("x % 0", 7.0), None);
+        assert_eq!(eval_at("1 + x / (x - 4)", 4.0), None);
+        assert_eq!(eval_at("0 * (1 / x)", 0.0), None);
+    }
+
+    #[test]
+    fn malformed_formulas_are_refused() {
+        for source in ["", "x +", "(x", "x)", "x y", "2 (x)", "0xg", "1..2", "x ^ 2", "*x"] {
+            assert!(Formula::parse(source).is_err(), "{}", source);
+        }
+        let formula = Formula::parse("x * 100 / 63").unwrap();
+        assert_eq!((formula.source(), formula.to_string().as_str()), ("x * 100 / 63", "x * 100 / 63"));
+    }
+}
@@ -0,0 +1,86 @@
+// This computer's own SMBus controller, opened in-process. Only the bus
+// service does this; everything else reaches the hardware through it.
+
+use super::{SmbusError, SmbusTransport};
+
+/// Opens the computer's SMBus controller with whatever driver the platform
+/// has for it.
+#[cfg(target_os = "linux")]
+pub fn open() -> Result<Box<dyn SmbusTransport>, SmbusError> {
+    use super::i2cdev::I2cDev;
+
+    let number = find_adapter(std::path::Path::new(SYSFS_I2C_DEV))
+        .ok_or_else(|| SmbusError::Other("No SMBus adapter in /sys/class/i2c-dev; is i2c-dev loaded?".to_owned()))?;
+    let bus = I2cDev::open_number(number)?;
+    log::info!("Using {}", bus.path().display());
+    Ok(Box::new(bus))
+}
+
+#[cfg(windows)]
+pub fn open() -> Result<Box<dyn SmbusTransport>, SmbusError> {
+    Ok(Box::new(super::i801::I801::open()?))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn open() -> Result<Box<dyn SmbusTransport>, SmbusError> {
+    Err(SmbusError::Unsupported("hardware access on this platform"))
+}
+
+#[cfg(target_os = "linux")]
+const SYSFS_I2C_DEV: &str = "/sys/class/i2c-dev";
+
+/// The number of the first i2c-dev adapter that is an SMBus host
+/// controller rather than, say, a graphics card's DDC. Their drivers all
+/// name them "SMBus <chipset> adapter ...".
+#[cfg(target_os = "linux")]
+pub fn find_adapter(sysfs: &std::path::Path) -> Option<u32> {
+    let mut found: Vec<u32> = std::fs::read_dir(sysfs).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let number = entry.file_name().to_str()?.strip_prefix("i2c-")?.parse().ok()?;
+            let name = std::fs::read_to_string(entry.path().join("name")).ok()?;
+            name.starts_with("SMBus").then_some(number)
+        })
+        .collect();
+    found.sort_unstable();
+    found.first().copied()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn sysfs(name: &str, adapters: &[(u32, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("smbus-snapin-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (number, adapter) in adapters {
+            let dir = root.join(format!("i2c-{}", number));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("name"), format!("{}\n", adapter)).unwrap();
+        }
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn finds_the_smbus_host_among_the_adapters() {
+        let root = sysfs("host", &[
+            (0, "i915 gmbus dpb"),
+            (12, "SMBus PIIX4 adapter port 2 at 0b00"),
+            (3, "SMBus I801 adapter at efa0"),
+            (4, "AMDGPU DM i2c hw bus 0"),
+        ]);
+        assert_eq!(find_adapter(&root), Some(3));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn no_smbus_host_no_adapter() {
+        let root = sysfs("none", &[(0, "i915 gmbus dpb")]);
+        assert_eq!(find_adapter(&root), None);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(find_adapter(&root), None);
+    }
+}
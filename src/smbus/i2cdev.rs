@@ -0,0 +1,166 @@
+// Linux's i2c-dev interface: one /dev/i2c-N file per adapter, driven with
+// the I2C_SMBUS ioctl. Needs the i2c-dev module loaded and read/write
+// access to the file.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use super::{SmbusError, SmbusTransport, BLOCK_MAX};
+
+// From linux/i2c-dev.h and linux/i2c.h
+const I2C_SLAVE: u64 = 0x0703;
+const I2C_SMBUS: u64 = 0x0720;
+
+const I2C_SMBUS_READ: u8 = 1;
+const I2C_SMBUS_WRITE: u8 = 0;
+
+const I2C_SMBUS_BYTE: u32 = 1;
+const I2C_SMBUS_BYTE_DATA: u32 = 2;
+const I2C_SMBUS_WORD_DATA: u32 = 3;
+const I2C_SMBUS_BLOCK_DATA: u32 = 5;
+const I2C_SMBUS_I2C_BLOCK_DATA: u32 = 8;
+
+// union i2c_smbus_data: a byte, a word, or a length and up to 32 bytes,
+// all starting at offset zero
+#[repr(C)]
+struct SmbusData {
+    block: [u8; BLOCK_MAX + 2],
+}
+
+#[repr(C)]
+struct SmbusIoctlData {
+    read_write: u8,
+    command: u8,
+    size: u32,
+    data: *mut SmbusData,
+}
+
+/// An adapter opened through i2c-dev.
+#[derive(Debug)]
+pub struct I2cDev {
+    file: File,
+    path: PathBuf,
+    // The address the file is pointed at, set again only when it changes
+    current: Option<u8>,
+}
+
+impl I2cDev {
+    pub fn open(path: &Path) -> Result<Self, SmbusError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| SmbusError::Other(format!("Couldn't open {}: {}", path.display(), e)))?;
+        Ok(Self { file, path: path.to_owned(), current: None })
+    }
+
+    /// Opens /dev/i2c-`number`.
+    pub fn open_number(number: u32) -> Result<Self, SmbusError> {
+        Self::open(Path::new(&format!("/dev/i2c-{}", number)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn select(&mut self, addr: u8) -> Result<(), SmbusError> {
+        if self.current == Some(addr) {
+            return Ok(());
+        }
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SLAVE as _, libc::c_ulong::from(addr)) };
+        if result < 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::EBUSY) => SmbusError::Other(format!("{:#04x} is in use by a kernel driver", addr)),
+                _ => SmbusError::Other(format!("Couldn't select {:#04x} on {}: {}", addr, self.path.display(), e)),
+            });
+        }
+        self.current = Some(addr);
+        Ok(())
+    }
+
+    fn transfer(&mut self, addr: u8, read_write: u8, command: u8, size: u32, data: &mut SmbusData) -> Result<(), SmbusError> {
+        self.select(addr)?;
+        let mut args = SmbusIoctlData { read_write, command, size, data };
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SMBUS as _, &mut args) };
+        if result >= 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        Err(match e.raw_os_error() {
+            // Adapters disagree on how to say nobody answered
+            Some(libc::ENXIO | libc::EREMOTEIO) => SmbusError::Nack { addr },
+            Some(libc::ETIMEDOUT | libc::EAGAIN) => SmbusError::Timeout,
+            Some(libc::EOPNOTSUPP) => SmbusError::Unsupported("by this adapter"),
+            _ => SmbusError::Other(format!("{}: {}", self.path.display(), e)),
+        })
+    }
+}
+
+fn empty() -> SmbusData {
+    SmbusData { block: [0; BLOCK_MAX + 2] }
+}
+
+impl SmbusTransport for I2cDev {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+        let mut data = empty();
+        self.transfer(addr, I2C_SMBUS_READ, command, I2C_SMBUS_BYTE_DATA, &mut data)?;
+        Ok(data.block[0])
+    }
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+        let mut data = empty();
+        data.block[0] = value;
+        self.transfer(addr, I2C_SMBUS_WRITE, command, I2C_SMBUS_BYTE_DATA, &mut data)
+    }
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+        let mut data = empty();
+        self.transfer(addr, I2C_SMBUS_READ, command, I2C_SMBUS_WORD_DATA, &mut data)?;
+        Ok(u16::from_ne_bytes([data.block[0], data.block[1]]))
+    }
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+        let mut data = empty();
+        data.block[..2].copy_from_slice(&value.to_ne_bytes());
+        self.transfer(addr, I2C_SMBUS_WRITE, command, I2C_SMBUS_WORD_DATA, &mut data)
+    }
+
+    fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+        let mut data = empty();
+        self.transfer(addr, I2C_SMBUS_READ, 0, I2C_SMBUS_BYTE, &mut data)?;
+        Ok(data.block[0])
+    }
+
+    fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+        let mut data = empty();
+        self.transfer(addr, I2C_SMBUS_READ, command, I2C_SMBUS_BLOCK_DATA, &mut data)?;
+        let len = (data.block[0] as usize).min(BLOCK_MAX);
+        Ok(data.block[1..=len].to_vec())
+    }
+
+    // The kernel takes up to 32 bytes at a time
+    fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let chunk = (len - bytes.len()).min(BLOCK_MAX);
+            let mut data = empty();
+            data.block[0] = chunk as u8;
+            self.transfer(addr, I2C_SMBUS_READ, command.wrapping_add(bytes.len() as u8), I2C_SMBUS_I2C_BLOCK_DATA, &mut data)?;
+            bytes.extend_from_slice(&data.block[1..=chunk]);
+        }
+        Ok(bytes)
+    }
+
+    fn write_i2c_block_data(&mut self, addr: u8, command: u8, bytes: &[u8]) -> Result<(), SmbusError> {
+        if bytes.len() > BLOCK_MAX {
+            return Err(SmbusError::Other(format!("{} bytes don't fit in one block write", bytes.len())));
+        }
+        let mut data = empty();
+        data.block[0] = bytes.len() as u8;
+        data.block[1..=bytes.len()].copy_from_slice(bytes);
+        self.transfer(addr, I2C_SMBUS_WRITE, command, I2C_SMBUS_I2C_BLOCK_DATA, &mut data)
+    }
+}
@@ -0,0 +1,445 @@
+// The SMBus host controller in Intel's chipsets (ICH, PCH), driven through
+// its I/O ports the way Linux's i2c-i801 does. Windows has no driver that
+// lets programs use it, so the bus service reaches the ports through
+// InpOut32's kernel driver (inpoutx64.dll), which has to be installed next
+// to it.
+//
+// The host is found by looking for Intel's SMBus function (class 0x0c05)
+// in PCI configuration space on bus 0, using configuration mechanism #1.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{SmbusError, SmbusTransport, BLOCK_MAX};
+
+/// Raw port access, one byte or one doubleword at a time.
+pub trait PortIo: fmt::Debug + Send {
+    fn read_u8(&mut self, port: u16) -> u8;
+
+    fn write_u8(&mut self, port: u16, value: u8);
+
+    fn read_u32(&mut self, port: u16) -> u32;
+
+    fn write_u32(&mut self, port: u16, value: u32);
+}
+
+// Configuration mechanism #1
+const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
+const PCI_CONFIG_DATA: u16 = 0xcfc;
+
+const INTEL: u32 = 0x8086;
+const CLASS_SMBUS: u32 = 0x0c05;
+// The SMBus I/O base, and the host configuration register whose bit 0
+// says the host is enabled
+const PCI_SMBBAR: u8 = 0x20;
+const PCI_HOSTC: u8 = 0x40;
+const HOSTC_HST_EN: u32 = 0x01;
+
+// Host registers, from the I/O base
+const HSTSTS: u16 = 0x00;
+const HSTCNT: u16 = 0x02;
+const HSTCMD: u16 = 0x03;
+const HSTADD: u16 = 0x04;
+const HSTDAT0: u16 = 0x05;
+const HSTDAT1: u16 = 0x06;
+const BLKDAT: u16 = 0x07;
+const AUXCTL: u16 = 0x0d;
+
+// HSTCNT
+const CNT_KILL: u8 = 0x02;
+const CNT_BYTE: u8 = 0x04;
+const CNT_BYTE_DATA: u8 = 0x08;
+const CNT_WORD_DATA: u8 = 0x0c;
+const CNT_BLOCK_DATA: u8 = 0x14;
+const CNT_START: u8 = 0x40;
+
+// HSTSTS. Everything but BUSY is cleared by writing it back.
+const STS_BUSY: u8 = 0x01;
+const STS_INTR: u8 = 0x02;
+const STS_DEV_ERR: u8 = 0x04;
+const STS_BUS_ERR: u8 = 0x08;
+const STS_FAILED: u8 = 0x10;
+const STS_BYTE_DONE: u8 = 0x80;
+const STS_FLAGS: u8 = STS_INTR | STS_DEV_ERR | STS_BUS_ERR | STS_FAILED | STS_BYTE_DONE;
+const STS_ERRORS: u8 = STS_DEV_ERR | STS_BUS_ERR | STS_FAILED;
+
+// AUXCTL: the 32-byte buffer, so a block comes in with one transaction
+const AUXCTL_E32B: u8 = 0x02;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where the host is in PCI configuration space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciFunction {
+    pub device: u8,
+    pub function: u8,
+}
+
+fn config_read(io: &mut dyn PortIo, at: PciFunction, register: u8) -> u32 {
+    let address = 0x8000_0000 | u32::from(at.device) << 11 | u32::from(at.function) << 8 | u32::from(register & 0xfc);
+    io.write_u32(PCI_CONFIG_ADDRESS, address);
+    io.read_u32(PCI_CONFIG_DATA)
+}
+
+/// Finds an enabled Intel SMBus host on PCI bus 0 and returns its I/O
+/// base.
+pub fn find_host(io: &mut dyn PortIo) -> Option<(PciFunction, u16)> {
+    for device in 0..32 {
+        for function in 0..8 {
+            let at = PciFunction { device, function };
+            let id = config_read(io, at, 0x00);
+            if id & 0xffff != INTEL || config_read(io, at, 0x08) >> 16 != CLASS_SMBUS {
+                continue;
+            }
+            let bar = config_read(io, at, PCI_SMBBAR);
+            if bar & 1 == 0 {
+                log::warn!("The SMBus host at 00:{:02x}.{} has no I/O base", device, function);
+                continue;
+            }
+            if config_read(io, at, PCI_HOSTC) & HOSTC_HST_EN == 0 {
+                log::warn!("The SMBus host at 00:{:02x}.{} is disabled", device, function);
+                continue;
+            }
+            return Some((at, (bar & 0xffe0) as u16));
+        }
+    }
+    None
+}
+
+/// An Intel SMBus host at an I/O base.
+#[derive(Debug)]
+pub struct I801 {
+    io: Box<dyn PortIo>,
+    base: u16,
+}
+
+impl I801 {
+    pub fn new(io: Box<dyn PortIo>, base: u16) -> Self {
+        Self { io, base }
+    }
+
+    /// Finds the host with `io`.
+    pub fn find(mut io: Box<dyn PortIo>) -> Result<Self, SmbusError> {
+        let (at, base) = find_host(io.as_mut())
+            .ok_or_else(|| SmbusError::Other("No Intel SMBus host controller on PCI bus 0".to_owned()))?;
+        log::info!("Using the SMBus host at 00:{:02x}.{}, I/O base {:#06x}", at.device, at.function, base);
+        Ok(Self::new(io, base))
+    }
+
+    /// Finds the host through InpOut32.
+    #[cfg(windows)]
+    pub fn open() -> Result<Self, SmbusError> {
+        Self::find(Box::new(inpout::InpOut::load()?))
+    }
+
+    fn read(&mut self, register: u16) -> u8 {
+        self.io.read_u8(self.base + register)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        self.io.write_u8(self.base + register, value)
+    }
+
+    // One transaction with the host's `protocol`, leaving any data in
+    // HSTDAT0, HSTDAT1 and the block buffer
+    fn transaction(&mut self, addr: u8, read: bool, command: u8, protocol: u8) -> Result<(), SmbusError> {
+        if self.read(HSTSTS) & STS_BUSY != 0 {
+            return Err(SmbusError::Other("The SMBus host is busy; is another driver using it?".to_owned()));
+        }
+        self.write(HSTSTS, STS_FLAGS);
+        self.write(HSTADD, addr << 1 | u8::from(read));
+        self.write(HSTCMD, command);
+        self.write(HSTCNT, protocol | CNT_START);
+
+        let deadline = Instant::now() + TIMEOUT;
+        let status = loop {
+            let status = self.read(HSTSTS);
+            if status & STS_BUSY == 0 && status & (STS_INTR | STS_ERRORS) != 0 {
+                break status;
+            }
+            if Instant::now() > deadline {
+                self.write(HSTCNT, CNT_KILL);
+                self.write(HSTCNT, 0);
+                self.write(HSTSTS, STS_FLAGS);
+                return Err(SmbusError::Timeout);
+            }
+            std::thread::yield_now();
+        };
+        self.write(HSTSTS, STS_FLAGS);
+
+        match status {
+            status if status & STS_DEV_ERR != 0 => Err(SmbusError::Nack { addr }),
+            status if status & STS_BUS_ERR != 0 => Err(SmbusError::Other("SMBus collision".to_owned())),
+            status if status & STS_FAILED != 0 => Err(SmbusError::Other("The SMBus host failed the transaction".to_owned())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SmbusTransport for I801 {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+        self.transaction(addr, true, command, CNT_BYTE_DATA)?;
+        Ok(self.read(HSTDAT0))
+    }
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+        self.write(HSTDAT0, value);
+        self.transaction(addr, false, command, CNT_BYTE_DATA)
+    }
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+        self.transaction(addr, true, command, CNT_WORD_DATA)?;
+        Ok(u16::from_le_bytes([self.read(HSTDAT0), self.read(HSTDAT1)]))
+    }
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+        let [low, high] = value.to_le_bytes();
+        self.write(HSTDAT0, low);
+        self.write(HSTDAT1, high);
+        self.transaction(addr, false, command, CNT_WORD_DATA)
+    }
+
+    fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+        self.transaction(addr, true, 0, CNT_BYTE)?;
+        Ok(self.read(HSTDAT0))
+    }
+
+    // Reading HSTCNT puts the buffer's pointer back at its start
+    fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+        let auxctl = self.read(AUXCTL);
+        self.write(AUXCTL, auxctl | AUXCTL_E32B);
+        let result = self.transaction(addr, true, command, CNT_BLOCK_DATA).map(|()| {
+            self.read(HSTCNT);
+            let len = (self.read(HSTDAT0) as usize).min(BLOCK_MAX);
+            (0..len).map(|_| self.read(BLKDAT)).collect()
+        });
+        self.write(AUXCTL, auxctl);
+        result
+    }
+}
+
+// InpOut32's 64-bit build, loaded when the service starts rather than
+// linked, so the service still runs without it for simulations
+#[cfg(windows)]
+mod inpout {
+    use std::fmt;
+    use std::mem::transmute;
+
+    use windows::core::{s, w, PCSTR};
+    use windows::Win32::Foundation::HMODULE;
+    use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+
+    use super::PortIo;
+    use crate::smbus::SmbusError;
+
+    type Proc = unsafe extern "system" fn() -> isize;
+    type IsDriverOpen = unsafe extern "system" fn() -> i32;
+    type ReadUchar = unsafe extern "system" fn(u16) -> u8;
+    type WriteUchar = unsafe extern "system" fn(u16, u8);
+    type ReadUlong = unsafe extern "system" fn(u32) -> u32;
+    type WriteUlong = unsafe extern "system" fn(u32, u32);
+
+    pub struct InpOut {
+        read_u8: ReadUchar,
+        write_u8: WriteUchar,
+        read_u32: ReadUlong,
+        write_u32: WriteUlong,
+    }
+
+    impl fmt::Debug for InpOut {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("InpOut")
+        }
+    }
+
+    impl InpOut {
+        pub fn load() -> Result<Self, SmbusError> {
+            let library = unsafe { LoadLibraryW(w!("inpoutx64.dll")) }
+                .map_err(|e| SmbusError::Other(format!("Couldn't load inpoutx64.dll: {}", e)))?;
+            unsafe {
+                let is_driver_open = transmute::<Proc, IsDriverOpen>(function(library, s!("IsInpOutDriverOpen"))?);
+                if is_driver_open() == 0 {
+                    return Err(SmbusError::Other("InpOut's driver isn't running; the service has to run as an administrator".to_owned()));
+                }
+                Ok(Self {
+                    read_u8: transmute::<Proc, ReadUchar>(function(library, s!("DlPortReadPortUchar"))?),
+                    write_u8: transmute::<Proc, WriteUchar>(function(library, s!("DlPortWritePortUchar"))?),
+                    read_u32: transmute::<Proc, ReadUlong>(function(library, s!("DlPortReadPortUlong"))?),
+                    write_u32: transmute::<Proc, WriteUlong>(function(library, s!("DlPortWritePortUlong"))?),
+                })
+            }
+        }
+    }
+
+    unsafe fn function(library: HMODULE, name: PCSTR) -> Result<Proc, SmbusError> {
+        GetProcAddress(library, name)
+            .ok_or_else(|| SmbusError::Other(format!("inpoutx64.dll has no {}", name.to_string().unwrap_or_default())))
+    }
+
+    impl PortIo for InpOut {
+        fn read_u8(&mut self, port: u16) -> u8 {
+            unsafe { (self.read_u8)(port) }
+        }
+
+        fn write_u8(&mut self, port: u16, value: u8) {
+            unsafe { (self.write_u8)(port, value) }
+        }
+
+        fn read_u32(&mut self, port: u16) -> u32 {
+            unsafe { (self.read_u32)(port.into()) }
+        }
+
+        fn write_u32(&mut self, port: u16, value: u32) {
+            unsafe { (self.write_u32)(port.into(), value) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::sim::SimulatedBus;
+
+    const BASE: u16 = 0xefa0;
+
+    // An ICH's SMBus function at 00:1f.3 in front of a simulated bus. A
+    // transaction completes as soon as it's started.
+    #[derive(Debug)]
+    struct FakeHost {
+        config_address: u32,
+        hostc: u32,
+        registers: [u8; 16],
+        block: Vec<u8>,
+        block_index: usize,
+        bus: SimulatedBus,
+    }
+
+    impl FakeHost {
+        fn new() -> Self {
+            Self { config_address: 0, hostc: HOSTC_HST_EN, registers: [0; 16], block: Vec::new(), block_index: 0, bus: SimulatedBus::demo() }
+        }
+
+        fn config(&self) -> u32 {
+            let (device, function, register) = ((self.config_address >> 11) & 0x1f, (self.config_address >> 8) & 0x07, self.config_address & 0xfc);
+            match (device, function, register) {
+                (0x1f, 3, 0x00) => 0x1c22_8086,
+                (0x1f, 3, 0x08) => 0x0c05_0005,
+                (0x1f, 3, 0x20) => u32::from(BASE) | 1,
+                (0x1f, 3, 0x40) => self.hostc,
+                (0x1f, 0, 0x00) => 0x1c44_8086,
+                (0x1f, 0, 0x08) => 0x0601_0005,
+                _ => 0xffff_ffff,
+            }
+        }
+
+        fn run(&mut self, protocol: u8) {
+            let addr = self.registers[HSTADD as usize] >> 1;
+            let read = self.registers[HSTADD as usize] & 1 != 0;
+            let command = self.registers[HSTCMD as usize];
+            let (data0, data1) = (self.registers[HSTDAT0 as usize], self.registers[HSTDAT1 as usize]);
+            let bus = &mut self.bus;
+            let result = match (protocol, read) {
+                (CNT_BYTE_DATA, true) => bus.read_byte_data(addr, command).map(|value| vec![value]),
+                (CNT_BYTE_DATA, false) => bus.write_byte_data(addr, command, data0).map(|()| Vec::new()),
+                (CNT_WORD_DATA, true) => bus.read_word_data(addr, command).map(|value| value.to_le_bytes().to_vec()),
+                (CNT_WORD_DATA, false) => bus.write_word_data(addr, command, u16::from_le_bytes([data0, data1])).map(|()| Vec::new()),
+                (CNT_BLOCK_DATA, true) if addr == 0x61 => Ok(vec![0xde, 0xad, 0xbe, 0xef]),
+                _ => Err(SmbusError::Unsupported("by the fake host")),
+            };
+            let status = match result {
+                Ok(bytes) if protocol == CNT_BLOCK_DATA => {
+                    self.registers[HSTDAT0 as usize] = bytes.len() as u8;
+                    self.block = bytes;
+                    STS_INTR
+                }
+                Ok(bytes) => {
+                    for (offset, value) in bytes.into_iter().enumerate() {
+                        self.registers[HSTDAT0 as usize + offset] = value;
+                    }
+                    STS_INTR
+                }
+                Err(SmbusError::Nack { .. }) => STS_DEV_ERR,
+                Err(_) => STS_FAILED,
+            };
+            self.registers[HSTSTS as usize] |= status;
+        }
+    }
+
+    impl PortIo for FakeHost {
+        fn read_u8(&mut self, port: u16) -> u8 {
+            let register = port.wrapping_sub(BASE);
+            match register {
+                HSTCNT => {
+                    self.block_index = 0;
+                    self.registers[HSTCNT as usize]
+                }
+                BLKDAT => {
+                    self.block_index += 1;
+                    self.block.get(self.block_index - 1).copied().unwrap_or(0)
+                }
+                register if register < 16 => self.registers[register as usize],
+                _ => 0xff,
+            }
+        }
+
+        fn write_u8(&mut self, port: u16, value: u8) {
+            match port.wrapping_sub(BASE) {
+                HSTSTS => self.registers[HSTSTS as usize] &= !(value & STS_FLAGS),
+                HSTCNT if value & CNT_START != 0 => self.run(value & !CNT_START),
+                register if register < 16 => self.registers[register as usize] = value,
+                _ => {}
+            }
+        }
+
+        fn read_u32(&mut self, port: u16) -> u32 {
+            match port {
+                PCI_CONFIG_DATA => self.config(),
+                _ => 0xffff_ffff,
+            }
+        }
+
+        fn write_u32(&mut self, port: u16, value: u32) {
+            if port == PCI_CONFIG_ADDRESS {
+                self.config_address = value;
+            }
+        }
+    }
+
+    #[test]
+    fn finds_the_enabled_host_on_bus_zero() {
+        let mut host = FakeHost::new();
+        assert_eq!(find_host(&mut host), Some((PciFunction { device: 0x1f, function: 3 }, BASE)));
+        host.hostc = 0;
+        assert_eq!(find_host(&mut host), None);
+        assert!(I801::find(Box::new(host)).is_err());
+    }
+
+    #[test]
+    fn runs_transactions_through_the_host() {
+        let mut bus = I801::find(Box::new(FakeHost::new())).unwrap();
+        let mut sim = SimulatedBus::demo();
+        assert_eq!(bus.read_byte_data(0x4c, 0x00).unwrap(), sim.read_byte_data(0x4c, 0x00).unwrap());
+        assert_eq!(bus.read_word_data(0x4c, 0x00).unwrap(), sim.read_word_data(0x4c, 0x00).unwrap());
+
+        bus.write_byte_data(0x4c, 0x4a, 0x00).unwrap();
+        assert_eq!(bus.read_byte_data(0x4c, 0x4a).unwrap(), 0x00);
+        bus.write_word_data(0x50, 0x10, 0x1234).unwrap();
+        assert_eq!(bus.read_word_data(0x50, 0x10).unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn host_errors_become_bus_errors() {
+        let mut bus = I801::find(Box::new(FakeHost::new())).unwrap();
+        assert_eq!(bus.read_byte_data(0x2d, 0x00), Err(SmbusError::Nack { addr: 0x2d }));
+        assert!(matches!(bus.read_block_data(0x4c, 0x00), Err(SmbusError::Other(_))));
+        // The status was cleared after each
+        assert!(bus.read_byte_data(0x4c, 0x00).is_ok());
+    }
+
+    #[test]
+    fn block_reads_come_through_the_buffer() {
+        let mut bus = I801::find(Box::new(FakeHost::new())).unwrap();
+        assert_eq!(bus.read_block_data(0x61, 0x99).unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(bus.read(AUXCTL), 0);
+    }
+}
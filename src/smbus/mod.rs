@@ -0,0 +1,28 @@
+// Domain layer: everything about talking to devices on the bus lives here.
+//
+// Nothing in this module may depend on COM, MMC or Win32 so it can be built
+// and exercised on any platform.
+
+mod transport;
+pub use transport::*;
+
+mod expr;
+pub use expr::Formula;
+
+mod value;
+pub use value::*;
+
+pub mod regmap;
+
+pub mod arp;
+pub mod dump;
+pub mod safety;
+pub mod scan;
+pub mod sim;
+
+#[cfg(target_os = "linux")]
+pub mod i2cdev;
+pub mod hardware;
+pub mod i801;
+
+pub mod drivers;
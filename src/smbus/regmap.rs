@@ -0,0 +1,671 @@
+// Register map descriptions.
+//
+// A register map is a TOML document that describes a device's registers:
+// name, address, width, access, bit fields, enumerations and scaling
+// formulas. The interpreter below turns raw register reads into named,
+// typed values so simple devices need no driver code at all:
+//
+//     [device]
+//     name = "EMC2101"
+//     addresses = [0x4c]
+//
+//     [[device.identify]]
+//     register = 0xfe
+//     value = 0x5d
+//
+//     [[register]]
+//     name = "FAN1_PWM"
+//     address = 0x4c
+//     access = "rw"
+//     scale = "x * 100 / 63"
+//     unit = "%"
+//
+// See `regmaps/` for complete examples.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{Formula, Property, SmbusError, SmbusTransport, Value};
+
+/// Register maps shipped with the snap-in.
+pub const BUILTIN_MAPS: &[(&str, &str)] = &[
+    ("emc2101.toml", include_str!("../../regmaps/emc2101.toml")),
+];
+
+#[derive(Debug)]
+pub enum RegMapError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for RegMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegMapError::Io(e) => write!(f, "couldn't read register map: {}", e),
+            RegMapError::Parse(e) => write!(f, "couldn't parse register map: {}", e),
+            RegMapError::Invalid(e) => write!(f, "invalid register map: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RegMapError {}
+
+impl From<std::io::Error> for RegMapError {
+    fn from(e: std::io::Error) -> Self {
+        RegMapError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Access {
+    #[default]
+    #[serde(rename = "ro")]
+    ReadOnly,
+    #[serde(rename = "rw")]
+    ReadWrite,
+    #[serde(rename = "wo")]
+    WriteOnly,
+}
+
+impl Access {
+    pub fn readable(self) -> bool {
+        self != Access::WriteOnly
+    }
+
+    pub fn writable(self) -> bool {
+        self != Access::ReadOnly
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+impl Width {
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::Byte => 8,
+            Width::Word => 16,
+        }
+    }
+}
+
+/// How a raw value (a whole register or a bit field) becomes a typed value.
+#[derive(Debug, Clone, Default)]
+pub struct Decode {
+    pub signed: bool,
+    pub unit: Option<String>,
+    pub scale: Option<Formula>,
+    pub enums: BTreeMap<u32, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub msb: u32,
+    pub lsb: u32,
+    pub decode: Decode,
+}
+
+impl Field {
+    pub fn extract(&self, raw: u32) -> u32 {
+        let width = self.msb - self.lsb + 1;
+        (raw >> self.lsb) & mask(width)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub name: String,
+    pub address: u8,
+    pub width: Width,
+    pub big_endian: bool,
+    pub access: Access,
+    pub description: Option<String>,
+    pub decode: Decode,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IdMatch {
+    pub register: u8,
+    pub value: u8,
+    pub mask: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegisterMap {
+    pub name: String,
+    pub vendor: Option<String>,
+    pub description: Option<String>,
+    pub addresses: Vec<u8>,
+    pub identify: Vec<IdMatch>,
+    pub registers: Vec<Register>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldReading {
+    pub name: String,
+    pub raw: u32,
+    pub value: Value,
+    pub unit: Option<String>,
+}
+
+/// The result of interpreting one register read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub name: String,
+    pub address: u8,
+    pub raw: u32,
+    pub value: Value,
+    pub unit: Option<String>,
+    pub fields: Vec<FieldReading>,
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.value_text())
+    }
+}
+
+impl Reading {
+    /// The value with its unit, without the register name.
+    pub fn value_text(&self) -> String {
+        self.value.with_unit(self.unit.as_deref())
+    }
+
+    pub fn to_property(&self) -> Property {
+        Property {
+            name: self.name.clone(),
+            value: self.value.clone(),
+            unit: self.unit.clone(),
+        }
+    }
+}
+
+impl FieldReading {
+    pub fn value_text(&self) -> String {
+        self.value.with_unit(self.unit.as_deref())
+    }
+}
+
+impl Decode {
+    fn apply(&self, raw: u32, bits: u32) -> Value {
+        if let Some(name) = self.enums.get(&raw) {
+            return Value::Enum(name.clone());
+        }
+
+        let number = if self.signed { sign_extend(raw, bits) } else { raw as i64 };
+
+        match &self.scale {
+            Some(formula) => match formula.eval(number as f64) {
+                Some(v) => Value::Scaled(v),
+                None => Value::Undefined,
+            },
+            None => Value::Integer(number),
+        }
+    }
+}
+
+impl Register {
+    /// Interprets a raw value as read from the bus.
+    pub fn decode(&self, raw: u32) -> Reading {
+        let raw = raw & mask(self.width.bits());
+        let fields = self.fields.iter()
+            .map(|field| {
+                let field_raw = field.extract(raw);
+                FieldReading {
+                    name: field.name.clone(),
+                    raw: field_raw,
+                    value: field.decode.apply(field_raw, field.msb - field.lsb + 1),
+                    unit: field.decode.unit.clone(),
+                }
+            })
+            .collect();
+
+        Reading {
+            name: self.name.clone(),
+            address: self.address,
+            raw,
+            value: self.decode.apply(raw, self.width.bits()),
+            unit: self.decode.unit.clone(),
+            fields,
+        }
+    }
+
+    pub fn read_raw(&self, bus: &mut dyn SmbusTransport, addr: u8) -> Result<u32, SmbusError> {
+        match self.width {
+            Width::Byte => bus.read_byte_data(addr, self.address).map(u32::from),
+            Width::Word => {
+                let word = bus.read_word_data(addr, self.address)?;
+                Ok(u32::from(if self.big_endian { word.swap_bytes() } else { word }))
+            }
+        }
+    }
+
+    pub fn read(&self, bus: &mut dyn SmbusTransport, addr: u8) -> Result<Reading, SmbusError> {
+        self.read_raw(bus, addr).map(|raw| self.decode(raw))
+    }
+
+    /// Writes a raw value in the register's byte order. Pass a `GuardedBus`
+    /// so the write obeys the safety policy.
+    pub fn write_raw(&self, bus: &mut dyn SmbusTransport, addr: u8, raw: u32) -> Result<(), SmbusError> {
+        let raw = raw & mask(self.width.bits());
+        match self.width {
+            Width::Byte => bus.write_byte_data(addr, self.address, raw as u8),
+            Width::Word => {
+                let word = raw as u16;
+                bus.write_word_data(addr, self.address, if self.big_endian { word.swap_bytes() } else { word })
+            }
+        }
+    }
+
+    /// A raw value as a number, sign-extended if the register is signed.
+    pub fn raw_number(&self, raw: u32) -> i64 {
+        let bits = self.width.bits();
+        let raw = raw & mask(bits);
+        if self.decode.signed { sign_extend(raw, bits) } else { i64::from(raw) }
+    }
+
+    /// The smallest and largest raw values, taking the sign into account.
+    pub fn raw_range(&self) -> (i64, i64) {
+        let bits = self.width.bits();
+        if self.decode.signed {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, i64::from(mask(bits)))
+        }
+    }
+}
+
+impl RegisterMap {
+    pub fn from_toml(text: &str) -> Result<Self, RegMapError> {
+        let raw: RawMap = toml::from_str(text).map_err(|e| RegMapError::Parse(e.to_string()))?;
+        raw.validate()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RegMapError> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text)
+    }
+
+    pub fn builtin() -> Vec<RegisterMap> {
+        BUILTIN_MAPS.iter()
+            .filter_map(|(file, text)| match Self::from_toml(text) {
+                Ok(map) => Some(map),
+                Err(e) => {
+                    log::error!("Built-in register map {}: {}", file, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn register_at(&self, address: u8) -> Option<&Register> {
+        self.registers.iter().find(|r| r.address == address)
+    }
+
+    /// Checks the identification registers of the device at `addr`. Maps
+    /// without any `identify` entries never match on their own.
+    pub fn identifies(&self, bus: &mut dyn SmbusTransport, addr: u8) -> Result<bool, SmbusError> {
+        if self.identify.is_empty() {
+            return Ok(false);
+        }
+        if !self.addresses.is_empty() && !self.addresses.contains(&addr) {
+            return Ok(false);
+        }
+
+        for id in &self.identify {
+            let value = bus.read_byte_data(addr, id.register)?;
+            if value & id.mask != id.value & id.mask {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads and decodes every readable register, in map order.
+    pub fn read_all(&self, bus: &mut dyn SmbusTransport, addr: u8) -> Vec<(String, Result<Reading, SmbusError>)> {
+        self.registers.iter()
+            .filter(|r| r.access.readable())
+            .map(|r| (r.name.clone(), r.read(bus, addr)))
+            .collect()
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    if bits >= 32 { u32::MAX } else { (1 << bits) - 1 }
+}
+
+fn sign_extend(raw: u32, bits: u32) -> i64 {
+    let raw = i64::from(raw);
+    if bits > 0 && raw & (1 << (bits - 1)) != 0 {
+        raw - (1 << bits)
+    } else {
+        raw
+    }
+}
+
+// On-disk layout. Kept separate so the public types can hold parsed
+// formulas and numeric enum keys.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMap {
+    device: RawDevice,
+    #[serde(default, rename = "register")]
+    registers: Vec<RawRegister>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDevice {
+    name: String,
+    vendor: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    addresses: Vec<u8>,
+    #[serde(default)]
+    identify: Vec<RawIdMatch>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawIdMatch {
+    register: u8,
+    value: u8,
+    #[serde(default = "full_mask")]
+    mask: u8,
+}
+
+fn full_mask() -> u8 {
+    0xff
+}
+
+// No deny_unknown_fields here or on fields: serde doesn't support it
+// together with flatten.
+#[derive(Deserialize)]
+struct RawRegister {
+    name: String,
+    address: u8,
+    #[serde(default = "default_width")]
+    width: u32,
+    #[serde(default)]
+    big_endian: bool,
+    #[serde(default)]
+    access: Access,
+    description: Option<String>,
+    #[serde(flatten)]
+    decode: RawDecode,
+    #[serde(default, rename = "field")]
+    fields: Vec<RawField>,
+}
+
+fn default_width() -> u32 {
+    8
+}
+
+#[derive(Deserialize)]
+struct RawDecode {
+    #[serde(default)]
+    signed: bool,
+    unit: Option<String>,
+    scale: Option<String>,
+    #[serde(default)]
+    enums: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RawField {
+    name: String,
+    bits: String,
+    #[serde(flatten)]
+    decode: RawDecode,
+}
+
+impl RawMap {
+    fn validate(self) -> Result<RegisterMap, RegMapError> {
+        let mut registers: Vec<Register> = Vec::with_capacity(self.registers.len());
+
+        for raw in self.registers {
+            if registers.iter().any(|r| r.name == raw.name) {
+                return Err(RegMapError::Invalid(format!("register {} is defined twice", raw.name)));
+            }
+
+            let width = match raw.width {
+                8 => Width::Byte,
+                16 => Width::Word,
+                other => {
+                    return Err(RegMapError::Invalid(format!(
+                        "register {}: width must be 8 or 16, not {}", raw.name, other
+                    )));
+                }
+            };
+
+            let mut fields = Vec::with_capacity(raw.fields.len());
+            for field in raw.fields {
+                let (msb, lsb) = parse_bits(&field.bits).ok_or_else(|| RegMapError::Invalid(format!(
+                    "register {}: field {} has bad bits \"{}\"", raw.name, field.name, field.bits
+                )))?;
+                if msb >= width.bits() {
+                    return Err(RegMapError::Invalid(format!(
+                        "register {}: field {} is outside the register", raw.name, field.name
+                    )));
+                }
+                let context = format!("{}.{}", raw.name, field.name);
+                fields.push(Field {
+                    name: field.name,
+                    msb,
+                    lsb,
+                    decode: field.decode.validate(&context)?,
+                });
+            }
+
+            let decode = raw.decode.validate(&raw.name)?;
+            registers.push(Register {
+                name: raw.name,
+                address: raw.address,
+                width,
+                big_endian: raw.big_endian,
+                access: raw.access,
+                description: raw.description,
+                decode,
+                fields,
+            });
+        }
+
+        Ok(RegisterMap {
+            name: self.device.name,
+            vendor: self.device.vendor,
+            description: self.device.description,
+            addresses: self.device.addresses,
+            identify: self.device.identify.into_iter()
+                .map(|id| IdMatch { register: id.register, value: id.value, mask: id.mask })
+                .collect(),
+            registers,
+        })
+    }
+}
+
+impl RawDecode {
+    fn validate(self, context: &str) -> Result<Decode, RegMapError> {
+        let scale = match self.scale {
+            Some(source) => Some(Formula::parse(&source)
+                .map_err(|e| RegMapError::Invalid(format!("{}: {}", context, e)))?),
+            None => None,
+        };
+
+        let mut enums = BTreeMap::new();
+        for (key, name) in self.enums {
+            let value = parse_number(&key).ok_or_else(|| RegMapError::Invalid(format!(
+                "{}: enum key \"{}\" is not a number", context, key
+            )))?;
+            enums.insert(value, name);
+        }
+
+        Ok(Decode { signed: self.signed, unit: self.unit, scale, enums })
+    }
+}
+
+// "7:5" or "3"
+fn parse_bits(bits: &str) -> Option<(u32, u32)> {
+    match bits.split_once(':') {
+        Some((msb, lsb)) => {
+            let msb: u32 = msb.trim().parse().ok()?;
+            let lsb: u32 = lsb.trim().parse().ok()?;
+            if msb >= lsb { Some((msb, lsb)) } else { None }
+        }
+        None => {
+            let bit: u32 = bits.trim().parse().ok()?;
+            Some((bit, bit))
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = r#"
+        [device]
+        name = "Test part"
+        addresses = [0x2e]
+
+        [[register]]
+        name = "TEMP"
+        address = 0x00
+        signed = true
+        unit = "°C"
+
+        [[register]]
+        name = "TACH"
+        address = 0x02
+        width = 16
+        big_endian = true
+        scale = "5400000 / x"
+        unit = "RPM"
+
+        [[register]]
+        name = "CONFIG"
+        address = 0x03
+        access = "rw"
+        [[register.field]]
+        name = "MODE"
+        bits = "7:6"
+        enums = { 0 = "Off", 1 = "Auto", 0x2 = "Manual" }
+        [[register.field]]
+        name = "OFFSET"
+        bits = "5:2"
+        signed = true
+        scale = "x / 2"
+        unit = "°C"
+        [[register.field]]
+        name = "STANDBY"
+        bits = "0"
+
+        [[register]]
+        name = "COMMAND"
+        address = 0x04
+        access = "wo"
+    "#;
+
+    fn map() -> RegisterMap {
+        RegisterMap::from_toml(MAP).unwrap()
+    }
+
+    #[test]
+    fn signed_registers_sign_extend() {
+        let map = map();
+        let temp = map.register("temp").unwrap();
+        assert_eq!(temp.decode(0xe7).value, Value::Integer(-25));
+        assert_eq!(temp.decode(0x7f).value, Value::Integer(127));
+        assert_eq!(temp.decode(0x1e7).raw, 0xe7);
+        assert_eq!(temp.raw_range(), (-128, 127));
+        assert_eq!(temp.decode(0xe7).value_text(), "-25 °C");
+    }
+
+    #[test]
+    fn scales_that_divide_by_zero_are_undefined() {
+        let map = map();
+        let tach = map.register_at(0x02).unwrap();
+        assert_eq!(tach.width, Width::Word);
+        assert_eq!(tach.decode(2700).value, Value::Scaled(2000.0));
+        assert_eq!(tach.decode(0).value, Value::Undefined);
+        assert_eq!(tach.raw_range(), (0, 0xffff));
+    }
+
+    #[test]
+    fn fields_are_extracted_and_decoded() {
+        let map = map();
+        let config = map.register("CONFIG").unwrap();
+        // Manual, an offset of -3 (-6 / 2) and standby
+        let reading = config.decode(0b1010_1001);
+        let fields: Vec<(&str, u32, &Value)> = reading.fields.iter()
+            .map(|field| (field.name.as_str(), field.raw, &field.value))
+            .collect();
+        assert_eq!(fields, [
+            ("MODE", 2, &Value::Enum("Manual".to_owned())),
+            ("OFFSET", 0b1010, &Value::Scaled(-3.0)),
+            ("STANDBY", 1, &Value::Integer(1)),
+        ]);
+        assert_eq!(reading.fields[1].value_text(), "-3 °C");
+        // A value without a name is shown as a number
+        assert_eq!(config.decode(0b1100_0000).fields[0].value, Value::Integer(3));
+    }
+
+    #[test]
+    fn write_only_registers_are_skipped_by_read_all() {
+        let map = map();
+        assert!(map.register("COMMAND").unwrap().access.writable());
+        assert!(!map.register("COMMAND").unwrap().access.readable());
+        assert!(!map.register("TEMP").unwrap().access.writable());
+        let mut bus = crate::smbus::sim::SimulatedBus::new();
+        let names: Vec<String> = map.read_all(&mut bus, 0x2e).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["TEMP", "TACH", "CONFIG"]);
+    }
+
+    fn invalid(registers: &str) -> String {
+        let text = format!("[device]\nname = \"Bad\"\n{}", registers);
+        match RegisterMap::from_toml(&text) {
+            Err(RegMapError::Invalid(e)) | Err(RegMapError::Parse(e)) => e,
+            other => panic!("{:?} loaded", other.map(|map| map.registers.len())),
+        }
+    }
+
+    #[test]
+    fn bad_maps_are_refused() {
+        let twice = "[[register]]\nname = \"A\"\naddress = 0\n[[register]]\nname = \"A\"\naddress = 1\n";
+        assert!(invalid(twice).contains("A is defined twice"));
+        let wide = "[[register]]\nname = \"A\"\naddress = 0\n[[register.field]]\nname = \"F\"\nbits = \"8:4\"\n";
+        assert!(invalid(wide).contains("field F is outside the register"));
+        let backwards = "[[register]]\nname = \"A\"\naddress = 0\n[[register.field]]\nname = \"F\"\nbits = \"2:4\"\n";
+        assert!(invalid(backwards).contains("bad bits \"2:4\""));
+        assert!(invalid("[[register]]\nname = \"A\"\naddress = 0\naccess = \"rx\"\n").contains("rx"));
+        assert!(invalid("[[register]]\nname = \"A\"\naddress = 0\nwidth = 32\n").contains("width must be 8 or 16, not 32"));
+        assert!(invalid("[[register]]\nname = \"A\"\naddress = 0\nscale = \"x /\"\n").starts_with("A: "));
+        assert!(invalid("[[register]]\nname = \"A\"\naddress = 0\nenums = { on = \"On\" }\n").contains("enum key \"on\""));
+    }
+
+    #[test]
+    fn builtin_maps_load() {
+        assert_eq!(RegisterMap::builtin().len(), BUILTIN_MAPS.len());
+    }
+}
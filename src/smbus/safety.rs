@@ -0,0 +1,249 @@
+// Write-safety layer.
+//
+// Every write that originates from the UI goes through a `GuardedBus`, which
+// checks it against the snap-in's `WritePolicy` before it reaches the
+// transport. Reads are passed through untouched.
+
+use std::ops::RangeInclusive;
+
+use super::{SmbusError, SmbusTransport};
+
+/// SPD EEPROMs on DDR3/DDR4 DIMMs. Corrupting one can leave a machine
+/// unable to train memory, so they are protected unless explicitly unlocked.
+pub const SPD_EEPROM_RANGE: RangeInclusive<u8> = 0x50..=0x57;
+
+/// DDR4 SPD page select and write-protect commands, plus DDR3 SPD
+/// software write protection.
+pub const SPD_CONTROL_RANGE: RangeInclusive<u8> = 0x30..=0x37;
+
+#[derive(Debug, Clone)]
+pub struct WritePolicy {
+    /// Master switch. When off every write is refused.
+    pub writes_enabled: bool,
+    /// Log writes instead of performing them.
+    pub dry_run: bool,
+    /// Address ranges that refuse writes. Each entry has a reason shown to
+    /// the user.
+    pub protected: Vec<(RangeInclusive<u8>, &'static str)>,
+    /// Addresses the user has explicitly unlocked, overriding `protected`.
+    pub unlocked: Vec<u8>,
+}
+
+impl Default for WritePolicy {
+    fn default() -> Self {
+        Self {
+            writes_enabled: true,
+            dry_run: false,
+            protected: vec![
+                (SPD_EEPROM_RANGE, "SPD EEPROM"),
+                (SPD_CONTROL_RANGE, "SPD write protection"),
+            ],
+            unlocked: Vec::new(),
+        }
+    }
+}
+
+impl WritePolicy {
+    /// Returns why a write to `addr` would be refused, or `None` if it is
+    /// allowed.
+    pub fn check(&self, addr: u8) -> Option<String> {
+        if !self.writes_enabled {
+            return Some("writes are disabled".to_owned());
+        }
+        if self.unlocked.contains(&addr) {
+            return None;
+        }
+        self.protected.iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(_, reason)| format!("{:#04x} is protected ({})", addr, reason))
+    }
+}
+
+/// A write that was requested through a `GuardedBus`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteRecord {
+    pub addr: u8,
+    pub command: u8,
+    pub data: Vec<u8>,
+    pub performed: bool,
+}
+
+/// Wraps a transport so that writes obey a `WritePolicy`.
+#[derive(Debug)]
+pub struct GuardedBus<'a> {
+    bus: &'a mut dyn SmbusTransport,
+    policy: &'a WritePolicy,
+    journal: Vec<WriteRecord>,
+}
+
+impl<'a> GuardedBus<'a> {
+    pub fn new(bus: &'a mut dyn SmbusTransport, policy: &'a WritePolicy) -> Self {
+        Self { bus, policy, journal: Vec::new() }
+    }
+
+    /// Writes requested so far, including the ones skipped by dry-run.
+    pub fn journal(&self) -> &[WriteRecord] {
+        &self.journal
+    }
+
+    // Returns Ok(true) if the write should go to the bus
+    fn admit(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<bool, SmbusError> {
+        if let Some(reason) = self.policy.check(addr) {
+            log::warn!("Refused write to {:#04x} register {:#04x}: {}", addr, command, reason);
+            return Err(SmbusError::Blocked { addr, reason });
+        }
+
+        let performed = !self.policy.dry_run;
+        if !performed {
+            log::info!("Dry run: write {:02x?} to {:#04x} register {:#04x}", data, addr, command);
+        }
+        self.journal.push(WriteRecord { addr, command, data: data.to_vec(), performed });
+        Ok(performed)
+    }
+}
+
+impl SmbusTransport for GuardedBus<'_> {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+        self.bus.read_byte_data(addr, command)
+    }
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+        if self.admit(addr, command, &[value])? {
+            self.bus.write_byte_data(addr, command, value)?;
+        }
+        Ok(())
+    }
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+        self.bus.read_word_data(addr, command)
+    }
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+        if self.admit(addr, command, &value.to_le_bytes())? {
+            self.bus.write_word_data(addr, command, value)?;
+        }
+        Ok(())
+    }
+
+    fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+        self.bus.receive_byte(addr)
+    }
+
+    fn read_block_data(&mut self, addr: u8, command: u8) -> Result<Vec<u8>, SmbusError> {
+        self.bus.read_block_data(addr, command)
+    }
+
+    fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+        self.bus.read_i2c_block_data(addr, command, len)
+    }
+
+    fn write_i2c_block_data(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+        if self.admit(addr, command, data)? {
+            self.bus.write_i2c_block_data(addr, command, data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::sim::SimulatedBus;
+
+    // The demo bus, counting the writes that reach it
+    #[derive(Debug)]
+    struct Counting {
+        bus: SimulatedBus,
+        writes: usize,
+    }
+
+    impl Counting {
+        fn new() -> Self {
+            Self { bus: SimulatedBus::demo(), writes: 0 }
+        }
+    }
+
+    impl SmbusTransport for Counting {
+        fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+            self.bus.read_byte_data(addr, command)
+        }
+
+        fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+            self.writes += 1;
+            self.bus.write_byte_data(addr, command, value)
+        }
+
+        fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+            self.bus.read_word_data(addr, command)
+        }
+
+        fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+            self.writes += 1;
+            self.bus.write_word_data(addr, command, value)
+        }
+
+        fn write_i2c_block_data(&mut self, _addr: u8, _command: u8, _data: &[u8]) -> Result<(), SmbusError> {
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spd_is_protected_until_unlocked() {
+        let policy = WritePolicy::default();
+        assert_eq!(policy.check(0x4c), None);
+        assert_eq!(policy.check(0x50).as_deref(), Some("0x50 is protected (SPD EEPROM)"));
+        assert_eq!(policy.check(0x57).as_deref(), Some("0x57 is protected (SPD EEPROM)"));
+        assert_eq!(policy.check(0x36).as_deref(), Some("0x36 is protected (SPD write protection)"));
+        assert_eq!(policy.check(0x58), None);
+
+        let policy = WritePolicy { unlocked: vec![0x50, 0x36], ..WritePolicy::default() };
+        assert_eq!((policy.check(0x50), policy.check(0x36)), (None, None));
+        assert!(policy.check(0x51).is_some());
+    }
+
+    #[test]
+    fn disabled_writes_are_refused_even_unlocked() {
+        let policy = WritePolicy { writes_enabled: false, unlocked: vec![0x50], ..WritePolicy::default() };
+        assert_eq!(policy.check(0x4c).as_deref(), Some("writes are disabled"));
+        assert_eq!(policy.check(0x50).as_deref(), Some("writes are disabled"));
+    }
+
+    #[test]
+    fn dry_runs_journal_writes_without_making_them() {
+        let mut bus = Counting::new();
+        let policy = WritePolicy { dry_run: true, ..WritePolicy::default() };
+        let mut guarded = GuardedBus::new(&mut bus, &policy);
+        guarded.write_byte_data(0x4c, 0x4a, 0x00).unwrap();
+        guarded.write_word_data(0x4c, 0x0b, 0x1234).unwrap();
+        guarded.write_i2c_block_data(0x4c, 0x10, &[1, 2, 3]).unwrap();
+        // Reads still go through
+        assert_eq!(guarded.read_byte_data(0x4c, 0x4a), Ok(0x20));
+
+        assert_eq!(guarded.journal(), [
+            WriteRecord { addr: 0x4c, command: 0x4a, data: vec![0x00], performed: false },
+            WriteRecord { addr: 0x4c, command: 0x0b, data: vec![0x34, 0x12], performed: false },
+            WriteRecord { addr: 0x4c, command: 0x10, data: vec![1, 2, 3], performed: false },
+        ]);
+        assert_eq!(bus.writes, 0);
+    }
+
+    #[test]
+    fn refused_writes_never_reach_the_bus() {
+        let mut bus = Counting::new();
+        let policy = WritePolicy::default();
+        let mut guarded = GuardedBus::new(&mut bus, &policy);
+        assert!(matches!(guarded.write_byte_data(0x50, 0x00, 0x12), Err(SmbusError::Blocked { addr: 0x50, .. })));
+        assert!(matches!(guarded.write_word_data(0x36, 0x00, 0), Err(SmbusError::Blocked { addr: 0x36, .. })));
+        assert!(matches!(guarded.write_i2c_block_data(0x51, 0x00, &[0]), Err(SmbusError::Blocked { addr: 0x51, .. })));
+        assert!(guarded.journal().is_empty());
+
+        // Allowed ones are made and journalled as performed
+        guarded.write_byte_data(0x4c, 0x4a, 0x00).unwrap();
+        assert_eq!(guarded.journal().len(), 1);
+        assert!(guarded.journal()[0].performed);
+        assert_eq!(bus.writes, 1);
+        assert_eq!(bus.bus.read_byte_data(0x50, 0x00), Ok(0xff));
+        assert_eq!(bus.bus.read_byte_data(0x4c, 0x4a), Ok(0x00));
+    }
+}
@@ -0,0 +1,62 @@
+use std::ops::RangeInclusive;
+
+use super::{SmbusError, SmbusTransport};
+
+/// Addresses probed by a default scan. Like i2cdetect this leaves out the
+/// reserved addresses at both ends of the 7-bit space.
+pub const SCAN_RANGE: RangeInclusive<u8> = 0x03..=0x77;
+
+/// Returns the addresses in `range` that acknowledge a read of register 0.
+pub fn scan(bus: &mut dyn SmbusTransport, range: RangeInclusive<u8>) -> Vec<u8> {
+    range
+        .filter(|&addr| match bus.read_byte_data(addr, 0) {
+            Ok(_) => true,
+            Err(SmbusError::Nack { .. }) => false,
+            Err(e) => {
+                log::debug!("Probe of {:#04x} failed: {}", addr, e);
+                false
+            }
+        })
+        .collect()
+}
+
+/// Parses a device address typed in hex, with or without `0x`. Only the
+/// addresses a scan probes are accepted; the rest are reserved.
+pub fn parse_address(text: &str) -> Result<u8, String> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    match u8::from_str_radix(digits, 16) {
+        Ok(addr) if SCAN_RANGE.contains(&addr) => Ok(addr),
+        _ => Err(format!(
+            "\"{}\" isn't an address from {:02x} to {:02x}",
+            text,
+            SCAN_RANGE.start(),
+            SCAN_RANGE.end(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smbus::sim::SimulatedBus;
+
+    #[test]
+    fn scans_find_the_devices_that_answer() {
+        assert_eq!(scan(&mut SimulatedBus::demo(), SCAN_RANGE), vec![0x4c, 0x50, 0x70]);
+        assert_eq!(scan(&mut SimulatedBus::demo(), 0x51..=0x6f), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn addresses_are_hex_outside_the_reserved_ones() {
+        assert_eq!(parse_address("4c"), Ok(0x4c));
+        assert_eq!(parse_address(" 0x2E "), Ok(0x2e));
+        assert_eq!(parse_address("03"), Ok(0x03));
+        assert_eq!(parse_address("77"), Ok(0x77));
+        assert_eq!(parse_address("02"), Err("\"02\" isn't an address from 03 to 77".to_owned()));
+        assert!(parse_address("78").is_err());
+        assert!(parse_address("1ff").is_err());
+        assert!(parse_address("fan").is_err());
+        assert!(parse_address("").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// A bus that only exists in memory, for trying the snap-in without
+// hardware and for looking at a snapshot taken elsewhere.
+//
+// Each device is a flat file of 256 byte registers. Word transactions read
+// and write two neighbouring registers, low byte first, which is close
+// enough for the devices the drivers know. Devices added with
+// `add_paged_device` also have a 16-bit register space behind a pointer,
+// the way ENE's RGB controllers do (see `drivers::rgb`).
+//
+// Snapshots are text: a `device` line for each address followed by its
+// registers in i2cdump's layout. Registers left out, or shown as XX, read
+// as 0xff.
+//
+//     # comment
+//     device 0x4c
+//     00: 26 34 00 00 08 46 46 00 00 00 00 00 00 00 00 00
+//     ...
+//
+// Snapshots only hold the flat registers.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use super::{SmbusError, SmbusTransport};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulatedBus {
+    devices: BTreeMap<u8, [u8; 256]>,
+    paged: BTreeMap<u8, PagedRegisters>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PagedRegisters {
+    pointer: u16,
+    values: BTreeMap<u16, u8>,
+}
+
+// Commands of a paged device: a word written to POINTER sets the pointer,
+// high byte first, then WRITE and READ reach the register it points at
+const PAGED_POINTER: u8 = 0x00;
+const PAGED_WRITE: u8 = 0x01;
+const PAGED_READ: u8 = 0x81;
+
+impl SimulatedBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_device(&mut self, addr: u8, registers: [u8; 256]) {
+        self.devices.insert(addr, registers);
+    }
+
+    /// Adds a device with `registers` and a paged register space holding
+    /// `paged`. Paged registers that aren't given read as zero.
+    pub fn add_paged_device(&mut self, addr: u8, registers: [u8; 256], paged: &[(u16, &[u8])]) {
+        let mut values = BTreeMap::new();
+        for (start, bytes) in paged {
+            for (offset, value) in bytes.iter().enumerate() {
+                values.insert(start.wrapping_add(offset as u16), *value);
+            }
+        }
+        self.devices.insert(addr, registers);
+        self.paged.insert(addr, PagedRegisters { pointer: 0, values });
+    }
+
+    /// A few devices the drivers recognise, for the "simulation" data
+    /// source.
+    pub fn demo() -> Self {
+        let mut bus = Self::new();
+
+        // EMC2101 fan controller: 38 °C inside, 52 °C at the diode, the fan
+        // at 1200 RPM and 70 % duty under manual control
+        let mut emc2101 = [0u8; 256];
+        emc2101[0x00] = 38;
+        emc2101[0x01] = 52;
+        emc2101[0x05] = 70;
+        emc2101[0x46] = 0x94;
+        emc2101[0x47] = 0x11;
+        emc2101[0x4a] = 0x20;
+        emc2101[0x4c] = 44;
+        emc2101[0xfd] = 0x16;
+        emc2101[0xfe] = 0x5d;
+        emc2101[0xff] = 0x01;
+        bus.add_device(0x4c, emc2101);
+
+        // A 24C02 EEPROM nobody has written to yet
+        bus.add_device(0x50, [0xff; 256]);
+
+        // The ENE RGB controller of a memory module, five LEDs glowing
+        // static orange
+        let mut ene = [0u8; 256];
+        for (i, register) in ene[0xa0..0xb0].iter_mut().enumerate() {
+            *register = i as u8;
+        }
+        let orange = [0xff, 0x00, 0x60].repeat(5);
+        bus.add_paged_device(0x70, ene, &[
+            (0x1000, b"AUDA0-E6K5-0101"),
+            (0x1c02, &[5]),
+            (0x8010, &orange),
+            (0x8021, &[1, 2, 0]),
+        ]);
+
+        bus
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SmbusError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| SmbusError::Other(format!("Couldn't read {}: {}", path.display(), e)))?;
+        Self::from_snapshot(&text)
+    }
+
+    pub fn from_snapshot(text: &str) -> Result<Self, SmbusError> {
+        let mut bus = Self::new();
+        let mut current: Option<u8> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let bad = |what: &str| SmbusError::Other(format!("Snapshot line {}: {}", number + 1, what));
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(addr) = line.strip_prefix("device") {
+                let addr = addr.trim();
+                let addr = u8::from_str_radix(addr.trim_start_matches("0x"), 16)
+                    .ok()
+                    .filter(|addr| *addr <= 0x7f)
+                    .ok_or_else(|| bad("expected a 7-bit address after \"device\""))?;
+                bus.devices.entry(addr).or_insert([0xff; 256]);
+                current = Some(addr);
+                continue;
+            }
+
+            let addr = current.ok_or_else(|| bad("registers before the first \"device\" line"))?;
+            let (offset, values) = line.split_once(':').ok_or_else(|| bad("expected \"offset: values\""))?;
+            let offset = usize::from_str_radix(offset.trim(), 16).map_err(|_| bad("the offset isn't hex"))?;
+            let registers = bus.devices.get_mut(&addr).ok_or_else(|| bad("unknown device"))?;
+            for (index, value) in values.split_whitespace().enumerate() {
+                let register = offset + index;
+                if register > 0xff {
+                    return Err(bad("registers past 0xff"));
+                }
+                registers[register] = match value {
+                    "XX" | "xx" => 0xff,
+                    value => u8::from_str_radix(value, 16).map_err(|_| bad("a value isn't a hex byte"))?,
+                };
+            }
+        }
+
+        Ok(bus)
+    }
+
+    pub fn to_snapshot(&self) -> String {
+        let mut text = String::new();
+        for (addr, registers) in &self.devices {
+            let _ = writeln!(text, "device {:#04x}", addr);
+            for (row, values) in registers.chunks(16).enumerate() {
+                let values: Vec<String> = values.iter().map(|value| format!("{:02x}", value)).collect();
+                let _ = writeln!(text, "{:02x}: {}", row * 16, values.join(" "));
+            }
+        }
+        text
+    }
+
+    fn registers(&mut self, addr: u8) -> Result<&mut [u8; 256], SmbusError> {
+        self.devices.get_mut(&addr).ok_or(SmbusError::Nack { addr })
+    }
+}
+
+impl SmbusTransport for SimulatedBus {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError> {
+        if let (Some(paged), PAGED_READ) = (self.paged.get(&addr), command) {
+            return Ok(paged.values.get(&paged.pointer).copied().unwrap_or(0));
+        }
+        Ok(self.registers(addr)?[command as usize])
+    }
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError> {
+        if let (Some(paged), PAGED_WRITE) = (self.paged.get_mut(&addr), command) {
+            paged.values.insert(paged.pointer, value);
+            return Ok(());
+        }
+        self.registers(addr)?[command as usize] = value;
+        Ok(())
+    }
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError> {
+        let registers = self.registers(addr)?;
+        let low = registers[command as usize];
+        let high = registers[command.wrapping_add(1) as usize];
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError> {
+        if let (Some(paged), PAGED_POINTER) = (self.paged.get_mut(&addr), command) {
+            paged.pointer = value.swap_bytes();
+            return Ok(());
+        }
+        let registers = self.registers(addr)?;
+        let [low, high] = value.to_le_bytes();
+        registers[command as usize] = low;
+        registers[command.wrapping_add(1) as usize] = high;
+        Ok(())
+    }
+
+    fn receive_byte(&mut self, addr: u8) -> Result<u8, SmbusError> {
+        self.read_byte_data(addr, 0)
+    }
+
+    fn write_i2c_block_data(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+        let registers = self.registers(addr)?;
+        for (index, value) in data.iter().enumerate() {
+            registers[command.wrapping_add(index as u8) as usize] = *value;
+        }
+        Ok(())
+    }
+}
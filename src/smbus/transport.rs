@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// Errors returned by an SMBus transport.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmbusError {
+    /// The device did not acknowledge its address.
+    Nack { addr: u8 },
+    /// The bus did not complete the transaction in time.
+    Timeout,
+    /// The write-safety layer refused the transaction.
+    Blocked { addr: u8, reason: String },
+    /// The controller does not implement this kind of transaction.
+    Unsupported(&'static str),
+    /// Anything else reported by the controller or the operating system.
+    Other(String),
+}
+
+impl fmt::Display for SmbusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmbusError::Nack { addr } => write!(f, "no acknowledge from address {:#04x}", addr),
+            SmbusError::Timeout => write!(f, "bus timeout"),
+            SmbusError::Blocked { addr, reason } => write!(f, "write to {:#04x} blocked: {}", addr, reason),
+            SmbusError::Unsupported(what) => write!(f, "transaction not supported: {}", what),
+            SmbusError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SmbusError {}
+
+/// A single SMBus segment that transactions can be issued on.
+///
+/// Addresses are 7-bit. Word transactions use SMBus byte order (low byte
+/// first on the wire), so implementations return the value already
+/// assembled. Transports are `Send` so the bus service can share one
+/// between connections.
+pub trait SmbusTransport: fmt::Debug + Send {
+    fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, SmbusError>;
+
+    fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), SmbusError>;
+
+    fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, SmbusError>;
+
+    fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), SmbusError>;
+
+    /// Reads one byte without sending a command first. Also used as an ACK
+    /// probe.
+    fn receive_byte(&mut self, _addr: u8) -> Result<u8, SmbusError> {
+        Err(SmbusError::Unsupported("receive byte"))
+    }
+
+    /// SMBus block read: the device sends a byte count followed by up to
+    /// 32 bytes.
+    fn read_block_data(&mut self, _addr: u8, _command: u8) -> Result<Vec<u8>, SmbusError> {
+        Err(SmbusError::Unsupported("block read"))
+    }
+
+    /// Reads `len` consecutive bytes starting at `command`. Controllers
+    /// without I2C block support fall back to one read per byte.
+    fn read_i2c_block_data(&mut self, addr: u8, command: u8, len: usize) -> Result<Vec<u8>, SmbusError> {
+        (0..len)
+            .map(|i| self.read_byte_data(addr, command.wrapping_add(i as u8)))
+            .collect()
+    }
+
+    /// Writes `command` followed by up to 32 bytes of `data` in one
+    /// transaction.
+    fn write_i2c_block_data(&mut self, _addr: u8, _command: u8, _data: &[u8]) -> Result<(), SmbusError> {
+        Err(SmbusError::Unsupported("I2C block write"))
+    }
+}
+
+/// Largest payload of an SMBus block or I2C block transaction.
+pub const BLOCK_MAX: usize = 32;
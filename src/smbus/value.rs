@@ -0,0 +1,88 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A typed value read from a device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Integer(i64),
+    Scaled(f64),
+    Enum(String),
+    Text(String),
+    /// The device returned something that has no meaningful value, e.g. a
+    /// tachometer period of zero.
+    Undefined,
+}
+
+impl Value {
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Value::Integer(v) => Some(*v as f64),
+            Value::Scaled(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Formats the value followed by `unit`. Percentages are written
+    /// without a space; enums and text never get a unit.
+    pub fn with_unit(&self, unit: Option<&str>) -> String {
+        match (self, unit) {
+            (Value::Integer(_), Some(unit)) | (Value::Scaled(_), Some(unit)) => {
+                if unit == "%" {
+                    format!("{}%", self)
+                } else {
+                    format!("{} {}", self, unit)
+                }
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Scaled(v) => {
+                // Round to three places and drop trailing zeroes
+                let text = format!("{:.3}", v);
+                let text = text.trim_end_matches('0').trim_end_matches('.');
+                f.write_str(if text == "-0" { "0" } else { text })
+            }
+            Value::Enum(name) | Value::Text(name) => f.write_str(name),
+            Value::Undefined => f.write_str("n/a"),
+        }
+    }
+}
+
+/// One named value shown for a device, e.g. `Voltage = 12.1 V`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Property {
+    pub name: String,
+    pub value: Value,
+    pub unit: Option<String>,
+}
+
+impl Property {
+    pub fn new(name: &str, value: Value, unit: Option<&str>) -> Self {
+        Self {
+            name: name.to_owned(),
+            value,
+            unit: unit.map(str::to_owned),
+        }
+    }
+
+    pub fn text(name: &str, text: impl Into<String>) -> Self {
+        Self::new(name, Value::Text(text.into()), None)
+    }
+
+    pub fn value_text(&self) -> String {
+        self.value.with_unit(self.unit.as_deref())
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.value_text())
+    }
+}
@@ -0,0 +1,166 @@
+// smbusctl run as a program against the simulated bus, which is the same
+// demo bus each time: an EMC2101 at 0x4c, a 24C02 at 0x50 and an ENE RGB
+// controller at 0x70. Exit codes are 0 for success, 1 when the bus fails
+// and 2 for usage errors.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+struct Run {
+    code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+fn smbusctl(args: &[&str]) -> Run {
+    let output = Command::new(env!("CARGO_BIN_EXE_smbusctl"))
+        .args(args)
+        .output()
+        .expect("couldn't run smbusctl");
+    Run {
+        code: output.status.code().expect("smbusctl was killed"),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+fn simulate(args: &[&str]) -> Run {
+    let mut all = vec!["--simulate"];
+    all.extend_from_slice(args);
+    smbusctl(&all)
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("smbusctl-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn detect_lists_the_demo_devices() {
+    let run = simulate(&["detect"]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+    let lines: Vec<&str> = run.stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("0x4c") && lines[0].ends_with("EMC2101"));
+    assert!(lines[1].starts_with("0x50") && lines[1].contains("24C02"));
+    assert!(lines[2].starts_with("0x70") && lines[2].contains("ENE"));
+
+    let run = simulate(&["detect", "0x08", "0x40"]);
+    assert_eq!(run.code, 0);
+    assert_eq!(run.stdout.trim(), "No devices found between 0x08 and 0x40");
+}
+
+#[test]
+fn json_output_parses() {
+    let run = simulate(&["--json", "detect"]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+    let devices: serde_json::Value = serde_json::from_str(&run.stdout).unwrap();
+    let addrs: Vec<u64> = devices.as_array().unwrap().iter().map(|device| device["addr"].as_u64().unwrap()).collect();
+    assert_eq!(addrs, vec![0x4c, 0x50, 0x70]);
+    assert_eq!(devices[0]["kind"], "FanController");
+
+    let run = simulate(&["--json", "get", "0x4c", "0"]);
+    let value: serde_json::Value = serde_json::from_str(&run.stdout).unwrap();
+    assert_eq!(value["value"], 0x26);
+}
+
+#[test]
+fn reads_registers_and_devices() {
+    let run = simulate(&["get", "0x4c", "0x00"]);
+    assert_eq!((run.code, run.stdout.trim()), (0, "0x26"));
+
+    let run = simulate(&["dump", "76"]);
+    assert_eq!(run.code, 0);
+    assert_eq!(run.stdout.lines().count(), 17);
+    assert!(run.stdout.lines().nth(1).unwrap().starts_with("00: 26 34"));
+
+    let run = simulate(&["identify", "0x4c"]);
+    assert_eq!(run.code, 0);
+    assert!(run.stdout.contains("Microchip"));
+
+    let run = simulate(&["sensors", "0x4c"]);
+    assert_eq!(run.code, 0);
+    assert!(run.stdout.contains("EXT_TEMP") && run.stdout.contains("52 °C"));
+
+    let run = simulate(&["fan", "0x4c"]);
+    assert_eq!(run.code, 0);
+    assert!(run.stdout.contains("FAN_MODE (setting)  Manual"));
+
+    let run = simulate(&["rgb", "0x70"]);
+    assert_eq!(run.code, 0);
+    assert!(run.stdout.contains("Static"));
+}
+
+#[test]
+fn writes_go_through_the_write_policy() {
+    let run = simulate(&["fan", "0x4c", "FAN_MODE", "Automatic"]);
+    assert_eq!((run.code, run.stdout.trim()), (0, "Written"));
+
+    let run = simulate(&["--dry-run", "set", "0x4c", "0x4a", "0x00"]);
+    assert_eq!((run.code, run.stdout.trim()), (0, "Dry run, nothing written"));
+
+    // SPD EEPROMs are protected unless unlocked
+    let run = simulate(&["set", "0x50", "0x00", "0x12"]);
+    assert_eq!(run.code, 1);
+    assert!(run.stderr.contains("protected"));
+    let run = simulate(&["--unlock", "0x50", "set", "0x50", "0x00", "0x12"]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+}
+
+#[test]
+fn eeproms_back_up_and_restore() {
+    let image = temp_file("eeprom.bin");
+    let path = image.to_str().unwrap();
+    let run = simulate(&["eeprom", "backup", "0x50", path]);
+    assert_eq!(run.code, 0, "{}", run.stderr);
+    assert!(run.stdout.starts_with("Saved the 256 bytes"));
+    // A header, then the demo's blank EEPROM
+    let bytes = std::fs::read(&image).unwrap();
+    assert!(bytes.len() > 256);
+    assert!(bytes[bytes.len() - 256..].iter().all(|byte| *byte == 0xff));
+
+    assert_eq!(simulate(&["eeprom", "restore", "0x50", path]).code, 1);
+    assert_eq!(simulate(&["--unlock", "0x50", "eeprom", "restore", "0x50", path]).code, 0);
+    std::fs::remove_file(image).unwrap();
+}
+
+#[test]
+fn snapshots_are_read_like_the_bus() {
+    let snapshot = temp_file("snapshot.txt");
+    std::fs::write(&snapshot, "device 0x2d\n00: 5a\n").unwrap();
+    let run = smbusctl(&["--snapshot", snapshot.to_str().unwrap(), "get", "0x2d", "0"]);
+    assert_eq!((run.code, run.stdout.trim()), (0, "0x5a"));
+    std::fs::remove_file(&snapshot).unwrap();
+
+    let run = smbusctl(&["--snapshot", snapshot.to_str().unwrap(), "detect"]);
+    assert_eq!(run.code, 1);
+    assert!(run.stderr.starts_with("Couldn't open the bus"));
+}
+
+#[test]
+fn bus_failures_exit_with_one() {
+    let run = simulate(&["get", "0x2d", "0"]);
+    assert_eq!(run.code, 1);
+    assert_eq!(run.stderr.trim(), "no acknowledge from address 0x2d");
+    assert!(run.stdout.is_empty());
+
+    assert_eq!(simulate(&["identify", "0x2d"]).code, 1);
+}
+
+#[test]
+fn usage_errors_exit_with_two() {
+    for args in [
+        &[][..],
+        &["bogus"],
+        &["--bogus", "detect"],
+        &["get", "0x4c"],
+        &["get", "0x80", "0"],
+        &["get", "0x4c", "0x100"],
+        &["detect", "0x40", "0x08"],
+        &["profile", "apply", "nothing"],
+    ] {
+        let run = simulate(args);
+        assert_eq!(run.code, 2, "{:?}: {}", args, run.stderr);
+        assert!(!run.stderr.is_empty());
+    }
+    assert!(simulate(&["bogus"]).stderr.contains("usage: smbusctl"));
+}